@@ -0,0 +1,733 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use clap::builder::{
+    BoolishValueParser, PossibleValuesParser, RangedU64ValueParser, TypedValueParser,
+};
+use clap::{value_parser, ArgAction, Parser, Subcommand};
+use reqwest::header::{HeaderName, HeaderValue};
+
+use parallel_fetch::{
+    parse_byte_range, parse_header, parse_size, Checksum, Clobber, Credentials, FetchError,
+    LogFormat, OnInterrupt, PublicKeyPin, Range, Result, RetryPolicy, SignatureKind, TraceContext,
+};
+
+use crate::daemon;
+
+#[derive(Parser)]
+#[command(
+    version,
+    author,
+    about,
+    subcommand_negates_reqs = true,
+    after_help = "Every option can also be given by an environment variable named after it, such as PARALLEL_FETCH_MAX_RETRIES for --max-retries, which the command line overrides. Flags are set by setting theirs to 1, true or yes, and --verbose by setting PARALLEL_FETCH_VERBOSE to the number of times to repeat it.
+
+Exits with 2 for invalid arguments, 3 when the server lacks support for a download, 4 on a network error, 5 when a download fails validation, 6 on an I/O error such as a full disk, 7 when another process is writing the output and 128 plus the signal number after a signal."
+)]
+/// The arguments of the parallel-fetch binary
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// url to download, may be repeated to download several files
+    #[arg(
+        short,
+        long,
+        env = "PARALLEL_FETCH_URL",
+        required_unless_present_any = ["input_file", "metalink"]
+    )]
+    pub url: Vec<String>,
+
+    /// file output location, or - to write the download to stdout
+    #[arg(short, long, env = "PARALLEL_FETCH_OUTPUT")]
+    pub output: Option<String>,
+
+    /// the number of parallel fetches to execute, defaults to choosing up
+    /// to 10 from the file size
+    #[arg(short = 'n', long, env = "PARALLEL_FETCH_FETCHES", value_parser = value_parser!(u64).range(1..))]
+    pub fetches: Option<u64>,
+
+    /// never split the file into ranges smaller than this, defaults to 1MiB
+    /// when --fetches is not given, accepts suffixes such as 8MiB
+    #[arg(long, env = "PARALLEL_FETCH_MIN_SPLIT_SIZE", value_parser = positive_size)]
+    pub min_split_size: Option<u64>,
+
+    /// start with a few connections and add or remove them as throughput
+    /// changes, up to --fetches
+    #[arg(long, env = "PARALLEL_FETCH_ADAPTIVE", value_parser = BoolishValueParser::new())]
+    pub adaptive: bool,
+
+    /// when a fetch runs out of ranges, take over the second half of the
+    /// range with the most left
+    #[arg(long, env = "PARALLEL_FETCH_SPLIT_SLOW_RANGES", value_parser = BoolishValueParser::new())]
+    pub split_slow_ranges: bool,
+
+    /// race a duplicate request for ranges taking several times longer than
+    /// the median, keeping whichever finishes first
+    #[arg(long, env = "PARALLEL_FETCH_HEDGE", value_parser = BoolishValueParser::new())]
+    pub hedge: bool,
+
+    /// split the content into ranges of this size, defaults to at most 8MiB
+    /// and one per fetch, accepts suffixes such as 8MiB
+    #[arg(long, env = "PARALLEL_FETCH_CHUNK_SIZE", value_parser = positive_size)]
+    pub chunk_size: Option<u64>,
+
+    /// the number of retry attempts to make on failed chunk downloads
+    #[arg(short = 'r', long, env = "PARALLEL_FETCH_MAX_RETRIES", default_value_t = 5, value_parser = value_parser!(u64).range(1..))]
+    pub max_retries: u64,
+
+    /// whether to check the downloaded files md5 sum as a hex string against
+    /// the server provided ETag
+    #[arg(short, long, env = "PARALLEL_FETCH_CHECK_ETAG", value_parser = BoolishValueParser::new())]
+    pub check_etag: bool,
+
+    /// whether to restart a failing download with fewer parallel fetches,
+    /// and then a single fetch, before giving up
+    #[arg(long, env = "PARALLEL_FETCH_DEGRADED_RESTART", value_parser = BoolishValueParser::new())]
+    pub degraded_restart: bool,
+
+    /// whether to record completed ranges in a state file next to the output
+    /// so an interrupted download can be resumed by rerunning it
+    #[arg(long, env = "PARALLEL_FETCH_RESUMABLE", value_parser = BoolishValueParser::new())]
+    pub resumable: bool,
+
+    /// whether to treat an existing output file as a partial download and
+    /// only fetch the remaining bytes
+    #[arg(long = "continue", env = "PARALLEL_FETCH_CONTINUE", value_parser = BoolishValueParser::new())]
+    pub resume: bool,
+
+    /// what to do with partial downloads on Ctrl-C or SIGTERM: keep them to
+    /// resume, or delete them
+    #[arg(
+        long,
+        env = "PARALLEL_FETCH_ON_INTERRUPT",
+        default_value = "keep",
+        value_parser = choice::<OnInterrupt>(&["keep", "delete"])
+    )]
+    pub on_interrupt: OnInterrupt,
+
+    /// the number of seconds downloads are given to stop on Ctrl-C or
+    /// SIGTERM before exiting regardless
+    #[arg(long, env = "PARALLEL_FETCH_GRACE_PERIOD", default_value_t = 10)]
+    pub grace_period: u64,
+
+    /// whether to reserve disk space for the whole output before downloading
+    #[arg(
+        long,
+        env = "PARALLEL_FETCH_PREALLOCATE",
+        value_parser = BoolishValueParser::new(),
+        conflicts_with = "resume"
+    )]
+    pub preallocate: bool,
+
+    /// free disk space to leave beyond the download, checked before
+    /// starting, accepts suffixes such as 1GiB
+    #[arg(long, env = "PARALLEL_FETCH_SPACE_MARGIN", default_value = "0", value_parser = parse_size)]
+    pub space_margin: u64,
+
+    /// the bytes each fetch buffers before writing to the output, defaults
+    /// to 8K, accepts suffixes such as 1MiB
+    #[arg(long, env = "PARALLEL_FETCH_WRITE_BUFFER", value_parser = positive_size)]
+    pub write_buffer: Option<u64>,
+
+    /// flush buffered bytes and sync the output to disk every this many
+    /// seconds
+    #[arg(long, env = "PARALLEL_FETCH_SYNC_INTERVAL", value_parser = value_parser!(u64).range(1..))]
+    pub sync_interval: Option<u64>,
+
+    /// whether to download to the output with .part appended, renaming it
+    /// once complete and verified
+    #[arg(long, env = "PARALLEL_FETCH_PART_FILE", value_parser = BoolishValueParser::new())]
+    pub part_file: bool,
+
+    /// what to do when the output already exists: overwrite it, skip the
+    /// download, fail, or rename to a numbered file
+    #[arg(
+        long,
+        env = "PARALLEL_FETCH_CLOBBER",
+        default_value = "overwrite",
+        value_parser = choice::<Clobber>(&["overwrite", "skip", "error", "rename"])
+    )]
+    pub clobber: Clobber,
+
+    /// whether to create missing directories of the output
+    #[arg(long, env = "PARALLEL_FETCH_CREATE_DIRS", value_parser = BoolishValueParser::new())]
+    pub create_dirs: bool,
+
+    /// whether to set the modification time of the output to the
+    /// Last-Modified time from the server
+    #[arg(long, env = "PARALLEL_FETCH_PRESERVE_TIMESTAMPS", value_parser = BoolishValueParser::new())]
+    pub preserve_timestamps: bool,
+
+    /// whether to save the source url, ETag, Last-Modified time, size and
+    /// digest of the output to a .meta.json file next to it
+    #[arg(long, env = "PARALLEL_FETCH_WRITE_METADATA", value_parser = BoolishValueParser::new())]
+    pub write_metadata: bool,
+
+    /// whether to download even when the output already matches the size
+    /// and ETag of the content
+    #[arg(long, env = "PARALLEL_FETCH_FORCE", value_parser = BoolishValueParser::new())]
+    pub force: bool,
+
+    /// whether to skip the download when the server reports the file
+    /// unchanged since the ETag and Last-Modified time saved by an earlier
+    /// --write-metadata
+    #[arg(long, env = "PARALLEL_FETCH_CONDITIONAL", value_parser = BoolishValueParser::new())]
+    pub conditional: bool,
+
+    /// a directory of earlier downloads to clone, hard link or copy the
+    /// output from when its url and ETag or its --checksum match one there,
+    /// which each download is added to
+    #[arg(long, env = "PARALLEL_FETCH_CACHE_DIR")]
+    pub cache_dir: Option<PathBuf>,
+
+    /// whether to download without looking in or adding to the --cache-dir
+    #[arg(long, env = "PARALLEL_FETCH_NO_CACHE", value_parser = BoolishValueParser::new())]
+    pub no_cache: bool,
+
+    /// the url of a zsync file indexing the blocks of the remote file, so
+    /// that only the blocks which the existing output lacks are downloaded
+    #[arg(long, env = "PARALLEL_FETCH_DELTA_INDEX")]
+    pub delta_index: Option<String>,
+
+    /// download only the bytes START-END of the remote file, such as
+    /// 1000-2000000, or 1000- for the rest of it
+    #[arg(long, env = "PARALLEL_FETCH_RANGE", value_parser = parse_byte_range)]
+    pub range: Option<Range>,
+
+    /// whether to restart the download once from scratch if the remote file
+    /// changes partway, rather than failing
+    #[arg(long, env = "PARALLEL_FETCH_RESTART_ON_CHANGE", value_parser = BoolishValueParser::new())]
+    pub restart_on_change: bool,
+
+    /// whether to accept a gzip or deflate compressed response, decoding it
+    /// while downloading
+    #[arg(long, env = "PARALLEL_FETCH_COMPRESSED", value_parser = BoolishValueParser::new())]
+    pub compressed: bool,
+
+    /// whether to decompress a downloaded .gz file, recognised by its suffix
+    /// or its first bytes
+    #[arg(long, env = "PARALLEL_FETCH_DECOMPRESS", value_parser = BoolishValueParser::new())]
+    pub decompress: bool,
+
+    /// whether to keep the compressed file after decompressing it
+    #[arg(
+        long,
+        env = "PARALLEL_FETCH_KEEP_COMPRESSED",
+        value_parser = BoolishValueParser::new(),
+        requires = "decompress"
+    )]
+    pub keep_compressed: bool,
+
+    /// whether to fall back to a single sequential download when the server
+    /// does not support range requests
+    #[arg(long, env = "PARALLEL_FETCH_ALLOW_SINGLE_STREAM", value_parser = BoolishValueParser::new())]
+    pub allow_single_stream: bool,
+
+    /// whether to test range support with a small range request when the
+    /// server does not send Accept-Ranges
+    #[arg(long, env = "PARALLEL_FETCH_PROBE_RANGES", value_parser = BoolishValueParser::new())]
+    pub probe_ranges: bool,
+
+    /// how to write logs, as lines for a terminal or as one JSON object per
+    /// line
+    #[arg(
+        long,
+        env = "PARALLEL_FETCH_LOG_FORMAT",
+        default_value = "terminal",
+        value_parser = choice::<LogFormat>(&["terminal", "json"])
+    )]
+    pub log_format: LogFormat,
+
+    /// log more detail, once for debug messages and twice for trace messages
+    #[arg(
+        short,
+        long,
+        env = "PARALLEL_FETCH_VERBOSE",
+        action = ArgAction::Count,
+        conflicts_with = "quiet"
+    )]
+    pub verbose: u8,
+
+    /// only log errors
+    #[arg(short, long, env = "PARALLEL_FETCH_QUIET", value_parser = BoolishValueParser::new())]
+    pub quiet: bool,
+
+    /// a file to also write logs to, rotated once it grows past 10MiB
+    #[arg(long, env = "PARALLEL_FETCH_LOG_FILE")]
+    pub log_file: Option<String>,
+
+    /// print a JSON summary of each download on completion instead of
+    /// logging
+    #[arg(long, env = "PARALLEL_FETCH_JSON", value_parser = BoolishValueParser::new())]
+    pub json: bool,
+
+    /// whether to hide progress bars, which are only shown when stdout is a
+    /// terminal
+    #[arg(long, env = "PARALLEL_FETCH_NO_PROGRESS", value_parser = BoolishValueParser::new())]
+    pub no_progress: bool,
+
+    /// make the HEAD request of each download and print the ranges it would
+    /// fetch, where it would be saved and how it would be verified, without
+    /// downloading anything
+    #[arg(
+        long,
+        env = "PARALLEL_FETCH_DRY_RUN",
+        value_parser = BoolishValueParser::new(),
+        conflicts_with = "watch"
+    )]
+    pub dry_run: bool,
+
+    /// only make the HEAD request of each download, following redirects one
+    /// at a time, and print the redirects, Content-Length, Accept-Ranges,
+    /// ETag and Last-Modified time the server answered with
+    #[arg(
+        long,
+        env = "PARALLEL_FETCH_HEAD_ONLY",
+        value_parser = BoolishValueParser::new(),
+        conflicts_with_all = ["dry_run", "watch"]
+    )]
+    pub head_only: bool,
+
+    /// keep running, checking the url every this many seconds and
+    /// downloading it again when its ETag or Last-Modified time changes,
+    /// without progress bars
+    #[arg(long, env = "PARALLEL_FETCH_WATCH", value_parser = value_parser!(u64).range(1..))]
+    pub watch: Option<u64>,
+
+    /// cap the combined download rate in bytes per second, accepts suffixes
+    /// such as 500K or 2MiB
+    #[arg(long, env = "PARALLEL_FETCH_LIMIT_RATE", value_parser = positive_size)]
+    pub limit_rate: Option<u64>,
+
+    /// the number of seconds to wait for each connection to be established
+    #[arg(long, env = "PARALLEL_FETCH_CONNECT_TIMEOUT", value_parser = value_parser!(u64).range(1..))]
+    pub connect_timeout: Option<u64>,
+
+    /// the number of seconds to wait for each read of response data before
+    /// failing the chunk
+    #[arg(long, env = "PARALLEL_FETCH_READ_TIMEOUT", value_parser = value_parser!(u64).range(1..))]
+    pub read_timeout: Option<u64>,
+
+    /// which errors to retry failed chunk downloads on
+    #[arg(
+        long,
+        env = "PARALLEL_FETCH_RETRY_POLICY",
+        default_value = "transient",
+        value_parser = choice::<RetryPolicy>(&["network", "transient", "all"])
+    )]
+    pub retry_policy: RetryPolicy,
+
+    /// the longest number of seconds to honor a Retry-After header on 429
+    /// and 503 responses
+    #[arg(long, env = "PARALLEL_FETCH_MAX_RETRY_AFTER", default_value_t = 60)]
+    pub max_retry_after: u64,
+
+    /// verify the downloaded file against a checksum such as sha256:<hex>,
+    /// supports sha1, sha256, sha512 and blake3
+    #[arg(long, env = "PARALLEL_FETCH_CHECKSUM", value_parser = value_parser!(Checksum))]
+    pub checksum: Option<Checksum>,
+
+    /// fail unless the download is exactly this many bytes, checked against
+    /// the Content-Length before fetching and the file after, accepts
+    /// suffixes such as 8MiB
+    #[arg(long, env = "PARALLEL_FETCH_EXPECTED_SIZE", value_parser = parse_size)]
+    pub expected_size: Option<u64>,
+
+    /// fail before fetching anything when the Content-Length is over this
+    /// many bytes, accepts suffixes such as 2GiB
+    #[arg(long, env = "PARALLEL_FETCH_MAX_FILESIZE", value_parser = parse_size)]
+    pub max_filesize: Option<u64>,
+
+    /// fail before fetching anything unless the server reports this
+    /// Content-Type, matched as a prefix or a glob such as application/*
+    #[arg(long, env = "PARALLEL_FETCH_EXPECT_CONTENT_TYPE")]
+    pub expect_content_type: Option<String>,
+
+    /// verify the downloaded file against its entry in a checksum file such
+    /// as SHA256SUMS
+    #[arg(long, env = "PARALLEL_FETCH_CHECKSUM_URL")]
+    pub checksum_url: Option<String>,
+
+    /// the part size used to upload the file to S3, needed to check
+    /// multipart ETags such as abc123-8, accepts suffixes such as 8MiB
+    #[arg(
+        long,
+        env = "PARALLEL_FETCH_ETAG_PART_SIZE",
+        value_parser = positive_size,
+        requires = "check_etag"
+    )]
+    pub etag_part_size: Option<u64>,
+
+    /// verify the downloaded file against checksums advertised in
+    /// Content-MD5, Digest, x-amz-checksum or x-goog-hash headers
+    #[arg(long, env = "PARALLEL_FETCH_VERIFY_HEADERS", value_parser = BoolishValueParser::new())]
+    pub verify_headers: bool,
+
+    /// verify a detached signature of the downloaded file, fetched from the
+    /// url with .minisig or .sig appended
+    #[arg(
+        long,
+        env = "PARALLEL_FETCH_SIGNATURE",
+        value_parser = choice::<SignatureKind>(&["minisign", "gpg"]),
+        requires = "public_key"
+    )]
+    pub signature: Option<SignatureKind>,
+
+    /// the minisign public key or GPG keyring to verify the signature with
+    #[arg(long, env = "PARALLEL_FETCH_PUBLIC_KEY", requires = "signature")]
+    pub public_key: Option<PathBuf>,
+
+    /// a local detached signature file to verify instead of fetching one
+    #[arg(long, env = "PARALLEL_FETCH_SIGNATURE_FILE", requires = "signature")]
+    pub signature_file: Option<PathBuf>,
+
+    /// a file listing urls to download, one per line, or - to read them from
+    /// stdin
+    #[arg(short, long, env = "PARALLEL_FETCH_INPUT_FILE")]
+    pub input_file: Option<String>,
+
+    /// a Metalink (.meta4) file listing files to download along with their
+    /// mirrors and hashes
+    #[arg(long, env = "PARALLEL_FETCH_METALINK")]
+    pub metalink: Option<PathBuf>,
+
+    /// another url serving the same file to fetch ranges from, may be
+    /// repeated
+    #[arg(long, env = "PARALLEL_FETCH_MIRROR")]
+    pub mirror: Vec<String>,
+
+    /// the most range requests in flight across every url, defaults to the
+    /// number of fetches
+    #[arg(long, env = "PARALLEL_FETCH_MAX_CONNECTIONS", value_parser = value_parser!(u64).range(1..))]
+    pub max_connections: Option<u64>,
+
+    /// stop every other url once one fails, rather than leaving them to
+    /// finish before reporting the failure
+    #[arg(long, env = "PARALLEL_FETCH_FAIL_FAST", value_parser = BoolishValueParser::new())]
+    pub fail_fast: bool,
+
+    /// the most range requests in flight to any one host
+    #[arg(long, env = "PARALLEL_FETCH_MAX_CONNECTIONS_PER_HOST", value_parser = value_parser!(u64).range(1..))]
+    pub max_connections_per_host: Option<u64>,
+
+    /// the W3C traceparent of a distributed trace to make the downloads part
+    /// of
+    #[arg(long, env = "PARALLEL_FETCH_TRACEPARENT", value_parser = value_parser!(TraceContext))]
+    pub traceparent: Option<TraceContext>,
+
+    /// an extra request header such as "Name: value", may be repeated
+    #[arg(short = 'H', long, env = "PARALLEL_FETCH_HEADER", value_parser = parse_header)]
+    pub header: Vec<(HeaderName, HeaderValue)>,
+
+    /// credentials for basic authentication, given as user:password
+    #[arg(
+        long,
+        env = "PARALLEL_FETCH_USER",
+        hide_env_values = true,
+        value_parser = value_parser!(Credentials)
+    )]
+    pub user: Option<Credentials>,
+
+    /// a token for bearer authentication, unless --user or
+    /// --bearer-token-file is given
+    #[arg(long, env = "PARALLEL_FETCH_BEARER_TOKEN", hide_env_values = true)]
+    pub bearer_token: Option<String>,
+
+    /// a file containing a token for bearer authentication
+    #[arg(
+        long,
+        env = "PARALLEL_FETCH_BEARER_TOKEN_FILE",
+        conflicts_with = "user"
+    )]
+    pub bearer_token_file: Option<PathBuf>,
+
+    /// a Netscape format cookie file to send cookies from
+    #[arg(long, env = "PARALLEL_FETCH_LOAD_COOKIES")]
+    pub load_cookies: Option<String>,
+
+    /// a file to save cookies to in Netscape format once downloads finish
+    #[arg(long, env = "PARALLEL_FETCH_SAVE_COOKIES")]
+    pub save_cookies: Option<String>,
+
+    /// an HTTP proxy to send requests through, defaults to the http_proxy,
+    /// https_proxy and all_proxy environment variables
+    #[arg(long, env = "PARALLEL_FETCH_PROXY")]
+    pub proxy: Option<String>,
+
+    /// credentials for the proxy, given as user:password
+    #[arg(
+        long,
+        env = "PARALLEL_FETCH_PROXY_USER",
+        hide_env_values = true,
+        value_parser = value_parser!(Credentials)
+    )]
+    pub proxy_user: Option<Credentials>,
+
+    /// a comma separated list of hosts to connect to directly, defaults to
+    /// the no_proxy environment variable
+    #[arg(long, env = "PARALLEL_FETCH_NO_PROXY")]
+    pub no_proxy: Option<String>,
+
+    /// a PEM bundle of certificate authorities to trust in addition to the
+    /// system's
+    #[arg(long, env = "PARALLEL_FETCH_CACERT")]
+    pub cacert: Option<PathBuf>,
+
+    /// skip TLS certificate verification, only use this for trusted internal
+    /// mirrors
+    #[arg(short = 'k', long, env = "PARALLEL_FETCH_INSECURE", value_parser = BoolishValueParser::new())]
+    pub insecure: bool,
+
+    /// a client certificate for mutual TLS, either a PEM file or a PKCS #12
+    /// archive
+    #[arg(long, env = "PARALLEL_FETCH_CERT")]
+    pub cert: Option<PathBuf>,
+
+    /// the PEM private key of the client certificate, if not in the
+    /// certificate file
+    #[arg(long, env = "PARALLEL_FETCH_KEY", requires = "cert")]
+    pub key: Option<PathBuf>,
+
+    /// the password of a PKCS #12 client certificate
+    #[arg(
+        long,
+        env = "PARALLEL_FETCH_CERT_PASSWORD",
+        hide_env_values = true,
+        requires = "cert"
+    )]
+    pub cert_password: Option<String>,
+
+    /// a base64 SHA-256 hash of the public key servers must present, such as
+    /// sha256//<base64>, may be repeated. Each server is checked once before
+    /// downloading on a connection of its own; the download's connections
+    /// and redirects are not pinned
+    #[arg(long, env = "PARALLEL_FETCH_PIN_SHA256", value_parser = value_parser!(PublicKeyPin))]
+    pub pin_sha256: Vec<PublicKeyPin>,
+
+    /// a ledger file which successfully downloaded urls are appended to
+    #[arg(long, env = "PARALLEL_FETCH_HISTORY")]
+    pub history: Option<String>,
+
+    /// skip urls which are already recorded as downloaded in the history
+    /// ledger
+    #[arg(
+        long,
+        env = "PARALLEL_FETCH_SKIP_EXISTING_HISTORY",
+        value_parser = BoolishValueParser::new(),
+        requires = "history"
+    )]
+    pub skip_existing_history: bool,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// runs downloads submitted over an HTTP API until stopped
+    Daemon {
+        /// the address to serve the API on
+        #[arg(long, env = "PARALLEL_FETCH_LISTEN", default_value = daemon::DEFAULT_ADDRESS)]
+        listen: SocketAddr,
+
+        /// the most downloads run at once, beyond which they queue by
+        /// priority
+        #[arg(
+            long,
+            env = "PARALLEL_FETCH_MAX_DOWNLOADS",
+            default_value_t = 3,
+            value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+        )]
+        max_downloads: usize,
+
+        /// the most connections open across all downloads
+        #[arg(
+            long,
+            env = "PARALLEL_FETCH_MAX_CONNECTIONS",
+            default_value_t = 10,
+            value_parser = value_parser!(u64).range(1..)
+        )]
+        max_connections: u64,
+
+        /// the file queued and running downloads are kept in, to carry on
+        /// with them after a restart
+        #[arg(long, env = "PARALLEL_FETCH_QUEUE_FILE", default_value = daemon::DEFAULT_QUEUE_FILE)]
+        queue_file: PathBuf,
+    },
+    /// submits a download to a running daemon, printing its id
+    Add {
+        /// the address of the daemon
+        #[arg(long, env = "PARALLEL_FETCH_DAEMON", default_value = daemon::DEFAULT_ADDRESS)]
+        daemon: String,
+
+        /// url to download
+        url: String,
+
+        /// file output location, relative to the current directory, which is
+        /// the default
+        #[arg(short, long, env = "PARALLEL_FETCH_OUTPUT")]
+        output: Option<String>,
+
+        /// the number of parallel fetches to execute
+        #[arg(short = 'n', long, env = "PARALLEL_FETCH_FETCHES", value_parser = value_parser!(u64).range(1..))]
+        fetches: Option<u64>,
+
+        /// downloads with a higher priority start first
+        #[arg(
+            long,
+            env = "PARALLEL_FETCH_PRIORITY",
+            default_value_t = 0,
+            allow_hyphen_values = true
+        )]
+        priority: i64,
+
+        /// when to start the download, as a time of day such as 02:00 or an
+        /// RFC 3339 time
+        #[arg(long, env = "PARALLEL_FETCH_START_AT")]
+        start_at: Option<String>,
+
+        /// the times of day the download may run in, such as 22:00-06:00,
+        /// stopping it outside them
+        #[arg(long, env = "PARALLEL_FETCH_WINDOW")]
+        window: Option<String>,
+    },
+    /// shows the progress of a running daemon's downloads
+    Status {
+        /// the address of the daemon
+        #[arg(long, env = "PARALLEL_FETCH_DAEMON", default_value = daemon::DEFAULT_ADDRESS)]
+        daemon: String,
+
+        /// the download to show, defaults to all of them
+        id: Option<u64>,
+    },
+    /// fetches the start of a file with 1, 2, 4 and more parallel fetches,
+    /// using the options given before it, to find the number of fetches
+    /// the server is fastest with
+    Bench {
+        /// url to benchmark
+        url: String,
+
+        /// the bytes to fetch from the start of the file with each number of
+        /// fetches, accepts suffixes such as 8MiB
+        #[arg(
+            long,
+            env = "PARALLEL_FETCH_SAMPLE_SIZE",
+            default_value = "16MiB",
+            value_parser = positive_size
+        )]
+        sample_size: u64,
+
+        /// the most parallel fetches to try
+        #[arg(
+            long,
+            env = "PARALLEL_FETCH_MAX_FETCHES",
+            default_value_t = 16,
+            value_parser = value_parser!(u64).range(1..)
+        )]
+        max_fetches: u64,
+
+        /// download the whole file with the fastest number of fetches
+        /// afterwards
+        #[arg(long, env = "PARALLEL_FETCH_APPLY", value_parser = BoolishValueParser::new())]
+        apply: bool,
+    },
+    /// cancels a download on a running daemon, keeping what it wrote
+    Cancel {
+        /// the address of the daemon
+        #[arg(long, env = "PARALLEL_FETCH_DAEMON", default_value = daemon::DEFAULT_ADDRESS)]
+        daemon: String,
+
+        /// the download to cancel
+        id: u64,
+    },
+}
+
+/// Parse a size as [`parse_size`] does, rejecting zero
+fn positive_size(size: &str) -> Result<u64> {
+    match parse_size(size)? {
+        0 => Err(FetchError::InvalidArgumentsError(
+            "Size must be greater than zero".to_owned(),
+        )),
+        size => Ok(size),
+    }
+}
+
+/// A parser accepting only values, listed in the help, which parse as T
+fn choice<T>(values: &'static [&'static str]) -> impl TypedValueParser<Value = T>
+where
+    T: std::str::FromStr<Err = FetchError> + Clone + Send + Sync + 'static,
+{
+    PossibleValuesParser::new(values).try_map(|value| value.parse::<T>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::error::ErrorKind;
+
+    fn parse(args: &[&str]) -> std::result::Result<Cli, clap::Error> {
+        Cli::try_parse_from(std::iter::once("parallel-fetch").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn definition_is_valid() {
+        use clap::CommandFactory;
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn rejects_out_of_range_values() {
+        let kind = |args: &[&str]| parse(args).err().map(|err| err.kind());
+        assert_eq!(
+            kind(&["-u", "http://a/", "--fetches", "0"]),
+            Some(ErrorKind::ValueValidation)
+        );
+        assert_eq!(
+            kind(&["-u", "http://a/", "--fetches", "many"]),
+            Some(ErrorKind::ValueValidation)
+        );
+        assert_eq!(
+            kind(&["-u", "http://a/", "--max-retries", "0"]),
+            Some(ErrorKind::ValueValidation)
+        );
+        assert_eq!(
+            kind(&["-u", "http://a/", "--chunk-size", "8 furlongs"]),
+            Some(ErrorKind::ValueValidation)
+        );
+        assert_eq!(
+            kind(&["-u", "http://a/", "--clobber", "sometimes"]),
+            Some(ErrorKind::InvalidValue)
+        );
+        assert_eq!(
+            kind(&["--fetches", "4"]),
+            Some(ErrorKind::MissingRequiredArgument)
+        );
+    }
+
+    #[test]
+    fn parses_typed_values() {
+        let cli = parse(&[
+            "-u",
+            "http://a/",
+            "-n",
+            "4",
+            "--chunk-size",
+            "8MiB",
+            "--clobber",
+            "rename",
+            "-H",
+            "X-A: b",
+        ])
+        .unwrap();
+        assert_eq!(cli.fetches, Some(4));
+        assert_eq!(cli.chunk_size, Some(8 << 20));
+        assert_eq!(cli.clobber, Clobber::Rename);
+        assert_eq!(cli.max_retries, 5);
+        assert_eq!(cli.header[0].0, "x-a");
+        assert!(cli.command.is_none());
+
+        let cli = parse(&["cancel", "7"]).unwrap();
+        match cli.command {
+            Some(Command::Cancel { id, .. }) => assert_eq!(id, 7),
+            _ => panic!("expected the cancel subcommand"),
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::fmt;
+use std::str::FromStr;
+
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION};
+
+use crate::errors::{FetchError, Result};
+
+#[derive(Clone, PartialEq)]
+/// Credentials sent in the Authorization header of every request
+pub enum Credentials {
+    /// HTTP basic authentication
+    Basic {
+        /// The user name
+        username: String,
+        /// The password, if any
+        password: Option<String>,
+    },
+    /// A bearer token, as used by OAuth 2 and most artifact registries
+    Bearer(String),
+}
+
+impl Credentials {
+    /// The value of the Authorization header for these credentials,
+    /// marked sensitive so that it is never logged
+    pub fn header_value(&self) -> Result<HeaderValue> {
+        let value = match self {
+            Credentials::Basic { username, password } => {
+                let pair = format!("{}:{}", username, password.as_deref().unwrap_or(""));
+                format!("Basic {}", base64::encode(&pair))
+            }
+            Credentials::Bearer(token) => format!("Bearer {}", token),
+        };
+        let mut value = HeaderValue::from_str(&value)?;
+        value.set_sensitive(true);
+        Ok(value)
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Credentials::Basic { username, .. } => f
+                .debug_struct("Basic")
+                .field("username", username)
+                .field("password", &"<redacted>")
+                .finish(),
+            Credentials::Bearer(_) => f.debug_tuple("Bearer").field(&"<redacted>").finish(),
+        }
+    }
+}
+
+impl FromStr for Credentials {
+    type Err = FetchError;
+
+    /// Parse basic credentials given as `user:password` or just `user`
+    fn from_str(user: &str) -> Result<Credentials> {
+        let mut parts = user.splitn(2, ':');
+        let username = parts.next().unwrap_or("");
+        if username.is_empty() {
+            return Err(FetchError::InvalidArgumentsError(
+                "User name must not be empty".to_owned(),
+            ));
+        }
+        Ok(Credentials::Basic {
+            username: username.to_owned(),
+            password: parts.next().map(String::from),
+        })
+    }
+}
+
+/// A copy of headers with any carrying credentials marked
+/// sensitive, so that their values are hidden when logged
+pub fn redact_headers(headers: &HeaderMap) -> HeaderMap {
+    let mut headers = headers.clone();
+    for (name, value) in headers.iter_mut() {
+        if name == AUTHORIZATION || name == PROXY_AUTHORIZATION || name == COOKIE {
+            value.set_sensitive(true);
+        }
+    }
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn basic_credentials() {
+        let credentials: Credentials = "aladdin:opensesame".parse().unwrap();
+        assert_eq!(
+            credentials.header_value().unwrap(),
+            "Basic YWxhZGRpbjpvcGVuc2VzYW1l"
+        );
+        assert_eq!(
+            format!("{:?}", credentials),
+            r#"Basic { username: "aladdin", password: "<redacted>" }"#
+        );
+        assert!("".parse::<Credentials>().is_err());
+    }
+
+    #[test]
+    fn redacts_credentials() {
+        let credentials = Credentials::Bearer("secret".to_owned());
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, credentials.header_value().unwrap());
+        headers.insert(COOKIE, "session=secret".parse().unwrap());
+        headers.insert("x-request-id", "42".parse().unwrap());
+
+        let logged = format!("{:?} {:?}", credentials, redact_headers(&headers));
+        assert!(!logged.contains("secret"));
+        assert!(logged.contains("42"));
+    }
+}
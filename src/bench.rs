@@ -0,0 +1,141 @@
+use std::sync::Arc;
+
+use slog::{info, warn};
+
+use crate::errors::Result;
+use crate::fetch::{fetch_partial, FetchOptions};
+use crate::output::{ChunkSink, SinkFuture};
+
+/// How far below the best throughput a smaller number of fetches may be
+/// and still be recommended, as a fraction of the best
+const THROUGHPUT_TOLERANCE: f64 = 0.05;
+
+#[derive(Debug, Clone, PartialEq)]
+/// The throughput a sample of a file was fetched at with each of several
+/// numbers of fetches, returned by `benchmark`
+pub struct Benchmark {
+    /// Each number of fetches tried, with the throughput in bytes per
+    /// second the sample was fetched at
+    pub throughputs: Vec<(u64, f64)>,
+}
+
+impl Benchmark {
+    /// The fewest fetches which came within 5% of the best throughput,
+    /// since more connections cost the server more for no real gain
+    pub fn best_fetches(&self) -> Option<u64> {
+        let best = self
+            .throughputs
+            .iter()
+            .map(|&(_, throughput)| throughput)
+            .fold(0.0, f64::max);
+        self.throughputs
+            .iter()
+            .filter(|&&(_, throughput)| throughput >= best * (1.0 - THROUGHPUT_TOLERANCE))
+            .map(|&(num_fetches, _)| num_fetches)
+            .min()
+    }
+}
+
+/// Find how many parallel fetches a server serves a file fastest with,
+/// by fetching its first sample_size bytes with the options build
+/// returns, first with 1 fetch and then 2, 4 and so on up to
+/// max_fetches. The sample is thrown away, so options acting on an
+/// output file are turned off. Once some number of fetches has been
+/// measured, a larger one which fails ends the benchmark rather than
+/// failing it, as servers limiting connections do
+pub async fn benchmark(
+    mut build: impl FnMut() -> FetchOptions,
+    sample_size: u64,
+    max_fetches: u64,
+) -> Result<Benchmark> {
+    let mut throughputs = Vec::new();
+    for num_fetches in fetch_counts(max_fetches) {
+        let mut options = sample_options(build());
+        options.num_fetches = num_fetches;
+        let logger = options.logger.clone();
+        match fetch_partial(options, 0..sample_size, Arc::new(DiscardSink)).await {
+            Ok(report) => {
+                let throughput = report.average_throughput();
+                info!(logger, "benchmarked"; "num_fetches" => num_fetches, "bytes" => report.bytes, "throughput" => throughput);
+                throughputs.push((num_fetches, throughput));
+            }
+            Err(err) if !throughputs.is_empty() => {
+                warn!(logger, "benchmark failed, stopping"; "num_fetches" => num_fetches, "error" => format!("{:?}", err));
+                break;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(Benchmark { throughputs })
+}
+
+/// The numbers of fetches to try: the powers of two below
+/// max_fetches, and max_fetches itself
+fn fetch_counts(max_fetches: u64) -> Vec<u64> {
+    let mut counts: Vec<u64> = (0..64)
+        .map(|power| 1 << power)
+        .take_while(|&count| count < max_fetches)
+        .collect();
+    counts.push(max_fetches.max(1));
+    counts
+}
+
+/// Options fetching a sample into a sink, without the options which
+/// need an output file or would report the sample as a download
+fn sample_options(mut options: FetchOptions) -> FetchOptions {
+    options.auto_fetches = false;
+    options.degraded_restart = false;
+    options.resume = false;
+    options.resumable = false;
+    options.preallocate = false;
+    options.part_file = false;
+    options.preserve_timestamps = false;
+    options.write_metadata = false;
+    options.conditional = false;
+    options.check_etag = false;
+    options.verify_headers = false;
+    options.checksum = None;
+    options.checksum_url = None;
+    options.signature = None;
+    options.expected_size = None;
+    options.decompress = false;
+    options.cache = None;
+    options.delta_index = None;
+    options.progress = None;
+    options.events = None;
+    options
+}
+
+#[derive(Debug)]
+/// A sink dropping everything written to it
+struct DiscardSink;
+
+impl ChunkSink for DiscardSink {
+    fn write_at(&self, _buf: Vec<u8>, _offset: u64) -> SinkFuture<'_> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_double_up_to_max() {
+        assert_eq!(fetch_counts(1), vec![1]);
+        assert_eq!(fetch_counts(8), vec![1, 2, 4, 8]);
+        assert_eq!(fetch_counts(10), vec![1, 2, 4, 8, 10]);
+    }
+
+    #[test]
+    fn best_prefers_fewer_fetches() {
+        let benchmark = Benchmark {
+            throughputs: vec![(1, 10.0), (2, 19.0), (4, 40.0), (8, 41.0), (16, 30.0)],
+        };
+        assert_eq!(benchmark.best_fetches(), Some(4));
+        let empty = Benchmark {
+            throughputs: Vec::new(),
+        };
+        assert_eq!(empty.best_fetches(), None);
+    }
+}
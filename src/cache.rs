@@ -0,0 +1,231 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::errors::Result;
+use crate::verify::{ChecksumAlgorithm, StreamingHasher};
+
+#[derive(Debug)]
+/// A directory of earlier downloads which an output is cloned or copied
+/// from rather than downloaded again. Each file is kept once under `sha256/`, named by
+/// its SHA-256 digest, and `etag/` maps a url and the strong ETag it was
+/// served with to the digest of the content
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    /// The cache kept in dir, which is created once something is added
+    pub fn new(dir: &Path) -> Cache {
+        Cache {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    /// The digest of a cached file for a download of url, found by the
+    /// SHA-256 digest it is expected to have or else by its strong ETag
+    pub fn lookup(
+        &self,
+        url: &str,
+        etag: Option<&str>,
+        digest: Option<&[u8]>,
+    ) -> Result<Option<Vec<u8>>> {
+        let digest = match (digest, etag) {
+            (Some(digest), _) => digest.to_vec(),
+            (None, Some(etag)) => match fs::read_to_string(self.index_path(url, etag)) {
+                Ok(digest) => match hex::decode(digest.trim()) {
+                    Ok(digest) => digest,
+                    Err(_) => return Ok(None),
+                },
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+                Err(err) => return Err(err.into()),
+            },
+            (None, None) => return Ok(None),
+        };
+        Ok(Some(digest).filter(|digest| self.entry_path(digest).is_file()))
+    }
+
+    /// Put the cached file with digest at path, as [`clone_file`] does.
+    /// A cached file which no longer has its digest,
+    /// having been changed through a hard link, is removed and false
+    /// returned
+    pub fn restore(&self, digest: &[u8], path: &Path) -> Result<bool> {
+        let entry = self.entry_path(digest);
+        let digests = StreamingHasher::new(&[ChecksumAlgorithm::Sha256]).finish(&entry)?;
+        if digests[0].1 != digest {
+            fs::remove_file(&entry)?;
+            return Ok(false);
+        }
+        match fs::remove_file(path) {
+            Ok(()) => {}
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+        clone_file(&entry, path)?;
+        Ok(true)
+    }
+
+    /// Add the file at path, which has digest and was downloaded from url
+    /// with etag, unless a file with its digest is already cached
+    pub fn insert(&self, url: &str, etag: Option<&str>, digest: &[u8], path: &Path) -> Result<()> {
+        let entry = self.entry_path(digest);
+        if !entry.is_file() {
+            fs::create_dir_all(self.dir.join("sha256"))?;
+            // Added under another name and renamed into place, so that an
+            // entry is never seen partly copied
+            let mut temp_path = entry.as_os_str().to_owned();
+            temp_path.push(format!(".{}.tmp", std::process::id()));
+            let temp_path = PathBuf::from(temp_path);
+            clone_file(path, &temp_path)?;
+            fs::rename(&temp_path, &entry)?;
+        }
+        if let Some(etag) = etag {
+            fs::create_dir_all(self.dir.join("etag"))?;
+            fs::write(self.index_path(url, etag), hex::encode(digest))?;
+        }
+        Ok(())
+    }
+
+    /// The location of the cached file with digest
+    fn entry_path(&self, digest: &[u8]) -> PathBuf {
+        self.dir.join("sha256").join(hex::encode(digest))
+    }
+
+    /// The location of the digest of the content of url with etag
+    fn index_path(&self, url: &str, etag: &str) -> PathBuf {
+        let key = Sha256::digest(format!("{}\n{}", url, etag).as_bytes());
+        self.dir.join("etag").join(hex::encode(key))
+    }
+}
+
+/// Put a copy of the file at from at to. On file systems with copy on
+/// write, such as btrfs, XFS and APFS, the copy shares the blocks of from
+/// until either is changed, so it is made instantly and takes no space.
+/// Elsewhere to is hard linked to from, or copied if links are not
+/// supported or they are on different file systems
+fn clone_file(from: &Path, to: &Path) -> io::Result<()> {
+    reflink(from, to)
+        .or_else(|_| fs::hard_link(from, to))
+        .or_else(|_| fs::copy(from, to).map(|_| ()))
+}
+
+/// Clone the file at from to the new file to, failing
+/// if the file system cannot share their blocks
+fn reflink(from: &Path, to: &Path) -> io::Result<()> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        use std::os::unix::io::AsRawFd;
+
+        let source = fs::File::open(from)?;
+        let target = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(to)?;
+        if unsafe { libc::ioctl(target.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) } != 0 {
+            let err = io::Error::last_os_error();
+            drop(target);
+            let _ = fs::remove_file(to);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = |path: &Path| {
+            CString::new(path.as_os_str().as_bytes())
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+        };
+        if unsafe { libc::clonefile(path(from)?.as_ptr(), path(to)?.as_ptr(), 0) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios"
+    )))]
+    {
+        let _ = (from, to);
+        Err(io::Error::other(
+            "Cloning files is not supported on this platform",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn restore_by_etag_and_digest() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = Cache::new(&temp_dir.path().join("cache"));
+        let output = temp_dir.path().join("out.bin");
+        fs::write(&output, b"HelloWorld").unwrap();
+        let digest = Sha256::digest(b"HelloWorld").to_vec();
+
+        assert_eq!(
+            cache.lookup("http://a/", Some("\"1\""), None).unwrap(),
+            None
+        );
+        cache
+            .insert("http://a/", Some("\"1\""), &digest, &output)
+            .unwrap();
+        assert_eq!(
+            cache.lookup("http://a/", Some("\"1\""), None).unwrap(),
+            Some(digest.clone())
+        );
+        assert_eq!(
+            cache.lookup("http://a/", Some("\"2\""), None).unwrap(),
+            None
+        );
+        assert_eq!(
+            cache.lookup("http://b/", None, Some(&digest)).unwrap(),
+            Some(digest.clone())
+        );
+
+        let restored = temp_dir.path().join("restored.bin");
+        assert!(cache.restore(&digest, &restored).unwrap());
+        assert_eq!(fs::read(&restored).unwrap(), b"HelloWorld");
+    }
+
+    #[test]
+    fn clone_file_copies() {
+        let temp_dir = TempDir::new().unwrap();
+        let from = temp_dir.path().join("from.bin");
+        let to = temp_dir.path().join("to.bin");
+        fs::write(&from, b"HelloWorld").unwrap();
+
+        clone_file(&from, &to).unwrap();
+        assert_eq!(fs::read(&to).unwrap(), b"HelloWorld");
+    }
+
+    #[test]
+    fn changed_entry_is_removed() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = Cache::new(&temp_dir.path().join("cache"));
+        let output = temp_dir.path().join("out.bin");
+        fs::write(&output, b"HelloWorld").unwrap();
+        let digest = Sha256::digest(b"HelloWorld").to_vec();
+        cache.insert("http://a/", None, &digest, &output).unwrap();
+
+        // As if the output were rewritten in place through a hard link
+        fs::write(cache.entry_path(&digest), b"HelloThere").unwrap();
+        let restored = temp_dir.path().join("restored.bin");
+        assert!(!cache.restore(&digest, &restored).unwrap());
+        assert_eq!(
+            cache.lookup("http://a/", None, Some(&digest)).unwrap(),
+            None
+        );
+    }
+}
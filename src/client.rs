@@ -0,0 +1,162 @@
+use std::env;
+
+use indicatif::HumanBytes;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Method;
+use serde_json::{json, Value};
+
+use parallel_fetch::{FetchError, Result};
+
+use crate::window::{parse_start_at, TimeWindow};
+
+/// Queue a download of url on the daemon at address, returning its id.
+/// A relative output is taken to be relative to the current directory,
+/// as is the default output, rather than to the daemon's. The download
+/// is held until start_at, a time of day or RFC 3339 time, and runs only
+/// within window, a daily range of times such as `22:00-06:00`
+pub async fn add(
+    address: &str,
+    url: &str,
+    output: Option<&str>,
+    fetches: Option<u64>,
+    priority: i64,
+    start_at: Option<&str>,
+    window: Option<&str>,
+) -> Result<u64> {
+    let current_dir = env::current_dir()?;
+    let output = match output {
+        Some(output) => current_dir.join(output),
+        None => current_dir,
+    };
+    let mut body = json!({ "url": url, "output": output, "priority": priority });
+    if let Some(fetches) = fetches {
+        body["fetches"] = fetches.into();
+    }
+    // A time of day is resolved here, in case the daemon runs elsewhere
+    if let Some(start_at) = start_at {
+        body["start_at"] = parse_start_at(start_at)?.to_rfc3339().into();
+    }
+    if let Some(window) = window {
+        body["window"] = window.parse::<TimeWindow>()?.to_string().into();
+    }
+    let response = request(address, Method::POST, "/downloads", Some(body)).await?;
+    response["id"].as_u64().ok_or_else(|| {
+        FetchError::ServerSupportError("The daemon returned no download id".to_owned())
+    })
+}
+
+/// The download with id, or every download if id is None, as the daemon
+/// at address reports them
+pub async fn status(address: &str, id: Option<u64>) -> Result<Vec<Value>> {
+    match id {
+        Some(id) => {
+            let download =
+                request(address, Method::GET, &format!("/downloads/{}", id), None).await?;
+            Ok(vec![download])
+        }
+        None => {
+            let response = request(address, Method::GET, "/downloads", None).await?;
+            Ok(response["downloads"]
+                .as_array()
+                .cloned()
+                .unwrap_or_default())
+        }
+    }
+}
+
+/// Cancel the download with id on the daemon at address
+pub async fn cancel(address: &str, id: u64) -> Result<Value> {
+    request(address, Method::DELETE, &format!("/downloads/{}", id), None).await
+}
+
+/// A line describing a download the daemon reported,
+/// such as `3  running  1.50 MiB/4.77 MiB  http://example.com/file`
+pub fn describe(download: &Value) -> String {
+    let mut line = format!(
+        "{}  {}  {}/{}  {}",
+        download["id"],
+        download["state"].as_str().unwrap_or("unknown"),
+        HumanBytes(download["downloaded"].as_u64().unwrap_or(0)),
+        HumanBytes(download["total"].as_u64().unwrap_or(0)),
+        download["url"].as_str().unwrap_or("")
+    );
+    if let Some(error) = download["error"].as_str() {
+        line.push_str(&format!("  ({})", error));
+    }
+    line
+}
+
+/// Send a request to the daemon at address, returning the JSON it responds
+/// with. A request the daemon refuses fails with the error it gives
+async fn request(address: &str, method: Method, path: &str, body: Option<Value>) -> Result<Value> {
+    let client = reqwest::Client::new();
+    let mut builder = client.request(method, &format!("http://{}{}", address, path));
+    if let Some(body) = body {
+        builder = builder
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.to_string());
+    }
+    let response = builder.send().await?;
+    let status = response.status();
+    let body: Value = serde_json::from_slice(&response.bytes().await?).map_err(|err| {
+        FetchError::ServerSupportError(format!("The daemon returned invalid JSON: {}", err))
+    })?;
+    if !status.is_success() {
+        return Err(FetchError::InvalidArgumentsError(format!(
+            "The daemon refused the request: {}",
+            body["error"].as_str().unwrap_or_else(|| status.as_str())
+        )));
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::mock;
+
+    #[test]
+    fn describes_downloads() {
+        let running = json!({
+            "id": 3,
+            "state": "running",
+            "downloaded": 1572864,
+            "total": 5000000,
+            "url": "http://example.com/file",
+        });
+        assert_eq!(
+            describe(&running),
+            "3  running  1.50 MiB/4.77 MiB  http://example.com/file"
+        );
+
+        let failed = json!({
+            "id": 4,
+            "state": "failed",
+            "downloaded": 0,
+            "total": 0,
+            "url": "http://example.com/missing",
+            "error": "Server returned 404 Not Found",
+        });
+        assert_eq!(
+            describe(&failed),
+            "4  failed  0 B/0 B  http://example.com/missing  (Server returned 404 Not Found)"
+        );
+    }
+
+    #[tokio::test]
+    async fn refused_request_gives_daemon_error() {
+        let _m = mock("POST", "/client-refused")
+            .with_status(400)
+            .with_body(r#"{"error": "A url is required"}"#)
+            .create();
+        let address = mockito::server_url().replace("http://", "");
+
+        let result = request(&address, Method::POST, "/client-refused", Some(json!({}))).await;
+        match result {
+            Err(FetchError::InvalidArgumentsError(message)) => {
+                assert_eq!(message, "The daemon refused the request: A url is required")
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::errors::{FetchError, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// What to do when the output of a download already exists
+pub enum Clobber {
+    /// Download over the existing file
+    Overwrite,
+    /// Leave the existing file and skip the download
+    Skip,
+    /// Fail without downloading
+    Error,
+    /// Download to the first of `file.1`, `file.2` and so on
+    /// which does not exist yet, as wget does
+    Rename,
+}
+
+impl Clobber {
+    /// The path to download to when the output is path,
+    /// or None if the download should be skipped
+    pub fn resolve(self, path: &Path) -> Result<Option<PathBuf>> {
+        if !path.exists() {
+            return Ok(Some(path.to_path_buf()));
+        }
+        match self {
+            Clobber::Overwrite => Ok(Some(path.to_path_buf())),
+            Clobber::Skip => Ok(None),
+            Clobber::Error => Err(FetchError::InvalidArgumentsError(format!(
+                "Output {:?} already exists",
+                path
+            ))),
+            Clobber::Rename => Ok((1..)
+                .map(|n| numbered_path(path, n))
+                .find(|renamed| !renamed.exists())),
+        }
+    }
+}
+
+/// path with `.n` appended
+fn numbered_path(path: &Path, n: usize) -> PathBuf {
+    let mut numbered = path.as_os_str().to_owned();
+    numbered.push(format!(".{}", n));
+    PathBuf::from(numbered)
+}
+
+impl FromStr for Clobber {
+    type Err = FetchError;
+
+    fn from_str(s: &str) -> Result<Clobber> {
+        match s {
+            "overwrite" => Ok(Clobber::Overwrite),
+            "skip" => Ok(Clobber::Skip),
+            "error" => Ok(Clobber::Error),
+            "rename" => Ok(Clobber::Rename),
+            _ => Err(FetchError::InvalidArgumentsError(format!(
+                "Unknown clobber policy {}",
+                s
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn existing_output_policies() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("file.bin");
+        assert_eq!(Clobber::Error.resolve(&path).unwrap(), Some(path.clone()));
+
+        fs::write(&path, b"existing").unwrap();
+        fs::write(temp_dir.path().join("file.bin.1"), b"existing").unwrap();
+        assert_eq!(
+            Clobber::Overwrite.resolve(&path).unwrap(),
+            Some(path.clone())
+        );
+        assert_eq!(Clobber::Skip.resolve(&path).unwrap(), None);
+        assert_eq!(
+            Clobber::Rename.resolve(&path).unwrap(),
+            Some(temp_dir.path().join("file.bin.2"))
+        );
+
+        let error = Clobber::Error.resolve(&path).expect_err("testing");
+        if let FetchError::InvalidArgumentsError(msg) = error {
+            assert!(msg.ends_with("already exists"));
+        } else {
+            panic!("Expected InvalidArgumentsError");
+        }
+    }
+}
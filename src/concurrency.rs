@@ -0,0 +1,141 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The connections an adaptive download starts with
+const INITIAL_LIMIT: usize = 2;
+
+/// How long throughput is measured for between adjustments
+const INTERVAL: Duration = Duration::from_secs(1);
+
+/// The fall in throughput, relative to the last interval,
+/// at which the number of connections is halved
+const DECREASE_THRESHOLD: f64 = 0.1;
+
+#[derive(Debug)]
+struct State {
+    max: usize,
+    limit: usize,
+    bytes: u64,
+    since: Instant,
+    previous: Option<f64>,
+}
+
+#[derive(Debug)]
+/// An additive increase, multiplicative decrease controller for the
+/// number of workers fetching ranges at once. It starts with a few
+/// connections and adds one each interval while the combined throughput
+/// holds up, halving them whenever it falls
+pub struct Concurrency {
+    state: Mutex<State>,
+    finished: AtomicBool,
+}
+
+impl Concurrency {
+    /// Control up to max workers
+    pub fn new(max: usize) -> Concurrency {
+        Concurrency {
+            state: Mutex::new(State {
+                max,
+                limit: INITIAL_LIMIT.min(max).max(1),
+                bytes: 0,
+                since: Instant::now(),
+                previous: None,
+            }),
+            finished: AtomicBool::new(false),
+        }
+    }
+
+    /// Start again from a few connections, as when a
+    /// download restarts with up to max workers
+    pub fn restart(&self, max: usize) {
+        *self.state.lock().unwrap() = Concurrency::new(max).state.into_inner().unwrap();
+        self.finished.store(false, Ordering::SeqCst);
+    }
+
+    /// The number of workers currently allowed to fetch
+    pub fn limit(&self) -> usize {
+        self.state.lock().unwrap().limit
+    }
+
+    /// Whether worker may start another range
+    pub fn admits(&self, worker: usize) -> bool {
+        worker < self.limit()
+    }
+
+    /// Record that no ranges are left, so waiting workers can stop
+    pub fn finish(&self) {
+        self.finished.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether no ranges are left
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::SeqCst)
+    }
+
+    /// Record bytes received by any worker, returning the
+    /// new limit if an interval has passed and it changed
+    pub fn record(&self, bytes: u64) -> Option<usize> {
+        self.record_at(bytes, Instant::now())
+    }
+
+    fn record_at(&self, bytes: u64, now: Instant) -> Option<usize> {
+        let mut state = self.state.lock().unwrap();
+        state.bytes += bytes;
+        let elapsed = now.duration_since(state.since);
+        if elapsed < INTERVAL {
+            return None;
+        }
+
+        let throughput = state.bytes as f64 / elapsed.as_secs_f64();
+        state.bytes = 0;
+        state.since = now;
+        let limit = match state.previous {
+            Some(previous) if throughput < previous * (1.0 - DECREASE_THRESHOLD) => {
+                (state.limit / 2).max(1)
+            }
+            _ => (state.limit + 1).min(state.max),
+        };
+        state.previous = Some(throughput);
+
+        if limit == state.limit {
+            return None;
+        }
+        state.limit = limit;
+        Some(limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn increases_while_throughput_holds() {
+        let concurrency = Concurrency::new(4);
+        assert_eq!(concurrency.limit(), 2);
+        assert!(concurrency.admits(1));
+        assert!(!concurrency.admits(2));
+
+        let start = Instant::now();
+        assert_eq!(concurrency.record_at(1000, start), None);
+        assert_eq!(concurrency.record_at(1000, start + INTERVAL), Some(3));
+        assert_eq!(concurrency.record_at(2000, start + INTERVAL * 2), Some(4));
+        // Capped at the most workers allowed
+        assert_eq!(concurrency.record_at(2000, start + INTERVAL * 3), None);
+    }
+
+    #[test]
+    fn halves_when_throughput_falls() {
+        let concurrency = Concurrency::new(8);
+        let start = Instant::now();
+        concurrency.record_at(1000, start + INTERVAL);
+        concurrency.record_at(1000, start + INTERVAL * 2);
+        concurrency.record_at(1000, start + INTERVAL * 3);
+        assert_eq!(concurrency.limit(), 5);
+        assert_eq!(concurrency.record_at(500, start + INTERVAL * 4), Some(2));
+
+        concurrency.restart(8);
+        assert_eq!(concurrency.limit(), 2);
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures_util::future::{select, Either};
+use futures_util::FutureExt;
+use tokio::timer::delay_for;
+use tokio_sync::oneshot;
+
+use crate::errors::{FetchError, Result};
+use crate::fetch::{fetch, FetchOptions, Range};
+use crate::report::FetchReport;
+
+/// How often paused workers check whether to carry on
+const PAUSE_CHECK: Duration = Duration::from_millis(50);
+
+/// How far back received bytes count towards the current speed
+const SPEED_WINDOW: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// The progress of a single range of a download
+pub struct RangeStatus {
+    /// The range, whose end moves in if its tail was split off
+    pub range: Range,
+    /// The bytes of range written so far
+    pub downloaded: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A snapshot of a download running in the background
+pub struct DownloadStatus {
+    /// Every range started so far, in order of their start
+    pub ranges: Vec<RangeStatus>,
+    /// The bytes written so far across all ranges
+    pub downloaded: u64,
+    /// The total length of the content, or 0 until it is known
+    pub total: u64,
+    /// The bytes per second received over the last few seconds
+    pub speed: f64,
+    /// Whether the download is paused
+    pub paused: bool,
+    /// Whether the download has finished, failed or been cancelled
+    pub finished: bool,
+}
+
+#[derive(Debug, Default)]
+struct Transfer {
+    ranges: BTreeMap<u64, RangeStatus>,
+    downloaded: u64,
+    total: u64,
+    /// When recent chunks were received and their lengths
+    received: VecDeque<(Instant, u64)>,
+}
+
+#[derive(Debug, Default)]
+/// The state shared between a download and its handle, through which
+/// it is paused and cancelled and reports the progress of its ranges
+pub struct DownloadControl {
+    paused: AtomicBool,
+    cancelled: AtomicBool,
+    finished: AtomicBool,
+    transfer: Mutex<Transfer>,
+}
+
+impl DownloadControl {
+    /// Record that range now has range_downloaded of its bytes written,
+    /// after a chunk of bytes, out of downloaded across the download
+    pub fn record(&self, range: Range, range_downloaded: u64, downloaded: u64, total: u64) {
+        let now = Instant::now();
+        let mut transfer = self.transfer.lock().unwrap();
+        let bytes = downloaded.saturating_sub(transfer.downloaded);
+        transfer.ranges.insert(
+            range.start,
+            RangeStatus {
+                range,
+                downloaded: range_downloaded,
+            },
+        );
+        transfer.downloaded = downloaded;
+        transfer.total = total;
+        transfer.received.push_back((now, bytes));
+        while let Some(&(at, _)) = transfer.received.front() {
+            if now.duration_since(at) <= SPEED_WINDOW {
+                break;
+            }
+            transfer.received.pop_front();
+        }
+    }
+
+    /// Whether the download is paused
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Wait for as long as the download is paused
+    pub async fn wait_while_paused(&self) {
+        while self.is_paused() && !self.cancelled.load(Ordering::SeqCst) {
+            delay_for(PAUSE_CHECK).await;
+        }
+    }
+
+    /// Wait until the download is cancelled
+    async fn cancelled(&self) {
+        while !self.cancelled.load(Ordering::SeqCst) {
+            delay_for(PAUSE_CHECK).await;
+        }
+    }
+
+    fn status(&self) -> DownloadStatus {
+        let transfer = self.transfer.lock().unwrap();
+        let now = Instant::now();
+        let recent: u64 = transfer
+            .received
+            .iter()
+            .filter(|(at, _)| now.duration_since(*at) <= SPEED_WINDOW)
+            .map(|(_, bytes)| bytes)
+            .sum();
+        DownloadStatus {
+            ranges: transfer.ranges.values().cloned().collect(),
+            downloaded: transfer.downloaded,
+            total: transfer.total,
+            speed: recent as f64 / SPEED_WINDOW.as_secs_f64(),
+            paused: self.is_paused(),
+            finished: self.finished.load(Ordering::SeqCst),
+        }
+    }
+}
+
+/// A download running in the background, started by `fetch_controlled`
+pub struct DownloadHandle {
+    control: Arc<DownloadControl>,
+    /// Receives the result of the download, until it has been taken
+    result: Option<oneshot::Receiver<Result<FetchReport>>>,
+}
+
+impl DownloadHandle {
+    /// Stop fetching once the chunks being read are written,
+    /// holding on to open connections until resumed
+    pub fn pause(&self) {
+        self.control.paused.store(true, Ordering::SeqCst);
+    }
+
+    /// Carry on with a paused download
+    pub fn resume(&self) {
+        self.control.paused.store(false, Ordering::SeqCst);
+    }
+
+    /// Abandon the download, which then finishes with a CancelledError.
+    /// What was written is left in place, along with the resume state
+    /// of a resumable download so it can be carried on later
+    pub fn cancel(&self) {
+        self.control.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// The progress of the download so far
+    pub fn status(&self) -> DownloadStatus {
+        self.control.status()
+    }
+
+    /// The result of the download if it has finished, without waiting.
+    /// Returns None while it is running, and after the result is taken.
+    /// A download which stopped without a result, as when it panicked,
+    /// gives a CancelledError
+    pub fn try_result(&mut self) -> Option<Result<FetchReport>> {
+        let result = match self.result.as_mut()?.now_or_never()? {
+            Ok(result) => result,
+            Err(_) => Err(stopped_error()),
+        };
+        self.result = None;
+        Some(result)
+    }
+
+    /// Wait for the download to finish, returning its result
+    pub async fn wait(self) -> Result<FetchReport> {
+        let result = match self.result {
+            Some(result) => result,
+            None => {
+                return Err(FetchError::CancelledError(
+                    "The result of the download was already taken".to_owned(),
+                ))
+            }
+        };
+        match result.await {
+            Ok(result) => result,
+            Err(_) => Err(stopped_error()),
+        }
+    }
+}
+
+/// The error of a download whose task ended without sending its result
+fn stopped_error() -> FetchError {
+    FetchError::CancelledError("The download stopped before finishing".to_owned())
+}
+
+/// Start fetching in the background, returning a handle through which the
+/// download can be paused, resumed, cancelled and watched. It runs on the
+/// default executor, so this must be called from within a tokio runtime
+pub fn fetch_controlled(mut options: FetchOptions) -> DownloadHandle {
+    let control = Arc::new(DownloadControl::default());
+    options.control = Some(control.clone());
+    let (sender, result) = oneshot::channel();
+
+    let running = control.clone();
+    tokio::spawn(async move {
+        let download = Box::pin(fetch(options));
+        let cancelled = Box::pin(running.cancelled());
+        let result = match select(download, cancelled).await {
+            Either::Left((result, _)) => result,
+            Either::Right(((), _)) => Err(FetchError::CancelledError(
+                "The download was cancelled".to_owned(),
+            )),
+        };
+        running.finished.store(true, Ordering::SeqCst);
+        // The handle may have been dropped without waiting
+        let _ = sender.send(result);
+    });
+
+    DownloadHandle {
+        control,
+        result: Some(result),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_tracks_ranges() {
+        let control = DownloadControl::default();
+        let first = Range { start: 0, end: 4 };
+        let second = Range { start: 5, end: 9 };
+        control.record(second, 2, 2, 10);
+        control.record(first, 5, 7, 10);
+        control.record(second, 5, 10, 10);
+
+        let status = control.status();
+        assert_eq!(
+            status.ranges,
+            vec![
+                RangeStatus {
+                    range: first,
+                    downloaded: 5,
+                },
+                RangeStatus {
+                    range: second,
+                    downloaded: 5,
+                },
+            ]
+        );
+        assert_eq!(status.downloaded, 10);
+        assert_eq!(status.speed, 10.0 / SPEED_WINDOW.as_secs_f64());
+        assert!(!status.paused);
+    }
+
+    #[test]
+    fn result_of_download_stopped_without_one() {
+        let (sender, result) = oneshot::channel();
+        let mut handle = DownloadHandle {
+            control: Arc::default(),
+            result: Some(result),
+        };
+        assert!(handle.try_result().is_none());
+
+        // As when the download's task panics
+        drop(sender);
+        match handle.try_result() {
+            Some(Err(FetchError::CancelledError(msg))) => {
+                assert_eq!("The download stopped before finishing", msg)
+            }
+            _ => panic!("Expected CancelledError"),
+        }
+        assert!(handle.try_result().is_none());
+    }
+}
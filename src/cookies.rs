@@ -0,0 +1,289 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, HeaderValue, SET_COOKIE};
+use reqwest::Url;
+
+use crate::errors::Result;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Cookie {
+    domain: String,
+    include_subdomains: bool,
+    path: String,
+    secure: bool,
+    http_only: bool,
+    /// Seconds since the epoch, or 0 for a session cookie
+    expires: i64,
+    name: String,
+    value: String,
+}
+
+impl Cookie {
+    fn matches(&self, url: &Url, now: i64) -> bool {
+        let host = match url.host_str() {
+            Some(host) => host.to_ascii_lowercase(),
+            None => return false,
+        };
+        let domain_matches = host == self.domain
+            || (self.include_subdomains && host.ends_with(&format!(".{}", self.domain)));
+        let path_matches = url.path().starts_with(&self.path);
+        let scheme_matches = !self.secure || url.scheme() == "https";
+        let live = self.expires == 0 || self.expires > now;
+        domain_matches && path_matches && scheme_matches && live
+    }
+
+    fn same_cookie(&self, other: &Cookie) -> bool {
+        self.domain == other.domain && self.path == other.path && self.name == other.name
+    }
+}
+
+#[derive(Debug, Default)]
+/// Cookies shared by every request of the downloads using it,
+/// which can be loaded from and saved to a Netscape format cookie file
+pub struct CookieJar {
+    cookies: Mutex<Vec<Cookie>>,
+}
+
+impl CookieJar {
+    /// Create an empty jar
+    pub fn new() -> CookieJar {
+        CookieJar::default()
+    }
+
+    /// Load the cookies of a Netscape format cookie file, as
+    /// written by curl, wget and browser export extensions
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<CookieJar> {
+        Ok(CookieJar::parse(&fs::read_to_string(path)?))
+    }
+
+    /// Parse the contents of a Netscape format cookie file,
+    /// skipping comments and malformed lines
+    pub fn parse(contents: &str) -> CookieJar {
+        let cookies = contents
+            .lines()
+            .filter_map(|line| {
+                let (line, http_only) = match line.strip_prefix("#HttpOnly_") {
+                    Some(line) => (line, true),
+                    None => (line, false),
+                };
+                if line.trim().is_empty() || line.starts_with('#') {
+                    return None;
+                }
+                let fields: Vec<&str> = line.split('\t').collect();
+                if fields.len() != 7 {
+                    return None;
+                }
+                Some(Cookie {
+                    domain: fields[0].trim_start_matches('.').to_ascii_lowercase(),
+                    include_subdomains: fields[1].eq_ignore_ascii_case("TRUE"),
+                    path: fields[2].to_owned(),
+                    secure: fields[3].eq_ignore_ascii_case("TRUE"),
+                    http_only,
+                    expires: fields[4].parse().ok()?,
+                    name: fields[5].to_owned(),
+                    value: fields[6].to_owned(),
+                })
+            })
+            .collect();
+        CookieJar {
+            cookies: Mutex::new(cookies),
+        }
+    }
+
+    /// Write every unexpired cookie to path in Netscape format
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let now = Utc::now().timestamp();
+        let mut contents = String::from("# Netscape HTTP Cookie File\n");
+        for cookie in self.cookies.lock().unwrap().iter() {
+            if cookie.expires != 0 && cookie.expires <= now {
+                continue;
+            }
+            let domain = if cookie.include_subdomains {
+                format!(".{}", cookie.domain)
+            } else {
+                cookie.domain.clone()
+            };
+            let _ = writeln!(
+                contents,
+                "{}{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                if cookie.http_only { "#HttpOnly_" } else { "" },
+                domain,
+                if cookie.include_subdomains {
+                    "TRUE"
+                } else {
+                    "FALSE"
+                },
+                cookie.path,
+                if cookie.secure { "TRUE" } else { "FALSE" },
+                cookie.expires,
+                cookie.name,
+                cookie.value
+            );
+        }
+        fs::write(path, contents)?;
+        Ok(())
+    }
+
+    /// The Cookie header to send with a request to url, if any cookies apply
+    pub fn header_for(&self, url: &Url) -> Option<HeaderValue> {
+        let now = Utc::now().timestamp();
+        let cookies = self.cookies.lock().unwrap();
+        let pairs: Vec<String> = cookies
+            .iter()
+            .filter(|cookie| cookie.matches(url, now))
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect();
+        if pairs.is_empty() {
+            return None;
+        }
+        let mut value = HeaderValue::from_str(&pairs.join("; ")).ok()?;
+        value.set_sensitive(true);
+        Some(value)
+    }
+
+    /// Store the cookies set by a response from url
+    pub fn store(&self, url: &Url, headers: &HeaderMap) {
+        let now = Utc::now().timestamp();
+        let mut cookies = self.cookies.lock().unwrap();
+        for value in headers.get_all(SET_COOKIE) {
+            let cookie = match value
+                .to_str()
+                .ok()
+                .and_then(|value| parse_set_cookie(url, value, now))
+            {
+                Some(cookie) => cookie,
+                None => continue,
+            };
+            cookies.retain(|existing| !existing.same_cookie(&cookie));
+            // A cookie set to expire immediately deletes any previous value
+            if cookie.expires == 0 || cookie.expires > now {
+                cookies.push(cookie);
+            }
+        }
+    }
+}
+
+/// Parse a Set-Cookie header received from url, rejecting
+/// cookies for domains other than that of url
+fn parse_set_cookie(url: &Url, value: &str, now: i64) -> Option<Cookie> {
+    let host = url.host_str()?.to_ascii_lowercase();
+    let mut attributes = value.split(';');
+    let (name, value) = split_pair(attributes.next()?)?;
+    if name.is_empty() {
+        return None;
+    }
+
+    let mut cookie = Cookie {
+        domain: host.clone(),
+        include_subdomains: false,
+        path: default_path(url),
+        secure: false,
+        http_only: false,
+        expires: 0,
+        name: name.to_owned(),
+        value: value.to_owned(),
+    };
+    let mut max_age = None;
+    for attribute in attributes {
+        let (key, value) = split_pair(attribute).unwrap_or((attribute.trim(), ""));
+        match key.to_ascii_lowercase().as_str() {
+            "domain" if !value.is_empty() => {
+                let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                if host != domain && !host.ends_with(&format!(".{}", domain)) {
+                    return None;
+                }
+                cookie.domain = domain;
+                cookie.include_subdomains = true;
+            }
+            "path" if value.starts_with('/') => cookie.path = value.to_owned(),
+            "secure" => cookie.secure = true,
+            "httponly" => cookie.http_only = true,
+            "expires" => {
+                if let Ok(expires) = DateTime::parse_from_rfc2822(value) {
+                    cookie.expires = expires.timestamp();
+                }
+            }
+            "max-age" => max_age = value.parse::<i64>().ok(),
+            _ => {}
+        }
+    }
+    // Max-Age takes precedence over Expires
+    if let Some(max_age) = max_age {
+        cookie.expires = if max_age <= 0 { 1 } else { now + max_age };
+    }
+    Some(cookie)
+}
+
+fn split_pair(pair: &str) -> Option<(&str, &str)> {
+    let split = pair.find('=')?;
+    let (name, value) = pair.split_at(split);
+    Some((name.trim(), value[1..].trim()))
+}
+
+/// The directory of url's path, which cookies without a Path attribute apply to
+fn default_path(url: &Url) -> String {
+    match url.path().rfind('/') {
+        Some(0) | None => "/".to_owned(),
+        Some(end) => url.path()[..end].to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const COOKIES: &str = "# Netscape HTTP Cookie File
+.example.com\tTRUE\t/\tFALSE\t0\tsession\tabc
+#HttpOnly_cdn.example.com\tFALSE\t/files\tTRUE\t0\ttoken\txyz
+example.com\tFALSE\t/\tFALSE\t1\texpired\told
+";
+
+    #[test]
+    fn load_and_match_cookies() {
+        let jar = CookieJar::parse(COOKIES);
+        let url = Url::parse("https://cdn.example.com/files/a.bin").unwrap();
+        assert_eq!(jar.header_for(&url).unwrap(), "session=abc; token=xyz");
+
+        let url = Url::parse("http://cdn.example.com/files/a.bin").unwrap();
+        assert_eq!(jar.header_for(&url).unwrap(), "session=abc");
+
+        let url = Url::parse("https://example.org/").unwrap();
+        assert_eq!(jar.header_for(&url), None);
+    }
+
+    #[test]
+    fn store_and_save_cookies() {
+        let jar = CookieJar::new();
+        let url = Url::parse("https://cdn.example.com/files/a.bin").unwrap();
+        let mut headers = HeaderMap::new();
+        headers.append(
+            SET_COOKIE,
+            "session=abc; Domain=example.com; Path=/; HttpOnly"
+                .parse()
+                .unwrap(),
+        );
+        headers.append(SET_COOKIE, "other=1; Domain=example.org".parse().unwrap());
+        headers.append(SET_COOKIE, "gate=open".parse().unwrap());
+        jar.store(&url, &headers);
+
+        let other = Url::parse("https://www.example.com/").unwrap();
+        assert_eq!(jar.header_for(&other).unwrap(), "session=abc");
+        assert_eq!(jar.header_for(&url).unwrap(), "session=abc; gate=open");
+
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("cookies.txt");
+        jar.save(&path).unwrap();
+        let saved = CookieJar::load(&path).unwrap();
+        assert_eq!(saved.header_for(&url).unwrap(), "session=abc; gate=open");
+
+        let mut headers = HeaderMap::new();
+        headers.append(SET_COOKIE, "gate=; Path=/files; Max-Age=0".parse().unwrap());
+        jar.store(&url, &headers);
+        assert_eq!(jar.header_for(&url).unwrap(), "session=abc");
+    }
+}
@@ -0,0 +1,751 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use chrono::{DateTime, FixedOffset};
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use reqwest::Url;
+use serde_json::{json, Value};
+use slog::{error, info, o, Logger};
+use tokio::timer::delay_for;
+
+use crate::window::{has_started, parse_start_at, TimeWindow};
+
+use parallel_fetch::{
+    fetch_controlled, ConnectionLimit, DownloadHandle, FetchError, FetchOptions, FetchReport,
+    Result,
+};
+
+/// The address the daemon listens on, and clients talk to, by default
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:7878";
+
+/// The file the daemon keeps its queue in, by default
+pub const DEFAULT_QUEUE_FILE: &str = "parallel-fetch-queue.json";
+
+/// The path under which downloads are submitted and listed
+const DOWNLOADS_PATH: &str = "/downloads";
+
+/// How often the queue is checked for downloads which have finished,
+/// making room for those waiting
+const QUEUE_CHECK: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, PartialEq)]
+/// What a download was submitted with, which is kept
+/// in the queue file until the download finishes
+struct Submission {
+    url: String,
+    output: Option<String>,
+    fetches: Option<u64>,
+    /// Downloads with a higher priority start first,
+    /// and those with the same in the order submitted
+    priority: i64,
+    /// When to start the download, if not straight away
+    start_at: Option<DateTime<FixedOffset>>,
+    /// The times of day the download may run in, stopping outside them
+    window: Option<TimeWindow>,
+}
+
+impl Submission {
+    fn from_json(body: &Value) -> std::result::Result<Submission, String> {
+        let url = match body["url"].as_str() {
+            Some(url) => url.to_owned(),
+            None => return Err("A url is required".to_owned()),
+        };
+        if let Err(err) = Url::parse(&url) {
+            return Err(format!("Invalid url {:?}: {}", url, err));
+        }
+        let fetches = body["fetches"].as_u64();
+        if fetches == Some(0) {
+            return Err("Number of fetches must be greater than zero".to_owned());
+        }
+        let start_at = match body["start_at"].as_str() {
+            Some(start_at) => Some(parse_start_at(start_at).map_err(|err| err.to_string())?),
+            None => None,
+        };
+        let window = match body["window"].as_str() {
+            Some(window) => Some(window.parse().map_err(|err: FetchError| err.to_string())?),
+            None => None,
+        };
+        Ok(Submission {
+            url,
+            output: body["output"].as_str().map(String::from),
+            fetches,
+            priority: body["priority"].as_i64().unwrap_or(0),
+            start_at,
+            window,
+        })
+    }
+
+    /// Whether the download may start now
+    fn is_due(&self) -> bool {
+        self.start_at.as_ref().is_none_or(has_started)
+            && self.window.is_none_or(TimeWindow::is_open)
+    }
+
+    fn to_json(&self, id: u64) -> Value {
+        json!({
+            "id": id,
+            "url": self.url,
+            "output": self.output,
+            "fetches": self.fetches,
+            "priority": self.priority,
+            "start_at": self.start_at.map(|start_at| start_at.to_rfc3339()),
+            "window": self.window.map(|window| window.to_string()),
+        })
+    }
+}
+
+/// A download submitted to the daemon
+struct Job {
+    submission: Submission,
+    /// The running download, or None while it is queued
+    handle: Option<DownloadHandle>,
+    /// The result once the download has finished, with any error as text
+    outcome: Option<std::result::Result<FetchReport, String>>,
+    cancelled: bool,
+}
+
+impl Job {
+    fn new(submission: Submission) -> Job {
+        Job {
+            submission,
+            handle: None,
+            outcome: None,
+            cancelled: false,
+        }
+    }
+
+    /// Take the result of the download if it has finished since last checked
+    fn refresh(&mut self) {
+        if let (None, Some(handle)) = (&self.outcome, &mut self.handle) {
+            self.outcome = handle
+                .try_result()
+                .map(|result| result.map_err(|err| err.to_string()));
+        }
+    }
+
+    /// Whether the download is queued or running
+    fn is_pending(&self) -> bool {
+        self.outcome.is_none()
+    }
+
+    /// The state of the download and its progress, as JSON
+    fn summary(&mut self, id: u64) -> Value {
+        self.refresh();
+        let status = self.handle.as_ref().map(|handle| handle.status());
+        let (state, path, error) = match (&self.outcome, &status) {
+            (None, None) if !self.submission.is_due() => ("scheduled", None, None),
+            (None, None) => ("queued", None, None),
+            (None, Some(status)) if status.paused => ("paused", None, None),
+            (None, Some(_)) => ("running", None, None),
+            (Some(Ok(report)), _) => ("finished", report.path.clone(), None),
+            (Some(Err(_)), _) if self.cancelled => ("cancelled", None, None),
+            (Some(Err(err)), _) => ("failed", None, Some(err.clone())),
+        };
+        let mut summary = self.submission.to_json(id);
+        summary["state"] = state.into();
+        summary["downloaded"] = status.as_ref().map_or(0, |status| status.downloaded).into();
+        summary["total"] = status.as_ref().map_or(0, |status| status.total).into();
+        summary["speed"] = status.as_ref().map_or(0.0, |status| status.speed).into();
+        summary["path"] = json!(path);
+        summary["error"] = json!(error);
+        summary
+    }
+}
+
+#[derive(Default)]
+struct Queue {
+    jobs: BTreeMap<u64, Job>,
+    /// The ids of the pending downloads last saved to the queue file
+    saved: Vec<u64>,
+}
+
+impl Queue {
+    /// The ids of the queued downloads which are due to start, those with
+    /// the highest priority first and then in the order submitted
+    fn due(&self) -> Vec<u64> {
+        let mut due: Vec<(i64, u64)> = self
+            .jobs
+            .iter()
+            .filter(|(_, job)| job.is_pending() && job.handle.is_none() && job.submission.is_due())
+            .map(|(&id, job)| (job.submission.priority, id))
+            .collect();
+        due.sort_by_key(|&(priority, id)| (Reverse(priority), id));
+        due.into_iter().map(|(_, id)| id).collect()
+    }
+}
+
+/// The downloads submitted to a running daemon. At most max_downloads run
+/// at once, sharing a limit on the connections open between them, while
+/// the rest wait in a queue which is saved to a file so that they survive
+/// a restart
+struct Daemon {
+    queue: Mutex<Queue>,
+    next_id: AtomicU64,
+    max_downloads: usize,
+    limit: Arc<ConnectionLimit>,
+    queue_file: PathBuf,
+    logger: Logger,
+}
+
+/// Serve the daemon's HTTP API on address until the process is stopped,
+/// running up to max_downloads of the downloads submitted to it at a time
+/// with at most max_connections open between them. Downloads queued or
+/// running are kept in queue_file, and any left there by an earlier run
+/// are queued again, running ones resuming where they stopped.
+/// The API takes and returns JSON:
+///
+/// - `POST /downloads` with `{"url": ..., "output": ..., "fetches": ...,
+///   "priority": ..., "start_at": ..., "window": ...}`, of which only the
+///   url is required, queues a download and returns its id. It is held
+///   until `start_at`, an RFC 3339 time, and stopped whenever the local time
+///   is outside `window`, such as `22:00-06:00`, carrying on where it
+///   stopped once the window opens
+/// - `GET /downloads` lists every download with its state and progress
+/// - `GET /downloads/<id>` returns one download along with its ranges
+/// - `DELETE /downloads/<id>` cancels a download, keeping what it wrote
+pub async fn run(
+    address: SocketAddr,
+    max_downloads: usize,
+    max_connections: u64,
+    queue_file: PathBuf,
+    logger: Logger,
+) -> Result<()> {
+    if max_downloads == 0 {
+        return Err(FetchError::InvalidArgumentsError(
+            "Number of max downloads must be greater than zero".to_owned(),
+        ));
+    }
+    if max_connections == 0 {
+        return Err(FetchError::InvalidArgumentsError(
+            "Number of max connections must be greater than zero".to_owned(),
+        ));
+    }
+
+    let daemon = Arc::new(Daemon::new(
+        max_downloads,
+        max_connections,
+        queue_file,
+        logger,
+    )?);
+
+    let scheduling = daemon.clone();
+    tokio::spawn(async move {
+        loop {
+            scheduling.schedule(&mut scheduling.queue());
+            delay_for(QUEUE_CHECK).await;
+        }
+    });
+
+    let serving = daemon.clone();
+    let make_service = make_service_fn(move |_| {
+        let daemon = serving.clone();
+        async move {
+            Ok::<_, hyper::Error>(service_fn(move |request| {
+                let daemon = daemon.clone();
+                async move { Ok::<_, hyper::Error>(daemon.handle(request).await) }
+            }))
+        }
+    });
+    let server = Server::try_bind(&address).map_err(server_error)?;
+    info!(daemon.logger, "daemon listening"; "address" => address.to_string());
+    server.serve(make_service).await.map_err(server_error)
+}
+
+impl Daemon {
+    /// A daemon with the downloads left in queue_file by an earlier run
+    /// queued again, numbering new downloads after them
+    fn new(
+        max_downloads: usize,
+        max_connections: u64,
+        queue_file: PathBuf,
+        logger: Logger,
+    ) -> Result<Daemon> {
+        let mut queue = Queue::default();
+        for (id, submission) in load_queue(&queue_file)? {
+            queue.jobs.insert(id, Job::new(submission));
+        }
+        if !queue.jobs.is_empty() {
+            info!(logger, "restored queue"; "path" => format!("{:?}", &queue_file), "downloads" => queue.jobs.len());
+        }
+        queue.saved = queue.jobs.keys().cloned().collect();
+        let next_id = queue.jobs.keys().last().map_or(1, |id| id + 1);
+        Ok(Daemon {
+            queue: Mutex::new(queue),
+            next_id: AtomicU64::new(next_id),
+            max_downloads,
+            limit: Arc::new(ConnectionLimit::new(max_connections as usize)),
+            queue_file,
+            logger,
+        })
+    }
+
+    /// The queue, even if a panic while it was held poisoned its lock.
+    /// The queue is never left half changed, so one download or request
+    /// failing that way does not bring down the rest
+    fn queue(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Respond to a request to the API
+    async fn handle(&self, request: Request<Body>) -> Response<Body> {
+        let path = request.uri().path().trim_end_matches('/').to_owned();
+        if path == DOWNLOADS_PATH {
+            return match *request.method() {
+                Method::GET => self.list(),
+                Method::POST => match read_json(request.into_body()).await {
+                    Ok(body) => self.submit(&body),
+                    Err(err) => error_response(StatusCode::BAD_REQUEST, &err),
+                },
+                _ => error_response(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed"),
+            };
+        }
+        let id = match path
+            .strip_prefix(DOWNLOADS_PATH)
+            .and_then(|rest| rest.strip_prefix('/'))
+            .and_then(|id| id.parse::<u64>().ok())
+        {
+            Some(id) => id,
+            None => return error_response(StatusCode::NOT_FOUND, "Not found"),
+        };
+        match *request.method() {
+            Method::GET => self.status(id),
+            Method::DELETE => self.cancel(id),
+            _ => error_response(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed"),
+        }
+    }
+
+    /// Queue the download described by body
+    fn submit(&self, body: &Value) -> Response<Body> {
+        let submission = match Submission::from_json(body) {
+            Ok(submission) => submission,
+            Err(err) => return error_response(StatusCode::BAD_REQUEST, &err),
+        };
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        info!(self.logger, "download submitted"; "id" => id, "url" => &submission.url, "output" => format!("{:?}", &submission.output), "priority" => submission.priority);
+        let mut queue = self.queue();
+        queue.jobs.insert(id, Job::new(submission));
+        self.schedule(&mut queue);
+        json_response(StatusCode::CREATED, json!({ "id": id }))
+    }
+
+    /// Start queued downloads which are due, those with the highest
+    /// priority first, while fewer than max_downloads are running, stop
+    /// running downloads whose windows have closed, and save the queue if
+    /// downloads have been added or have finished since it was saved
+    fn schedule(&self, queue: &mut Queue) {
+        for (&id, job) in queue.jobs.iter_mut() {
+            job.refresh();
+            if let (Some(window), Some(handle), true) =
+                (job.submission.window, &job.handle, job.is_pending())
+            {
+                // A paused download would keep its connections and its place
+                // among max_downloads until the window opens, so it is
+                // cancelled and queued again, resuming where it stopped
+                if !window.is_open() {
+                    handle.cancel();
+                    job.handle = None;
+                    info!(self.logger, "time window closed, download stopped"; "id" => id, "window" => window.to_string());
+                }
+            }
+        }
+        let running = queue
+            .jobs
+            .values()
+            .filter(|job| job.is_pending() && job.handle.is_some())
+            .count();
+        for id in queue
+            .due()
+            .into_iter()
+            .take(self.max_downloads.saturating_sub(running))
+        {
+            if let Some(job) = queue.jobs.get_mut(&id) {
+                self.start(id, job);
+            }
+        }
+
+        let pending: Vec<u64> = queue
+            .jobs
+            .iter()
+            .filter(|(_, job)| job.is_pending())
+            .map(|(&id, _)| id)
+            .collect();
+        if pending != queue.saved {
+            let submissions: Vec<Value> = pending
+                .iter()
+                .map(|id| queue.jobs[id].submission.to_json(*id))
+                .collect();
+            match save_queue(&self.queue_file, submissions) {
+                Ok(()) => queue.saved = pending,
+                Err(err) => {
+                    error!(self.logger, "failed to save queue"; "path" => format!("{:?}", &self.queue_file), "error" => format!("{:?}", err))
+                }
+            }
+        }
+    }
+
+    /// Start the queued download with id. It is resumable, so
+    /// that it carries on where it stopped if the daemon restarts
+    fn start(&self, id: u64, job: &mut Job) {
+        let submission = &job.submission;
+        let mut builder = FetchOptions::builder(submission.url.clone())
+            .output_option(submission.output.clone())
+            .connection_limit(self.limit.clone())
+            .resumable(true)
+            .logger(self.logger.new(o!("id" => id)));
+        if let Some(fetches) = submission.fetches {
+            builder = builder.num_fetches(fetches);
+        }
+        info!(self.logger, "download started"; "id" => id);
+        job.handle = Some(fetch_controlled(builder.build()));
+    }
+
+    /// Every download, in the order they were submitted
+    fn list(&self) -> Response<Body> {
+        let mut queue = self.queue();
+        let downloads: Vec<Value> = queue
+            .jobs
+            .iter_mut()
+            .map(|(&id, job)| job.summary(id))
+            .collect();
+        json_response(StatusCode::OK, json!({ "downloads": downloads }))
+    }
+
+    /// The download with id, along with the progress of each of its ranges
+    fn status(&self, id: u64) -> Response<Body> {
+        let mut queue = self.queue();
+        let job = match queue.jobs.get_mut(&id) {
+            Some(job) => job,
+            None => return error_response(StatusCode::NOT_FOUND, "No such download"),
+        };
+        let mut summary = job.summary(id);
+        let ranges = job
+            .handle
+            .as_ref()
+            .map(|handle| handle.status().ranges)
+            .unwrap_or_default();
+        summary["ranges"] = ranges
+            .iter()
+            .map(|range| {
+                json!({
+                    "start": range.range.start,
+                    "end": range.range.end,
+                    "downloaded": range.downloaded,
+                })
+            })
+            .collect();
+        json_response(StatusCode::OK, summary)
+    }
+
+    /// Cancel the download with id, unless it has already finished
+    fn cancel(&self, id: u64) -> Response<Body> {
+        let mut queue = self.queue();
+        let job = match queue.jobs.get_mut(&id) {
+            Some(job) => job,
+            None => return error_response(StatusCode::NOT_FOUND, "No such download"),
+        };
+        job.refresh();
+        if job.is_pending() {
+            match &job.handle {
+                Some(handle) => handle.cancel(),
+                None => {
+                    job.outcome = Some(Err("The download was cancelled".to_owned()));
+                }
+            }
+            job.cancelled = true;
+            info!(self.logger, "download cancelled"; "id" => id);
+        }
+        let summary = job.summary(id);
+        self.schedule(&mut queue);
+        json_response(StatusCode::ACCEPTED, summary)
+    }
+}
+
+/// The downloads saved in the queue file at path, with their ids,
+/// or none if there is no queue file
+fn load_queue(path: &Path) -> Result<Vec<(u64, Submission)>> {
+    let contents = match fs::read(path) {
+        Ok(contents) => contents,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let invalid = |reason: &str| {
+        FetchError::InvalidArgumentsError(format!("Invalid queue file {:?}: {}", path, reason))
+    };
+    let queue: Value =
+        serde_json::from_slice(&contents).map_err(|err| invalid(&err.to_string()))?;
+    queue["downloads"]
+        .as_array()
+        .ok_or_else(|| invalid("no downloads"))?
+        .iter()
+        .map(|download| {
+            let id = download["id"].as_u64().ok_or_else(|| invalid("no id"))?;
+            Ok((
+                id,
+                Submission::from_json(download).map_err(|err| invalid(&err))?,
+            ))
+        })
+        .collect()
+}
+
+/// Replace the queue file at path with one holding downloads
+fn save_queue(path: &Path, downloads: Vec<Value>) -> Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    fs::write(&temp_path, json!({ "downloads": downloads }).to_string())?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+/// Read the whole of body as JSON
+async fn read_json(mut body: Body) -> std::result::Result<Value, String> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.next().await {
+        bytes.extend_from_slice(&chunk.map_err(|err| err.to_string())?);
+    }
+    serde_json::from_slice(&bytes).map_err(|err| format!("Invalid JSON: {}", err))
+}
+
+fn json_response(status: StatusCode, body: Value) -> Response<Body> {
+    let mut response = Response::new(Body::from(body.to_string()));
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("application/json"),
+    );
+    response
+}
+
+fn error_response(status: StatusCode, error: &str) -> Response<Body> {
+    json_response(status, json!({ "error": error }))
+}
+
+fn server_error(err: hyper::Error) -> FetchError {
+    io::Error::other(err.to_string()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slog::Discard;
+    use std::net::TcpListener;
+    use tempfile::TempDir;
+
+    fn submission(body: Value) -> Submission {
+        Submission::from_json(&body).unwrap()
+    }
+
+    /// Have daemon handle a request, returning the status and JSON it
+    /// responds with
+    async fn send(daemon: &Daemon, method: Method, uri: &str, body: &str) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::from(body.to_owned()))
+            .unwrap();
+        let response = daemon.handle(request).await;
+        let status = response.status();
+        (status, read_json(response.into_body()).await.unwrap())
+    }
+
+    #[test]
+    fn queue_file_round_trip() {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let path = temp_dir.path().join("queue.json");
+        assert_eq!(load_queue(&path).unwrap(), Vec::new());
+
+        let first = submission(json!({
+            "url": "http://example.com/first",
+            "priority": 2,
+            "start_at": "2999-01-01T02:00:00+01:00",
+            "window": "22:00-06:00",
+        }));
+        let second = submission(json!({
+            "url": "http://example.com/second",
+            "output": "/downloads/second",
+            "fetches": 4,
+        }));
+        save_queue(&path, vec![first.to_json(3), second.to_json(7)]).unwrap();
+        assert_eq!(load_queue(&path).unwrap(), vec![(3, first), (7, second)]);
+
+        let daemon = Daemon::new(1, 1, path, Logger::root(Discard, o!())).unwrap();
+        assert_eq!(daemon.next_id.load(Ordering::SeqCst), 8);
+        assert_eq!(daemon.queue().saved, vec![3, 7]);
+    }
+
+    #[test]
+    fn submissions_need_a_url_and_valid_window() {
+        assert_eq!(
+            Submission::from_json(&json!({ "output": "file" })),
+            Err("A url is required".to_owned())
+        );
+        assert!(Submission::from_json(&json!({ "url": "not a url" })).is_err());
+        assert_eq!(
+            Submission::from_json(&json!({
+                "url": "http://example.com/file",
+                "fetches": 0,
+            })),
+            Err("Number of fetches must be greater than zero".to_owned())
+        );
+        assert!(Submission::from_json(&json!({
+            "url": "http://example.com/file",
+            "window": "22:00",
+        }))
+        .is_err());
+        assert!(Submission::from_json(&json!({
+            "url": "http://example.com/file",
+            "start_at": "tomorrow",
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn queued_by_priority_then_id() {
+        let mut queue = Queue::default();
+        for &(id, priority) in &[(1, 0), (2, 5), (3, 0), (4, 5), (5, -1)] {
+            let job = Job::new(submission(json!({
+                "url": "http://example.com/file",
+                "priority": priority,
+            })));
+            queue.jobs.insert(id, job);
+        }
+        let later = submission(json!({
+            "url": "http://example.com/later",
+            "priority": 9,
+            "start_at": "2999-01-01T00:00:00+00:00",
+        }));
+        queue.jobs.insert(6, Job::new(later));
+        assert_eq!(queue.due(), vec![2, 4, 1, 3, 5]);
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_requests() {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let daemon = Daemon::new(
+            1,
+            1,
+            temp_dir.path().join("queue.json"),
+            Logger::root(Discard, o!()),
+        )
+        .unwrap();
+
+        let (status, body) = send(&daemon, Method::GET, "/elsewhere", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"], "Not found");
+        let (status, _) = send(&daemon, Method::GET, "/downloads/first", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, body) = send(&daemon, Method::GET, "/downloads/9", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"], "No such download");
+        let (status, _) = send(&daemon, Method::DELETE, "/downloads/9", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _) = send(&daemon, Method::PUT, "/downloads", "").await;
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+        let (status, _) = send(&daemon, Method::POST, "/downloads/1", "{}").await;
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+
+        let (status, body) = send(&daemon, Method::POST, "/downloads", "not json").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"].as_str().unwrap().starts_with("Invalid JSON"));
+        let (status, body) = send(&daemon, Method::POST, "/downloads", "{}").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "A url is required");
+        let (status, _) = send(
+            &daemon,
+            Method::POST,
+            "/downloads",
+            r#"{"url": "not a url"}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(daemon.queue().jobs.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn survives_a_poisoned_queue() {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let daemon = Arc::new(
+            Daemon::new(
+                1,
+                1,
+                temp_dir.path().join("queue.json"),
+                Logger::root(Discard, o!()),
+            )
+            .unwrap(),
+        );
+
+        let panicking = daemon.clone();
+        let _ = std::thread::spawn(move || {
+            let _queue = panicking.queue();
+            panic!("poisoning the queue");
+        })
+        .join();
+        assert!(daemon.queue.is_poisoned());
+
+        let (status, body) = send(&daemon, Method::GET, "/downloads", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["downloads"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn submits_and_cancels() {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let queue_file = temp_dir.path().join("queue.json");
+        let daemon = Daemon::new(1, 1, queue_file.clone(), Logger::root(Discard, o!())).unwrap();
+        // Takes connections without ever answering,
+        // so the first download runs until it is cancelled
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/file", listener.local_addr().unwrap());
+
+        for (id, name) in (1..).zip(&["first", "second"]) {
+            let submission = json!({
+                "url": url,
+                "output": temp_dir.path().join(name),
+            });
+            let (status, body) = send(
+                &daemon,
+                Method::POST,
+                "/downloads/",
+                &submission.to_string(),
+            )
+            .await;
+            assert_eq!(status, StatusCode::CREATED);
+            assert_eq!(body, json!({ "id": id }));
+        }
+        assert_eq!(load_queue(&queue_file).unwrap().len(), 2);
+
+        let (status, body) = send(&daemon, Method::GET, "/downloads/1", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["state"], "running");
+        assert_eq!(body["url"], url);
+
+        // Waiting for the first to finish, so cancelled straight away
+        let (status, body) = send(&daemon, Method::DELETE, "/downloads/2", "").await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(body["state"], "cancelled");
+        assert_eq!(load_queue(&queue_file).unwrap().len(), 1);
+
+        let (status, _) = send(&daemon, Method::DELETE, "/downloads/1", "").await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        // The download stops the next time it checks for cancellation
+        let mut state = Value::Null;
+        for _ in 0..40 {
+            delay_for(Duration::from_millis(50)).await;
+            let (_, body) = send(&daemon, Method::GET, "/downloads", "").await;
+            state = body["downloads"][0]["state"].clone();
+            if state != "running" {
+                break;
+            }
+        }
+        assert_eq!(state, "cancelled");
+    }
+}
@@ -0,0 +1,483 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use md4::{Digest, Md4};
+
+use crate::errors::{FetchError, Result};
+use crate::fetch::Range;
+use crate::verify::{Checksum, ChecksumAlgorithm, StreamingHasher};
+
+/// The block size zsync uses for files of up to 100 MB
+pub const DEFAULT_BLOCK_SIZE: usize = 2048;
+
+/// How much of a local file is read at a time while looking for blocks
+const READ_SIZE: usize = 1 << 20;
+
+#[derive(Debug, Clone, PartialEq)]
+/// The checksums of each block of a remote file, read from a zsync
+/// control file, from which the blocks a local copy already holds can be
+/// found wherever they have moved to, so that only the rest is downloaded
+pub struct DeltaIndex {
+    block_size: usize,
+    length: u64,
+    /// How many blocks in a row must match before a match is trusted
+    seq_matches: usize,
+    /// How many bytes of each rolling checksum the index keeps
+    rsum_bytes: usize,
+    /// How many bytes of each MD4 digest the index keeps
+    checksum_bytes: usize,
+    sha1: Option<Vec<u8>>,
+    blocks: Vec<BlockSum>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The checksums of a block, truncated as the index gives them
+struct BlockSum {
+    rsum: u32,
+    checksum: Vec<u8>,
+}
+
+impl DeltaIndex {
+    /// Index the file at path in blocks of block_size bytes, keeping
+    /// whole checksums so that a single matching block is trusted
+    pub fn build(path: &Path, block_size: usize) -> Result<DeltaIndex> {
+        if block_size == 0 {
+            return Err(FetchError::InvalidArgumentsError(
+                "Block size must be greater than zero".to_owned(),
+            ));
+        }
+        let mut file = File::open(path)?;
+        let length = file.metadata()?.len();
+        let mut blocks = Vec::new();
+        let mut buffer = vec![0; block_size];
+        loop {
+            let n = read_fully(&mut file, &mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            // The last block is padded with zeros
+            buffer[n..].iter_mut().for_each(|byte| *byte = 0);
+            blocks.push(BlockSum {
+                rsum: Rsum::of(&buffer).key(4),
+                checksum: md4(&buffer).to_vec(),
+            });
+            if n < block_size {
+                break;
+            }
+        }
+        let sha1 = StreamingHasher::new(&[ChecksumAlgorithm::Sha1]).finish(path)?;
+        Ok(DeltaIndex {
+            block_size,
+            length,
+            seq_matches: 1,
+            rsum_bytes: 4,
+            checksum_bytes: 16,
+            sha1: sha1.into_iter().next().map(|(_, digest)| digest),
+            blocks,
+        })
+    }
+
+    /// Parse a zsync control file
+    pub fn parse(bytes: &[u8]) -> Result<DeltaIndex> {
+        let invalid =
+            |reason: &str| FetchError::ValidationError(format!("Invalid zsync file: {}", reason));
+        let header_end = bytes
+            .windows(2)
+            .position(|window| window == b"\n\n")
+            .ok_or_else(|| invalid("no end to its header"))?;
+        let header =
+            std::str::from_utf8(&bytes[..header_end]).map_err(|_| invalid("header is not text"))?;
+        let mut fields = HashMap::new();
+        for line in header.lines() {
+            let mut parts = line.splitn(2, ':');
+            if let (Some(key), Some(value)) = (parts.next(), parts.next()) {
+                fields.insert(key.trim(), value.trim());
+            }
+        }
+        let number = |key: &str| -> Result<u64> {
+            fields
+                .get(key)
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(|| invalid(&format!("missing or invalid {}", key)))
+        };
+        let block_size = number("Blocksize")? as usize;
+        let length = number("Length")?;
+        let lengths: Vec<usize> = fields
+            .get("Hash-Lengths")
+            .ok_or_else(|| invalid("missing Hash-Lengths"))?
+            .split(',')
+            .map(|length| length.trim().parse())
+            .collect::<std::result::Result<_, _>>()
+            .map_err(|_| invalid("invalid Hash-Lengths"))?;
+        let (seq_matches, rsum_bytes, checksum_bytes) = match lengths[..] {
+            [seq_matches @ 1..=2, rsum_bytes @ 1..=4, checksum_bytes @ 3..=16] => {
+                (seq_matches, rsum_bytes, checksum_bytes)
+            }
+            _ => return Err(invalid("invalid Hash-Lengths")),
+        };
+        if block_size == 0 {
+            return Err(invalid("block size of zero"));
+        }
+        let sha1 = match fields.get("SHA-1") {
+            Some(sha1) => Some(hex::decode(sha1).map_err(|_| invalid("invalid SHA-1"))?),
+            None => None,
+        };
+
+        let count = length.div_ceil(block_size as u64) as usize;
+        let sums = &bytes[header_end + 2..];
+        let size = rsum_bytes + checksum_bytes;
+        let needed = count
+            .checked_mul(size)
+            .ok_or_else(|| invalid("too many blocks"))?;
+        if sums.len() < needed {
+            return Err(invalid("too few block checksums"));
+        }
+        let blocks = sums
+            .chunks(size)
+            .take(count)
+            .map(|sum| BlockSum {
+                rsum: sum[..rsum_bytes]
+                    .iter()
+                    .fold(0, |rsum, &byte| rsum << 8 | u32::from(byte)),
+                checksum: sum[rsum_bytes..].to_vec(),
+            })
+            .collect();
+        Ok(DeltaIndex {
+            block_size,
+            length,
+            seq_matches,
+            rsum_bytes,
+            checksum_bytes,
+            sha1,
+            blocks,
+        })
+    }
+
+    /// Write the index as a zsync control file for the file at url
+    pub fn to_zsync(&self, url: &str) -> Vec<u8> {
+        let filename = url.rsplit('/').next().unwrap_or(url);
+        let mut bytes = format!(
+            "zsync: 0.6.2\nFilename: {}\nBlocksize: {}\nLength: {}\nHash-Lengths: {},{},{}\nURL: {}\n",
+            filename,
+            self.block_size,
+            self.length,
+            self.seq_matches,
+            self.rsum_bytes,
+            self.checksum_bytes,
+            url
+        )
+        .into_bytes();
+        if let Some(sha1) = &self.sha1 {
+            bytes.extend_from_slice(format!("SHA-1: {}\n", hex::encode(sha1)).as_bytes());
+        }
+        bytes.push(b'\n');
+        for block in &self.blocks {
+            bytes.extend_from_slice(&block.rsum.to_be_bytes()[4 - self.rsum_bytes..]);
+            bytes.extend_from_slice(&block.checksum);
+        }
+        bytes
+    }
+
+    /// The length of the indexed file
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+
+    /// The SHA-1 checksum of the whole indexed file, if the index gives it
+    pub fn checksum(&self) -> Option<Checksum> {
+        self.sha1.as_ref().map(|digest| Checksum {
+            algorithm: ChecksumAlgorithm::Sha1,
+            digest: digest.clone(),
+        })
+    }
+
+    /// Create the file at path with each block of the indexed file which
+    /// the file at local holds copied into place, returning the ranges
+    /// copied. The rest of the file is left to be downloaded
+    pub fn copy_blocks(&self, local: &Path, path: &Path) -> Result<Vec<Range>> {
+        let found = self.find_blocks(local)?;
+        let mut source = File::open(local)?;
+        let mut target = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)?;
+        let mut copied: Vec<Range> = Vec::new();
+        let mut buffer = vec![0; self.block_size];
+        for (index, offset) in found.into_iter().enumerate() {
+            let offset = match offset {
+                Some(offset) => offset,
+                None => continue,
+            };
+            let start = index as u64 * self.block_size as u64;
+            let len = (self.length - start).min(self.block_size as u64) as usize;
+            source.seek(SeekFrom::Start(offset))?;
+            // A block may have matched the zeros past the end of local
+            let n = read_fully(&mut source, &mut buffer[..len])?;
+            buffer[n..len].iter_mut().for_each(|byte| *byte = 0);
+            target.seek(SeekFrom::Start(start))?;
+            target.write_all(&buffer[..len])?;
+            let end = start + len as u64 - 1;
+            match copied.last_mut() {
+                Some(last) if last.end + 1 == start => last.end = end,
+                _ => copied.push(Range { start, end }),
+            }
+        }
+        Ok(copied)
+    }
+
+    /// Where in the file at local each block of the indexed file was
+    /// found, rolling a checksum over every offset as rsync does
+    fn find_blocks(&self, local: &Path) -> Result<Vec<Option<u64>>> {
+        let mut found = vec![None; self.blocks.len()];
+        let mut remaining = found.len();
+        let mut by_rsum: HashMap<u32, Vec<usize>> = HashMap::new();
+        for (index, block) in self.blocks.iter().enumerate() {
+            by_rsum.entry(block.rsum).or_default().push(index);
+        }
+
+        let mut file = File::open(local)?;
+        let local_length = file.metadata()?.len();
+        let block_size = self.block_size;
+        // The bytes needed past an offset to check a match there and roll on
+        let span = block_size * self.seq_matches + 1;
+        let mut window: Vec<u8> = Vec::new();
+        let mut base = 0;
+        let mut read_all = false;
+        let mut offset = 0;
+        let mut rsum = None;
+        while offset < local_length && remaining > 0 {
+            let at = (offset - base) as usize;
+            if window.len() < at + span {
+                window.drain(..at);
+                base = offset;
+                let start = window.len();
+                window.resize(start + READ_SIZE, 0);
+                let n = read_fully(&mut file, &mut window[start..])?;
+                window.truncate(start + n);
+                if n < READ_SIZE && !read_all {
+                    // Blocks are matched as if local were padded with zeros
+                    read_all = true;
+                    window.resize(window.len() + span, 0);
+                }
+                continue;
+            }
+
+            let data = &window[at..];
+            let sum = rsum.unwrap_or_else(|| Rsum::of(&data[..block_size]));
+            let mut matched = false;
+            if let Some(candidates) = by_rsum.get(&sum.key(self.rsum_bytes)) {
+                let digest = md4(&data[..block_size]);
+                for &index in candidates {
+                    if found[index].is_none() && self.confirm(index, &digest, data) {
+                        found[index] = Some(offset);
+                        remaining -= 1;
+                        matched = true;
+                    }
+                }
+            }
+            if matched {
+                // The next block most likely follows on
+                offset += block_size as u64;
+                rsum = None;
+            } else {
+                rsum = Some(sum.roll(data[0], data[block_size], block_size));
+                offset += 1;
+            }
+        }
+        Ok(found)
+    }
+
+    /// Whether the block with index is at the start of data, which has the
+    /// MD4 digest given. Truncated checksums are only trusted when the
+    /// blocks after it match too
+    fn confirm(&self, index: usize, digest: &[u8; 16], data: &[u8]) -> bool {
+        if digest[..self.checksum_bytes] != self.blocks[index].checksum[..] {
+            return false;
+        }
+        (1..self.seq_matches).all(|k| match self.blocks.get(index + k) {
+            Some(next) => {
+                let block = &data[k * self.block_size..(k + 1) * self.block_size];
+                Rsum::of(block).key(self.rsum_bytes) == next.rsum
+                    && md4(block)[..self.checksum_bytes] == next.checksum[..]
+            }
+            None => true,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// The rolling checksum zsync uses, which can be moved along
+/// a file a byte at a time
+struct Rsum {
+    a: u16,
+    b: u16,
+}
+
+impl Rsum {
+    fn of(block: &[u8]) -> Rsum {
+        let len = block.len();
+        block
+            .iter()
+            .enumerate()
+            .fold(Rsum { a: 0, b: 0 }, |sum, (i, &byte)| Rsum {
+                a: sum.a.wrapping_add(u16::from(byte)),
+                b: sum
+                    .b
+                    .wrapping_add(((len - i) as u16).wrapping_mul(u16::from(byte))),
+            })
+    }
+
+    /// The checksum of the block one byte on, which drops old and adds new
+    fn roll(self, old: u8, new: u8, block_size: usize) -> Rsum {
+        let a = self
+            .a
+            .wrapping_sub(u16::from(old))
+            .wrapping_add(u16::from(new));
+        let dropped = (u32::from(old)).wrapping_mul(block_size as u32) as u16;
+        Rsum {
+            a,
+            b: self.b.wrapping_add(a).wrapping_sub(dropped),
+        }
+    }
+
+    /// The last bytes of the checksum, as an index keeps them
+    fn key(self, bytes: usize) -> u32 {
+        let sum = u32::from(self.a) << 16 | u32::from(self.b);
+        match bytes {
+            4 => sum,
+            _ => sum & ((1 << (8 * bytes)) - 1),
+        }
+    }
+}
+
+/// Read into buffer until it is full or the end of
+/// reader is reached, returning the bytes read
+fn read_fully(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buffer.len() {
+        match reader.read(&mut buffer[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(read)
+}
+
+/// The MD4 digest of data, which zsync uses for block checksums
+fn md4(data: &[u8]) -> [u8; 16] {
+    let mut digest = [0; 16];
+    digest.copy_from_slice(&Md4::digest(data));
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn md4_digests() {
+        assert_eq!(hex::encode(md4(b"")), "31d6cfe0d16ae931b73c59d7e0c089c0");
+        assert_eq!(hex::encode(md4(b"abc")), "a448017aaf21d8525fc10ae87aa6729d");
+        assert_eq!(
+            hex::encode(md4(
+                b"12345678901234567890123456789012345678901234567890123456789012345678901234567890"
+            )),
+            "e33b4ddc9c38f2199c3e7b164fcc0536"
+        );
+    }
+
+    #[test]
+    fn rsum_rolls() {
+        let data = b"the quick brown fox jumps over the lazy dog";
+        let mut sum = Rsum::of(&data[..8]);
+        for offset in 1..data.len() - 8 {
+            sum = sum.roll(data[offset - 1], data[offset + 7], 8);
+            let expected = Rsum::of(&data[offset..offset + 8]);
+            assert_eq!(sum.key(4), expected.key(4));
+        }
+    }
+
+    #[test]
+    fn copy_moved_blocks() {
+        let temp_dir = TempDir::new().unwrap();
+        let remote = temp_dir.path().join("remote.bin");
+        let local = temp_dir.path().join("local.bin");
+        let output = temp_dir.path().join("out.bin");
+        let blocks: Vec<Vec<u8>> = (0..6u8).map(|i| vec![i; 16]).collect();
+        // Blocks 1 and 4 changed, block 0 moved along by 3 bytes and the
+        // last block is short
+        let remote_contents = [
+            &blocks[0][..],
+            &blocks[1],
+            &blocks[2],
+            &blocks[3],
+            &blocks[4][..5],
+        ]
+        .concat();
+        let local_contents = [&b"new"[..], &blocks[0], &blocks[5], &blocks[2], &blocks[3]].concat();
+        fs::write(&remote, &remote_contents).unwrap();
+        fs::write(&local, &local_contents).unwrap();
+
+        let built = DeltaIndex::build(&remote, 16).unwrap();
+        let index = DeltaIndex::parse(&built.to_zsync("http://example.com/remote.bin")).unwrap();
+        assert_eq!(index, built);
+        assert_eq!(index.length(), 69);
+
+        let copied = index.copy_blocks(&local, &output).unwrap();
+        assert_eq!(
+            copied,
+            vec![Range { start: 0, end: 15 }, Range { start: 32, end: 63 }]
+        );
+        let contents = fs::read(&output).unwrap();
+        assert_eq!(&contents[..16], &remote_contents[..16]);
+        assert_eq!(&contents[32..64], &remote_contents[32..64]);
+    }
+
+    #[test]
+    fn truncated_checksums_need_a_run() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("remote.bin");
+        let contents: Vec<u8> = (0..64u8).collect();
+        fs::write(&path, &contents).unwrap();
+
+        let mut index = DeltaIndex::build(&path, 16).unwrap();
+        index.seq_matches = 2;
+        index.rsum_bytes = 2;
+        index.checksum_bytes = 3;
+        for block in &mut index.blocks {
+            block.rsum &= 0xffff;
+            block.checksum.truncate(3);
+        }
+        let index = DeltaIndex::parse(&index.to_zsync("remote.bin")).unwrap();
+
+        // Only the first block of the remote file is in place, so only
+        // the run of the last two blocks can be trusted
+        let local = temp_dir.path().join("local.bin");
+        fs::write(
+            &local,
+            [&contents[..16], &[0; 16], &contents[32..]].concat(),
+        )
+        .unwrap();
+        let found = index.find_blocks(&local).unwrap();
+        assert_eq!(found, vec![None, None, Some(32), Some(48)]);
+    }
+
+    #[test]
+    fn huge_length_rejected() {
+        let zsync = b"zsync: 0.6.2\nBlocksize: 1\nLength: 18446744073709551615\n\
+            Hash-Lengths: 2,4,16\n\n";
+        let error = DeltaIndex::parse(zsync).expect_err("testing");
+        if let FetchError::ValidationError(msg) = error {
+            assert!(msg.ends_with("too many blocks"));
+        } else {
+            panic!("Expected ValidationError");
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use std::fmt;
+use std::io::Write;
+use std::mem;
+
+use flate2::write::MultiGzDecoder;
+use flate2::{Decompress, FlushDecompress, Status};
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_ENCODING};
+
+use crate::errors::{FetchError, Result};
+
+/// The Accept-Encoding sent unless the response may be compressed, so that
+/// Content-Length and ranges count the bytes of the file itself
+pub const IDENTITY: &str = "identity";
+
+/// The Accept-Encoding sent when the response may be compressed.
+/// There is no brotli decoder, so br is left out
+pub const COMPRESSED: &str = "gzip, deflate";
+
+/// The room made for the output of each call to the zlib decoder
+const OUTPUT_SIZE: usize = 32 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// A Content-Encoding which can be decoded while downloading
+pub enum ContentEncoding {
+    /// gzip, or x-gzip
+    Gzip,
+    /// deflate, which over HTTP is zlib wrapped
+    Deflate,
+}
+
+impl ContentEncoding {
+    /// The encoding of a response with headers, or None if it is not encoded.
+    /// Encodings other than gzip and deflate are an error, as they cannot
+    /// be decoded
+    pub fn from_headers(headers: &HeaderMap) -> Result<Option<ContentEncoding>> {
+        let value = match headers.get(CONTENT_ENCODING) {
+            Some(value) => value.to_str()?.trim().to_ascii_lowercase(),
+            None => return Ok(None),
+        };
+        match value.as_str() {
+            "" | IDENTITY => Ok(None),
+            "gzip" | "x-gzip" => Ok(Some(ContentEncoding::Gzip)),
+            "deflate" => Ok(Some(ContentEncoding::Deflate)),
+            _ => Err(FetchError::ServerSupportError(format!(
+                "Server sent a response with unsupported Content-Encoding {}",
+                value
+            ))),
+        }
+    }
+}
+
+/// The Accept-Encoding header to send
+pub fn accept_encoding(compressed: bool) -> HeaderValue {
+    HeaderValue::from_static(if compressed { COMPRESSED } else { IDENTITY })
+}
+
+fn invalid(err: impl fmt::Display) -> FetchError {
+    FetchError::ValidationError(format!("Could not decompress: {}", err))
+}
+
+/// The flate2 decoder of an encoding
+#[derive(Debug)]
+enum Inflater {
+    /// gzip members, decoded to the Vec
+    Gzip(MultiGzDecoder<Vec<u8>>),
+    /// A zlib stream, and whether its end has been decoded
+    Deflate(Decompress, bool),
+}
+
+/// Decodes a compressed body chunk by chunk as it is received. The
+/// checksum and length in the trailer of the encoding are checked once
+/// it arrives, in place of the Content-Length. gzip members written one
+/// after another decode to their contents one after another
+#[derive(Debug)]
+pub struct Decoder {
+    inflater: Inflater,
+}
+
+impl Decoder {
+    pub fn new(encoding: ContentEncoding) -> Decoder {
+        let inflater = match encoding {
+            ContentEncoding::Gzip => Inflater::Gzip(MultiGzDecoder::new(Vec::new())),
+            ContentEncoding::Deflate => Inflater::Deflate(Decompress::new(true), false),
+        };
+        Decoder { inflater }
+    }
+
+    /// Decode as much as can be of the body so far, after chunk
+    pub fn decode(&mut self, chunk: &[u8]) -> Result<Vec<u8>> {
+        match &mut self.inflater {
+            Inflater::Gzip(inflater) => {
+                inflater.write_all(chunk).map_err(invalid)?;
+                Ok(mem::take(inflater.get_mut()))
+            }
+            Inflater::Deflate(inflater, ended) => inflate(inflater, ended, chunk),
+        }
+    }
+
+    /// Decode the rest of the body once all of it has been received
+    pub fn finish(&mut self) -> Result<Vec<u8>> {
+        match &mut self.inflater {
+            Inflater::Gzip(inflater) => {
+                inflater.try_finish().map_err(invalid)?;
+                Ok(mem::take(inflater.get_mut()))
+            }
+            Inflater::Deflate(_, true) => Ok(Vec::new()),
+            Inflater::Deflate(_, false) => Err(invalid("the response ended early")),
+        }
+    }
+}
+
+/// Decode chunk of a zlib stream, ignoring anything after its end
+fn inflate(inflater: &mut Decompress, ended: &mut bool, mut chunk: &[u8]) -> Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    while !*ended && !chunk.is_empty() {
+        decoded.reserve(chunk.len().max(OUTPUT_SIZE));
+        let (read, written) = (inflater.total_in(), decoded.len());
+        let status = inflater
+            .decompress_vec(chunk, &mut decoded, FlushDecompress::None)
+            .map_err(invalid)?;
+        let consumed = (inflater.total_in() - read) as usize;
+        chunk = &chunk[consumed..];
+        *ended = status == Status::StreamEnd;
+        if consumed == 0 && decoded.len() == written {
+            break;
+        }
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &[u8] = b"The quick brown fox jumps over the lazy dog. \
+        Pack my box with five dozen liquor jugs. How vexingly quick daft zebras jump! ";
+
+    /// TEXT three times over, gzipped with a dynamic Huffman block
+    const TEXT_GZIP: [u8; 124] = [
+        31, 139, 8, 0, 0, 0, 0, 0, 2, 3, 229, 141, 75, 22, 131, 32, 16, 4, 175, 210, 185, 128, 231,
+        200, 50, 11, 47, 0, 58, 32, 9, 50, 145, 175, 112, 122, 231, 229, 229, 22, 174, 171, 186,
+        107, 222, 8, 71, 113, 203, 7, 58, 114, 11, 48, 124, 226, 93, 246, 111, 2, 87, 138, 200,
+        130, 189, 26, 29, 43, 219, 9, 47, 37, 222, 222, 161, 69, 106, 46, 111, 48, 174, 146, 160,
+        65, 1, 222, 29, 133, 163, 108, 109, 154, 240, 228, 134, 74, 167, 11, 214, 247, 255, 253,
+        170, 76, 198, 32, 29, 85, 250, 5, 30, 152, 111, 153, 190, 0, 161, 136, 233, 171, 113, 1, 0,
+        0,
+    ];
+
+    /// "HelloWorld" a hundred times over, zlib wrapped with a fixed Huffman block
+    const HELLO_ZLIB: [u8; 27] = [
+        120, 156, 243, 72, 205, 201, 201, 15, 207, 47, 202, 73, 241, 24, 101, 141, 178, 70, 89,
+        195, 148, 5, 0, 237, 190, 142, 128,
+    ];
+
+    fn decode_in_chunks(encoding: ContentEncoding, encoded: &[u8], size: usize) -> Result<Vec<u8>> {
+        let mut decoder = Decoder::new(encoding);
+        let mut decoded = Vec::new();
+        for chunk in encoded.chunks(size) {
+            decoded.extend(decoder.decode(chunk)?);
+        }
+        decoded.extend(decoder.finish()?);
+        Ok(decoded)
+    }
+
+    #[test]
+    fn decodes_in_chunks() {
+        for size in &[1, 7, 1000] {
+            assert_eq!(
+                decode_in_chunks(ContentEncoding::Gzip, &TEXT_GZIP, *size).unwrap(),
+                TEXT.repeat(3)
+            );
+            assert_eq!(
+                decode_in_chunks(ContentEncoding::Deflate, &HELLO_ZLIB, *size).unwrap(),
+                b"HelloWorld".repeat(100)
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_gzip_members_in_turn() {
+        let encoded = [&TEXT_GZIP[..], &TEXT_GZIP[..]].concat();
+        assert_eq!(
+            decode_in_chunks(ContentEncoding::Gzip, &encoded, 5).unwrap(),
+            TEXT.repeat(6)
+        );
+    }
+
+    #[test]
+    fn damaged_body_fails() {
+        let error = decode_in_chunks(ContentEncoding::Gzip, &TEXT_GZIP[..TEXT_GZIP.len() - 4], 16)
+            .expect_err("testing");
+        if let FetchError::ValidationError(msg) = error {
+            assert!(msg.starts_with("Could not decompress"));
+        } else {
+            panic!("Expected ValidationError");
+        }
+
+        let error =
+            decode_in_chunks(ContentEncoding::Deflate, &HELLO_ZLIB[..20], 16).expect_err("testing");
+        if let FetchError::ValidationError(msg) = error {
+            assert!(msg.ends_with("ended early"));
+        } else {
+            panic!("Expected ValidationError");
+        }
+
+        let mut corrupted = HELLO_ZLIB;
+        corrupted[25] ^= 1;
+        let error =
+            decode_in_chunks(ContentEncoding::Deflate, &corrupted, 16).expect_err("testing");
+        if let FetchError::ValidationError(msg) = error {
+            assert!(msg.starts_with("Could not decompress"));
+        } else {
+            panic!("Expected ValidationError");
+        }
+    }
+
+    #[test]
+    fn encodings_from_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(ContentEncoding::from_headers(&headers).unwrap(), None);
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("identity"));
+        assert_eq!(ContentEncoding::from_headers(&headers).unwrap(), None);
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("x-gzip"));
+        assert_eq!(
+            ContentEncoding::from_headers(&headers).unwrap(),
+            Some(ContentEncoding::Gzip)
+        );
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("br"));
+        assert!(ContentEncoding::from_headers(&headers).is_err());
+    }
+}
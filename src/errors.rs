@@ -5,6 +5,8 @@ use std::result;
 
 use reqwest;
 
+use crate::fetch::Range;
+
 #[derive(Debug)]
 /// Errors during Fetch
 pub enum FetchError {
@@ -20,26 +22,135 @@ pub enum FetchError {
     IoError(io::Error),
     /// Error in creating header
     InvalidHeaderValueError(reqwest::header::InvalidHeaderValue),
+    /// A server's public key did not match any pin
+    PinMismatchError(String),
+    /// Not enough disk space for the output
+    InsufficientSpaceError(String),
+    /// The remote file is larger than the most allowed
+    FileTooLargeError(String),
+    /// The remote file changed during the download
+    ContentChangedError(String),
+    /// The download was cancelled through its handle
+    CancelledError(String),
+    /// The download was stopped by its Interrupt, leaving what it wrote
+    InterruptedError(String),
+    /// Another process is writing the output
+    LockedError(String),
+    /// A range could not be fetched, giving up after attempts tries
+    RangeError {
+        /// The url the last attempt was made to
+        url: String,
+        /// The range which failed
+        range: Range,
+        /// The number of attempts made
+        attempts: u64,
+        /// The error of the last attempt
+        source: Box<FetchError>,
+    },
+    /// Some ranges of a download failed, once the ranges being fetched
+    /// alongside them had finished
+    RangesError {
+        /// The error of each fetch which failed, a RangeError for each
+        /// range which could not be fetched
+        failed: Vec<FetchError>,
+        /// The bytes of the ranges which were fetched, not counting any
+        /// resumed from before
+        completed: u64,
+    },
 }
 
-impl fmt::Display for FetchError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.description())
+impl FetchError {
+    /// The error underlying any context added to it,
+    /// such as the last failure of a range
+    pub fn inner(&self) -> &FetchError {
+        match self {
+            FetchError::RangeError { source, .. } => source.inner(),
+            FetchError::RangesError { failed, .. } if !failed.is_empty() => failed[0].inner(),
+            err => err,
+        }
+    }
+
+    /// The ranges which could not be fetched, for a RangeError or
+    /// RangesError
+    pub fn failed_ranges(&self) -> Vec<Range> {
+        match self {
+            FetchError::RangeError { range, .. } => vec![*range],
+            FetchError::RangesError { failed, .. } => {
+                failed.iter().flat_map(FetchError::failed_ranges).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// The exit code the binary fails with on this error, so that scripts
+    /// can tell failures apart: 2 for invalid arguments, 3 for a server
+    /// lacking support, 4 for a network error, 5 for a failed validation,
+    /// 6 for an I/O error, 7 for an output locked by another process, and
+    /// 1 for a download which was stopped. A failed range exits as its
+    /// last attempt failed
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            FetchError::InvalidArgumentsError(_) | FetchError::InvalidHeaderValueError(_) => 2,
+            FetchError::ServerSupportError(_) => 3,
+            FetchError::ReqwestError(_) => 4,
+            FetchError::ValidationError(_)
+            | FetchError::PinMismatchError(_)
+            | FetchError::FileTooLargeError(_)
+            | FetchError::ContentChangedError(_) => 5,
+            FetchError::IoError(_) | FetchError::InsufficientSpaceError(_) => 6,
+            FetchError::LockedError(_) => 7,
+            FetchError::CancelledError(_) | FetchError::InterruptedError(_) => 1,
+            FetchError::RangeError { source, .. } => source.exit_code(),
+            FetchError::RangesError { failed, .. } => {
+                failed.first().map_or(1, FetchError::exit_code)
+            }
+        }
     }
 }
 
-impl Error for FetchError {
-    fn description(&self) -> &str {
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            FetchError::ServerSupportError(string) => string,
-            FetchError::InvalidArgumentsError(string) => string,
-            FetchError::ValidationError(string) => string,
-            FetchError::ReqwestError(err) => err.description(),
-            FetchError::IoError(err) => err.description(),
-            FetchError::InvalidHeaderValueError(err) => err.description(),
+            FetchError::ServerSupportError(string) => write!(f, "{}", string),
+            FetchError::InvalidArgumentsError(string) => write!(f, "{}", string),
+            FetchError::ValidationError(string) => write!(f, "{}", string),
+            FetchError::ReqwestError(err) => write!(f, "{}", err),
+            FetchError::IoError(err) => write!(f, "{}", err),
+            FetchError::InvalidHeaderValueError(err) => write!(f, "{}", err),
+            FetchError::PinMismatchError(string) => write!(f, "{}", string),
+            FetchError::InsufficientSpaceError(string) => write!(f, "{}", string),
+            FetchError::FileTooLargeError(string) => write!(f, "{}", string),
+            FetchError::ContentChangedError(string) => write!(f, "{}", string),
+            FetchError::CancelledError(string) => write!(f, "{}", string),
+            FetchError::InterruptedError(string) => write!(f, "{}", string),
+            FetchError::LockedError(string) => write!(f, "{}", string),
+            FetchError::RangeError {
+                url,
+                range,
+                attempts,
+                source,
+            } => write!(
+                f,
+                "Range {}-{} from {} failed after {} attempts: {}",
+                range.start, range.end, url, attempts, source
+            ),
+            FetchError::RangesError { failed, completed } => {
+                write!(
+                    f,
+                    "{} of the fetches failed with {} bytes fetched",
+                    failed.len(),
+                    completed
+                )?;
+                for (index, err) in failed.iter().enumerate() {
+                    write!(f, "{} {}", if index == 0 { ":" } else { ";" }, err)?;
+                }
+                Ok(())
+            }
         }
     }
+}
 
+impl Error for FetchError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
             FetchError::ServerSupportError(_) => None,
@@ -48,34 +159,77 @@ impl Error for FetchError {
             FetchError::ReqwestError(err) => Some(err),
             FetchError::IoError(err) => Some(err),
             FetchError::InvalidHeaderValueError(err) => Some(err),
+            FetchError::PinMismatchError(_) => None,
+            FetchError::InsufficientSpaceError(_) => None,
+            FetchError::FileTooLargeError(_) => None,
+            FetchError::ContentChangedError(_) => None,
+            FetchError::CancelledError(_) => None,
+            FetchError::InterruptedError(_) => None,
+            FetchError::LockedError(_) => None,
+            FetchError::RangeError { source, .. } => Some(source.as_ref()),
+            FetchError::RangesError { failed, .. } => {
+                failed.first().map(|err| err as &(dyn Error + 'static))
+            }
         }
     }
 }
 
-impl From<io::Error> for Box<FetchError> {
-    fn from(err: io::Error) -> Box<FetchError> {
-        Box::new(FetchError::IoError(err))
+impl From<io::Error> for FetchError {
+    fn from(err: io::Error) -> FetchError {
+        FetchError::IoError(err)
     }
 }
 
-impl From<reqwest::Error> for Box<FetchError> {
-    fn from(err: reqwest::Error) -> Box<FetchError> {
-        Box::new(FetchError::ReqwestError(err))
+impl From<reqwest::Error> for FetchError {
+    fn from(err: reqwest::Error) -> FetchError {
+        FetchError::ReqwestError(err)
     }
 }
 
-impl From<reqwest::header::ToStrError> for Box<FetchError> {
-    fn from(_err: reqwest::header::ToStrError) -> Box<FetchError> {
-        Box::new(FetchError::ServerSupportError(
-            "Could not parse header to string".to_owned(),
-        ))
+impl From<reqwest::header::ToStrError> for FetchError {
+    fn from(_err: reqwest::header::ToStrError) -> FetchError {
+        FetchError::ServerSupportError("Could not parse header to string".to_owned())
     }
 }
 
-impl From<reqwest::header::InvalidHeaderValue> for Box<FetchError> {
-    fn from(err: reqwest::header::InvalidHeaderValue) -> Box<FetchError> {
-        Box::new(FetchError::InvalidHeaderValueError(err))
+impl From<reqwest::header::InvalidHeaderValue> for FetchError {
+    fn from(err: reqwest::header::InvalidHeaderValue) -> FetchError {
+        FetchError::InvalidHeaderValueError(err)
     }
 }
 /// A Result that wraps FetchError
-pub type Result<T> = result::Result<T, Box<FetchError>>;
+pub type Result<T> = result::Result<T, FetchError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes_by_category() {
+        let invalid = FetchError::InvalidArgumentsError("invalid".to_owned());
+        let io = FetchError::IoError(io::Error::other("disk"));
+        assert_eq!(invalid.exit_code(), 2);
+        assert_eq!(
+            FetchError::ServerSupportError("no".to_owned()).exit_code(),
+            3
+        );
+        assert_eq!(FetchError::ValidationError("bad".to_owned()).exit_code(), 5);
+        assert_eq!(io.exit_code(), 6);
+        assert_eq!(FetchError::LockedError("locked".to_owned()).exit_code(), 7);
+
+        let range = FetchError::RangeError {
+            url: "http://example.com/file".to_owned(),
+            range: Range { start: 0, end: 9 },
+            attempts: 3,
+            source: Box::new(FetchError::ContentChangedError("changed".to_owned())),
+        };
+        assert_eq!(range.exit_code(), 5);
+
+        let ranges = FetchError::RangesError {
+            failed: vec![range],
+            completed: 10,
+        };
+        assert_eq!(ranges.exit_code(), 5);
+        assert_eq!(ranges.failed_ranges(), vec![Range { start: 0, end: 9 }]);
+    }
+}
@@ -0,0 +1,131 @@
+use std::future::Future;
+use std::path::PathBuf;
+
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::Stream;
+
+use crate::errors::Result;
+use crate::fetch::{fetch, FetchOptions, Range};
+use crate::report::FetchReport;
+use crate::verify::ChecksumAlgorithm;
+
+#[derive(Debug, Clone, PartialEq)]
+/// Something which happened during a download, in the order it happened
+pub enum DownloadEvent {
+    /// The url was probed and the file found to be content_length bytes long
+    HeadReceived {
+        /// The url which answered, after any redirects
+        url: String,
+        /// The length of the file, if the server reported it
+        content_length: Option<u64>,
+        /// Whether the file will be fetched in ranges rather than in a single stream
+        ranges: bool,
+    },
+    /// The download is about to be fetched with num_fetches workers,
+    /// at the start and again on every restart
+    RangePlanned {
+        /// The length of the file
+        content_length: u64,
+        /// The number of workers fetching ranges
+        num_fetches: u64,
+    },
+    /// An attempt at fetching a range was started
+    ChunkStarted {
+        /// The range, less any bytes already written
+        range: Range,
+        /// The url the range is requested from
+        url: String,
+    },
+    /// A chunk of a range was written
+    ChunkProgress {
+        /// The range the chunk belongs to
+        range: Range,
+        /// The bytes of range written so far
+        range_downloaded: u64,
+        /// The bytes written so far across all ranges
+        downloaded: u64,
+        /// The length of the file, or 0 if the server did not report it
+        total: u64,
+    },
+    /// An attempt at fetching a range failed and will be retried
+    ChunkRetried {
+        /// The range which failed
+        range: Range,
+        /// The number of attempts made so far
+        attempts: u64,
+        /// Why the attempt failed
+        error: String,
+    },
+    /// A range was written in full
+    ChunkCompleted {
+        /// The range, whose end moves in if its tail was split off
+        range: Range,
+    },
+    /// A range was fetched much more slowly than those before it, as
+    /// when a server or CDN node throttles some of the connections.
+    /// Sent just before the range's ChunkCompleted
+    ChunkSlow {
+        /// The range which was slow
+        range: Range,
+        /// The throughput of the range in bytes per second, including retries
+        throughput: f64,
+        /// The median throughput of the ranges completed before it
+        median: f64,
+    },
+    /// The output matched every checksum and signature it was checked against
+    Verified {
+        /// The algorithms of the checksums which matched
+        checksums: Vec<ChecksumAlgorithm>,
+        /// Whether a signature was verified
+        signature: bool,
+    },
+    /// The output was moved into place
+    Saved {
+        /// Where the download was saved, after any renaming or decompressing
+        path: PathBuf,
+    },
+    /// The download ended, the last event sent
+    Finished {
+        /// Why the download failed, if it did
+        error: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone)]
+/// Sends the events of a download to the stream returned by `fetch_with_events`
+pub struct Events {
+    sender: UnboundedSender<DownloadEvent>,
+}
+
+impl Events {
+    pub fn new(sender: UnboundedSender<DownloadEvent>) -> Events {
+        Events { sender }
+    }
+
+    /// Send event, unless the stream has been dropped
+    pub fn send(&self, event: DownloadEvent) {
+        let _ = self.sender.unbounded_send(event);
+    }
+}
+
+/// Fetch as `fetch` does, also returning a stream of the events of the
+/// download. The stream ends after Finished, once the download is awaited
+pub fn fetch_with_events(
+    mut options: FetchOptions,
+) -> (
+    impl Future<Output = Result<FetchReport>>,
+    impl Stream<Item = DownloadEvent>,
+) {
+    let (sender, receiver) = unbounded();
+    let events = Events::new(sender);
+    options.events = Some(events.clone());
+
+    let download = async move {
+        let result = fetch(options).await;
+        events.send(DownloadEvent::Finished {
+            error: result.as_ref().err().map(|err| err.to_string()),
+        });
+        result
+    };
+    (download, receiver)
+}
@@ -1,18 +1,80 @@
-use std::path::PathBuf;
-use std::io::SeekFrom;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use futures_util::future::try_join_all;
-use reqwest::header::{HeaderMap, ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, ETAG, RANGE};
-use reqwest::StatusCode;
-use slog::{self, info, Logger};
-use tokio::io::BufWriter;
-use tokio::fs::OpenOptions;
-use tokio::prelude::*;
+use chrono::Utc;
+use futures::channel::mpsc::UnboundedSender;
+use futures_util::future::{join_all, select, Either};
+use reqwest::header::{
+    HeaderMap, HeaderValue, ACCEPT_ENCODING, ACCEPT_RANGES, AUTHORIZATION, CONTENT_LENGTH,
+    CONTENT_RANGE, CONTENT_TYPE, COOKIE, ETAG, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+    IF_RANGE, LAST_MODIFIED, RANGE, RETRY_AFTER,
+};
+use reqwest::{StatusCode, Url};
+use slog::{self, debug, info, warn, Logger};
+use tokio::timer::{delay_for, Timeout};
 
+use crate::auth::{redact_headers, Credentials};
+use crate::cache::Cache;
+use crate::clobber::Clobber;
+use crate::concurrency::Concurrency;
+use crate::control::DownloadControl;
+use crate::cookies::CookieJar;
+use crate::delta::DeltaIndex;
+use crate::encoding::{accept_encoding, ContentEncoding, Decoder};
 use crate::errors::{FetchError, Result};
-use crate::utils::{check_etag, create_ranges, parse_path};
+use crate::events::{DownloadEvent, Events};
+use crate::hedge::Hedging;
+use crate::interrupt::{Interrupt, OnInterrupt};
+use crate::limit::{ConnectionLimit, ConnectionPermit, FetchLimits, HostLimits, HostPermit};
+use crate::lock::OutputLock;
+use crate::metadata::Metadata;
+use crate::metrics::FetchMetrics;
+use crate::mirror::Mirrors;
+use crate::output::{ChunkSink, MemorySink, Output, OutputFile, RangeWriter, DEFAULT_WRITE_BUFFER};
+use crate::plan::DownloadPlan;
+use crate::postprocess::decompress;
+use crate::progress::{Progress, ProgressCallback};
+use crate::proxy::ProxyOptions;
+use crate::report::{FetchReport, Recorder};
+use crate::resume::ResumeState;
+use crate::retry::RetryPolicy;
+use crate::scheduler::{ChunkScheduler, Claim, ScheduleState, StaticScheduler};
+use crate::throughput::Throughputs;
+use crate::tls::{check_public_key_pins, configure_tls, ClientCertificate, PublicKeyPin};
+use crate::trace::TraceContext;
+use crate::utils::{
+    available_space, check_etag, content_type_matches, degraded_fetches, existing_range,
+    parse_http_date, parse_path, parse_retry_after, part_path, preallocate, split_fetches,
+    split_multipart_etag, subtract_ranges,
+};
+use crate::verify::{
+    find_checksum, header_checksums, Checksum, ChecksumAlgorithm, SignatureCheck, StreamingHasher,
+};
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+/// The header carrying the trace context of a request
+const TRACEPARENT: &str = "traceparent";
+
+/// The smallest range a download chooses to split off by itself
+const DEFAULT_MIN_SPLIT_SIZE: u64 = 1024 * 1024;
+
+/// How often a worker held back by adaptive concurrency checks
+/// whether it may fetch again
+const WORKER_WAIT: Duration = Duration::from_millis(100);
+
+/// How often a hedged range checks whether it is straggling
+const HEDGE_CHECK: Duration = Duration::from_millis(250);
+
+/// The most bytes left in a range for it to be hedged,
+/// as the duplicate request is held in memory
+const MAX_HEDGE_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 /// A range of bytes to fetch
 pub struct Range {
     /// The start of the range
@@ -21,235 +83,3226 @@ pub struct Range {
     pub end: u64,
 }
 
-impl slog::Value for Range {
-    fn serialize(
-        &self,
-        _rec: &slog::Record,
-        key: slog::Key,
-        serializer: &mut dyn slog::Serializer,
-    ) -> slog::Result {
-        serializer.emit_str(key, &format!("{:?}", self))
+impl slog::Value for Range {
+    fn serialize(
+        &self,
+        _rec: &slog::Record,
+        key: slog::Key,
+        serializer: &mut dyn slog::Serializer,
+    ) -> slog::Result {
+        serializer.emit_str(key, &format!("{:?}", self))
+    }
+}
+
+#[non_exhaustive]
+/// Options for fetching, built with [`FetchOptions::builder`]
+pub struct FetchOptions {
+    /// The url to fetch from
+    pub url: String,
+    /// An optional output location
+    pub output_option: Option<String>,
+    /// The number of parallel fetches to execute
+    pub num_fetches: u64,
+    /// A logger, which discards everything unless one is set
+    pub logger: Logger,
+    /// Whether to attempt to check an etag for validation
+    pub check_etag: bool,
+    /// The number of times to attempt to retry a failed chunk fetch
+    pub max_retries: u64,
+    /// Whether to restart a failed download with fewer parallel fetches
+    pub degraded_restart: bool,
+    /// A scheduler deciding which range each fetch downloads,
+    /// defaults to a StaticScheduler
+    pub scheduler: Option<Box<dyn ChunkScheduler>>,
+    /// Whether to record completed ranges in a state file next to
+    /// the output, so that rerunning an interrupted download only
+    /// fetches the missing ranges
+    pub resumable: bool,
+    /// Whether to treat an existing output file as the already
+    /// downloaded start of the content and only fetch the rest.
+    /// A file left by an interrupted parallel download may contain
+    /// gaps, which `resumable` tracks correctly
+    pub resume: bool,
+    /// Whether to fall back to a single plain GET when the server
+    /// does not support range requests
+    pub allow_single_stream: bool,
+    /// A callback receiving progress as chunks are written
+    pub progress: Option<ProgressCallback>,
+    /// Optional metrics receiving counts of requests, bytes and
+    /// retries and the latency of each range, shared between downloads
+    pub metrics: Option<Arc<dyn FetchMetrics>>,
+    /// An optional cap on the combined download rate in bytes per second
+    pub limit_rate: Option<u64>,
+    /// An optional timeout for establishing each connection
+    pub connect_timeout: Option<Duration>,
+    /// An optional timeout for each read of response headers or body data
+    pub read_timeout: Option<Duration>,
+    /// The longest Retry-After delay to wait for when a chunk fetch is
+    /// rejected with a 429 or 503, or None to retry without waiting
+    pub max_retry_after: Option<Duration>,
+    /// Which classes of errors cause a failed chunk fetch to be retried
+    pub retry_policy: RetryPolicy,
+    /// An optional checksum to verify the downloaded file against
+    pub checksum: Option<Checksum>,
+    /// An optional url of a checksum file such as `SHA256SUMS`,
+    /// whose entry for the downloaded file is verified against
+    pub checksum_url: Option<String>,
+    /// An optional detached signature to verify the downloaded file with
+    pub signature: Option<SignatureCheck>,
+    /// The exact size in bytes the download must have, that of the range
+    /// when one is given. A Content-Length which differs fails the
+    /// download before fetching, and a written file which differs after
+    pub expected_size: Option<u64>,
+    /// The most bytes a download may have, checked against the
+    /// Content-Length before anything is fetched, or None for no limit
+    pub max_filesize: Option<u64>,
+    /// The Content-Type the server must report for the download to go
+    /// ahead, such as `application/octet-stream`, catching error pages
+    /// served in place of the file. A `*` matches any characters, and a
+    /// pattern without one matches as a prefix
+    pub expect_content_type: Option<String>,
+    /// The part size of S3 style multipart uploads, needed to check
+    /// their ETags. Without it multipart ETags are skipped with a warning
+    pub etag_part_size: Option<u64>,
+    /// Whether to verify the downloaded file against any checksums
+    /// advertised in the `Content-MD5`, `Digest`, `x-amz-checksum-*`
+    /// or `x-goog-hash` response headers
+    pub verify_headers: bool,
+    /// An optional limit on requests in flight shared with other
+    /// downloads, which `fetch_many` sets when it is None
+    pub connection_limit: Option<Arc<ConnectionLimit>>,
+    /// Limits on the requests in flight to each host, which may be
+    /// shared between downloads as it is by `fetch_many`
+    pub host_limits: Option<Arc<HostLimits>>,
+    /// An optional client to reuse, such as one shared between downloads.
+    /// Its own settings apply instead of those such as connect_timeout
+    pub client: Option<reqwest::Client>,
+    /// Other urls serving the same content as url, which
+    /// ranges are spread across
+    pub mirrors: Vec<String>,
+    /// Extra headers sent with the HEAD request and every GET of the content
+    pub headers: HeaderMap,
+    /// Optional credentials sent with the HEAD request and every GET
+    /// of the content, including those to mirrors
+    pub credentials: Option<Credentials>,
+    /// An optional cookie jar whose cookies are sent with the HEAD
+    /// request and every GET of the content, and which stores any
+    /// cookies they set. It may be shared with other downloads
+    pub cookies: Option<Arc<CookieJar>>,
+    /// The trace context of the span which triggered the download, if it
+    /// is part of a distributed trace. The download is traced as a child
+    /// of it, sending a traceparent header with each request
+    pub trace: Option<TraceContext>,
+    /// An optional HTTP proxy to route requests through,
+    /// otherwise connections are made directly
+    pub proxy: Option<ProxyOptions>,
+    /// An optional PEM bundle of certificate authorities to trust
+    /// in addition to the system's, such as that of a private CA
+    pub ca_certificate: Option<PathBuf>,
+    /// Whether to skip TLS certificate verification entirely.
+    /// Only suitable for trusted internal mirrors
+    pub insecure: bool,
+    /// An optional client certificate to present to
+    /// servers requiring mutual TLS
+    pub client_certificate: Option<ClientCertificate>,
+    /// Public key pins, one of which the server of url and each mirror
+    /// must match. Each server is checked once before the download
+    /// starts on a connection of its own, as the client's pooled
+    /// connections do not expose the certificates they were made with.
+    /// The connections the download uses, and the servers it is
+    /// redirected to, are not checked, so this does not pin them
+    pub public_key_pins: Vec<PublicKeyPin>,
+    /// Whether to try a small range request when the server does not
+    /// send Accept-Ranges, fetching in parallel if it is honoured
+    pub probe_ranges: bool,
+    /// An optional target size for each range, so that the number of
+    /// ranges follows the content length and num_fetches only caps how
+    /// many are fetched at once. Ignored when a scheduler is given
+    pub chunk_size: Option<u64>,
+    /// Whether to choose the number of fetches from the content length,
+    /// with num_fetches as the most used
+    pub auto_fetches: bool,
+    /// An optional size below which ranges are never split further, so
+    /// that small files use fewer fetches. Defaults to 1 MiB when
+    /// auto_fetches is set
+    pub min_split_size: Option<u64>,
+    /// Whether to start with a few connections and add or remove them
+    /// as the combined throughput changes, with num_fetches as the most
+    pub adaptive: bool,
+    /// Whether a worker left without ranges takes over the second half
+    /// of what remains of the in-flight range with the most left, so one
+    /// slow connection does not hold up the download. Neither half is
+    /// made smaller than min_split_size
+    pub split_slow_ranges: bool,
+    /// Whether to race a duplicate request for the rest of a range taking
+    /// several times longer than the median range, keeping whichever
+    /// finishes first. The duplicate is held in memory, so only ranges
+    /// with at most 64 MiB left are hedged
+    pub hedge: bool,
+    /// Whether to reserve disk space for the whole output before
+    /// fetching, failing at once if the disk is full. Conflicts with
+    /// resume, which takes the length of the output as the bytes fetched
+    pub preallocate: bool,
+    /// The free disk space to leave beyond the content,
+    /// which is checked for before fetching starts
+    pub space_margin: u64,
+    /// The bytes each range buffers before writing them to the output,
+    /// defaulting to 8 KiB. Larger buffers make fewer system calls
+    pub write_buffer: Option<usize>,
+    /// How often to flush buffered bytes and sync the output to disk,
+    /// or None to leave it to the operating system
+    pub sync_interval: Option<Duration>,
+    /// Whether to download to the output path with `.part` appended,
+    /// renaming it to the output only once it is complete and verified
+    pub part_file: bool,
+    /// What to do when the output already exists and
+    /// the download is not resuming into it
+    pub clobber: Clobber,
+    /// Whether to create any missing directories of the output
+    /// instead of rejecting it
+    pub create_dirs: bool,
+    /// Whether to set the modification time of the output
+    /// to the Last-Modified time the server reports
+    pub preserve_timestamps: bool,
+    /// Whether to save the source url, ETag, Last-Modified time, size and
+    /// SHA-256 digest of the output in a `.meta.json` file next to it
+    pub write_metadata: bool,
+    /// Whether to download even when the output already has the length
+    /// of the content and, if the ETag is checked, its digest
+    pub force: bool,
+    /// Whether to skip the download when the output exists and the server
+    /// answers 304 Not Modified to a HEAD request sent with the ETag and
+    /// Last-Modified time saved in its metadata by `write_metadata`
+    pub conditional: bool,
+    /// An optional directory of earlier downloads. An output whose url and
+    /// strong ETag, or whose expected SHA-256 digest, match a file kept
+    /// there is cloned from it instead of downloaded, sharing its blocks
+    /// on file systems with copy on write and otherwise hard linked or
+    /// copied, and each completed download is added to it
+    pub cache: Option<PathBuf>,
+    /// An optional url of a zsync control file indexing the blocks of the
+    /// remote file. When the output exists, the blocks it shares with the
+    /// remote file are copied from it and only the rest are downloaded,
+    /// into a new file which then replaces it
+    pub delta_index: Option<String>,
+    /// An optional flag which stops the download when raised,
+    /// keeping what was written so it can be resumed
+    pub interrupt: Option<Interrupt>,
+    /// Whether to keep what an interrupted download wrote for resuming,
+    /// the default, or delete it
+    pub on_interrupt: OnInterrupt,
+    /// Whether to restart the download from scratch, once, when the
+    /// remote file changes partway instead of failing
+    pub restart_on_change: bool,
+    /// The bytes of the remote file to fetch, rather than all of it,
+    /// written from the start of the output. A range ending past the
+    /// end of the file is cut short
+    pub range: Option<Range>,
+    /// Whether to accept a gzip or deflate compressed response, decoding
+    /// it while downloading. Otherwise only the identity encoding is
+    /// accepted, so that Content-Length and ranges count the bytes of the
+    /// file. A compressed response is fetched with a single GET, as its
+    /// ranges cannot be decoded apart, and its ETag and checksum headers
+    /// are not checked, as they describe the compressed bytes
+    pub compressed: bool,
+    /// Whether to decompress a gzip compressed file once it is downloaded,
+    /// recognising it by its suffix, which is dropped, or its first bytes
+    pub decompress: bool,
+    /// Whether to keep the compressed file when decompressing
+    pub keep_compressed: bool,
+    /// Where to write the download instead of the output file. Options
+    /// which need the file, such as resuming or verifying checksums,
+    /// cannot be used with a sink
+    pub sink: Option<Arc<dyn ChunkSink>>,
+    /// Set by `fetch_controlled` to pause, cancel and
+    /// watch the download through its handle
+    pub(crate) control: Option<Arc<DownloadControl>>,
+    /// Set by `fetch_with_events` to report what happens during the download
+    pub(crate) events: Option<Events>,
+    /// Gathers the report returned by `fetch`
+    pub(crate) recorder: Option<Arc<Recorder>>,
+}
+
+impl fmt::Debug for FetchOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FetchOptions")
+            .field("url", &self.url)
+            .field("output_option", &self.output_option)
+            .field("num_fetches", &self.num_fetches)
+            .field("logger", &self.logger)
+            .field("check_etag", &self.check_etag)
+            .field("max_retries", &self.max_retries)
+            .field("degraded_restart", &self.degraded_restart)
+            .field("scheduler", &self.scheduler)
+            .field("resumable", &self.resumable)
+            .field("resume", &self.resume)
+            .field("allow_single_stream", &self.allow_single_stream)
+            .field("progress", &self.progress.is_some())
+            .field("metrics", &self.metrics.is_some())
+            .field("limit_rate", &self.limit_rate)
+            .field("connect_timeout", &self.connect_timeout)
+            .field("read_timeout", &self.read_timeout)
+            .field("max_retry_after", &self.max_retry_after)
+            .field("retry_policy", &self.retry_policy)
+            .field("checksum", &self.checksum)
+            .field("checksum_url", &self.checksum_url)
+            .field("signature", &self.signature)
+            .field("expected_size", &self.expected_size)
+            .field("max_filesize", &self.max_filesize)
+            .field("expect_content_type", &self.expect_content_type)
+            .field("etag_part_size", &self.etag_part_size)
+            .field("verify_headers", &self.verify_headers)
+            .field("connection_limit", &self.connection_limit)
+            .field("host_limits", &self.host_limits)
+            .field("client", &self.client.is_some())
+            .field("mirrors", &self.mirrors)
+            .field("headers", &redact_headers(&self.headers))
+            .field("credentials", &self.credentials)
+            .field("cookies", &self.cookies.is_some())
+            .field("trace", &self.trace)
+            .field("proxy", &self.proxy)
+            .field("ca_certificate", &self.ca_certificate)
+            .field("insecure", &self.insecure)
+            .field("client_certificate", &self.client_certificate)
+            .field("public_key_pins", &self.public_key_pins)
+            .field("probe_ranges", &self.probe_ranges)
+            .field("chunk_size", &self.chunk_size)
+            .field("auto_fetches", &self.auto_fetches)
+            .field("min_split_size", &self.min_split_size)
+            .field("adaptive", &self.adaptive)
+            .field("split_slow_ranges", &self.split_slow_ranges)
+            .field("hedge", &self.hedge)
+            .field("preallocate", &self.preallocate)
+            .field("space_margin", &self.space_margin)
+            .field("write_buffer", &self.write_buffer)
+            .field("sync_interval", &self.sync_interval)
+            .field("part_file", &self.part_file)
+            .field("clobber", &self.clobber)
+            .field("create_dirs", &self.create_dirs)
+            .field("preserve_timestamps", &self.preserve_timestamps)
+            .field("write_metadata", &self.write_metadata)
+            .field("force", &self.force)
+            .field("conditional", &self.conditional)
+            .field("cache", &self.cache)
+            .field("delta_index", &self.delta_index)
+            .field("interrupt", &self.interrupt)
+            .field("on_interrupt", &self.on_interrupt)
+            .field("restart_on_change", &self.restart_on_change)
+            .field("range", &self.range)
+            .field("compressed", &self.compressed)
+            .field("decompress", &self.decompress)
+            .field("keep_compressed", &self.keep_compressed)
+            .field("sink", &self.sink)
+            .field("control", &self.control.is_some())
+            .field("events", &self.events.is_some())
+            .finish()
+    }
+}
+
+impl FetchOptions {
+    /// Start building the options for fetching url. Like the command
+    /// line, it defaults to choosing up to 10 fetches from the file size
+    /// and retrying transient errors up to 5 times, honouring Retry-After
+    /// delays of up to a minute. Logging is discarded unless a logger is set
+    pub fn builder(url: impl Into<String>) -> FetchOptionsBuilder {
+        FetchOptionsBuilder {
+            options: FetchOptions {
+                url: url.into(),
+                output_option: None,
+                num_fetches: 10,
+                logger: Logger::root(slog::Discard, slog::o!()),
+                check_etag: false,
+                max_retries: 5,
+                degraded_restart: false,
+                scheduler: None,
+                resumable: false,
+                resume: false,
+                allow_single_stream: false,
+                progress: None,
+                metrics: None,
+                limit_rate: None,
+                connect_timeout: None,
+                read_timeout: None,
+                max_retry_after: Some(Duration::from_secs(60)),
+                retry_policy: RetryPolicy::Transient,
+                checksum: None,
+                checksum_url: None,
+                signature: None,
+                expected_size: None,
+                max_filesize: None,
+                expect_content_type: None,
+                etag_part_size: None,
+                verify_headers: false,
+                connection_limit: None,
+                host_limits: None,
+                client: None,
+                mirrors: Vec::new(),
+                headers: HeaderMap::new(),
+                credentials: None,
+                cookies: None,
+                trace: None,
+                proxy: None,
+                ca_certificate: None,
+                insecure: false,
+                client_certificate: None,
+                public_key_pins: Vec::new(),
+                probe_ranges: false,
+                chunk_size: None,
+                auto_fetches: true,
+                min_split_size: None,
+                adaptive: false,
+                split_slow_ranges: false,
+                hedge: false,
+                preallocate: false,
+                space_margin: 0,
+                write_buffer: None,
+                sync_interval: None,
+                part_file: false,
+                clobber: Clobber::Overwrite,
+                create_dirs: false,
+                preserve_timestamps: false,
+                write_metadata: false,
+                force: false,
+                conditional: false,
+                cache: None,
+                delta_index: None,
+                interrupt: None,
+                on_interrupt: OnInterrupt::Keep,
+                restart_on_change: false,
+                range: None,
+                compressed: false,
+                decompress: false,
+                keep_compressed: false,
+                sink: None,
+                control: None,
+                events: None,
+                recorder: None,
+            },
+        }
+    }
+
+    /// Send event to the stream of `fetch_with_events`, if there is one
+    pub(crate) fn emit(&self, event: DownloadEvent) {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(&event);
+        }
+        if let Some(events) = &self.events {
+            events.send(event);
+        }
+    }
+
+    /// Whether the interrupt of the options has been raised
+    pub(crate) fn is_interrupted(&self) -> bool {
+        self.interrupt
+            .as_ref()
+            .is_some_and(|interrupt| interrupt.is_interrupted())
+    }
+
+    /// Fail once the interrupt of the options has been raised
+    pub(crate) fn check_interrupted(&self) -> Result<()> {
+        if self.is_interrupted() {
+            return Err(FetchError::InterruptedError(
+                "The download was interrupted".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Pass a measurement to the metrics of the options, if there are
+    /// any, and to the recorder of the report
+    pub(crate) fn measure(&self, measure: impl Fn(&dyn FetchMetrics)) {
+        if let Some(recorder) = &self.recorder {
+            measure(recorder.as_ref());
+        }
+        if let Some(metrics) = &self.metrics {
+            measure(metrics.as_ref());
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Builds FetchOptions, starting from the defaults of [`FetchOptions::builder`]
+pub struct FetchOptionsBuilder {
+    options: FetchOptions,
+}
+
+impl FetchOptionsBuilder {
+    /// Sets [`FetchOptions::num_fetches`], fetching with exactly
+    /// that many rather than choosing up to it from the file size
+    pub fn num_fetches(mut self, num_fetches: u64) -> FetchOptionsBuilder {
+        self.options.num_fetches = num_fetches;
+        self.options.auto_fetches = false;
+        self
+    }
+
+    /// Sets [`FetchOptions::output_option`]
+    pub fn output_option(
+        mut self,
+        output_option: impl Into<Option<String>>,
+    ) -> FetchOptionsBuilder {
+        self.options.output_option = output_option.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::logger`]
+    pub fn logger(mut self, logger: Logger) -> FetchOptionsBuilder {
+        self.options.logger = logger;
+        self
+    }
+
+    /// Sets [`FetchOptions::check_etag`]
+    pub fn check_etag(mut self, check_etag: bool) -> FetchOptionsBuilder {
+        self.options.check_etag = check_etag;
+        self
+    }
+
+    /// Sets [`FetchOptions::max_retries`]
+    pub fn max_retries(mut self, max_retries: u64) -> FetchOptionsBuilder {
+        self.options.max_retries = max_retries;
+        self
+    }
+
+    /// Sets [`FetchOptions::degraded_restart`]
+    pub fn degraded_restart(mut self, degraded_restart: bool) -> FetchOptionsBuilder {
+        self.options.degraded_restart = degraded_restart;
+        self
+    }
+
+    /// Sets [`FetchOptions::scheduler`]
+    pub fn scheduler(mut self, scheduler: Box<dyn ChunkScheduler>) -> FetchOptionsBuilder {
+        self.options.scheduler = Some(scheduler);
+        self
+    }
+
+    /// Sets [`FetchOptions::resumable`]
+    pub fn resumable(mut self, resumable: bool) -> FetchOptionsBuilder {
+        self.options.resumable = resumable;
+        self
+    }
+
+    /// Sets [`FetchOptions::resume`]
+    pub fn resume(mut self, resume: bool) -> FetchOptionsBuilder {
+        self.options.resume = resume;
+        self
+    }
+
+    /// Sets [`FetchOptions::allow_single_stream`]
+    pub fn allow_single_stream(mut self, allow_single_stream: bool) -> FetchOptionsBuilder {
+        self.options.allow_single_stream = allow_single_stream;
+        self
+    }
+
+    /// Sets [`FetchOptions::progress`]
+    pub fn progress(mut self, progress: ProgressCallback) -> FetchOptionsBuilder {
+        self.options.progress = Some(progress);
+        self
+    }
+
+    /// Sets [`FetchOptions::metrics`]
+    pub fn metrics(mut self, metrics: Arc<dyn FetchMetrics>) -> FetchOptionsBuilder {
+        self.options.metrics = Some(metrics);
+        self
+    }
+
+    /// Sets [`FetchOptions::limit_rate`]
+    pub fn limit_rate(mut self, limit_rate: impl Into<Option<u64>>) -> FetchOptionsBuilder {
+        self.options.limit_rate = limit_rate.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::connect_timeout`]
+    pub fn connect_timeout(
+        mut self,
+        connect_timeout: impl Into<Option<Duration>>,
+    ) -> FetchOptionsBuilder {
+        self.options.connect_timeout = connect_timeout.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::read_timeout`]
+    pub fn read_timeout(
+        mut self,
+        read_timeout: impl Into<Option<Duration>>,
+    ) -> FetchOptionsBuilder {
+        self.options.read_timeout = read_timeout.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::max_retry_after`]
+    pub fn max_retry_after(
+        mut self,
+        max_retry_after: impl Into<Option<Duration>>,
+    ) -> FetchOptionsBuilder {
+        self.options.max_retry_after = max_retry_after.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::retry_policy`]
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> FetchOptionsBuilder {
+        self.options.retry_policy = retry_policy;
+        self
+    }
+
+    /// Sets [`FetchOptions::checksum`]
+    pub fn checksum(mut self, checksum: impl Into<Option<Checksum>>) -> FetchOptionsBuilder {
+        self.options.checksum = checksum.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::checksum_url`]
+    pub fn checksum_url(mut self, checksum_url: impl Into<Option<String>>) -> FetchOptionsBuilder {
+        self.options.checksum_url = checksum_url.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::signature`]
+    pub fn signature(
+        mut self,
+        signature: impl Into<Option<SignatureCheck>>,
+    ) -> FetchOptionsBuilder {
+        self.options.signature = signature.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::expected_size`]
+    pub fn expected_size(mut self, expected_size: impl Into<Option<u64>>) -> FetchOptionsBuilder {
+        self.options.expected_size = expected_size.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::max_filesize`]
+    pub fn max_filesize(mut self, max_filesize: impl Into<Option<u64>>) -> FetchOptionsBuilder {
+        self.options.max_filesize = max_filesize.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::expect_content_type`]
+    pub fn expect_content_type(
+        mut self,
+        expect_content_type: impl Into<Option<String>>,
+    ) -> FetchOptionsBuilder {
+        self.options.expect_content_type = expect_content_type.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::etag_part_size`]
+    pub fn etag_part_size(mut self, etag_part_size: impl Into<Option<u64>>) -> FetchOptionsBuilder {
+        self.options.etag_part_size = etag_part_size.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::verify_headers`]
+    pub fn verify_headers(mut self, verify_headers: bool) -> FetchOptionsBuilder {
+        self.options.verify_headers = verify_headers;
+        self
+    }
+
+    /// Sets [`FetchOptions::connection_limit`]
+    pub fn connection_limit(
+        mut self,
+        connection_limit: impl Into<Option<Arc<ConnectionLimit>>>,
+    ) -> FetchOptionsBuilder {
+        self.options.connection_limit = connection_limit.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::host_limits`]
+    pub fn host_limits(
+        mut self,
+        host_limits: impl Into<Option<Arc<HostLimits>>>,
+    ) -> FetchOptionsBuilder {
+        self.options.host_limits = host_limits.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::client`]
+    pub fn client(mut self, client: impl Into<Option<reqwest::Client>>) -> FetchOptionsBuilder {
+        self.options.client = client.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::mirrors`]
+    pub fn mirrors(mut self, mirrors: Vec<String>) -> FetchOptionsBuilder {
+        self.options.mirrors = mirrors;
+        self
+    }
+
+    /// Sets [`FetchOptions::headers`]
+    pub fn headers(mut self, headers: HeaderMap) -> FetchOptionsBuilder {
+        self.options.headers = headers;
+        self
+    }
+
+    /// Sets [`FetchOptions::credentials`]
+    pub fn credentials(
+        mut self,
+        credentials: impl Into<Option<Credentials>>,
+    ) -> FetchOptionsBuilder {
+        self.options.credentials = credentials.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::cookies`]
+    pub fn cookies(mut self, cookies: impl Into<Option<Arc<CookieJar>>>) -> FetchOptionsBuilder {
+        self.options.cookies = cookies.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::trace`]
+    pub fn trace(mut self, trace: impl Into<Option<TraceContext>>) -> FetchOptionsBuilder {
+        self.options.trace = trace.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::proxy`]
+    pub fn proxy(mut self, proxy: impl Into<Option<ProxyOptions>>) -> FetchOptionsBuilder {
+        self.options.proxy = proxy.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::ca_certificate`]
+    pub fn ca_certificate(
+        mut self,
+        ca_certificate: impl Into<Option<PathBuf>>,
+    ) -> FetchOptionsBuilder {
+        self.options.ca_certificate = ca_certificate.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::insecure`]
+    pub fn insecure(mut self, insecure: bool) -> FetchOptionsBuilder {
+        self.options.insecure = insecure;
+        self
+    }
+
+    /// Sets [`FetchOptions::client_certificate`]
+    pub fn client_certificate(
+        mut self,
+        client_certificate: impl Into<Option<ClientCertificate>>,
+    ) -> FetchOptionsBuilder {
+        self.options.client_certificate = client_certificate.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::public_key_pins`]
+    pub fn public_key_pins(mut self, public_key_pins: Vec<PublicKeyPin>) -> FetchOptionsBuilder {
+        self.options.public_key_pins = public_key_pins;
+        self
+    }
+
+    /// Sets [`FetchOptions::probe_ranges`]
+    pub fn probe_ranges(mut self, probe_ranges: bool) -> FetchOptionsBuilder {
+        self.options.probe_ranges = probe_ranges;
+        self
+    }
+
+    /// Sets [`FetchOptions::chunk_size`]
+    pub fn chunk_size(mut self, chunk_size: impl Into<Option<u64>>) -> FetchOptionsBuilder {
+        self.options.chunk_size = chunk_size.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::auto_fetches`]
+    pub fn auto_fetches(mut self, auto_fetches: bool) -> FetchOptionsBuilder {
+        self.options.auto_fetches = auto_fetches;
+        self
+    }
+
+    /// Sets [`FetchOptions::min_split_size`]
+    pub fn min_split_size(mut self, min_split_size: impl Into<Option<u64>>) -> FetchOptionsBuilder {
+        self.options.min_split_size = min_split_size.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::adaptive`]
+    pub fn adaptive(mut self, adaptive: bool) -> FetchOptionsBuilder {
+        self.options.adaptive = adaptive;
+        self
+    }
+
+    /// Sets [`FetchOptions::split_slow_ranges`]
+    pub fn split_slow_ranges(mut self, split_slow_ranges: bool) -> FetchOptionsBuilder {
+        self.options.split_slow_ranges = split_slow_ranges;
+        self
+    }
+
+    /// Sets [`FetchOptions::hedge`]
+    pub fn hedge(mut self, hedge: bool) -> FetchOptionsBuilder {
+        self.options.hedge = hedge;
+        self
+    }
+
+    /// Sets [`FetchOptions::preallocate`]
+    pub fn preallocate(mut self, preallocate: bool) -> FetchOptionsBuilder {
+        self.options.preallocate = preallocate;
+        self
+    }
+
+    /// Sets [`FetchOptions::space_margin`]
+    pub fn space_margin(mut self, space_margin: u64) -> FetchOptionsBuilder {
+        self.options.space_margin = space_margin;
+        self
+    }
+
+    /// Sets [`FetchOptions::write_buffer`]
+    pub fn write_buffer(mut self, write_buffer: impl Into<Option<usize>>) -> FetchOptionsBuilder {
+        self.options.write_buffer = write_buffer.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::sync_interval`]
+    pub fn sync_interval(
+        mut self,
+        sync_interval: impl Into<Option<Duration>>,
+    ) -> FetchOptionsBuilder {
+        self.options.sync_interval = sync_interval.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::part_file`]
+    pub fn part_file(mut self, part_file: bool) -> FetchOptionsBuilder {
+        self.options.part_file = part_file;
+        self
+    }
+
+    /// Sets [`FetchOptions::clobber`]
+    pub fn clobber(mut self, clobber: Clobber) -> FetchOptionsBuilder {
+        self.options.clobber = clobber;
+        self
+    }
+
+    /// Sets [`FetchOptions::create_dirs`]
+    pub fn create_dirs(mut self, create_dirs: bool) -> FetchOptionsBuilder {
+        self.options.create_dirs = create_dirs;
+        self
+    }
+
+    /// Sets [`FetchOptions::preserve_timestamps`]
+    pub fn preserve_timestamps(mut self, preserve_timestamps: bool) -> FetchOptionsBuilder {
+        self.options.preserve_timestamps = preserve_timestamps;
+        self
+    }
+
+    /// Sets [`FetchOptions::write_metadata`]
+    pub fn write_metadata(mut self, write_metadata: bool) -> FetchOptionsBuilder {
+        self.options.write_metadata = write_metadata;
+        self
+    }
+
+    /// Sets [`FetchOptions::force`]
+    pub fn force(mut self, force: bool) -> FetchOptionsBuilder {
+        self.options.force = force;
+        self
+    }
+
+    /// Sets [`FetchOptions::conditional`]
+    pub fn conditional(mut self, conditional: bool) -> FetchOptionsBuilder {
+        self.options.conditional = conditional;
+        self
+    }
+
+    /// Sets [`FetchOptions::cache`]
+    pub fn cache(mut self, cache: impl Into<Option<PathBuf>>) -> FetchOptionsBuilder {
+        self.options.cache = cache.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::delta_index`]
+    pub fn delta_index(mut self, delta_index: impl Into<Option<String>>) -> FetchOptionsBuilder {
+        self.options.delta_index = delta_index.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::interrupt`]
+    pub fn interrupt(mut self, interrupt: impl Into<Option<Interrupt>>) -> FetchOptionsBuilder {
+        self.options.interrupt = interrupt.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::on_interrupt`]
+    pub fn on_interrupt(mut self, on_interrupt: OnInterrupt) -> FetchOptionsBuilder {
+        self.options.on_interrupt = on_interrupt;
+        self
+    }
+
+    /// Sets [`FetchOptions::restart_on_change`]
+    pub fn restart_on_change(mut self, restart_on_change: bool) -> FetchOptionsBuilder {
+        self.options.restart_on_change = restart_on_change;
+        self
+    }
+
+    /// Sets [`FetchOptions::range`]
+    pub fn range(mut self, range: impl Into<Option<Range>>) -> FetchOptionsBuilder {
+        self.options.range = range.into();
+        self
+    }
+
+    /// Sets [`FetchOptions::compressed`]
+    pub fn compressed(mut self, compressed: bool) -> FetchOptionsBuilder {
+        self.options.compressed = compressed;
+        self
+    }
+
+    /// Sets [`FetchOptions::decompress`]
+    pub fn decompress(mut self, decompress: bool) -> FetchOptionsBuilder {
+        self.options.decompress = decompress;
+        self
+    }
+
+    /// Sets [`FetchOptions::keep_compressed`]
+    pub fn keep_compressed(mut self, keep_compressed: bool) -> FetchOptionsBuilder {
+        self.options.keep_compressed = keep_compressed;
+        self
+    }
+
+    /// Sets [`FetchOptions::sink`]
+    pub fn sink(mut self, sink: Arc<dyn ChunkSink>) -> FetchOptionsBuilder {
+        self.options.sink = Some(sink);
+        self
+    }
+
+    /// Sends the events of the download to sender, as `fetch_with_events`
+    /// does, for downloads which are run some other way such as through
+    /// `fetch_many`. Finished is not sent, as the result says as much
+    pub fn events(mut self, sender: UnboundedSender<DownloadEvent>) -> FetchOptionsBuilder {
+        self.options.events = Some(Events::new(sender));
+        self
+    }
+
+    /// The options built
+    pub fn build(self) -> FetchOptions {
+        self.options
+    }
+}
+
+/// State shared by every range fetch of a download
+struct FetchContext<'a> {
+    client: &'a reqwest::Client,
+    options: &'a FetchOptions,
+    path: &'a PathBuf,
+    output: &'a Output,
+    /// The length of the bytes being fetched
+    content_length: u64,
+    /// Where the bytes being fetched start in the remote file
+    start: u64,
+    /// The length of the remote file
+    total_length: u64,
+    progress: Progress<'a>,
+    limiter: Option<&'a RateLimiter>,
+    hasher: Option<&'a Mutex<StreamingHasher>>,
+    mirrors: &'a Mirrors,
+    concurrency: Option<&'a Concurrency>,
+    hedging: Option<&'a Hedging>,
+    /// The throughput of completed ranges, to spot slow ones
+    throughputs: Throughputs,
+    /// The validator of the content when resuming, sent in If-Range
+    if_range: Option<HeaderValue>,
+    /// The ETag of the content, which every range response must
+    /// match, and which is sent in If-Match when it is strong
+    etag: Option<HeaderValue>,
+}
+
+/// The checks to run against the downloaded file,
+/// gathered before the download starts
+struct Verification {
+    checksums: Vec<Checksum>,
+    signature: Option<Vec<u8>>,
+    /// Hashes the download as it is written for the checksums
+    /// and ETag, if any need checking
+    hasher: Option<Mutex<StreamingHasher>>,
+}
+
+#[derive(Debug, Default)]
+/// The outcome of a single attempt at fetching a range
+struct Attempt {
+    /// The bytes written before the attempt finished or failed
+    written: u64,
+    /// Whether the written bytes were flushed to the output,
+    /// so that a retry can resume after them
+    flushed: bool,
+    /// The delay requested by the server before retrying
+    retry_after: Option<Duration>,
+}
+
+/// A token bucket shared by every range fetch of a download,
+/// limiting their combined rate
+struct RateLimiter {
+    rate: u64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    fn new(rate: u64) -> RateLimiter {
+        RateLimiter {
+            rate,
+            bucket: Mutex::new(Bucket {
+                tokens: rate as f64,
+                last: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take bytes from the bucket, waiting until they are paid for
+    /// if the bucket does not hold enough
+    async fn consume(&self, bytes: u64) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.last).as_secs_f64() * self.rate as f64;
+            // Allow bursts of up to one second worth of bytes
+            bucket.tokens = (bucket.tokens + refill).min(self.rate as f64) - bytes as f64;
+            bucket.last = now;
+            if bucket.tokens < 0.0 {
+                Duration::from_secs_f64(-bucket.tokens / self.rate as f64)
+            } else {
+                Duration::from_secs(0)
+            }
+        };
+
+        if wait > Duration::from_secs(0) {
+            delay_for(wait).await;
+        }
+    }
+}
+
+/// Tracks the ranges handed out by a ChunkScheduler
+struct Schedule {
+    scheduler: Box<dyn ChunkScheduler>,
+    content_length: u64,
+    completed: Vec<Range>,
+    in_flight: Vec<Range>,
+    /// The claims of the in_flight ranges, in the same order
+    claims: Vec<Arc<Mutex<Claim>>>,
+    pending: VecDeque<Range>,
+    existing: Vec<Range>,
+    resume: Option<ResumeState>,
+    /// The smallest range split off a slow one, or None to never split
+    split_size: Option<u64>,
+    /// Set once a fetch failed, so that the ranges being fetched
+    /// finish but no more are started
+    stopped: bool,
+}
+
+impl Schedule {
+    fn plan(&mut self, num_fetches: u64) -> Result<()> {
+        self.completed.clear();
+        self.in_flight.clear();
+        self.claims.clear();
+        self.pending.clear();
+        self.stopped = false;
+        self.scheduler.plan(self.content_length, num_fetches)
+    }
+
+    fn next_range(
+        &mut self,
+        worker: usize,
+        throughput: Option<f64>,
+    ) -> Option<(Range, Arc<Mutex<Claim>>)> {
+        if self.stopped {
+            return None;
+        }
+        loop {
+            if let Some(range) = self.pending.pop_front() {
+                return Some(self.start(range));
+            }
+
+            let state = ScheduleState {
+                content_length: self.content_length,
+                completed: &self.completed,
+                in_flight: &self.in_flight,
+                worker,
+                throughput,
+            };
+            let range = match self.scheduler.next_range(&state) {
+                Some(range) => range,
+                None => return self.split_slowest(),
+            };
+
+            // Skip over any parts of the range which were
+            // written by a previous run
+            let skipped = self.skipped();
+            self.pending.extend(subtract_ranges(range, &skipped));
+        }
+    }
+
+    fn skipped(&self) -> Vec<Range> {
+        let mut skipped = self.existing.clone();
+        if let Some(resume) = &self.resume {
+            skipped.extend_from_slice(resume.completed());
+        }
+        skipped
+    }
+
+    fn start(&mut self, range: Range) -> (Range, Arc<Mutex<Claim>>) {
+        let claim = Arc::new(Mutex::new(Claim::new(range)));
+        self.in_flight.push(range);
+        self.claims.push(claim.clone());
+        (range, claim)
+    }
+
+    /// Hand the unwritten tail of the in-flight range
+    /// with the most left to write to another worker
+    fn split_slowest(&mut self) -> Option<(Range, Arc<Mutex<Claim>>)> {
+        let split_size = self.split_size?;
+        let index = (0..self.claims.len())
+            .max_by_key(|&index| self.claims[index].lock().unwrap().remaining())?;
+        let tail = self.claims[index].lock().unwrap().split(split_size)?;
+        self.in_flight[index].end = tail.start - 1;
+        Some(self.start(tail))
+    }
+
+    fn complete(&mut self, range: Range) -> Result<()> {
+        // The range may have ended early if its tail was split off
+        if let Some(index) = self.in_flight.iter().position(|r| r.start == range.start) {
+            self.in_flight.remove(index);
+            self.claims.remove(index);
+        }
+        self.completed.push(range);
+        if let Some(resume) = &mut self.resume {
+            resume.record(range)?;
+        }
+        Ok(())
+    }
+}
+
+/// Fail before fetching if the disk holding path lacks room for the
+/// rest of the content and the margin
+fn check_space(options: &FetchOptions, path: &Path, content_length: u64) -> Result<()> {
+    let available = match available_space(path)? {
+        Some(available) => available,
+        None => return Ok(()),
+    };
+    // Bytes already in an existing output take no further space
+    let existing = match std::fs::metadata(path) {
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    };
+    let needed = content_length
+        .saturating_sub(existing)
+        .saturating_add(options.space_margin);
+    info!(options.logger, "disk space"; "needed" => needed, "available" => available);
+    if needed > available {
+        return Err(FetchError::InsufficientSpaceError(format!(
+            "Not enough disk space for {:?}: {} bytes needed, {} available",
+            path, needed, available
+        )));
+    }
+    Ok(())
+}
+
+/// What probing the url revealed about the file before planning ranges
+struct Probe {
+    headers: HeaderMap,
+    /// The url which answered the probe, after any redirects
+    url: Url,
+    /// The total size of the file, when known from a range response
+    content_length: Option<u64>,
+    /// Whether the server honoured a range request, if one was made
+    ranges: Option<bool>,
+}
+
+/// Probe the url with HEAD. Some servers reject HEAD or leave headers out
+/// of its response, so when it fails or lacks a Content-Length, the first
+/// byte is requested with a ranged GET instead. The same request tests
+/// range support when probe_ranges is set and Accept-Ranges is missing
+async fn probe(client: &reqwest::Client, options: &FetchOptions) -> Result<Probe> {
+    let head = send_head(client, options).await;
+    if let Ok(head) = &head {
+        // A server refusing ranges outright has nothing more to reveal
+        let refuses_ranges = head
+            .headers()
+            .get(ACCEPT_RANGES)
+            .is_some_and(|value| value == "none");
+        let untested_ranges = options.probe_ranges && !head.headers().contains_key(ACCEPT_RANGES);
+        if (head.headers().contains_key(CONTENT_LENGTH) && !untested_ranges) || refuses_ranges {
+            return Ok(Probe {
+                headers: head.headers().clone(),
+                url: head.url().clone(),
+                content_length: None,
+                ranges: None,
+            });
+        }
+    }
+
+    info!(options.logger, "probing with a range request"; "head" => format!("{:?}", head.as_ref().map(|head| head.status())));
+    match probe_range(client, options).await {
+        Ok(probe) => Ok(probe),
+        Err(err) => {
+            info!(options.logger, "range probe failed"; "error" => format!("{:?}", &err));
+            let head = head?;
+            Ok(Probe {
+                headers: head.headers().clone(),
+                url: head.url().clone(),
+                content_length: None,
+                ranges: None,
+            })
+        }
+    }
+}
+
+pub(crate) async fn send_head(
+    client: &reqwest::Client,
+    options: &FetchOptions,
+) -> Result<reqwest::Response> {
+    options.measure(|metrics| metrics.request_issued(&options.url));
+    let head = client
+        .head(&options.url)
+        .headers(request_headers(options, &options.url))
+        .send()
+        .await?;
+    store_cookies(options, &head);
+    Ok(head.error_for_status()?)
+}
+
+/// The metadata of an earlier download of the same url to output_path, if
+/// conditional downloads are asked for, the output is complete and it
+/// still has the size recorded for it
+fn previous_download(
+    options: &FetchOptions,
+    output_path: &Path,
+    interrupted: bool,
+) -> Result<Option<Metadata>> {
+    if !options.conditional
+        || interrupted
+        || options.resume
+        || options.sink.is_some()
+        || options.range.is_some()
+    {
+        return Ok(None);
+    }
+    let size = match std::fs::metadata(output_path) {
+        Ok(file) => file.len(),
+        Err(_) => return Ok(None),
+    };
+    Ok(Metadata::load(output_path)?.filter(|metadata| {
+        metadata.url == options.url
+            && metadata.size == size
+            && (metadata.etag.is_some() || metadata.last_modified.is_some())
+    }))
+}
+
+/// Whether the remote file is unchanged since the download previous
+/// described, asking with a conditional HEAD request
+async fn not_modified(
+    client: &reqwest::Client,
+    options: &FetchOptions,
+    previous: &Metadata,
+) -> Result<bool> {
+    let mut headers = request_headers(options, &options.url);
+    if let Some(etag) = &previous.etag {
+        headers.insert(IF_NONE_MATCH, HeaderValue::from_str(etag)?);
+    }
+    if let Some(last_modified) = &previous.last_modified {
+        headers.insert(IF_MODIFIED_SINCE, HeaderValue::from_str(last_modified)?);
+    }
+    options.measure(|metrics| metrics.request_issued(&options.url));
+    let head = client.head(&options.url).headers(headers).send().await?;
+    store_cookies(options, &head);
+    if head.status() == StatusCode::NOT_MODIFIED {
+        return Ok(true);
+    }
+    head.error_for_status()?;
+    Ok(false)
+}
+
+/// Request the first byte of the file, learning its total size from the
+/// Content-Range of a 206, or that ranges are unsupported from a 200
+async fn probe_range(client: &reqwest::Client, options: &FetchOptions) -> Result<Probe> {
+    let mut headers = request_headers(options, &options.url);
+    headers.insert(RANGE, HeaderValue::from_static("bytes=0-0"));
+    options.measure(|metrics| metrics.request_issued(&options.url));
+    let res = client.get(&options.url).headers(headers).send().await?;
+    store_cookies(options, &res);
+    let res = res.error_for_status()?;
+
+    let mut headers = res.headers().clone();
+    if res.status() != StatusCode::PARTIAL_CONTENT {
+        let content_length = match headers.get(CONTENT_LENGTH) {
+            Some(value) => value.to_str()?.parse::<u64>().ok(),
+            None => None,
+        };
+        return Ok(Probe {
+            headers,
+            url: res.url().clone(),
+            content_length,
+            ranges: Some(false),
+        });
+    }
+
+    let content_length = headers
+        .get(CONTENT_RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.rsplit('/').next())
+        .and_then(|total| total.parse::<u64>().ok())
+        .ok_or_else(|| {
+            FetchError::ServerSupportError(
+                "Range response did not include the total size in its Content-Range header"
+                    .to_owned(),
+            )
+        })?;
+    // Content-MD5 describes the single byte returned, not the file
+    headers.remove("content-md5");
+    Ok(Probe {
+        headers,
+        url: res.url().clone(),
+        content_length: Some(content_length),
+        ranges: Some(true),
+    })
+}
+
+/// Fetch a url which accepts range requests w/ parallel requests,
+/// returning a report of how the download went
+pub async fn fetch(mut options: FetchOptions) -> Result<FetchReport> {
+    let recorder = Arc::new(Recorder::new());
+    options.recorder = Some(recorder.clone());
+    let logger = options.logger.clone();
+    download(options).await?;
+
+    let report = recorder.report();
+    info!(logger, "download finished";
+        "path" => format!("{:?}", &report.path),
+        "bytes" => report.bytes,
+        "duration" => format!("{:?}", report.duration),
+        "average_throughput" => report.average_throughput(),
+        "peak_throughput" => report.peak_throughput,
+        "retried_ranges" => report.retries.len(),
+        "checksums" => format!("{:?}", &report.checksums),
+        "signature" => report.signature);
+    Ok(report)
+}
+
+/// Fetch the download of options, restarting it
+/// from scratch if the remote file changes partway
+async fn download(mut options: FetchOptions) -> Result<()> {
+    check_sink_options(&options)?;
+    let mut output_path = parse_path(&options.output_option, &options.url, options.create_dirs)?;
+    let download_path = |output_path: &Path| {
+        if options.part_file {
+            part_path(output_path)
+        } else {
+            output_path.to_path_buf()
+        }
+    };
+    let requested_path = output_path.clone();
+    // A resumable download's output has its full length before it is complete
+    let interrupted =
+        options.resumable && ResumeState::state_path(&download_path(&output_path)).exists();
+    // An output which may still be fresh is replaced in place if it is not
+    let previous = previous_download(&options, &output_path, interrupted)?;
+    if !options.resume && !interrupted && options.sink.is_none() && previous.is_none() {
+        output_path = match options.clobber.resolve(&output_path)? {
+            Some(output_path) => output_path,
+            None => {
+                info!(options.logger, "skipping existing output"; "path" => format!("{:?}", &output_path));
+                return Ok(());
+            }
+        };
+    }
+    // The existing output is read from while the new one is assembled
+    let delta = options.delta_index.is_some()
+        && !interrupted
+        && !options.resume
+        && options.range.is_none()
+        && requested_path.is_file();
+    let path = if delta {
+        part_path(&output_path)
+    } else {
+        download_path(&output_path)
+    };
+    let lock = match options.sink {
+        Some(_) => None,
+        None => Some(OutputLock::acquire(&output_path)?),
+    };
+
+    if let Some(parent) = &options.trace {
+        let span = parent.child();
+        info!(options.logger, "tracing"; "trace_id" => span.trace_id(), "span_id" => span.span_id(), "parent_id" => parent.span_id());
+        options.trace = Some(span);
+    }
+
+    debug!(options.logger, "fetching"; "options" => format!("{:?}", &options));
+
+    if options.limit_rate == Some(0) {
+        return Err(FetchError::InvalidArgumentsError(
+            "Rate limit must be greater than zero".to_owned(),
+        ));
+    }
+    let limiter = options.limit_rate.map(RateLimiter::new);
+
+    if options.preallocate && options.resume {
+        return Err(FetchError::InvalidArgumentsError(
+            "Preallocating the output conflicts with resume, use resumable instead".to_owned(),
+        ));
+    }
+
+    if options.write_buffer == Some(0) {
+        return Err(FetchError::InvalidArgumentsError(
+            "Write buffer must be greater than zero".to_owned(),
+        ));
+    }
+
+    if options.num_fetches == 0 {
+        return Err(FetchError::InvalidArgumentsError(
+            "Number of fetches must be greater than zero".to_owned(),
+        ));
+    }
+
+    if options.chunk_size == Some(0) {
+        return Err(FetchError::InvalidArgumentsError(
+            "Chunk size must be greater than zero".to_owned(),
+        ));
+    }
+
+    if options.min_split_size == Some(0) {
+        return Err(FetchError::InvalidArgumentsError(
+            "Minimum split size must be greater than zero".to_owned(),
+        ));
+    }
+
+    if options.range.is_some() && (options.check_etag || options.verify_headers) {
+        return Err(FetchError::InvalidArgumentsError(
+            "The ETag and checksum headers describe the whole file, not a range of it".to_owned(),
+        ));
+    }
+
+    if options.etag_part_size == Some(0) {
+        return Err(FetchError::InvalidArgumentsError(
+            "ETag part size must be greater than zero".to_owned(),
+        ));
+    }
+
+    let client = match &options.client {
+        Some(client) => client.clone(),
+        None => build_client(&options)?,
+    };
+
+    if !options.public_key_pins.is_empty() {
+        for url in std::iter::once(&options.url).chain(&options.mirrors) {
+            check_public_key_pins(url, &options.public_key_pins).await?;
+            info!(options.logger, "public key checked"; "url" => url);
+        }
+    }
+
+    if let Some(credentials) = &options.credentials {
+        let value = credentials.header_value()?;
+        options.headers.insert(AUTHORIZATION, value);
+    }
+
+    if let Some(previous) = &previous {
+        if not_modified(&client, &options, previous).await? {
+            info!(options.logger, "remote file not modified, skipping"; "path" => format!("{:?}", &output_path));
+            return Ok(());
+        }
+        // The new version may have the same length as the output
+        options.force = true;
+    }
+
+    let mut checksums: Vec<Checksum> = options.checksum.iter().cloned().collect();
+    if let Some(checksum_url) = &options.checksum_url {
+        checksums.push(fetch_checksum(&client, &options, checksum_url, &output_path).await?);
+    }
+    let signature = match &options.signature {
+        Some(check) => Some(fetch_signature(&client, &options, check).await?),
+        None => None,
+    };
+    let delta_index = match &options.delta_index {
+        Some(index_url) if delta => match fetch_delta_index(&client, &options, index_url).await {
+            Ok(index) => Some(index),
+            Err(err) => {
+                warn!(options.logger, "failed to fetch the delta index, downloading the whole file"; "error" => format!("{:?}", err));
+                None
+            }
+        },
+        _ => None,
+    };
+    let probe = probe(&client, &options).await?;
+
+    let headers = &probe.headers;
+    if let Some(recorder) = &options.recorder {
+        recorder.validators(headers);
+    }
+    check_content_type(&options, headers)?;
+
+    let encoding = if options.compressed {
+        ContentEncoding::from_headers(headers)?
+    } else {
+        None
+    };
+    if let Some(encoding) = encoding {
+        if options.range.is_some() {
+            return Err(FetchError::ServerSupportError(
+                "Server compressed the response, which cannot be fetched in part".to_owned(),
+            ));
+        }
+        if options.check_etag || options.verify_headers {
+            warn!(options.logger, "skipping the ETag and checksum headers of a compressed response"; "encoding" => format!("{:?}", encoding));
+            options.check_etag = false;
+            options.verify_headers = false;
+        }
+    }
+
+    let etag_header_option = headers.get(ETAG);
+    let reported_length = probe.content_length.or_else(|| {
+        headers
+            .get(CONTENT_LENGTH)?
+            .to_str()
+            .ok()?
+            .parse::<u64>()
+            .ok()
+    });
+
+    let delta_index = match delta_index {
+        Some(index) if encoding.is_none() && Some(index.length()) == reported_length => {
+            if let Some(checksum) = index.checksum() {
+                checksums.push(checksum);
+            }
+            // The existing output is to be updated, even if it has the same length
+            options.force = true;
+            Some(index)
+        }
+        Some(index) => {
+            warn!(options.logger, "delta index does not describe the remote file, downloading the whole file"; "index_length" => index.length(), "content_length" => format!("{:?}", reported_length));
+            None
+        }
+        None => None,
+    };
+
+    if !options.force
+        && !interrupted
+        && options.sink.is_none()
+        && options.range.is_none()
+        && encoding.is_none()
+        && matches_existing(
+            &options,
+            &requested_path,
+            reported_length,
+            etag_header_option,
+        )?
+    {
+        info!(options.logger, "output already matches, skipping"; "path" => format!("{:?}", &requested_path));
+        return Ok(());
+    }
+
+    if options.verify_headers {
+        let advertised = header_checksums(headers);
+        info!(options.logger, "checksum headers"; "algorithms" => format!("{:?}", advertised.iter().map(|checksum| checksum.algorithm).collect::<Vec<_>>()));
+        checksums.extend(advertised);
+    }
+
+    let mut algorithms: Vec<ChecksumAlgorithm> = checksums
+        .iter()
+        .map(|checksum| checksum.algorithm)
+        .collect();
+    algorithms.extend(etag_algorithm(&options, etag_header_option));
+    // Parts of files are not cached
+    let cache = match &options.cache {
+        Some(dir) if options.range.is_none() => Some(Cache::new(dir)),
+        _ => None,
+    };
+    if options.write_metadata || cache.is_some() {
+        algorithms.push(ChecksumAlgorithm::Sha256);
+    }
+    let hasher = if algorithms.is_empty() {
+        None
+    } else {
+        Some(Mutex::new(StreamingHasher::new(&algorithms)))
+    };
+    let verification = Verification {
+        checksums,
+        signature,
+        hasher,
+    };
+
+    // Bytes kept to be resumed are not thrown away for a cached copy
+    let cache = cache.filter(|_| !interrupted && !options.resume);
+    if let Some(cache) = &cache {
+        let etag = match strong_etag(etag_header_option) {
+            Some(etag) => Some(etag.to_str()?),
+            None => None,
+        };
+        let expected = verification
+            .checksums
+            .iter()
+            .find(|checksum| checksum.algorithm == ChecksumAlgorithm::Sha256)
+            .map(|checksum| &checksum.digest[..]);
+        if let Some(digest) = cache.lookup(&options.url, etag, expected)? {
+            if cache.restore(&digest, &path)? {
+                info!(options.logger, "restored from cache"; "digest" => hex::encode(&digest), "path" => format!("{:?}", &path));
+                let digests = verify_output(&options, verification, etag_header_option, &path)?;
+                return finish_output(&options, &path, &output_path, &probe, digests);
+            }
+            warn!(options.logger, "removed cached file which was changed"; "digest" => hex::encode(&digest));
+        }
+    }
+
+    let accept_ranges = headers.get(ACCEPT_RANGES);
+
+    let single_stream_reason =
+        single_stream_reason(probe.ranges, accept_ranges, encoding.is_some());
+    options.emit(DownloadEvent::HeadReceived {
+        url: probe.url.to_string(),
+        content_length: reported_length,
+        ranges: single_stream_reason.is_none(),
+    });
+
+    if let Some(reason) = single_stream_reason {
+        if !(options.allow_single_stream || encoding.is_some()) || options.range.is_some() {
+            return Err(FetchError::ServerSupportError(reason.to_owned()));
+        }
+        check_reported_size(&options, reported_length, encoding.is_some())?;
+        info!(options.logger, "falling back to single stream"; "reason" => reason);
+        fetch_single_stream(
+            &client,
+            &options,
+            &path,
+            limiter.as_ref(),
+            verification.hasher.as_ref(),
+        )
+        .await?;
+        let digests = verify_output(&options, verification, etag_header_option, &path)?;
+        return finish_output(&options, &path, &output_path, &probe, digests);
+    }
+
+    let total_length = match probe.content_length {
+        Some(content_length) => content_length,
+        None => header_content_length(headers)?,
+    };
+
+    debug!(options.logger, "head";"content_length" => total_length, "etag" => format!("{:?}", &etag_header_option));
+    debug!(options.logger, "head"; "accept_ranges" => format!("{:?}", &accept_ranges), "content_length" => total_length, "etag" => format!("{:?}", &etag_header_option));
+
+    let (start, content_length) = requested_range(options.range, total_length)?;
+    check_reported_size(&options, Some(content_length), false)?;
+    if options.range.is_some() {
+        info!(options.logger, "fetching part of the file"; "start" => start, "end" => start + content_length - 1);
+    }
+
+    let scheduler = take_scheduler(&mut options);
+    let resume = if options.resumable {
+        let etag = match etag_header_option {
+            Some(etag) => Some(etag.to_str()?),
+            None => None,
+        };
+        let last_modified = match headers.get(LAST_MODIFIED) {
+            Some(last_modified) => Some(last_modified.to_str()?),
+            None => None,
+        };
+        let resume = ResumeState::open(&path, content_length, etag, last_modified)?;
+        info!(options.logger, "resuming"; "completed" => format!("{:?}", resume.completed()));
+        Some(resume)
+    } else {
+        None
+    };
+    let existing = if options.resume {
+        let existing = existing_range(&path, content_length)?;
+        info!(options.logger, "continuing"; "existing" => format!("{:?}", &existing));
+        existing.into_iter().collect()
+    } else if let Some(index) = &delta_index {
+        let copied = index.copy_blocks(&requested_path, &path)?;
+        let bytes: u64 = copied.iter().map(|range| range.end - range.start + 1).sum();
+        info!(options.logger, "copied blocks from the existing output"; "bytes" => bytes, "content_length" => content_length);
+        copied
+    } else {
+        Vec::new()
+    };
+    let sink: Arc<dyn ChunkSink> = match &options.sink {
+        Some(sink) => sink.clone(),
+        None => {
+            check_space(&options, &path, content_length)?;
+            if options.preallocate {
+                preallocate(&path, content_length)?;
+                info!(options.logger, "preallocated"; "path" => format!("{:?}", &path), "content_length" => content_length);
+            }
+            Arc::new(OutputFile::open(&path)?)
+        }
+    };
+    let output = Output::new(
+        sink,
+        options.write_buffer.unwrap_or(DEFAULT_WRITE_BUFFER),
+        options.sync_interval,
+    );
+    let schedule = Mutex::new(Schedule {
+        scheduler,
+        content_length,
+        completed: Vec::new(),
+        in_flight: Vec::new(),
+        claims: Vec::new(),
+        pending: VecDeque::new(),
+        existing,
+        resume,
+        split_size: if options.split_slow_ranges {
+            Some(options.min_split_size.unwrap_or(DEFAULT_MIN_SPLIT_SIZE))
+        } else {
+            None
+        },
+        stopped: false,
+    });
+
+    let missing: u64 = subtract_ranges(
+        Range {
+            start: 0,
+            end: content_length.saturating_sub(1),
+        },
+        &schedule.lock().unwrap().skipped(),
+    )
+    .iter()
+    .map(|range| range.end - range.start + 1)
+    .sum();
+
+    let mirrors =
+        Mirrors::new(std::iter::once(options.url.clone()).chain(options.mirrors.iter().cloned()));
+    let hedging = if options.hedge {
+        Some(Hedging::new())
+    } else {
+        None
+    };
+    let concurrency = if options.adaptive {
+        Some(Concurrency::new(options.num_fetches as usize))
+    } else {
+        None
+    };
+
+    // Resumed bytes were fetched before, so each range request is made
+    // conditional on the content being the same as it was then
+    let if_range = if missing < content_length {
+        strong_etag(etag_header_option)
+            .or_else(|| headers.get(LAST_MODIFIED))
+            .cloned()
+    } else {
+        None
+    };
+
+    let ctx = FetchContext {
+        client: &client,
+        options: &options,
+        path: &path,
+        output: &output,
+        content_length,
+        start,
+        total_length,
+        progress: Progress::new(
+            &options,
+            content_length,
+            content_length.saturating_sub(missing),
+        ),
+        limiter: limiter.as_ref(),
+        hasher: verification.hasher.as_ref(),
+        mirrors: &mirrors,
+        concurrency: concurrency.as_ref(),
+        hedging: hedging.as_ref(),
+        throughputs: Throughputs::new(),
+        if_range,
+        etag: etag_header_option.cloned(),
+    };
+
+    let num_fetches = planned_fetches(&options, content_length);
+    info!(options.logger, "planning"; "num_fetches" => num_fetches);
+
+    let plan = if options.degraded_restart {
+        degraded_fetches(num_fetches)
+    } else {
+        vec![num_fetches]
+    };
+
+    let mut changed = false;
+    for (i, &num_fetches) in plan.iter().enumerate() {
+        if i > 0 {
+            info!(options.logger, "restarting"; "num_fetches" => num_fetches);
+        }
+
+        match fetch_ranges(&ctx, &schedule, num_fetches).await {
+            Ok(()) => break,
+            Err(err) => {
+                if let FetchError::InterruptedError(_) = err.inner() {
+                    match options.on_interrupt {
+                        OnInterrupt::Keep => save_interrupted(
+                            &options,
+                            &path,
+                            content_length,
+                            headers,
+                            &schedule.lock().unwrap(),
+                        )?,
+                        OnInterrupt::Delete => delete_interrupted(&options, &path)?,
+                    }
+                    return Err(err);
+                }
+                if let FetchError::ContentChangedError(_) = err.inner() {
+                    if ctx.if_range.is_some() || options.restart_on_change {
+                        warn!(options.logger, "remote file changed since it was partly fetched"; "error" => format!("{:?}", &err));
+                        changed = true;
+                        break;
+                    }
+                }
+                if i + 1 == plan.len() || !is_restartable(&err) {
+                    return Err(err);
+                }
+                info!(options.logger, "download failed"; "error" => format!("{:?}", &err));
+            }
+        }
+    }
+
+    let schedule = schedule.into_inner().unwrap();
+    if let Some(resume) = schedule.resume {
+        resume.remove()?;
+    }
+    if changed {
+        // The bytes kept from before belong to the old content
+        if options.sink.is_none() {
+            std::fs::remove_file(&path)?;
+        }
+        info!(options.logger, "restarting from scratch"; "path" => format!("{:?}", &path));
+        options.scheduler = Some(schedule.scheduler);
+        options.resume = false;
+        // Restart at most once for a file which keeps changing
+        options.restart_on_change = false;
+        drop(lock);
+        return Box::pin(download(options)).await;
+    }
+    output.finish().await?;
+
+    let digests = verify_output(&options, verification, etag_header_option, &path)?;
+    finish_output(&options, &path, &output_path, &probe, digests)
+}
+
+/// Why a download whose probe found ranges supported or not, and which got
+/// accept_ranges in its headers, has to be fetched as a single stream, or
+/// None if its ranges can be fetched in parallel
+fn single_stream_reason(
+    ranges: Option<bool>,
+    accept_ranges: Option<&HeaderValue>,
+    compressed: bool,
+) -> Option<&'static str> {
+    match (ranges, accept_ranges) {
+        _ if compressed => Some("Server compressed the response"),
+        (Some(true), _) => None,
+        (Some(false), _) => Some("Server ignored a range request"),
+        (None, None) => Some("Server does not include Accept-Ranges header"),
+        (None, Some(value)) if value == "none" => Some("Server's Accept-Ranges header set to none"),
+        (None, Some(_)) => None,
+    }
+}
+
+/// The length of the remote file given by the Content-Length of headers
+fn header_content_length(headers: &HeaderMap) -> Result<u64> {
+    headers
+        .get(CONTENT_LENGTH)
+        .ok_or_else(|| {
+            FetchError::ServerSupportError(
+                "Server does not include Content-Length header".to_owned(),
+            )
+        })?
+        .to_str()?
+        .parse::<u64>()
+        .map_err(|_| {
+            FetchError::ServerSupportError(
+                "Server returned Content-Length header that cannot be parsed to u64".to_owned(),
+            )
+        })
+}
+
+/// The first byte and the length of the part of a file of total_length
+/// bytes to download, which is the whole file unless range is given
+fn requested_range(range: Option<Range>, total_length: u64) -> Result<(u64, u64)> {
+    match range {
+        Some(range) => {
+            if range.start > range.end || range.start >= total_length {
+                return Err(FetchError::InvalidArgumentsError(format!(
+                    "Range {}-{} is not within the {} bytes of the file",
+                    range.start, range.end, total_length
+                )));
+            }
+            let end = range.end.min(total_length - 1);
+            Ok((range.start, end - range.start + 1))
+        }
+        None => Ok((0, total_length)),
+    }
+}
+
+/// The scheduler of options, or else one splitting the content
+/// into ranges of its chunk size
+fn take_scheduler(options: &mut FetchOptions) -> Box<dyn ChunkScheduler> {
+    options
+        .scheduler
+        .take()
+        .unwrap_or_else(|| match options.chunk_size {
+            Some(chunk_size) => Box::new(StaticScheduler::with_chunk_size(chunk_size)),
+            None => Box::new(StaticScheduler::default()),
+        })
+}
+
+/// The number of fetches to download content_length bytes with, which
+/// is fewer than asked for if the ranges would be smaller than the
+/// minimum split size
+fn planned_fetches(options: &FetchOptions, content_length: u64) -> u64 {
+    let min_split_size = match options.min_split_size {
+        Some(size) => Some(size),
+        None if options.auto_fetches => Some(DEFAULT_MIN_SPLIT_SIZE),
+        None => None,
+    };
+    match min_split_size {
+        Some(size) => split_fetches(content_length, options.num_fetches, size),
+        None => options.num_fetches,
+    }
+}
+
+/// Record the ranges written before an interrupt in a resume state for
+/// path, unless the download was already keeping one, so that running it
+/// again with `resumable` fetches only the rest
+fn save_interrupted(
+    options: &FetchOptions,
+    path: &Path,
+    content_length: u64,
+    headers: &HeaderMap,
+    schedule: &Schedule,
+) -> Result<()> {
+    if schedule.resume.is_some() || options.sink.is_some() || options.range.is_some() {
+        return Ok(());
+    }
+    let etag = match headers.get(ETAG) {
+        Some(etag) => Some(etag.to_str()?),
+        None => None,
+    };
+    let last_modified = match headers.get(LAST_MODIFIED) {
+        Some(last_modified) => Some(last_modified.to_str()?),
+        None => None,
+    };
+    let mut resume = ResumeState::open(path, content_length, etag, last_modified)?;
+    for &range in schedule.existing.iter().chain(&schedule.completed) {
+        resume.record(range)?;
+    }
+    info!(options.logger, "saved resume state"; "path" => format!("{:?}", ResumeState::state_path(path)));
+    Ok(())
+}
+
+/// Delete the partial output at path of an interrupted download and any
+/// resume state of it, unless the download was written to a sink
+fn delete_interrupted(options: &FetchOptions, path: &Path) -> Result<()> {
+    if options.sink.is_some() {
+        return Ok(());
+    }
+    for file in &[path.to_path_buf(), ResumeState::state_path(path)] {
+        if file.exists() {
+            std::fs::remove_file(file)?;
+        }
+    }
+    info!(options.logger, "deleted partial output"; "path" => format!("{:?}", path));
+    Ok(())
+}
+
+/// Fail if options write to a sink but ask for anything
+/// which needs the download in the output file
+fn check_sink_options(options: &FetchOptions) -> Result<()> {
+    if options.sink.is_none() {
+        return Ok(());
+    }
+    let conflicts = [
+        (options.resume, "resume"),
+        (options.resumable, "resumable"),
+        (options.preallocate, "preallocate"),
+        (options.part_file, "part_file"),
+        (options.preserve_timestamps, "preserve_timestamps"),
+        (options.write_metadata, "write_metadata"),
+        (options.check_etag, "check_etag"),
+        (options.verify_headers, "verify_headers"),
+        (options.checksum.is_some(), "checksum"),
+        (options.checksum_url.is_some(), "checksum_url"),
+        (options.signature.is_some(), "signature"),
+        (options.expected_size.is_some(), "expected_size"),
+        (options.decompress, "decompress"),
+        (options.cache.is_some(), "cache"),
+        (options.delta_index.is_some(), "delta_index"),
+    ];
+    match conflicts.iter().find(|(set, _)| *set) {
+        Some((_, name)) => Err(FetchError::InvalidArgumentsError(format!(
+            "The {} option needs an output file and cannot be used with a sink",
+            name
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Move a download made to a separate path into place at output_path,
+/// then set its modification time, add it to the cache, save its metadata
+/// and decompress it if the options ask
+fn finish_output(
+    options: &FetchOptions,
+    path: &Path,
+    output_path: &Path,
+    probe: &Probe,
+    digests: Vec<(ChecksumAlgorithm, Vec<u8>)>,
+) -> Result<()> {
+    let last_modified = probe.headers.get(LAST_MODIFIED);
+    if path != output_path {
+        std::fs::rename(path, output_path)?;
+        info!(options.logger, "renamed"; "from" => format!("{:?}", path), "to" => format!("{:?}", output_path));
+    }
+    if options.preserve_timestamps {
+        match last_modified
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_http_date)
+        {
+            Some(modified) => {
+                let file = std::fs::OpenOptions::new().write(true).open(output_path)?;
+                file.set_modified(modified)?;
+                info!(options.logger, "preserved timestamp"; "last_modified" => format!("{:?}", last_modified));
+            }
+            None => info!(options.logger, "no Last-Modified time to preserve"),
+        }
+    }
+    let digest = find_digest(&digests, ChecksumAlgorithm::Sha256);
+    if let Some(dir) = options
+        .cache
+        .as_ref()
+        .filter(|_| options.range.is_none() && !digest.is_empty())
+    {
+        let etag = strong_etag(probe.headers.get(ETAG)).and_then(|etag| etag.to_str().ok());
+        Cache::new(dir).insert(&options.url, etag, digest, output_path)?;
+        debug!(options.logger, "cached"; "digest" => hex::encode(digest));
+    }
+    if options.write_metadata {
+        let header = |name| {
+            probe
+                .headers
+                .get(name)
+                .and_then(|value: &HeaderValue| value.to_str().ok())
+                .map(String::from)
+        };
+        let metadata = Metadata {
+            url: options.url.clone(),
+            final_url: probe.url.to_string(),
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            size: std::fs::metadata(output_path)?.len(),
+            digests: digests
+                .into_iter()
+                .map(|(algorithm, digest)| (algorithm, hex::encode(digest)))
+                .collect(),
+        };
+        metadata.save(output_path)?;
+        info!(options.logger, "saved metadata"; "path" => format!("{:?}", Metadata::path(output_path)));
+    }
+    let mut saved = output_path.to_path_buf();
+    if options.decompress {
+        match decompress(output_path, options.keep_compressed)? {
+            Some(decompressed) => {
+                info!(options.logger, "decompressed"; "path" => format!("{:?}", decompressed));
+                saved = decompressed;
+            }
+            None => info!(options.logger, "output is not compressed"),
+        }
+    }
+    if options.sink.is_none() {
+        options.emit(DownloadEvent::Saved { path: saved });
+    }
+    Ok(())
+}
+
+/// Build a client configured by options, which can be shared
+/// between downloads with the same connection settings
+pub fn build_client(options: &FetchOptions) -> Result<reqwest::Client> {
+    Ok(client_builder(options)?.build()?)
+}
+
+/// A builder of the client configured by options, for callers
+/// which need to configure it further
+pub(crate) fn client_builder(options: &FetchOptions) -> Result<reqwest::ClientBuilder> {
+    let mut client_builder = reqwest::Client::builder();
+    if let Some(connect_timeout) = options.connect_timeout {
+        client_builder = client_builder.connect_timeout(connect_timeout);
+    }
+    client_builder = configure_tls(client_builder, options)?;
+    if let Some(proxy) = &options.proxy {
+        if let Some(proxy) = proxy.proxy()? {
+            client_builder = client_builder.proxy(proxy);
+        }
+    }
+    Ok(client_builder)
+}
+
+/// The headers to send with a request to url: those of options along
+/// with any cookies which apply, the encodings accepted and, when traced,
+/// a traceparent naming a new span for the request
+pub(crate) fn request_headers(options: &FetchOptions, url: &str) -> HeaderMap {
+    let mut headers = options.headers.clone();
+    if !headers.contains_key(ACCEPT_ENCODING) {
+        headers.insert(ACCEPT_ENCODING, accept_encoding(options.compressed));
+    }
+    if let Some(jar) = &options.cookies {
+        if let Some(cookie) = Url::parse(url).ok().and_then(|url| jar.header_for(&url)) {
+            headers.insert(COOKIE, cookie);
+        }
+    }
+    if let Some(trace) = &options.trace {
+        headers.insert(TRACEPARENT, trace.child().header_value());
+    }
+    headers
+}
+
+/// Keep any cookies set by res in the cookie jar of options
+pub(crate) fn store_cookies(options: &FetchOptions, res: &reqwest::Response) {
+    if let Some(jar) = &options.cookies {
+        jar.store(res.url(), res.headers());
+    }
+}
+
+/// Fetch a url as `fetch` does, assembling its content in memory
+/// rather than writing it to a file. Options which need an output
+/// file, such as resuming or verifying checksums, cannot be used
+pub async fn fetch_to_vec(mut options: FetchOptions) -> Result<Vec<u8>> {
+    let sink = Arc::new(MemorySink::default());
+    options.sink = Some(sink.clone());
+    fetch(options).await?;
+    Ok(sink.take())
+}
+
+/// Fetch the bytes in range of the remote file at the url of options,
+/// split between parallel requests as a whole download is, writing
+/// them to sink from its start. The server must support range requests
+pub async fn fetch_partial(
+    mut options: FetchOptions,
+    range: std::ops::Range<u64>,
+    sink: Arc<dyn ChunkSink>,
+) -> Result<FetchReport> {
+    if range.start >= range.end {
+        return Err(FetchError::InvalidArgumentsError(format!(
+            "Range {}..{} is empty",
+            range.start, range.end
+        )));
+    }
+    options.range = Some(Range {
+        start: range.start,
+        end: range.end - 1,
+    });
+    options.sink = Some(sink);
+    fetch(options).await
+}
+
+/// Work out what fetching options would do from a HEAD request alone:
+/// where the output would be saved, the ranges of the remote file which
+/// would be requested and how the output would be verified. Nothing is
+/// written, so missing directories of the output are not created and
+/// fail the plan, and none of the content is requested, not even to
+/// probe range support
+pub async fn plan_download(mut options: FetchOptions) -> Result<DownloadPlan> {
+    check_sink_options(&options)?;
+    let path = match options.sink {
+        Some(_) => None,
+        None => {
+            let path = parse_path(&options.output_option, &options.url, false)?;
+            if options.resume {
+                Some(path)
+            } else {
+                options.clobber.resolve(&path)?
+            }
+        }
+    };
+
+    let client = match &options.client {
+        Some(client) => client.clone(),
+        None => build_client(&options)?,
+    };
+    if let Some(credentials) = &options.credentials {
+        let value = credentials.header_value()?;
+        options.headers.insert(AUTHORIZATION, value);
+    }
+    let head = send_head(&client, &options).await?;
+    let headers = head.headers();
+    check_content_type(&options, headers)?;
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|value: &HeaderValue| value.to_str().ok())
+            .map(String::from)
+    };
+
+    let compressed = options.compressed && ContentEncoding::from_headers(headers)?.is_some();
+    let single_stream = single_stream_reason(None, headers.get(ACCEPT_RANGES), compressed);
+    let (total_length, num_fetches, ranges) = match single_stream {
+        Some(reason) => {
+            if !(options.allow_single_stream || compressed) || options.range.is_some() {
+                return Err(FetchError::ServerSupportError(reason.to_owned()));
+            }
+            let total_length = header_content_length(headers).ok();
+            check_reported_size(&options, total_length, compressed)?;
+            let ranges = total_length
+                .filter(|&length| length > 0)
+                .map(|length| Range {
+                    start: 0,
+                    end: length - 1,
+                });
+            (total_length, 1, ranges.into_iter().collect())
+        }
+        None => {
+            let total_length = header_content_length(headers)?;
+            let (start, content_length) = requested_range(options.range, total_length)?;
+            check_reported_size(&options, Some(content_length), false)?;
+            let num_fetches = planned_fetches(&options, content_length);
+            let mut scheduler = take_scheduler(&mut options);
+            scheduler.plan(content_length, num_fetches)?;
+            // Each range is taken to be fetched before the next is asked for
+            let mut completed = Vec::new();
+            while let Some(range) = scheduler.next_range(&ScheduleState {
+                content_length,
+                completed: &completed,
+                in_flight: &[],
+                worker: 0,
+                throughput: None,
+            }) {
+                completed.push(range);
+            }
+            let ranges = completed
+                .into_iter()
+                .map(|range| Range {
+                    start: start + range.start,
+                    end: start + range.end,
+                })
+                .collect();
+            (Some(total_length), num_fetches, ranges)
+        }
+    };
+
+    let mut checksums: Vec<Checksum> = options.checksum.iter().cloned().collect();
+    if options.verify_headers && !compressed {
+        checksums.extend(header_checksums(headers));
+    }
+    let etag_check = if compressed {
+        None
+    } else {
+        etag_algorithm(&options, headers.get(ETAG))
+    };
+    Ok(DownloadPlan {
+        url: head.url().to_string(),
+        path,
+        total_length,
+        single_stream: single_stream.map(String::from),
+        num_fetches,
+        ranges,
+        checksums,
+        checksum_url: options.checksum_url.clone(),
+        signature_url: options
+            .signature
+            .as_ref()
+            .map(|check| format!("{}{}", options.url, check.kind.extension())),
+        etag_check,
+        etag: header(ETAG),
+        last_modified: header(LAST_MODIFIED),
+    })
+}
+
+/// Fetch several downloads concurrently, within limits on the requests
+/// in flight between them, which may be given as just the most
+/// connections in total. Returns the result of each download in order,
+/// so one failing leaves the others to finish unless the limits fail fast
+pub async fn fetch_many(
+    options: impl IntoIterator<Item = FetchOptions>,
+    limits: impl Into<FetchLimits>,
+) -> Result<Vec<Result<FetchReport>>> {
+    let limits = limits.into();
+    if limits.connections == 0 {
+        return Err(FetchError::InvalidArgumentsError(
+            "Number of max connections must be greater than zero".to_owned(),
+        ));
+    }
+    if limits.connections_per_host == Some(0) {
+        return Err(FetchError::InvalidArgumentsError(
+            "Number of max connections per host must be greater than zero".to_owned(),
+        ));
+    }
+    let limit = Arc::new(ConnectionLimit::new(limits.connections as usize));
+    let host_limits = limits
+        .connections_per_host
+        .map(|connections| Arc::new(HostLimits::new(connections as usize)));
+
+    let options: Vec<FetchOptions> = options
+        .into_iter()
+        .map(|mut options| {
+            if options.connection_limit.is_none() {
+                options.connection_limit = Some(limit.clone());
+            }
+            if options.host_limits.is_none() {
+                options.host_limits = host_limits.clone();
+            }
+            if limits.fail_fast && options.interrupt.is_none() {
+                options.interrupt = Some(Interrupt::new());
+            }
+            options
+        })
+        .collect();
+    let interrupts: Vec<Interrupt> = if limits.fail_fast {
+        options
+            .iter()
+            .filter_map(|options| options.interrupt.clone())
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let downloads = options.into_iter().map(|options| {
+        let logger = options.logger.clone();
+        let interrupts = &interrupts;
+        async move {
+            let result = fetch(options).await;
+            if let Err(err) = &result {
+                let interrupted = matches!(err.inner(), FetchError::InterruptedError(_));
+                if !interrupts.is_empty() && !interrupted {
+                    info!(logger, "stopping the other downloads"; "error" => format!("{}", err));
+                    interrupts.iter().for_each(Interrupt::interrupt);
+                }
+            }
+            result
+        }
+    });
+
+    Ok(join_all(downloads).await)
+}
+
+/// Run every verification requested by options against the downloaded file
+fn verify_output(
+    options: &FetchOptions,
+    verification: Verification,
+    etag_header_option: Option<&HeaderValue>,
+    path: &Path,
+) -> Result<Vec<(ChecksumAlgorithm, Vec<u8>)>> {
+    if let Some(expected_size) = options.expected_size {
+        let size = std::fs::metadata(path)?.len();
+        if size != expected_size {
+            return Err(FetchError::ValidationError(format!(
+                "Downloaded {} bytes but expected {}",
+                size, expected_size
+            )));
+        }
+        info!(options.logger, "verified size"; "bytes" => size);
+    }
+
+    let digests = match verification.hasher {
+        Some(hasher) => {
+            let hasher = hasher.into_inner().unwrap();
+            info!(options.logger, "hashed while downloading"; "bytes" => hasher.hashed());
+            hasher.finish(path)?
+        }
+        None => Vec::new(),
+    };
+
+    verify_etag(options, etag_header_option, &digests, path)?;
+    for checksum in &verification.checksums {
+        checksum.check(find_digest(&digests, checksum.algorithm))?;
+        info!(options.logger, "verified checksum"; "algorithm" => format!("{}", checksum.algorithm));
+    }
+    if let (Some(check), Some(signature)) = (&options.signature, verification.signature) {
+        check.verify(path, &signature)?;
+        info!(options.logger, "verified signature"; "kind" => format!("{:?}", check.kind));
     }
+    if !verification.checksums.is_empty() || options.signature.is_some() {
+        options.emit(DownloadEvent::Verified {
+            checksums: verification
+                .checksums
+                .iter()
+                .map(|checksum| checksum.algorithm)
+                .collect(),
+            signature: options.signature.is_some(),
+        });
+    }
+    Ok(digests)
 }
 
-#[derive(Debug)]
-/// Options for fetching
-pub struct FetchOptions {
-    /// The url to fetch from
-    pub url: String,
-    /// An optional output location
-    pub output_option: Option<String>,
-    /// The number of parallel fetches to execute
-    pub num_fetches: u64,
-    /// A logger
-    pub logger: Logger,
-    /// Whether to attempt to check an etag for validation
-    pub check_etag: bool,
-    /// The number of times to attempt to retry a failed chunk fetch
-    pub max_retries: u64,
+/// Fail if the length the server reports for the download is over the
+/// max filesize of the options, or differs from their expected size
+/// unless it is the length of a compressed response
+fn check_reported_size(
+    options: &FetchOptions,
+    length: Option<u64>,
+    compressed: bool,
+) -> Result<()> {
+    if let (Some(max_filesize), Some(length)) = (options.max_filesize, length) {
+        if length > max_filesize {
+            return Err(FetchError::FileTooLargeError(format!(
+                "Server reports {} bytes, more than the most allowed {}",
+                length, max_filesize
+            )));
+        }
+    }
+    if compressed {
+        return Ok(());
+    }
+    match (options.expected_size, length) {
+        (Some(expected_size), Some(length)) if length != expected_size => {
+            Err(FetchError::ValidationError(format!(
+                "Server reports {} bytes but expected {}",
+                length, expected_size
+            )))
+        }
+        _ => Ok(()),
+    }
 }
 
-/// Fetch a url which accepts range requests w/ parallel requests
-pub async fn fetch(options: FetchOptions) -> Result<()> {
-    let path = parse_path(&options.output_option, &options.url)?;
+/// Fail unless the Content-Type in headers matches the expected content
+/// type of the options, if they have one
+fn check_content_type(options: &FetchOptions, headers: &HeaderMap) -> Result<()> {
+    let pattern = match &options.expect_content_type {
+        Some(pattern) => pattern,
+        None => return Ok(()),
+    };
+    match headers.get(CONTENT_TYPE).map(HeaderValue::to_str) {
+        Some(Ok(content_type)) if content_type_matches(pattern, content_type) => Ok(()),
+        Some(Ok(content_type)) => Err(FetchError::ValidationError(format!(
+            "Server reports Content-Type {} but expected {}",
+            content_type, pattern
+        ))),
+        _ => Err(FetchError::ValidationError(format!(
+            "Server reports no Content-Type but expected {}",
+            pattern
+        ))),
+    }
+}
 
-    info!(options.logger, "fetching"; "options" => format!("{:?}", &options));
+/// Fetch and parse the zsync control file at index_url
+async fn fetch_delta_index(
+    client: &reqwest::Client,
+    options: &FetchOptions,
+    index_url: &str,
+) -> Result<DeltaIndex> {
+    let read_timeout = options.read_timeout;
+    options.measure(|metrics| metrics.request_issued(index_url));
+    let res = with_read_timeout(read_timeout, client.get(index_url).send())
+        .await??
+        .error_for_status()?;
+    let bytes = with_read_timeout(read_timeout, res.bytes()).await??;
+    let index = DeltaIndex::parse(&bytes)?;
+    info!(options.logger, "fetched delta index"; "index_url" => index_url, "length" => index.length());
+    Ok(index)
+}
 
-    let client = reqwest::Client::new();
-    let head = client.head(&options.url).send().await?.error_for_status()?;
+/// Read the detached signature of check, fetching it from
+/// next to the download url unless a local file was given
+async fn fetch_signature(
+    client: &reqwest::Client,
+    options: &FetchOptions,
+    check: &SignatureCheck,
+) -> Result<Vec<u8>> {
+    if let Some(signature_file) = &check.signature_file {
+        return Ok(std::fs::read(signature_file)?);
+    }
+    let signature_url = format!("{}{}", options.url, check.kind.extension());
+    info!(options.logger, "fetching signature"; "signature_url" => &signature_url);
+    let read_timeout = options.read_timeout;
+    options.measure(|metrics| metrics.request_issued(&signature_url));
+    let res = with_read_timeout(read_timeout, client.get(&signature_url).send())
+        .await??
+        .error_for_status()?;
+    let signature = with_read_timeout(read_timeout, res.bytes()).await??;
+    Ok(signature.to_vec())
+}
 
-    let headers = head.headers();
+/// Fetch the checksum file at checksum_url and find the entry
+/// for the file being downloaded, named either as in the url or the output
+async fn fetch_checksum(
+    client: &reqwest::Client,
+    options: &FetchOptions,
+    checksum_url: &str,
+    path: &Path,
+) -> Result<Checksum> {
+    let read_timeout = options.read_timeout;
+    options.measure(|metrics| metrics.request_issued(checksum_url));
+    let res = with_read_timeout(read_timeout, client.get(checksum_url).send())
+        .await??
+        .error_for_status()?;
+    let contents = with_read_timeout(read_timeout, res.text()).await??;
 
-    let etag_header_option = headers.get(ETAG);
+    let url = Url::parse(&options.url)
+        .map_err(|_| FetchError::InvalidArgumentsError("Url could not be parsed".to_owned()))?;
+    let mut filenames = Vec::new();
+    if let Some(filename) = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+    {
+        filenames.push(filename);
+    }
+    if let Some(filename) = path.file_name().and_then(|filename| filename.to_str()) {
+        filenames.push(filename);
+    }
 
-    let accept_ranges = headers.get(ACCEPT_RANGES).ok_or_else(|| {
-        Box::new(FetchError::ServerSupportError(
-            "Server does not include Accept-Ranges header".to_owned(),
+    let checksum = find_checksum(&contents, &filenames).ok_or_else(|| {
+        FetchError::ValidationError(format!(
+            "Checksum file has no entry for {}",
+            filenames.join(" or ")
         ))
     })?;
+    info!(options.logger, "found checksum"; "checksum_url" => checksum_url, "algorithm" => format!("{}", checksum.algorithm));
+    Ok(checksum)
+}
 
-    let content_length = headers
-        .get(CONTENT_LENGTH)
-        .ok_or_else(|| {
-            Box::new(FetchError::ServerSupportError(
-                "Server does not include Content-Length header".to_owned(),
-            ))
-        })?
-        .to_str()?
-        .parse::<u64>()
-        .map_err(|_| {
-            Box::new(FetchError::ServerSupportError(
-                "Server returned Content-Length header that cannot be parsed to u64".to_owned(),
-            ))
-        })?;
-
-    info!(options.logger, "head";"content_length" => content_length, "etag" => format!("{:?}", &etag_header_option));
-    info!(options.logger, "head"; "accept_ranges" => format!("{:?}", &accept_ranges), "content_length" => content_length, "etag" => format!("{:?}", &etag_header_option));
+fn find_digest(digests: &[(ChecksumAlgorithm, Vec<u8>)], algorithm: ChecksumAlgorithm) -> &[u8] {
+    digests
+        .iter()
+        .find(|(digest_algorithm, _)| *digest_algorithm == algorithm)
+        .map(|(_, digest)| &digest[..])
+        .unwrap_or(&[])
+}
 
-    if accept_ranges == "none" {
-        return Err(Box::new(FetchError::ServerSupportError(
-            "Server's Accept-Ranges header set to none".to_owned(),
-        )));
+/// Whether the file at path already holds the content, having its
+/// length and, when the ETag is checked, the digest the ETag gives
+fn matches_existing(
+    options: &FetchOptions,
+    path: &Path,
+    content_length: Option<u64>,
+    etag_header_option: Option<&HeaderValue>,
+) -> Result<bool> {
+    let length = match std::fs::metadata(path) {
+        Ok(metadata) if metadata.is_file() => metadata.len(),
+        _ => return Ok(false),
+    };
+    if content_length != Some(length) {
+        return Ok(false);
     }
+    match etag_algorithm(options, etag_header_option) {
+        Some(algorithm) => {
+            let digests = StreamingHasher::new(&[algorithm]).finish(path)?;
+            Ok(verify_etag(options, etag_header_option, &digests, path).is_ok())
+        }
+        None => Ok(true),
+    }
+}
 
-    let mut fetches = Vec::new();
+/// The ETag, unless it is weak and so cannot be used to validate ranges
+fn strong_etag(etag_header_option: Option<&HeaderValue>) -> Option<&HeaderValue> {
+    etag_header_option.filter(|etag| !etag.as_bytes().starts_with(b"W/"))
+}
 
-    let ranges = create_ranges(content_length, options.num_fetches)?;
-    for range in ranges {
-        fetches.push(fetch_retryer(
-            &client,
-            &options.url,
-            range,
-            &path,
-            content_length,
-            &options.logger,
-            options.max_retries,
-        ));
+/// The algorithm to hash the download with to check its ETag,
+/// or None if the ETag is not being checked or cannot be
+fn etag_algorithm(
+    options: &FetchOptions,
+    etag_header_option: Option<&HeaderValue>,
+) -> Option<ChecksumAlgorithm> {
+    if !options.check_etag {
+        return None;
     }
+    let etag = etag_header_option?.to_str().ok()?.replace("\"", "");
+    match split_multipart_etag(&etag) {
+        Some(_) => options.etag_part_size.map(ChecksumAlgorithm::Md5Multipart),
+        None => Some(ChecksumAlgorithm::Md5),
+    }
+}
 
-    try_join_all(fetches).await?;
-
+fn verify_etag(
+    options: &FetchOptions,
+    etag_header_option: Option<&HeaderValue>,
+    digests: &[(ChecksumAlgorithm, Vec<u8>)],
+    path: &Path,
+) -> Result<()> {
     if options.check_etag {
         if let Some(etag) = etag_header_option {
-            check_etag(&etag.to_str()?.replace("\"", ""), &path)
+            let etag = etag.to_str()?.replace("\"", "");
+            match (split_multipart_etag(&etag), options.etag_part_size) {
+                (Some((hash, parts)), Some(part_size)) => {
+                    let length = std::fs::metadata(path)?.len();
+                    let expected_parts = std::cmp::max(1, length.div_ceil(part_size));
+                    if parts != expected_parts {
+                        return Err(FetchError::ValidationError(format!(
+                            "Multipart ETag has {} parts but the part size gives {}",
+                            parts, expected_parts
+                        )));
+                    }
+                    check_etag(
+                        hash,
+                        find_digest(digests, ChecksumAlgorithm::Md5Multipart(part_size)),
+                    )
+                }
+                (Some(_), None) => {
+                    warn!(options.logger, "skipping multipart ETag check without a part size"; "etag" => &etag);
+                    Ok(())
+                }
+                (None, _) => check_etag(&etag, find_digest(digests, ChecksumAlgorithm::Md5)),
+            }
         } else {
-            Err(Box::new(FetchError::ServerSupportError(
+            Err(FetchError::ServerSupportError(
                 "Server did not include ETag header".to_owned(),
-            )))
+            ))
         }
     } else {
         Ok(())
     }
 }
 
-async fn fetch_retryer(
-    client: &reqwest::Client,
+/// Wait for a connection to url within the limits of options,
+/// holding it until the returned permits are dropped
+async fn acquire_connection<'a>(
+    options: &'a FetchOptions,
     url: &str,
-    range: Range,
+) -> (Option<HostPermit>, Option<ConnectionPermit<'a>>) {
+    // Waiting on a busy host before taking a shared connection
+    // leaves that connection to downloads from other hosts
+    let host = match &options.host_limits {
+        Some(limits) => Some(limits.acquire(url).await),
+        None => None,
+    };
+    let connection = match &options.connection_limit {
+        Some(limit) => Some(limit.acquire().await),
+        None => None,
+    };
+    (host, connection)
+}
+
+/// Await future, failing with a timed out IoError
+/// if it does not complete within read_timeout
+async fn with_read_timeout<F: Future>(
+    read_timeout: Option<Duration>,
+    future: F,
+) -> Result<F::Output> {
+    match read_timeout {
+        Some(duration) => Timeout::new(future, duration).await.map_err(|_| {
+            FetchError::IoError(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Timed out reading response",
+            ))
+        }),
+        None => Ok(future.await),
+    }
+}
+
+/// Fetch the whole url with a single plain GET, for servers
+/// which do not support range requests
+async fn fetch_single_stream(
+    client: &reqwest::Client,
+    options: &FetchOptions,
     path: &PathBuf,
-    total_length: u64,
-    logger: &Logger,
-    max_retries: u64,
+    limiter: Option<&RateLimiter>,
+    hasher: Option<&Mutex<StreamingHasher>>,
+) -> Result<()> {
+    let url = &options.url;
+    let logger = &options.logger;
+
+    let _permits = acquire_connection(options, url).await;
+
+    let sink: Arc<dyn ChunkSink> = match &options.sink {
+        Some(sink) => sink.clone(),
+        None => Arc::new(OutputFile::create(path)?),
+    };
+    let output = Output::new(
+        sink,
+        options.write_buffer.unwrap_or(DEFAULT_WRITE_BUFFER),
+        options.sync_interval,
+    );
+    let mut writer = output.writer(0);
+
+    info!(logger, "fetching"; "url" => url);
+
+    let read_timeout = options.read_timeout;
+    let request = client.get(url).headers(request_headers(options, url));
+    options.measure(|metrics| metrics.request_issued(url));
+    let res = with_read_timeout(read_timeout, request.send()).await??;
+    store_cookies(options, &res);
+    let mut res = res.error_for_status()?;
+
+    let mut decoder = if options.compressed {
+        ContentEncoding::from_headers(res.headers())?.map(Decoder::new)
+    } else {
+        None
+    };
+    // The length of a compressed response is not that of the file
+    let total = match decoder {
+        Some(_) => 0,
+        None => res.content_length().unwrap_or(0),
+    };
+    let range = Range {
+        start: 0,
+        end: total.saturating_sub(1),
+    };
+    let progress = Progress::new(options, total, 0);
+    options.emit(DownloadEvent::ChunkStarted {
+        range,
+        url: url.clone(),
+    });
+    let mut written = 0;
+
+    loop {
+        let received = with_read_timeout(read_timeout, res.chunk()).await??;
+        let chunk = match (&mut decoder, &received) {
+            (None, Some(received)) => Cow::Borrowed(&received[..]),
+            (None, None) => break,
+            (Some(decoder), Some(received)) => Cow::Owned(decoder.decode(received)?),
+            (Some(decoder), None) => Cow::Owned(decoder.finish()?),
+        };
+        if let Some(control) = &options.control {
+            control.wait_while_paused().await;
+        }
+        options.check_interrupted()?;
+        writer.write(&chunk).await?;
+        if let Some(hasher) = hasher {
+            hasher.lock().unwrap().update(written, &chunk);
+        }
+        written += chunk.len() as u64;
+        progress.advance(range, written, chunk.len() as u64);
+        match &received {
+            Some(received) => {
+                options.measure(|metrics| metrics.bytes_downloaded(received.len() as u64));
+                if let Some(limiter) = limiter {
+                    limiter.consume(received.len() as u64).await;
+                }
+            }
+            None => break,
+        }
+    }
+
+    writer.flush().await?;
+    output.finish().await?;
+    options.emit(DownloadEvent::ChunkCompleted { range });
+
+    info!(logger, "written"; "path" => format!("{:?}", &path));
+
+    Ok(())
+}
+
+async fn fetch_ranges(
+    ctx: &FetchContext<'_>,
+    schedule: &Mutex<Schedule>,
+    num_fetches: u64,
+) -> Result<()> {
+    schedule.lock().unwrap().plan(num_fetches)?;
+    ctx.options.emit(DownloadEvent::RangePlanned {
+        content_length: ctx.content_length,
+        num_fetches,
+    });
+    if let Some(concurrency) = ctx.concurrency {
+        concurrency.restart(num_fetches as usize);
+    }
+
+    let workers = (0..num_fetches as usize).map(|worker| async move {
+        let result = fetch_worker(ctx, schedule, worker).await;
+        if result.is_err() {
+            schedule.lock().unwrap().stopped = true;
+            if let Some(concurrency) = ctx.concurrency {
+                concurrency.finish();
+            }
+        }
+        result
+    });
+
+    // Workers stop without failing when interrupted, so that
+    // each can record what it wrote before the download fails
+    let failed: Vec<FetchError> = join_all(workers)
+        .await
+        .into_iter()
+        .filter_map(Result::err)
+        .collect();
+    if failed.is_empty() {
+        return ctx.options.check_interrupted();
+    }
+    let completed = schedule
+        .lock()
+        .unwrap()
+        .completed
+        .iter()
+        .map(|range| range.end - range.start + 1)
+        .sum();
+    Err(FetchError::RangesError { failed, completed })
+}
+
+async fn fetch_worker(
+    ctx: &FetchContext<'_>,
+    schedule: &Mutex<Schedule>,
+    worker: usize,
 ) -> Result<()> {
+    let mut throughput = None;
+
+    loop {
+        if let Some(concurrency) = ctx.concurrency {
+            // Workers beyond the current limit wait until it grows
+            while !concurrency.admits(worker) {
+                if concurrency.is_finished() {
+                    return Ok(());
+                }
+                delay_for(WORKER_WAIT).await;
+            }
+        }
+
+        if let Some(control) = &ctx.options.control {
+            control.wait_while_paused().await;
+        }
+        if ctx.options.is_interrupted() {
+            return Ok(());
+        }
+
+        let next_range = schedule.lock().unwrap().next_range(worker, throughput);
+        let (range, claim) = match next_range {
+            Some(next_range) => next_range,
+            None => {
+                if let Some(concurrency) = ctx.concurrency {
+                    concurrency.finish();
+                }
+                return Ok(());
+            }
+        };
+
+        let started = Instant::now();
+
+        match fetch_retryer(ctx, range, &claim).await {
+            Err(err) if matches!(err.inner(), FetchError::InterruptedError(_)) => {
+                // Keep what was written of the range for a later run
+                let next = claim.lock().unwrap().next();
+                if next > range.start {
+                    schedule.lock().unwrap().complete(Range {
+                        start: range.start,
+                        end: next - 1,
+                    })?;
+                }
+                return Ok(());
+            }
+            result => result?,
+        }
+        let range = Range {
+            start: range.start,
+            end: claim.lock().unwrap().end(),
+        };
+
+        let elapsed = started.elapsed().as_secs_f64();
+        throughput = if elapsed > 0.0 {
+            Some((range.end - range.start + 1) as f64 / elapsed)
+        } else {
+            None
+        };
+        if let (Some(hedging), Some(throughput)) = (ctx.hedging, throughput) {
+            hedging.record(throughput);
+        }
+        if let Some(throughput) = throughput {
+            if let Some(median) = ctx
+                .throughputs
+                .record(range.end - range.start + 1, throughput)
+            {
+                warn!(ctx.options.logger, "slow range"; "range" => &range, "throughput" => throughput, "median" => median);
+                ctx.options.emit(DownloadEvent::ChunkSlow {
+                    range,
+                    throughput,
+                    median,
+                });
+            }
+        }
+
+        schedule.lock().unwrap().complete(range)?;
+        ctx.options.emit(DownloadEvent::ChunkCompleted { range });
+    }
+}
+
+/// Whether a failed download may succeed if restarted with fewer fetches
+fn is_restartable(error: &FetchError) -> bool {
+    match error.inner() {
+        FetchError::ReqwestError(err) => match err.status() {
+            Some(status) => !status.is_client_error(),
+            None => true,
+        },
+        _ => false,
+    }
+}
+
+async fn fetch_retryer(ctx: &FetchContext<'_>, range: Range, claim: &Mutex<Claim>) -> Result<()> {
+    let logger = &ctx.options.logger;
+    let max_retries = ctx.options.max_retries;
     let mut attempts = 0;
 
     if max_retries == 0 {
-        return Err(Box::new(FetchError::InvalidArgumentsError(
+        return Err(FetchError::InvalidArgumentsError(
             "Number of max-retries must be greater than zero".to_owned(),
-        )));
+        ));
     }
 
+    // Bytes of the range already on disk from earlier attempts
+    let mut offset = 0;
+
     loop {
-        let result = fetch_range(&client, &url, range, &path, total_length, &logger).await;
+        let range = Range {
+            start: range.start,
+            end: claim.lock().unwrap().end(),
+        };
+        let mut attempt = Attempt::default();
+        let (mirror, url) = ctx.mirrors.pick();
+        ctx.options.emit(DownloadEvent::ChunkStarted {
+            range: Range {
+                start: range.start + offset,
+                end: range.end,
+            },
+            url: url.clone(),
+        });
+        let started = Instant::now();
+        let result = match ctx.hedging {
+            Some(hedging) if range.end - range.start - offset < MAX_HEDGE_SIZE => {
+                fetch_hedged(ctx, hedging, &url, range, offset, claim, &mut attempt).await
+            }
+            _ => fetch_range(ctx, &url, range, offset, claim, &mut attempt).await,
+        };
 
         if let Err(error) = result {
-            if let FetchError::ReqwestError(error) = *error {
-                attempts += 1;
-                if let Some(status) = error.status() {
-                    if status.is_client_error() {
-                        return Err(Box::new(FetchError::ReqwestError(error)));
-                    }
-                }
-                if attempts >= max_retries {
-                    return Err(Box::new(FetchError::ReqwestError(error)));
-                } else {
-                    info!(logger, "retrying"; "attempts" => attempts, "max_retries" => max_retries);
-                }
+            // Errors caused by the mirror rather than the local machine
+            let from_mirror = matches!(
+                error,
+                FetchError::ReqwestError(_) | FetchError::ServerSupportError(_)
+            );
+            let retryable = ctx.options.retry_policy.is_retryable(&error);
+            let demoted = ctx.mirrors.failed(mirror, from_mirror && !retryable);
+            if demoted && !ctx.options.mirrors.is_empty() {
+                warn!(logger, "demoted failing mirror"; "url" => &url);
+            }
+
+            if attempt.flushed {
+                offset += attempt.written;
             } else {
-                return Err(error);
+                ctx.progress.rewind(attempt.written);
+            }
+            if offset > range.end - range.start {
+                return Ok(());
+            }
+            // A range which cannot be retried on one mirror may still succeed on another
+            let switch_mirror = from_mirror && ctx.mirrors.has_alternative(mirror);
+            attempts += 1;
+            if (!retryable && !switch_mirror) || attempts >= max_retries {
+                ctx.progress.rewind(offset);
+                return Err(FetchError::RangeError {
+                    url,
+                    range,
+                    attempts,
+                    source: Box::new(error),
+                });
+            }
+            info!(logger, "retrying"; "attempts" => attempts, "max_retries" => max_retries, "error" => format!("{:?}", &error));
+            ctx.options.emit(DownloadEvent::ChunkRetried {
+                range,
+                attempts,
+                error: error.to_string(),
+            });
+            ctx.options.measure(|metrics| metrics.chunk_retried(range));
+            if let (Some(retry_after), Some(max_retry_after)) =
+                (attempt.retry_after, ctx.options.max_retry_after)
+            {
+                let delay = std::cmp::min(retry_after, max_retry_after);
+                info!(logger, "waiting to retry"; "range" => &range, "delay" => format!("{:?}", delay));
+                delay_for(delay).await;
             }
         } else {
+            ctx.options
+                .measure(|metrics| metrics.chunk_latency(range, started.elapsed()));
+            let elapsed = started.elapsed().as_secs_f64();
+            let throughput = if elapsed > 0.0 {
+                Some(attempt.written as f64 / elapsed)
+            } else {
+                None
+            };
+            for url in ctx.mirrors.succeeded(mirror, throughput) {
+                warn!(logger, "demoted slow mirror"; "url" => url);
+            }
             return result;
         }
     }
 }
 
+async fn write_body(
+    ctx: &FetchContext<'_>,
+    range: Range,
+    offset: u64,
+    res: &mut reqwest::Response,
+    writer: &mut RangeWriter<'_>,
+    claim: &Mutex<Claim>,
+    attempt: &mut Attempt,
+) -> Result<()> {
+    let read_timeout = ctx.options.read_timeout;
+    while let Some(mut chunk) = with_read_timeout(read_timeout, res.chunk()).await?? {
+        if let Some(control) = &ctx.options.control {
+            control.wait_while_paused().await;
+        }
+        ctx.options.check_interrupted()?;
+        // Stop early if the tail of the range was split off
+        let position = range.start + offset + attempt.written;
+        let allowed = claim.lock().unwrap().take(position, chunk.len() as u64);
+        let split = allowed < chunk.len() as u64;
+        chunk.truncate(allowed as usize);
+        writer.write(&chunk).await?;
+        if let Some(hasher) = ctx.hasher {
+            hasher
+                .lock()
+                .unwrap()
+                .update(range.start + offset + attempt.written, &chunk);
+        }
+        attempt.written += chunk.len() as u64;
+        ctx.progress
+            .advance(range, offset + attempt.written, chunk.len() as u64);
+        if let Some(concurrency) = ctx.concurrency {
+            if let Some(limit) = concurrency.record(chunk.len() as u64) {
+                info!(ctx.options.logger, "adjusted concurrency"; "limit" => limit);
+            }
+        }
+        ctx.options
+            .measure(|metrics| metrics.bytes_downloaded(chunk.len() as u64));
+        if let Some(limiter) = ctx.limiter {
+            limiter.consume(chunk.len() as u64).await;
+        }
+        if split {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Fetch range, skipping the first offset bytes which are already on disk
 async fn fetch_range(
-    client: &reqwest::Client,
+    ctx: &FetchContext<'_>,
     url: &str,
     range: Range,
-    path: &PathBuf,
-    total_length: u64,
-    logger: &Logger,
+    offset: u64,
+    claim: &Mutex<Claim>,
+    attempt: &mut Attempt,
 ) -> Result<()> {
-    let mut out_file = OpenOptions::new().create(true).write(true).open(path).await?;
+    let path = ctx.path;
+    let logger = &ctx.options.logger;
 
-    out_file.seek(SeekFrom::Start(range.start)).await?;
+    let _permits = acquire_connection(ctx.options, url).await;
 
-    let mut writer = BufWriter::new(out_file);
+    let mut writer = ctx.output.writer(range.start + offset);
 
-    info!(logger, "fetching"; "range" => &range);
+    let mut res = request_range(ctx, url, range, offset, attempt).await?;
 
-    let mut headers = HeaderMap::new();
-    headers.insert(
-        RANGE,
-        format!("bytes={}-{}", range.start, range.end).parse()?,
-    );
+    // Flush whatever was written even if the body fails partway,
+    // so that a retry only needs to fetch the rest of the range
+    let written = write_body(ctx, range, offset, &mut res, &mut writer, claim, attempt).await;
+    writer.flush().await?;
+    attempt.flushed = true;
+    written?;
 
-    let mut res = client
-        .get(url)
-        .headers(headers)
-        .send()
-        .await?
-        .error_for_status()?;
+    debug!(logger, "written"; "range" => &range, "path" => format!("{:?}", &path));
+
+    Ok(())
+}
+
+/// Request range after its first offset bytes, checking
+/// that the response is for exactly those bytes
+async fn request_range(
+    ctx: &FetchContext<'_>,
+    url: &str,
+    range: Range,
+    offset: u64,
+    attempt: &mut Attempt,
+) -> Result<reqwest::Response> {
+    let client = ctx.client;
+    let total_length = ctx.total_length;
+    let logger = &ctx.options.logger;
+    // Where the range lies in the remote file
+    let start = ctx.start + range.start + offset;
+    let end = ctx.start + range.end;
+
+    debug!(logger, "fetching"; "range" => &range, "offset" => offset);
+
+    let mut headers = request_headers(ctx.options, url);
+    headers.insert(RANGE, format!("bytes={}-{}", start, end).parse()?);
+    // Mirrors may report different validators for the same content
+    if url == ctx.options.url {
+        if let Some(if_range) = &ctx.if_range {
+            headers.insert(IF_RANGE, if_range.clone());
+        }
+        if let Some(if_match) = strong_etag(ctx.etag.as_ref()) {
+            headers.insert(IF_MATCH, if_match.clone());
+        }
+    }
+
+    let read_timeout = ctx.options.read_timeout;
+    ctx.options.measure(|metrics| metrics.request_issued(url));
+    let res = with_read_timeout(read_timeout, client.get(url).headers(headers).send()).await??;
+    store_cookies(ctx.options, &res);
+
+    let status = res.status();
+    if status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE {
+        attempt.retry_after = match res.headers().get(RETRY_AFTER) {
+            Some(value) => parse_retry_after(value.to_str()?, Utc::now()),
+            None => None,
+        };
+    }
+    if status == StatusCode::PRECONDITION_FAILED
+        && strong_etag(ctx.etag.as_ref()).is_some()
+        && url == ctx.options.url
+    {
+        return Err(FetchError::ContentChangedError(
+            "Remote file changed during the download".to_owned(),
+        ));
+    }
+
+    let res = res.error_for_status()?;
 
     let res_headers = res.headers();
 
     let status = res.status();
 
+    // A server answers If-Range with the whole file once it has changed
+    if status == StatusCode::OK && ctx.if_range.is_some() && url == ctx.options.url {
+        return Err(FetchError::ContentChangedError(
+            "Remote file changed since the download was interrupted".to_owned(),
+        ));
+    }
+
     if status != StatusCode::PARTIAL_CONTENT {
-        return Err(Box::new(FetchError::ServerSupportError(
+        return Err(FetchError::ServerSupportError(
             "Range response status code was not a 206".to_owned(),
-        )));
+        ));
+    }
+
+    if url == ctx.options.url {
+        if let (Some(expected), Some(etag)) = (&ctx.etag, res_headers.get(ETAG)) {
+            if etag != expected {
+                return Err(FetchError::ContentChangedError(format!(
+                    "Range response ETag {:?} differs from {:?}",
+                    etag, expected
+                )));
+            }
+        }
     }
 
     let content_range = res_headers
         .get(CONTENT_RANGE)
         .ok_or_else(|| {
-            Box::new(FetchError::ServerSupportError(
+            FetchError::ServerSupportError(
                 "Range response did not include Content-Range header".to_owned(),
-            ))
+            )
         })?
         .to_str()?;
 
     let content_length = res_headers
         .get(CONTENT_LENGTH)
         .ok_or_else(|| {
-            Box::new(FetchError::ServerSupportError(
+            FetchError::ServerSupportError(
                 "Range response did not include Content-Length header".to_owned(),
-            ))
+            )
         })?
         .to_str()?
         .parse::<u64>()
         .map_err(|_| {
-            Box::new(FetchError::ServerSupportError(
+            FetchError::ServerSupportError(
                 "Server returned Content-Length header that cannot be parsed to u64".to_owned(),
-            ))
+            )
         })?;
 
     let etag = res_headers.get(ETAG);
 
-    info!(logger, "received"; "range" => &range, "content_range" => &content_range, "content_length" => content_length, "etag" => format!("{:?}", &etag), "status" => format!("{}", res.status()));
+    debug!(logger, "received"; "range" => &range, "content_range" => &content_range, "content_length" => content_length, "etag" => format!("{:?}", &etag), "status" => format!("{}", res.status()));
 
-    if content_range != format!("bytes {}-{}/{}", range.start, range.end, total_length) {
-        return Err(Box::new(FetchError::ServerSupportError(
+    if content_range != format!("bytes {}-{}/{}", start, end, total_length) {
+        return Err(FetchError::ServerSupportError(
             "Range response Content-Range headers did not match expected".to_owned(),
-        )));
+        ));
     }
 
-    if content_length - 1 != range.end - range.start {
-        return Err(Box::new(FetchError::ServerSupportError(
+    if content_length - 1 != end - start {
+        return Err(FetchError::ServerSupportError(
             "Range response Content-Length was incorrect".to_owned(),
-        )));
+        ));
     }
 
-    while let Some(chunk) = res.chunk().await? {
-        writer.write(&chunk).await?;
+    Ok(res)
+}
+
+/// Fetch range as fetch_range does, but if it takes much longer than
+/// the ranges before it, race a duplicate request for the same bytes
+/// on another connection and keep whichever finishes first
+async fn fetch_hedged(
+    ctx: &FetchContext<'_>,
+    hedging: &Hedging,
+    url: &str,
+    range: Range,
+    offset: u64,
+    claim: &Mutex<Claim>,
+    attempt: &mut Attempt,
+) -> Result<()> {
+    let logger = &ctx.options.logger;
+    let fetch = Box::pin(fetch_range(ctx, url, range, offset, claim, attempt));
+
+    let bytes = range.end - range.start + 1 - offset;
+    let started = Instant::now();
+    let straggling = Box::pin(async {
+        loop {
+            delay_for(HEDGE_CHECK).await;
+            if let Some(deadline) = hedging.deadline(bytes) {
+                if started.elapsed() > deadline {
+                    return;
+                }
+            }
+        }
+    });
+    let fetch = match select(fetch, straggling).await {
+        Either::Left((result, _)) => return result,
+        Either::Right(((), fetch)) => fetch,
+    };
+
+    let (mirror, hedge_url) = ctx.mirrors.pick();
+    info!(logger, "hedging straggling range"; "range" => &range, "url" => &hedge_url);
+    let hedge = Box::pin(fetch_range_body(ctx, &hedge_url, range, offset));
+    let (hedged, fetch) = match select(fetch, hedge).await {
+        Either::Left((result, _)) => {
+            ctx.mirrors.cancelled(mirror);
+            return result;
+        }
+        Either::Right(raced) => raced,
+    };
+    match hedged {
+        Ok(body) => {
+            drop(fetch);
+            ctx.mirrors.succeeded(mirror, None);
+            info!(logger, "hedge finished first"; "range" => &range, "url" => &hedge_url);
+            write_hedge(ctx, range, offset, claim, attempt, &body).await
+        }
+        Err(err) => {
+            ctx.mirrors.failed(mirror, false);
+            info!(logger, "hedge failed"; "range" => &range, "error" => format!("{:?}", &err));
+            fetch.await
+        }
     }
+}
 
-    writer.flush().await?;
+/// Fetch range after its first offset bytes into memory
+async fn fetch_range_body(
+    ctx: &FetchContext<'_>,
+    url: &str,
+    range: Range,
+    offset: u64,
+) -> Result<Vec<u8>> {
+    let _permits = acquire_connection(ctx.options, url).await;
 
-    info!(logger, "written"; "range" => &range, "path" => format!("{:?}", &path));
+    let mut res = request_range(ctx, url, range, offset, &mut Attempt::default()).await?;
+    let mut body = Vec::with_capacity((range.end - range.start + 1 - offset) as usize);
+    while let Some(chunk) = with_read_timeout(ctx.options.read_timeout, res.chunk()).await?? {
+        body.extend_from_slice(&chunk);
+        ctx.options
+            .measure(|metrics| metrics.bytes_downloaded(chunk.len() as u64));
+        if let Some(limiter) = ctx.limiter {
+            limiter.consume(chunk.len() as u64).await;
+        }
+    }
+    Ok(body)
+}
+
+/// Write the body fetched by a hedge which finished first. The abandoned
+/// request already hashed and counted its first attempt.written bytes,
+/// though they may not have reached the file, so all of body is written
+async fn write_hedge(
+    ctx: &FetchContext<'_>,
+    range: Range,
+    offset: u64,
+    claim: &Mutex<Claim>,
+    attempt: &mut Attempt,
+    body: &[u8],
+) -> Result<()> {
+    let start = range.start + offset;
+    let position = start + attempt.written;
+    let fresh = (body.len() as u64).saturating_sub(attempt.written);
+    let end = (attempt.written + claim.lock().unwrap().take(position, fresh)) as usize;
 
+    ctx.output.write_at(body[..end].to_vec(), start).await?;
+
+    let fresh = &body[attempt.written as usize..end];
+    if let Some(hasher) = ctx.hasher {
+        hasher.lock().unwrap().update(position, fresh);
+    }
+    attempt.written = end as u64;
+    attempt.flushed = true;
+    ctx.progress
+        .advance(range, offset + attempt.written, fresh.len() as u64);
     Ok(())
 }
@@ -0,0 +1,57 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How many times longer than expected a range may take before it is hedged
+const HEDGE_FACTOR: f64 = 3.0;
+
+#[derive(Debug, Default)]
+/// The throughput of the ranges completed so far, from which the
+/// time a range should take is predicted so stragglers can be hedged
+pub struct Hedging {
+    throughputs: Mutex<Vec<f64>>,
+}
+
+impl Hedging {
+    /// Track the throughput of a download's ranges
+    pub fn new() -> Hedging {
+        Hedging::default()
+    }
+
+    /// Record the throughput in bytes per second of a completed range
+    pub fn record(&self, throughput: f64) {
+        self.throughputs.lock().unwrap().push(throughput);
+    }
+
+    /// How long fetching bytes may take before a duplicate request is
+    /// sent, or None until a range has completed to compare against
+    pub fn deadline(&self, bytes: u64) -> Option<Duration> {
+        let mut throughputs = self.throughputs.lock().unwrap().clone();
+        if throughputs.is_empty() {
+            return None;
+        }
+        throughputs.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let median = throughputs[throughputs.len() / 2];
+        if median <= 0.0 {
+            return None;
+        }
+        Some(Duration::from_secs_f64(
+            bytes as f64 / median * HEDGE_FACTOR,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deadline_follows_median_throughput() {
+        let hedging = Hedging::new();
+        assert_eq!(hedging.deadline(1000), None);
+
+        hedging.record(100.0);
+        hedging.record(1000.0);
+        hedging.record(500.0);
+        assert_eq!(hedging.deadline(1000), Some(Duration::from_secs(6)));
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::errors::Result;
+
+#[derive(Debug)]
+/// A ledger of urls which have been successfully downloaded,
+/// stored as one url per line
+pub struct History {
+    path: PathBuf,
+    urls: HashSet<String>,
+}
+
+impl History {
+    /// Load a ledger from path, treating a missing file as empty
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<History> {
+        let path = path.as_ref().to_path_buf();
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        let urls = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect();
+
+        Ok(History { path, urls })
+    }
+
+    /// Whether url has already been successfully downloaded
+    pub fn contains(&self, url: &str) -> bool {
+        self.urls.contains(url)
+    }
+
+    /// Append a successfully downloaded url to the ledger
+    pub fn record(&mut self, url: &str) -> Result<()> {
+        if self.urls.contains(url) {
+            return Ok(());
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", url)?;
+        self.urls.insert(url.to_owned());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn load_missing_file_is_empty() {
+        let temp_dir = TempDir::new().unwrap();
+        let history = History::load(temp_dir.path().join("history")).unwrap();
+
+        assert!(!history.contains("https://test.com/big-image.jpg"));
+    }
+
+    #[test]
+    fn record_then_load() {
+        let temp_dir = TempDir::new().unwrap();
+        let ledger = temp_dir.path().join("history");
+
+        let mut history = History::load(&ledger).unwrap();
+        history.record("https://test.com/big-image.jpg").unwrap();
+        history.record("https://test.com/big-image.jpg").unwrap();
+        assert!(history.contains("https://test.com/big-image.jpg"));
+
+        let contents = fs::read_to_string(&ledger).unwrap();
+        assert_eq!(contents, "https://test.com/big-image.jpg\n");
+
+        let history = History::load(&ledger).unwrap();
+        assert!(history.contains("https://test.com/big-image.jpg"));
+        assert!(!history.contains("https://test.com/other.jpg"));
+    }
+}
@@ -0,0 +1,113 @@
+use reqwest::header::{
+    HeaderMap, ACCEPT_RANGES, AUTHORIZATION, CONTENT_LENGTH, ETAG, LAST_MODIFIED, LOCATION,
+};
+use reqwest::{RedirectPolicy, Url};
+use slog::debug;
+
+use crate::errors::{FetchError, Result};
+use crate::fetch::{client_builder, request_headers, store_cookies, FetchOptions};
+
+/// The most redirects followed, as many as reqwest follows by default
+const MAX_REDIRECTS: usize = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+/// What a server says about a file in answer to a HEAD request,
+/// returned by `inspect`
+pub struct ServerInfo {
+    /// Each redirect followed, as its status and the url which sent it
+    pub redirects: Vec<(u16, String)>,
+    /// The url which answered, after any redirects
+    pub url: String,
+    /// The status of the answer, which is reported rather than failed on
+    pub status: u16,
+    /// The Content-Length of the file, if given
+    pub content_length: Option<u64>,
+    /// The Accept-Ranges header, which is `bytes` when the file can be
+    /// fetched in parallel ranges
+    pub accept_ranges: Option<String>,
+    /// The ETag of the file, if it has one
+    pub etag: Option<String>,
+    /// The Last-Modified time of the file, if it has one
+    pub last_modified: Option<String>,
+}
+
+/// Send the url of options a HEAD request, as a download would, following
+/// redirects one at a time so that each can be reported, to see why a
+/// parallel fetch of it might fail
+pub async fn inspect(options: FetchOptions) -> Result<ServerInfo> {
+    let client = client_builder(&options)?
+        .redirect(RedirectPolicy::none())
+        .build()?;
+    let mut url = Url::parse(&options.url)
+        .map_err(|_| FetchError::InvalidArgumentsError(format!("Invalid url {}", options.url)))?;
+    let mut credentials = match &options.credentials {
+        Some(credentials) => Some(credentials.header_value()?),
+        None => None,
+    };
+    let mut redirects = Vec::new();
+    loop {
+        let mut headers = request_headers(&options, url.as_str());
+        if let Some(credentials) = &credentials {
+            headers.insert(AUTHORIZATION, credentials.clone());
+        }
+        let head = client.head(url.as_str()).headers(headers).send().await?;
+        store_cookies(&options, &head);
+        debug!(options.logger, "head"; "url" => url.as_str(), "status" => head.status().as_u16());
+
+        let location = head
+            .headers()
+            .get(LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .filter(|_| head.status().is_redirection());
+        let next = match location {
+            Some(location) => url.join(location).map_err(|_| {
+                FetchError::ServerSupportError(format!("Invalid redirect to {}", location))
+            })?,
+            None => {
+                return Ok(server_info(
+                    redirects,
+                    url,
+                    head.status().as_u16(),
+                    head.headers(),
+                ))
+            }
+        };
+        if redirects.len() == MAX_REDIRECTS {
+            return Err(FetchError::ServerSupportError(format!(
+                "More than {} redirects",
+                MAX_REDIRECTS
+            )));
+        }
+        // Credentials are not passed on to another host, as reqwest does
+        if next.host_str() != url.host_str() {
+            credentials = None;
+        }
+        redirects.push((head.status().as_u16(), url.to_string()));
+        url = next;
+    }
+}
+
+/// The info of the answer with headers and status which url gave
+/// after redirects
+fn server_info(
+    redirects: Vec<(u16, String)>,
+    url: Url,
+    status: u16,
+    headers: &HeaderMap,
+) -> ServerInfo {
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from)
+    };
+    ServerInfo {
+        redirects,
+        url: url.to_string(),
+        status,
+        content_length: header(CONTENT_LENGTH).and_then(|length| length.parse().ok()),
+        accept_ranges: header(ACCEPT_RANGES),
+        etag: header(ETAG),
+        last_modified: header(LAST_MODIFIED),
+    }
+}
@@ -0,0 +1,57 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::errors::{FetchError, Result};
+
+#[derive(Debug, Clone, Default)]
+/// A flag which stops the downloads sharing it once raised, as when the
+/// user presses Ctrl-C. Each range being fetched stops before writing its
+/// next chunk, keeping what it wrote, and the ranges written are recorded
+/// in the download's resume state so that a later resumable run fetches
+/// only the rest. The downloads then fail with an InterruptedError
+pub struct Interrupt {
+    raised: Arc<AtomicBool>,
+}
+
+impl Interrupt {
+    /// A flag which has not been raised
+    pub fn new() -> Interrupt {
+        Interrupt::default()
+    }
+
+    /// Raise the flag, stopping the downloads sharing it
+    pub fn interrupt(&self) {
+        self.raised.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the flag has been raised
+    pub fn is_interrupted(&self) -> bool {
+        self.raised.load(Ordering::SeqCst)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// What to do with what an interrupted download wrote
+pub enum OnInterrupt {
+    /// Keep the partial output and record the ranges written
+    /// so that a resumable run fetches only the rest
+    Keep,
+    /// Delete the partial output and any resume state
+    Delete,
+}
+
+impl FromStr for OnInterrupt {
+    type Err = FetchError;
+
+    fn from_str(s: &str) -> Result<OnInterrupt> {
+        match s {
+            "keep" => Ok(OnInterrupt::Keep),
+            "delete" => Ok(OnInterrupt::Delete),
+            _ => Err(FetchError::InvalidArgumentsError(format!(
+                "Unknown interrupt policy {}",
+                s
+            ))),
+        }
+    }
+}
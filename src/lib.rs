@@ -2,9 +2,84 @@
 
 //! Parallel Fetch !
 
+mod auth;
+mod bench;
+mod cache;
+mod clobber;
+mod concurrency;
+mod control;
+mod cookies;
+mod delta;
+mod encoding;
 mod errors;
+mod events;
 mod fetch;
+mod hedge;
+mod history;
+mod inspect;
+mod interrupt;
+mod limit;
+mod lock;
+mod logging;
+mod metadata;
+mod metalink;
+mod metrics;
+mod mirror;
+mod output;
+mod plan;
+mod postprocess;
+mod progress;
+mod proxy;
+mod reader;
+mod report;
+mod resume;
+mod retry;
+mod scheduler;
+mod throughput;
+mod tls;
+mod trace;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
 mod utils;
+mod verify;
+mod watch;
 
+pub use auth::Credentials;
+pub use bench::{benchmark, Benchmark};
+pub use clobber::Clobber;
+pub use control::{fetch_controlled, DownloadHandle, DownloadStatus, RangeStatus};
+pub use cookies::CookieJar;
+pub use delta::{DeltaIndex, DEFAULT_BLOCK_SIZE};
 pub use errors::{FetchError, Result};
-pub use fetch::{fetch, FetchOptions};
+pub use events::{fetch_with_events, DownloadEvent};
+pub use fetch::{
+    build_client, fetch, fetch_many, fetch_partial, fetch_to_vec, plan_download, FetchOptions,
+    FetchOptionsBuilder, Range,
+};
+pub use history::History;
+pub use inspect::{inspect, ServerInfo};
+pub use interrupt::{Interrupt, OnInterrupt};
+pub use limit::{ConnectionLimit, ConnectionPermit, FetchLimits, HostLimits, HostPermit};
+pub use logging::{JsonDrain, LogFormat};
+pub use metadata::Metadata;
+pub use metalink::{parse_metalink, MetalinkFile};
+pub use metrics::FetchMetrics;
+pub use output::{
+    ChunkSink, MemorySink, OrderedSink, OutputFile, SinkFuture, DEFAULT_ORDERED_WINDOW,
+};
+pub use plan::DownloadPlan;
+pub use postprocess::{decompress, Compression};
+pub use progress::{ProgressCallback, ProgressUpdate};
+pub use proxy::{parse_no_proxy, ProxyOptions};
+pub use reader::ParallelReader;
+pub use report::FetchReport;
+pub use retry::RetryPolicy;
+pub use scheduler::{ChunkScheduler, ScheduleState, StaticScheduler};
+pub use tls::{ClientCertificate, PublicKeyPin};
+pub use trace::TraceContext;
+pub use utils::{create_ranges, parse_byte_range, parse_header, parse_size, parse_url_list};
+pub use verify::{
+    find_checksum, header_checksums, Checksum, ChecksumAlgorithm, SignatureCheck, SignatureKind,
+    StreamingHasher,
+};
+pub use watch::fetch_watched;
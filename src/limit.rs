@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use futures_util::future::poll_fn;
+use reqwest::Url;
+use tokio_sync::semaphore::{Permit, Semaphore};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+/// The limits shared by every download run by `fetch_many`
+pub struct FetchLimits {
+    /// The most requests in flight across every download
+    pub connections: u64,
+    /// The most requests in flight to any one host, if limited
+    pub connections_per_host: Option<u64>,
+    /// Whether the first download to fail stops the others, rather than
+    /// leaving them to finish
+    pub fail_fast: bool,
+}
+
+impl FetchLimits {
+    /// Allow up to connections requests in flight, to any hosts
+    pub fn new(connections: u64) -> FetchLimits {
+        FetchLimits {
+            connections,
+            connections_per_host: None,
+            fail_fast: false,
+        }
+    }
+
+    /// Also allow at most connections requests in flight to each host
+    pub fn per_host(mut self, connections: u64) -> FetchLimits {
+        self.connections_per_host = Some(connections);
+        self
+    }
+
+    /// Also stop every download once one fails, by raising their
+    /// interrupts, so that each keeps or deletes what it wrote as when
+    /// interrupted and fails with an InterruptedError
+    pub fn fail_fast(mut self) -> FetchLimits {
+        self.fail_fast = true;
+        self
+    }
+}
+
+impl From<u64> for FetchLimits {
+    fn from(connections: u64) -> FetchLimits {
+        FetchLimits::new(connections)
+    }
+}
+
+/// Limits the number of requests in flight across every
+/// download sharing it, such as those run by `fetch_many`
+pub struct ConnectionLimit {
+    connections: usize,
+    semaphore: Semaphore,
+}
+
+impl ConnectionLimit {
+    /// Create a limit allowing connections requests at once
+    pub fn new(connections: usize) -> ConnectionLimit {
+        ConnectionLimit {
+            connections,
+            semaphore: Semaphore::new(connections),
+        }
+    }
+
+    /// Wait for a connection to become available, holding
+    /// it until the returned permit is dropped
+    pub async fn acquire(&self) -> ConnectionPermit<'_> {
+        let mut permit = Permit::new();
+        // The semaphore is never closed, so acquiring cannot fail
+        let _ = poll_fn(|cx| permit.poll_acquire(cx, &self.semaphore)).await;
+        ConnectionPermit {
+            permit,
+            limit: self,
+        }
+    }
+}
+
+impl fmt::Debug for ConnectionLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionLimit")
+            .field("connections", &self.connections)
+            .field("available", &self.semaphore.available_permits())
+            .finish()
+    }
+}
+
+/// Limits the number of requests in flight to each host,
+/// across every download sharing it
+pub struct HostLimits {
+    connections: usize,
+    hosts: Mutex<HashMap<String, Arc<ConnectionLimit>>>,
+}
+
+impl HostLimits {
+    /// Create a limit allowing connections requests to each host at once
+    pub fn new(connections: usize) -> HostLimits {
+        HostLimits {
+            connections,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Wait for a connection to the host of url to become
+    /// available, holding it until the returned permit is dropped
+    pub async fn acquire(&self, url: &str) -> HostPermit {
+        // Urls which cannot be parsed fail when requested, so share a limit
+        let host = Url::parse(url)
+            .ok()
+            .and_then(|url| {
+                Some(format!(
+                    "{}:{}",
+                    url.host_str()?,
+                    url.port_or_known_default()?
+                ))
+            })
+            .unwrap_or_default();
+        let limit = self
+            .hosts
+            .lock()
+            .unwrap()
+            .entry(host)
+            .or_insert_with(|| Arc::new(ConnectionLimit::new(self.connections)))
+            .clone();
+        let mut permit = Permit::new();
+        let _ = poll_fn(|cx| permit.poll_acquire(cx, &limit.semaphore)).await;
+        HostPermit { permit, limit }
+    }
+}
+
+impl fmt::Debug for HostLimits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HostLimits")
+            .field("connections", &self.connections)
+            .field("hosts", &self.hosts.lock().unwrap().keys())
+            .finish()
+    }
+}
+
+/// A connection to a host held from HostLimits
+pub struct HostPermit {
+    permit: Permit,
+    limit: Arc<ConnectionLimit>,
+}
+
+impl Drop for HostPermit {
+    fn drop(&mut self) {
+        self.permit.release(&self.limit.semaphore);
+    }
+}
+
+/// A connection held from a ConnectionLimit
+pub struct ConnectionPermit<'a> {
+    permit: Permit,
+    limit: &'a ConnectionLimit,
+}
+
+impl<'a> Drop for ConnectionPermit<'a> {
+    fn drop(&mut self) {
+        self.permit.release(&self.limit.semaphore);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn permits_are_released() {
+        let limit = ConnectionLimit::new(2);
+        let first = limit.acquire().await;
+        let second = limit.acquire().await;
+        assert_eq!(limit.semaphore.available_permits(), 0);
+
+        drop(first);
+        assert_eq!(limit.semaphore.available_permits(), 1);
+        drop(second);
+        assert_eq!(limit.semaphore.available_permits(), 2);
+    }
+
+    #[tokio::test]
+    async fn hosts_are_limited_separately() {
+        let limits = HostLimits::new(1);
+        let first = limits.acquire("http://example.com/a").await;
+        let _other = limits.acquire("http://example.org/a").await;
+        let host = limits.hosts.lock().unwrap()["example.com:80"].clone();
+        assert_eq!(host.semaphore.available_permits(), 0);
+
+        drop(first);
+        let _second = limits.acquire("http://example.com/b").await;
+        assert_eq!(limits.hosts.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,169 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::errors::{FetchError, Result};
+
+#[derive(Debug)]
+/// A lock file next to an output, held while a download writes it so that
+/// another process downloading to the same path fails instead of writing
+/// into it too. The lock file holds the id of the process which took it,
+/// and is removed once the lock is dropped
+pub struct OutputLock {
+    path: PathBuf,
+    /// The open lock file. On unix an advisory lock is held on it until it
+    /// is closed, which the system does too when the process exits
+    _file: File,
+}
+
+impl OutputLock {
+    /// The location of the lock file for an output path
+    pub fn lock_path(output: &Path) -> PathBuf {
+        let mut path = output.as_os_str().to_owned();
+        path.push(".lock");
+        PathBuf::from(path)
+    }
+
+    /// Lock output, failing if another process holds the lock. A lock file
+    /// left by a process which has since exited without removing it is no
+    /// longer locked, so it is taken over
+    #[cfg(unix)]
+    pub fn acquire(output: &Path) -> Result<OutputLock> {
+        use std::os::unix::fs::MetadataExt;
+        use std::os::unix::io::AsRawFd;
+
+        let path = OutputLock::lock_path(output);
+        loop {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)?;
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+                let err = io::Error::last_os_error();
+                if err.raw_os_error() == Some(libc::EWOULDBLOCK) {
+                    return Err(locked(output, &path));
+                }
+                return Err(err.into());
+            }
+
+            // The process holding the lock before may have removed the
+            // file since it was opened, leaving this one locking nothing
+            let opened = file.metadata()?;
+            match fs::metadata(&path) {
+                Ok(current) if (current.dev(), current.ino()) == (opened.dev(), opened.ino()) => {}
+                Ok(_) => continue,
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            }
+
+            file.set_len(0)?;
+            write!(file, "{}", std::process::id())?;
+            return Ok(OutputLock { path, _file: file });
+        }
+    }
+
+    /// Lock output, failing if another process holds the lock. Whether
+    /// that process is still running is not known, so a lock file left
+    /// behind must be removed by hand
+    #[cfg(not(unix))]
+    pub fn acquire(output: &Path) -> Result<OutputLock> {
+        let path = OutputLock::lock_path(output);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                write!(file, "{}", std::process::id())?;
+                Ok(OutputLock { path, _file: file })
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                Err(locked(output, &path))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+impl Drop for OutputLock {
+    fn drop(&mut self) {
+        // Removed while still locked, so no other process
+        // can lock the file once this one is gone
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The error for output being locked by another process, naming it
+/// if the lock file at path says which it is
+fn locked(output: &Path, path: &Path) -> FetchError {
+    let holder = fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| contents.trim().parse::<u32>().ok());
+    FetchError::LockedError(format!(
+        "Output {:?} is being written by another process{}, \
+         remove {:?} if it is not",
+        output,
+        holder.map(|pid| format!(" ({})", pid)).unwrap_or_default(),
+        path
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn second_lock_fails() {
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().join("out.bin");
+        let lock_path = OutputLock::lock_path(&output);
+
+        let lock = OutputLock::acquire(&output).unwrap();
+        assert_eq!(
+            fs::read_to_string(&lock_path).unwrap(),
+            std::process::id().to_string()
+        );
+        match OutputLock::acquire(&output) {
+            Err(FetchError::LockedError(_)) => {}
+            result => panic!("Expected a LockedError, got {:?}", result),
+        }
+        drop(lock);
+        assert!(!lock_path.exists());
+        OutputLock::acquire(&output).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stale_lock_is_taken_over() {
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().join("out.bin");
+        // Beyond the largest process id Linux allows
+        fs::write(OutputLock::lock_path(&output), "4194305").unwrap();
+        OutputLock::acquire(&output).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stale_lock_is_taken_over_once() {
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().join("out.bin");
+        for _ in 0..20 {
+            fs::write(OutputLock::lock_path(&output), "4194305").unwrap();
+            let barrier = std::sync::Barrier::new(8);
+            let acquired = std::thread::scope(|scope| {
+                let threads: Vec<_> = (0..8)
+                    .map(|_| {
+                        scope.spawn(|| {
+                            barrier.wait();
+                            OutputLock::acquire(&output)
+                        })
+                    })
+                    .collect();
+                threads
+                    .into_iter()
+                    .map(|thread| thread.join().unwrap())
+                    .collect::<Vec<_>>()
+            });
+            assert_eq!(acquired.iter().filter(|lock| lock.is_ok()).count(), 1);
+        }
+    }
+}
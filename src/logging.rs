@@ -0,0 +1,177 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use chrono::{SecondsFormat, Utc};
+use serde_json::{Map, Value};
+use slog::{Drain, Key, OwnedKVList, Record, KV};
+
+use crate::errors::FetchError;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// How log records are written
+pub enum LogFormat {
+    /// Human readable lines for a terminal
+    Terminal,
+    /// One JSON object per line, for log collectors
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = FetchError;
+
+    fn from_str(s: &str) -> Result<LogFormat, Self::Err> {
+        match s {
+            "terminal" => Ok(LogFormat::Terminal),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(FetchError::InvalidArgumentsError(format!(
+                "Unknown log format {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// A drain writing each record to writer as a line of JSON, holding its
+/// time, level and message along with its key-value pairs
+pub struct JsonDrain<W: Write> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write> JsonDrain<W> {
+    /// A drain writing to writer, which is usually stderr
+    pub fn new(writer: W) -> JsonDrain<W> {
+        JsonDrain {
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+impl<W: Write> Drain for JsonDrain<W> {
+    type Ok = ();
+    type Err = io::Error;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> io::Result<()> {
+        let mut fields = Map::new();
+        fields.insert(
+            "ts".to_owned(),
+            Value::from(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)),
+        );
+        fields.insert(
+            "level".to_owned(),
+            Value::from(record.level().as_str().to_lowercase()),
+        );
+        fields.insert("msg".to_owned(), Value::from(record.msg().to_string()));
+
+        // Pairs of the record take precedence over those of the logger
+        let mut serializer = JsonSerializer(&mut fields);
+        values.serialize(record, &mut serializer)?;
+        record.kv().serialize(record, &mut serializer)?;
+
+        let mut writer = self.writer.lock().unwrap();
+        serde_json::to_writer(&mut *writer, &fields)?;
+        writer.write_all(b"\n")?;
+        writer.flush()
+    }
+}
+
+/// Collects key-value pairs into a JSON object, keeping numbers and bools typed
+struct JsonSerializer<'a>(&'a mut Map<String, Value>);
+
+impl JsonSerializer<'_> {
+    fn insert(&mut self, key: Key, value: impl Into<Value>) -> slog::Result {
+        self.0.insert(key.to_string(), value.into());
+        Ok(())
+    }
+}
+
+impl slog::Serializer for JsonSerializer<'_> {
+    fn emit_arguments(&mut self, key: Key, val: &fmt::Arguments) -> slog::Result {
+        self.insert(key, val.to_string())
+    }
+
+    fn emit_str(&mut self, key: Key, val: &str) -> slog::Result {
+        self.insert(key, val)
+    }
+
+    fn emit_bool(&mut self, key: Key, val: bool) -> slog::Result {
+        self.insert(key, val)
+    }
+
+    fn emit_u64(&mut self, key: Key, val: u64) -> slog::Result {
+        self.insert(key, val)
+    }
+
+    fn emit_i64(&mut self, key: Key, val: i64) -> slog::Result {
+        self.insert(key, val)
+    }
+
+    fn emit_u32(&mut self, key: Key, val: u32) -> slog::Result {
+        self.insert(key, val)
+    }
+
+    fn emit_i32(&mut self, key: Key, val: i32) -> slog::Result {
+        self.insert(key, val)
+    }
+
+    fn emit_usize(&mut self, key: Key, val: usize) -> slog::Result {
+        self.insert(key, val as u64)
+    }
+
+    fn emit_f64(&mut self, key: Key, val: f64) -> slog::Result {
+        self.insert(key, val)
+    }
+
+    fn emit_unit(&mut self, key: Key) -> slog::Result {
+        self.insert(key, Value::Null)
+    }
+
+    fn emit_none(&mut self, key: Key) -> slog::Result {
+        self.insert(key, Value::Null)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slog::{info, o, Logger};
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn records_as_json_lines() {
+        let buffer = SharedBuffer::default();
+        let logger = Logger::root(
+            JsonDrain::new(buffer.clone()).fuse(),
+            o!("url" => "http://example.com"),
+        );
+        info!(logger, "written"; "bytes" => 10u64, "resumed" => false);
+        info!(logger, "finished"; "url" => "http://example.com/file");
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["level"], "info");
+        assert_eq!(lines[0]["msg"], "written");
+        assert_eq!(lines[0]["bytes"], 10);
+        assert_eq!(lines[0]["resumed"], false);
+        assert_eq!(lines[0]["url"], "http://example.com");
+        assert_eq!(lines[1]["url"], "http://example.com/file");
+    }
+}
@@ -1,86 +1,923 @@
-use clap::{value_t, App, Arg};
-use slog::{error, info};
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use futures::channel::mpsc::{unbounded, UnboundedReceiver};
+use futures::{join, StreamExt};
+use futures_util::future::join_all;
+use indicatif::{HumanBytes, HumanDuration, MultiProgress, ProgressBar, ProgressStyle};
+use reqwest::header::HeaderMap;
+use slog::{error, info, o, Discard, Drain, Duplicate, LevelFilter, Logger};
+use sloggers::file::FileLoggerBuilder;
 use sloggers::terminal::TerminalLoggerBuilder;
 use sloggers::types::Severity;
 use sloggers::Build;
+use tokio::timer::delay_for;
+
+use parallel_fetch::{
+    benchmark, build_client, fetch_many, fetch_watched, inspect, parse_metalink, parse_no_proxy,
+    parse_url_list, plan_download, Benchmark, Checksum, ClientCertificate, CookieJar, Credentials,
+    DownloadEvent, DownloadPlan, FetchError, FetchLimits, FetchOptions, History, Interrupt,
+    JsonDrain, LogFormat, OnInterrupt, OrderedSink, ProgressCallback, ProgressUpdate, ProxyOptions,
+    Range, Result, ServerInfo, SignatureCheck, DEFAULT_ORDERED_WINDOW,
+};
+
+mod args;
+mod client;
+mod daemon;
+mod window;
+
+use args::{Cli, Command};
+
+/// The size past which the log file is rotated
+const LOG_FILE_ROTATE_SIZE: u64 = 10 * 1024 * 1024;
 
-use parallel_fetch::{fetch, FetchOptions, Result};
+/// How many rotated log files are kept
+const LOG_FILE_ROTATE_KEEP: usize = 4;
+
+/// How often the downloads check whether a signal stopping them was received
+const SIGNAL_CHECK: Duration = Duration::from_millis(100);
+
+/// Set to the signal received by the SIGINT and SIGTERM handler
+static SIGNAL_RECEIVED: AtomicI32 = AtomicI32::new(0);
 
 #[tokio::main]
-pub async fn main() -> Result<()> {
-    let mut builder = TerminalLoggerBuilder::new();
-    builder.level(Severity::Info);
+pub async fn main() {
+    if let Err(err) = run().await {
+        eprintln!("Error: {:?}", err);
+        std::process::exit(err.exit_code());
+    }
+}
+
+/// Carry out the command line, returning the error it failed with
+async fn run() -> Result<()> {
+    let mut cli = Cli::parse();
 
-    let logger = builder.build().unwrap();
+    let json = cli.json;
+    let severity = match cli.verbose {
+        _ if cli.quiet => Severity::Error,
+        0 => Severity::Info,
+        1 => Severity::Debug,
+        _ => Severity::Trace,
+    };
+    let console = match cli.log_format {
+        _ if json => Logger::root(Discard, o!()),
+        LogFormat::Terminal => {
+            let mut builder = TerminalLoggerBuilder::new();
+            builder.level(severity);
+            builder.build().unwrap()
+        }
+        LogFormat::Json => {
+            let drain = LevelFilter::new(JsonDrain::new(io::stderr()), severity.as_level());
+            Logger::root(drain.fuse(), o!())
+        }
+    };
+    let logger = match &cli.log_file {
+        Some(path) => {
+            let mut builder = FileLoggerBuilder::new(path);
+            builder
+                .level(severity)
+                .rotate_size(LOG_FILE_ROTATE_SIZE)
+                .rotate_keep(LOG_FILE_ROTATE_KEEP);
+            let file = builder.build().map_err(|err| {
+                FetchError::InvalidArgumentsError(format!("Cannot log to {}: {}", path, err))
+            })?;
+            Logger::root(Duplicate::new(console, file).fuse(), o!())
+        }
+        None => console,
+    };
     info!(logger, "starting"; "version" => env!("CARGO_PKG_VERSION"));
 
-    let matches = App::new(env!("CARGO_PKG_NAME"))
-        .version(env!("CARGO_PKG_VERSION"))
-        .author(env!("CARGO_PKG_AUTHORS"))
-        .about(env!("CARGO_PKG_DESCRIPTION"))
-        .arg(
-            Arg::with_name("url")
-                .short("u")
-                .long("url")
-                .help("url to download")
-                .takes_value(true)
-                .required(true),
-        )
-        .arg(
-            Arg::with_name("output")
-                .short("o")
-                .long("output")
-                .help("file output location")
-                .takes_value(true),
-        )
-        .arg(
-            Arg::with_name("fetches")
-                .short("n")
-                .long("fetches")
-                .help("the number of parallel fetches to execute, defaults to 10")
-                .takes_value(true),
-        )
-        .arg(
-            Arg::with_name("max-retries")
-                .short("r")
-                .long("max-retries")
-                .help("the number of retry attempts to make on failed chunk downloads, defaults to 5")
-                .takes_value(true),
-        )
-        .arg(
-            Arg::with_name("check-etag")
-                .short("c")
-                .long("check-etag")
-                .help("whether to check the downloaded files md5 sum as a hex string against the server provided ETag")
-        )
-        .get_matches();
-
-    // unwrap is safe because url is required
-    let url = matches.value_of("url").unwrap().to_owned();
-
-    let output_option = matches.value_of("output").map(String::from);
-
-    let num_fetches = value_t!(matches.value_of("fetches"), u64).unwrap_or(10);
-
-    let max_retries = value_t!(matches.value_of("max-retries"), u64).unwrap_or(5);
-
-    let options = FetchOptions {
-        url,
-        output_option,
-        num_fetches,
-        logger: logger.clone(),
-        check_etag: matches.is_present("check-etag"),
-        max_retries,
+    let mut bench = None;
+    let downloads = match cli.command.take() {
+        Some(Command::Daemon {
+            listen,
+            max_downloads,
+            max_connections,
+            queue_file,
+        }) => return daemon::run(listen, max_downloads, max_connections, queue_file, logger).await,
+        Some(Command::Add {
+            daemon,
+            url,
+            output,
+            fetches,
+            priority,
+            start_at,
+            window,
+        }) => {
+            let id = client::add(
+                &daemon,
+                &url,
+                output.as_deref(),
+                fetches,
+                priority,
+                start_at.as_deref(),
+                window.as_deref(),
+            )
+            .await?;
+            println!("{}", id);
+            return Ok(());
+        }
+        Some(Command::Status { daemon, id }) => Some(client::status(&daemon, id).await?),
+        Some(Command::Cancel { daemon, id }) => Some(vec![client::cancel(&daemon, id).await?]),
+        Some(Command::Bench {
+            url,
+            sample_size,
+            max_fetches,
+            apply,
+        }) => {
+            cli.url = vec![url];
+            bench = Some((sample_size, max_fetches, apply));
+            None
+        }
+        None => None,
+    };
+    if let Some(downloads) = downloads {
+        for download in &downloads {
+            if json {
+                println!("{}", download);
+            } else {
+                println!("{}", client::describe(download));
+            }
+        }
+        return Ok(());
+    }
+
+    let mut urls = cli.url.clone();
+
+    match cli.input_file.as_deref() {
+        Some("-") => {
+            let mut contents = String::new();
+            io::stdin().read_to_string(&mut contents)?;
+            urls.extend(parse_url_list(&contents));
+        }
+        Some(path) => urls.extend(parse_url_list(&fs::read_to_string(path)?)),
+        None => {}
+    }
+
+    let output_option = cli.output.clone();
+
+    let max_connections = cli.max_connections.or(cli.fetches).unwrap_or(10);
+    let mut limits = FetchLimits::new(max_connections);
+    if let Some(per_host) = cli.max_connections_per_host {
+        limits = limits.per_host(per_host);
+    }
+    if cli.fail_fast {
+        limits = limits.fail_fast();
+    }
+
+    let connect_timeout = cli.connect_timeout.map(Duration::from_secs);
+
+    let read_timeout = cli.read_timeout.map(Duration::from_secs);
+
+    let on_interrupt = cli.on_interrupt;
+
+    let grace_period = Duration::from_secs(cli.grace_period);
+
+    let sync_interval = cli.sync_interval.map(Duration::from_secs);
+
+    let mut headers = HeaderMap::new();
+    for (name, value) in &cli.header {
+        headers.append(name, value.clone());
+    }
+
+    let credentials = if let Some(user) = &cli.user {
+        Some(user.clone())
+    } else if let Some(path) = &cli.bearer_token_file {
+        Some(Credentials::Bearer(
+            fs::read_to_string(path)?.trim().to_owned(),
+        ))
+    } else {
+        cli.bearer_token.clone().map(Credentials::Bearer)
+    };
+
+    let cookies = match &cli.load_cookies {
+        Some(path) => Some(Arc::new(CookieJar::load(path)?)),
+        None if cli.save_cookies.is_some() => Some(Arc::new(CookieJar::new())),
+        None => None,
+    };
+
+    let mut proxy = ProxyOptions::from_env();
+    proxy.url = cli.proxy.clone();
+    if let Some(user) = &cli.proxy_user {
+        proxy.credentials = Some(user.clone());
+    }
+    if let Some(hosts) = &cli.no_proxy {
+        proxy.no_proxy = parse_no_proxy(hosts);
+    }
+
+    let client_certificate = cli.cert.clone().map(|certificate| ClientCertificate {
+        certificate,
+        key: cli.key.clone(),
+        password: cli.cert_password.clone(),
+    });
+
+    let signature = cli.signature.map(|kind| SignatureCheck {
+        kind,
+        // unwrap is safe because signature requires public-key
+        public_key: cli.public_key.clone().unwrap(),
+        signature_file: cli.signature_file.clone(),
+    });
+
+    let watch = cli.watch.map(Duration::from_secs);
+
+    let show_progress = !cli.no_progress
+        && !cli.dry_run
+        && !cli.head_only
+        && !json
+        && watch.is_none()
+        && io::stdout().is_terminal();
+
+    let mut history = match &cli.history {
+        Some(path) => Some(History::load(path)?),
+        None => None,
+    };
+
+    if cli.skip_existing_history {
+        if let Some(history) = &history {
+            urls.retain(|url| {
+                let skip = history.contains(url);
+                if skip {
+                    info!(logger, "skipping url already in history"; "url" => url);
+                }
+                !skip
+            });
+        }
+    }
+
+    let metalink_files = match &cli.metalink {
+        Some(path) => parse_metalink(&fs::read_to_string(path)?)?,
+        None => Vec::new(),
+    };
+
+    let mut downloads: Vec<Download> = urls
+        .into_iter()
+        .map(|url| Download {
+            url,
+            output_option: output_option.clone(),
+            checksum: cli.checksum.clone(),
+            expected_size: cli.expected_size,
+            mirrors: cli.mirror.clone(),
+        })
+        .collect();
+
+    let several = downloads.len() + metalink_files.len() > 1;
+    for file in metalink_files {
+        let output_option = match &output_option {
+            Some(output) if !several && !Path::new(output).is_dir() => output.clone(),
+            Some(output) => Path::new(output)
+                .join(&file.name)
+                .to_string_lossy()
+                .into_owned(),
+            None => file.name.clone(),
+        };
+        let checksum = file.checksum().cloned();
+        let mut urls = file.urls.into_iter();
+        downloads.push(Download {
+            // parse_metalink rejects files without urls
+            url: urls.next().unwrap(),
+            output_option: Some(output_option),
+            checksum,
+            expected_size: file.size,
+            mirrors: urls.collect(),
+        });
+    }
+
+    let to_stdout = output_option.as_deref() == Some("-");
+    if several && bench.is_some() {
+        return Err(FetchError::InvalidArgumentsError(
+            "Only a single url can be benchmarked".to_owned(),
+        ));
+    }
+    if several && to_stdout {
+        return Err(FetchError::InvalidArgumentsError(
+            "Only a single url can be written to stdout".to_owned(),
+        ));
+    }
+    if json && to_stdout {
+        return Err(FetchError::InvalidArgumentsError(
+            "The JSON summary and the download cannot both be written to stdout".to_owned(),
+        ));
+    }
+
+    if several {
+        if let Some(output) = &output_option {
+            if !Path::new(output).is_dir() {
+                return Err(FetchError::InvalidArgumentsError(
+                    "Output must be a directory when downloading several urls".to_owned(),
+                ));
+            }
+        }
+        if cli.checksum.is_some() {
+            return Err(FetchError::InvalidArgumentsError(
+                "A checksum can only be given when downloading a single url".to_owned(),
+            ));
+        }
+        if cli.expected_size.is_some() {
+            return Err(FetchError::InvalidArgumentsError(
+                "An expected size can only be given when downloading a single url".to_owned(),
+            ));
+        }
+        if !cli.mirror.is_empty() {
+            return Err(FetchError::InvalidArgumentsError(
+                "Mirrors can only be given when downloading a single url".to_owned(),
+            ));
+        }
+    }
+
+    if watch.is_some() && (several || json || to_stdout) {
+        return Err(FetchError::InvalidArgumentsError(
+            "Only a single url downloaded to a file can be watched".to_owned(),
+        ));
+    }
+
+    let multi_progress = MultiProgress::new();
+    let mut events = Vec::new();
+    let interrupt = Interrupt::new();
+    watch_signals(interrupt.clone(), grace_period, logger.clone());
+
+    let sampling = Cell::new(false);
+    let mut build_options = |download: Download| {
+        // The sink writing to stdout takes the place of an output file
+        let output_option = if to_stdout {
+            None
+        } else {
+            download.output_option
+        };
+        let mut builder = FetchOptions::builder(download.url.clone())
+            .output_option(output_option)
+            .logger(logger.clone())
+            .interrupt(interrupt.clone())
+            .on_interrupt(on_interrupt)
+            .check_etag(cli.check_etag)
+            .max_retries(cli.max_retries)
+            .degraded_restart(cli.degraded_restart)
+            .resumable(cli.resumable)
+            .resume(cli.resume)
+            .allow_single_stream(cli.allow_single_stream)
+            .limit_rate(cli.limit_rate)
+            .connect_timeout(connect_timeout)
+            .read_timeout(read_timeout)
+            .max_retry_after(Duration::from_secs(cli.max_retry_after))
+            .retry_policy(cli.retry_policy)
+            .checksum(download.checksum)
+            .checksum_url(cli.checksum_url.clone())
+            .expected_size(download.expected_size)
+            .max_filesize(cli.max_filesize)
+            .expect_content_type(cli.expect_content_type.clone())
+            .signature(signature.clone())
+            .etag_part_size(cli.etag_part_size)
+            .verify_headers(cli.verify_headers)
+            .mirrors(download.mirrors)
+            .headers(headers.clone())
+            .credentials(credentials.clone())
+            .cookies(cookies.clone())
+            .trace(cli.traceparent.clone())
+            .proxy(proxy.clone())
+            .ca_certificate(cli.cacert.clone())
+            .insecure(cli.insecure)
+            .client_certificate(client_certificate.clone())
+            .public_key_pins(cli.pin_sha256.clone())
+            .probe_ranges(cli.probe_ranges)
+            .chunk_size(cli.chunk_size)
+            .min_split_size(cli.min_split_size)
+            .adaptive(cli.adaptive)
+            .split_slow_ranges(cli.split_slow_ranges)
+            .hedge(cli.hedge)
+            .preallocate(cli.preallocate)
+            .space_margin(cli.space_margin)
+            .write_buffer(cli.write_buffer.map(|size| size as usize))
+            .sync_interval(sync_interval)
+            .part_file(cli.part_file)
+            .clobber(cli.clobber)
+            .create_dirs(cli.create_dirs)
+            .preserve_timestamps(cli.preserve_timestamps)
+            .write_metadata(cli.write_metadata)
+            .force(cli.force)
+            .conditional(cli.conditional)
+            .cache(cli.cache_dir.clone().filter(|_| !cli.no_cache))
+            .delta_index(cli.delta_index.clone())
+            .restart_on_change(cli.restart_on_change)
+            .range(cli.range)
+            .compressed(cli.compressed)
+            .decompress(cli.decompress)
+            .keep_compressed(cli.keep_compressed);
+        // Samples fetched by the benchmark are neither shown nor summarised
+        if show_progress && !sampling.get() {
+            builder = builder.progress(progress_bars(&multi_progress, &download.url));
+        }
+        if json && !sampling.get() {
+            let (sender, receiver) = unbounded();
+            events.push(receiver);
+            builder = builder.events(sender);
+        }
+        if to_stdout {
+            builder = builder.sink(Arc::new(OrderedSink::new(
+                io::stdout(),
+                DEFAULT_ORDERED_WINDOW,
+            )));
+        }
+        // Without -n the number of fetches is chosen from the file size
+        if let Some(num_fetches) = cli.fetches {
+            builder = builder.num_fetches(num_fetches);
+        }
+        builder.build()
     };
 
-    match fetch(options).await {
-        Ok(_) => {
-            info!(logger, "Successfully downloaded");
-            Ok(())
+    if cli.head_only {
+        let mut answers = Vec::new();
+        for download in downloads {
+            let info = inspect(build_options(download)).await?;
+            if json {
+                answers.push(server_info_json(&info));
+            } else {
+                print!("{}", describe_server_info(&info));
+            }
+        }
+        if json {
+            println!("{}", serde_json::json!({ "downloads": answers }));
+        }
+        return Ok(());
+    }
+
+    if cli.dry_run {
+        let mut plans = Vec::new();
+        for download in downloads {
+            let plan = plan_download(build_options(download)).await?;
+            if json {
+                plans.push(plan_json(&plan));
+            } else {
+                print!("{}", describe_plan(&plan));
+            }
+        }
+        if json {
+            println!("{}", serde_json::json!({ "downloads": plans }));
+        }
+        return Ok(());
+    }
+
+    if let Some(interval) = watch {
+        // Only a single download is watched, if its url was not skipped
+        let download = match downloads.pop() {
+            Some(download) => download,
+            None => {
+                info!(logger, "no url left to watch");
+                return Ok(());
+            }
+        };
+        let result = fetch_watched(|| build_options(download.clone()), interval).await;
+        if let Err(FetchError::InterruptedError(_)) = result {
+            exit_interrupted(logger, on_interrupt);
+        }
+        if interrupt.is_interrupted() {
+            info!(logger, "stopped watching");
+            drop(logger);
+            std::process::exit(signal_exit_code());
+        }
+        return result;
+    }
+
+    let mut best_fetches = None;
+    if let Some((sample_size, max_fetches, apply)) = bench {
+        let download = match downloads.first() {
+            Some(download) => download.clone(),
+            None => {
+                info!(logger, "no url left to benchmark");
+                return Ok(());
+            }
+        };
+        sampling.set(true);
+        let benchmark =
+            benchmark(|| build_options(download.clone()), sample_size, max_fetches).await?;
+        sampling.set(false);
+        best_fetches = benchmark.best_fetches();
+        if json {
+            println!("{}", benchmark_json(&benchmark));
+        } else {
+            print!("{}", describe_benchmark(&benchmark));
+        }
+        if !apply {
+            return Ok(());
+        }
+    }
+
+    let mut options: Vec<FetchOptions> = downloads.into_iter().map(build_options).collect();
+    if let Some(num_fetches) = best_fetches {
+        for options in &mut options {
+            options.num_fetches = num_fetches;
+            options.auto_fetches = false;
+        }
+    }
+
+    let urls: Vec<String> = options.iter().map(|options| options.url.clone()).collect();
+
+    // Every url shares one client, and with it a connection pool
+    if let Some(first) = options.first() {
+        let client = build_client(first)?;
+        for options in &mut options {
+            options.client = Some(client.clone());
+        }
+    }
+
+    let start = Instant::now();
+    let summaries = join_all(events.into_iter().map(|events| summarise(events, start)));
+    let (results, summaries) = join!(fetch_many(options, limits), summaries);
+    let results = results?;
+
+    if let (Some(jar), Some(path)) = (&cookies, &cli.save_cookies) {
+        jar.save(path)?;
+    }
+
+    let mut errors = Vec::new();
+    let mut outcomes = Vec::new();
+    let mut reports = Vec::new();
+    for (index, (url, result)) in urls.iter().zip(results).enumerate() {
+        if let Some(summary) = summaries.get(index) {
+            reports.push(summary.report(url, result.as_ref().err()));
+        }
+        match result {
+            Ok(_) => {
+                info!(logger, "Successfully downloaded"; "url" => url);
+                if let Some(history) = &mut history {
+                    history.record(url)?;
+                }
+                outcomes.push((url, None));
+            }
+            Err(err) => {
+                error!(logger, "download failed"; "url" => url, "error" => format!("{:?}", &err));
+                outcomes.push((url, Some(err.to_string())));
+                errors.push(err);
+            }
+        }
+    }
+
+    if urls.len() > 1 {
+        info!(logger, "finished"; "succeeded" => urls.len() - errors.len(), "failed" => errors.len());
+        for (url, error) in &outcomes {
+            match error {
+                Some(error) => error!(logger, "failed"; "url" => url, "error" => error),
+                None => info!(logger, "succeeded"; "url" => url),
+            }
+        }
+    }
+
+    if json {
+        println!("{}", serde_json::json!({ "downloads": reports }));
+    }
+
+    // The interrupt is also raised by --fail-fast, which exits as the
+    // download which failed did
+    if SIGNAL_RECEIVED.load(Ordering::SeqCst) != 0 {
+        exit_interrupted(logger, on_interrupt);
+    }
+
+    let stopped = |err: &FetchError| matches!(err.inner(), FetchError::InterruptedError(_));
+    let index = errors.iter().position(|err| !stopped(err)).unwrap_or(0);
+    match errors.into_iter().nth(index) {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+/// Exit after the downloads were stopped by a signal,
+/// saying what became of what they wrote
+fn exit_interrupted(logger: Logger, on_interrupt: OnInterrupt) -> ! {
+    match on_interrupt {
+        OnInterrupt::Keep => error!(
+            logger,
+            "interrupted, run again with --resumable to carry on"
+        ),
+        OnInterrupt::Delete => error!(logger, "interrupted, partial downloads deleted"),
+    }
+    // Exiting skips destructors, so the log is flushed first
+    drop(logger);
+    std::process::exit(signal_exit_code());
+}
+
+/// Stop the downloads sharing interrupt on Ctrl-C or on SIGTERM, as sent
+/// when a container is stopped, so that each stops before writing its next
+/// chunk and keeps or deletes what it wrote. If they have not stopped
+/// after grace_period, or another signal is received, exit regardless
+fn watch_signals(interrupt: Interrupt, grace_period: Duration, logger: Logger) {
+    #[cfg(unix)]
+    {
+        extern "C" fn on_signal(signal: libc::c_int) {
+            if SIGNAL_RECEIVED.swap(signal, Ordering::SeqCst) != 0 {
+                // Only async-signal-safe calls may be made here
+                unsafe { libc::_exit(128 + signal) };
+            }
+        }
+        let handler: extern "C" fn(libc::c_int) = on_signal;
+        for &signal in &[libc::SIGINT, libc::SIGTERM] {
+            unsafe { libc::signal(signal, handler as libc::sighandler_t) };
+        }
+    }
+
+    tokio::spawn(async move {
+        while SIGNAL_RECEIVED.load(Ordering::SeqCst) == 0 {
+            delay_for(SIGNAL_CHECK).await;
         }
-        Err(err) => {
-            error!(logger, "download failed"; "error" => format!("{:?}", &err));
-            Err(err)
+        info!(logger, "stopping downloads"; "signal" => SIGNAL_RECEIVED.load(Ordering::SeqCst), "grace_period" => format!("{:?}", grace_period));
+        // The log is only flushed once every logger is dropped
+        drop(logger);
+        interrupt.interrupt();
+        delay_for(grace_period).await;
+        std::process::exit(signal_exit_code());
+    });
+}
+
+/// The exit code after a signal stopped the downloads, as a shell
+/// reports a process killed by it, such as 130 after SIGINT
+fn signal_exit_code() -> i32 {
+    128 + SIGNAL_RECEIVED.load(Ordering::SeqCst)
+}
+
+#[derive(Debug, Default)]
+/// What a download did, gathered from its events for the JSON summary
+struct Summary {
+    output: Option<PathBuf>,
+    bytes: u64,
+    duration: Duration,
+    /// The retries of each range which needed any, by the start of the range
+    retries: BTreeMap<u64, (Range, u64)>,
+    checksums: Vec<String>,
+    signature: bool,
+}
+
+/// Gather the events of a download started at start
+/// until it finishes and drops its sender
+async fn summarise(mut events: UnboundedReceiver<DownloadEvent>, start: Instant) -> Summary {
+    let mut summary = Summary::default();
+    while let Some(event) = events.next().await {
+        match event {
+            DownloadEvent::ChunkProgress { downloaded, .. } => summary.bytes = downloaded,
+            DownloadEvent::ChunkRetried {
+                range, attempts, ..
+            } => {
+                summary.retries.insert(range.start, (range, attempts));
+            }
+            DownloadEvent::Verified {
+                checksums,
+                signature,
+            } => {
+                summary.checksums = checksums.iter().map(ToString::to_string).collect();
+                summary.signature = signature;
+            }
+            DownloadEvent::Saved { path } => summary.output = Some(path),
+            _ => {}
         }
     }
+    summary.duration = start.elapsed();
+    summary
+}
+
+impl Summary {
+    fn report(&self, url: &str, error: Option<&FetchError>) -> serde_json::Value {
+        let seconds = self.duration.as_secs_f64();
+        let retries: Vec<_> = self
+            .retries
+            .values()
+            .map(|(range, retries)| {
+                serde_json::json!({
+                    "start": range.start,
+                    "end": range.end,
+                    "retries": retries,
+                })
+            })
+            .collect();
+        serde_json::json!({
+            "url": url,
+            "output": self.output,
+            "bytes": self.bytes,
+            "duration": seconds,
+            "speed": if seconds > 0.0 { self.bytes as f64 / seconds } else { 0.0 },
+            "retries": retries,
+            "checksums": self.checksums,
+            "signature": self.signature,
+            "error": error.map(ToString::to_string),
+        })
+    }
+}
+
+/// A benchmark as lines for a terminal: the throughput of each number
+/// of fetches, then the number recommended
+fn describe_benchmark(benchmark: &Benchmark) -> String {
+    let mut lines: Vec<String> = benchmark
+        .throughputs
+        .iter()
+        .map(|&(num_fetches, throughput)| {
+            format!(
+                "{:>4} fetches {:>12}/s",
+                num_fetches,
+                HumanBytes(throughput as u64).to_string()
+            )
+        })
+        .collect();
+    if let Some(best) = benchmark.best_fetches() {
+        lines.push(format!("fastest with --fetches {}", best));
+    }
+    lines.join("\n") + "\n"
+}
+
+/// A benchmark as JSON, for bench with --json
+fn benchmark_json(benchmark: &Benchmark) -> serde_json::Value {
+    let throughputs: Vec<_> = benchmark
+        .throughputs
+        .iter()
+        .map(|(num_fetches, throughput)| {
+            serde_json::json!({ "fetches": num_fetches, "throughput": throughput })
+        })
+        .collect();
+    serde_json::json!({
+        "throughputs": throughputs,
+        "best_fetches": benchmark.best_fetches(),
+    })
+}
+
+/// What a server answered as lines for a terminal: each redirect
+/// followed, then the answer and its headers
+fn describe_server_info(info: &ServerInfo) -> String {
+    let mut lines: Vec<String> = info
+        .redirects
+        .iter()
+        .map(|(status, url)| format!("{} {}", status, url))
+        .collect();
+    lines.push(format!("{} {}", info.status, info.url));
+    // Accept-Ranges may itself be none
+    let header = |value: &Option<String>| value.clone().unwrap_or_else(|| "not sent".to_owned());
+    lines.push(format!(
+        "  Content-Length: {}",
+        header(&info.content_length.map(|length| length.to_string()))
+    ));
+    lines.push(format!("  Accept-Ranges: {}", header(&info.accept_ranges)));
+    lines.push(format!("  ETag: {}", header(&info.etag)));
+    lines.push(format!("  Last-Modified: {}", header(&info.last_modified)));
+    lines.join("\n") + "\n"
+}
+
+/// What a server answered as JSON, for --head-only with --json
+fn server_info_json(info: &ServerInfo) -> serde_json::Value {
+    let redirects: Vec<_> = info
+        .redirects
+        .iter()
+        .map(|(status, url)| serde_json::json!({ "status": status, "url": url }))
+        .collect();
+    serde_json::json!({
+        "redirects": redirects,
+        "url": info.url,
+        "status": info.status,
+        "content_length": info.content_length,
+        "accept_ranges": info.accept_ranges,
+        "etag": info.etag,
+        "last_modified": info.last_modified,
+    })
+}
+
+/// A plan as lines for a terminal: the url and output, then each range
+/// and what the output would be verified against
+fn describe_plan(plan: &DownloadPlan) -> String {
+    let mut lines = vec![plan.url.clone()];
+    lines.push(match &plan.path {
+        Some(path) => format!("  output {}", path.display()),
+        None => "  output skipped or written to stdout".to_owned(),
+    });
+    lines.push(format!(
+        "  length {}, ETag {}, Last-Modified {}",
+        plan.total_length
+            .map_or("unknown".to_owned(), |length| format!("{} bytes", length)),
+        plan.etag.as_deref().unwrap_or("none"),
+        plan.last_modified.as_deref().unwrap_or("none"),
+    ));
+    if let Some(reason) = &plan.single_stream {
+        lines.push(format!("  single stream: {}", reason));
+    }
+    lines.push(format!(
+        "  {} ranges over {} fetches",
+        plan.ranges.len(),
+        plan.num_fetches
+    ));
+    for range in &plan.ranges {
+        lines.push(format!(
+            "    {:>12}-{:<12} {:>12} bytes",
+            range.start,
+            range.end,
+            range.end - range.start + 1
+        ));
+    }
+    let mut checks: Vec<String> = plan
+        .checksums
+        .iter()
+        .map(|checksum| format!("{} checksum", checksum.algorithm))
+        .collect();
+    checks.extend(
+        plan.checksum_url
+            .iter()
+            .map(|url| format!("checksum listed in {}", url)),
+    );
+    checks.extend(
+        plan.etag_check
+            .map(|algorithm| format!("ETag as {}", algorithm)),
+    );
+    checks.extend(
+        plan.signature_url
+            .iter()
+            .map(|url| format!("signature from {}", url)),
+    );
+    if checks.is_empty() {
+        checks.push("nothing".to_owned());
+    }
+    lines.push(format!("  verify {}", checks.join(", ")));
+    lines.join("\n") + "\n"
+}
+
+/// A plan as JSON, for --dry-run with --json
+fn plan_json(plan: &DownloadPlan) -> serde_json::Value {
+    let ranges: Vec<_> = plan
+        .ranges
+        .iter()
+        .map(|range| serde_json::json!({ "start": range.start, "end": range.end }))
+        .collect();
+    let checksums: Vec<_> = plan
+        .checksums
+        .iter()
+        .map(|checksum| format!("{}:{}", checksum.algorithm, hex::encode(&checksum.digest)))
+        .collect();
+    serde_json::json!({
+        "url": plan.url,
+        "output": plan.path,
+        "length": plan.total_length,
+        "etag": plan.etag,
+        "last_modified": plan.last_modified,
+        "single_stream": plan.single_stream,
+        "fetches": plan.num_fetches,
+        "ranges": ranges,
+        "checksums": checksums,
+        "checksum_url": plan.checksum_url,
+        "etag_check": plan.etag_check.map(|algorithm| algorithm.to_string()),
+        "signature_url": plan.signature_url,
+    })
+}
+
+#[derive(Clone)]
+/// The source and destination of one file to download
+struct Download {
+    url: String,
+    output_option: Option<String>,
+    checksum: Option<Checksum>,
+    expected_size: Option<u64>,
+    mirrors: Vec<String>,
+}
+
+/// Build a progress callback which draws a bar for each
+/// range being fetched below an aggregate bar for the download of url.
+/// A range's bar is cleared once it completes
+fn progress_bars(multi: &MultiProgress, url: &str) -> ProgressCallback {
+    let multi = multi.clone();
+
+    let total = multi.add(ProgressBar::new(0));
+    total.set_style(
+        ProgressStyle::default_bar()
+            .template("{prefix} {bar:40} {percent:>3}% {bytes}/{total_bytes} {bytes_per_sec} {msg}")
+            .unwrap(),
+    );
+    total.set_prefix(url.to_owned());
+
+    let range_style = ProgressStyle::default_bar()
+        .template("{prefix:>24} {bar:40} {bytes}/{total_bytes}")
+        .unwrap();
+    let bars = Mutex::new(HashMap::new());
+
+    Box::new(move |update: ProgressUpdate| {
+        total.set_length(update.total);
+        total.set_position(update.downloaded);
+        if let Some(eta) = update.eta {
+            total.set_message(format!("{} left", HumanDuration(eta)));
+        }
+
+        let range = update.range;
+        let range_length = range.end - range.start + 1;
+
+        let mut bars = bars.lock().unwrap();
+        let bar = bars.entry(range).or_insert_with(|| {
+            let bar = multi.add(ProgressBar::new(range_length));
+            bar.set_style(range_style.clone());
+            bar.set_prefix(format!("{}-{}", range.start, range.end));
+            bar
+        });
+        bar.set_position(update.range_downloaded);
+
+        if update.range_downloaded >= range_length {
+            bar.finish_and_clear();
+            bars.remove(&range);
+        }
+        if update.downloaded >= update.total {
+            total.finish();
+        }
+    })
 }
@@ -0,0 +1,129 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use serde_json::{json, Map, Value};
+
+use crate::errors::{FetchError, Result};
+use crate::verify::ChecksumAlgorithm;
+
+#[derive(Debug, Clone, PartialEq)]
+/// Where a download came from and what was received, saved next to
+/// the output so later runs and other tools can judge its freshness
+pub struct Metadata {
+    /// The url the download was requested from
+    pub url: String,
+    /// The url the server answered from, after any redirects
+    pub final_url: String,
+    /// The ETag of the content, if the server sent one
+    pub etag: Option<String>,
+    /// The Last-Modified time of the content, if the server sent one
+    pub last_modified: Option<String>,
+    /// The length of the output in bytes
+    pub size: u64,
+    /// The hex digests of the output, by algorithm
+    pub digests: Vec<(ChecksumAlgorithm, String)>,
+}
+
+impl Metadata {
+    /// The file the metadata of a download to output is saved
+    /// in, named output with `.meta.json` appended
+    pub fn path(output: &Path) -> PathBuf {
+        let mut path = output.as_os_str().to_owned();
+        path.push(".meta.json");
+        PathBuf::from(path)
+    }
+
+    /// Load the metadata saved next to output,
+    /// or None if there is no metadata file
+    pub fn load(output: &Path) -> Result<Option<Metadata>> {
+        let path = Metadata::path(output);
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let invalid = || FetchError::ValidationError(format!("Invalid metadata file {:?}", path));
+        let metadata: Value = serde_json::from_str(&contents).map_err(|_| invalid())?;
+        let text = |key: &str| metadata[key].as_str().map(String::from);
+        // Digests of algorithms which cannot be named back, such as
+        // multipart ETags, are left out
+        let digests = match metadata["digests"].as_object() {
+            Some(digests) => digests
+                .iter()
+                .filter_map(|(algorithm, digest)| {
+                    Some((algorithm.parse().ok()?, digest.as_str()?.to_owned()))
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        Ok(Some(Metadata {
+            url: text("url").ok_or_else(invalid)?,
+            final_url: text("final_url").ok_or_else(invalid)?,
+            etag: text("etag"),
+            last_modified: text("last_modified"),
+            size: metadata["size"].as_u64().ok_or_else(invalid)?,
+            digests,
+        }))
+    }
+
+    /// Save the metadata next to output, recording when it was fetched
+    pub fn save(&self, output: &Path) -> Result<()> {
+        let digests: Map<String, Value> = self
+            .digests
+            .iter()
+            .map(|(algorithm, digest)| (algorithm.to_string(), Value::from(digest.as_str())))
+            .collect();
+        let metadata = json!({
+            "url": self.url,
+            "final_url": self.final_url,
+            "etag": self.etag,
+            "last_modified": self.last_modified,
+            "size": self.size,
+            "digests": digests,
+            "fetched": Utc::now().to_rfc3339(),
+        });
+        fs::write(
+            Metadata::path(output),
+            serde_json::to_string_pretty(&metadata).unwrap_or_default(),
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn save_next_to_output() {
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().join("file.bin");
+        let metadata = Metadata {
+            url: "http://example.com/file".to_owned(),
+            final_url: "http://cdn.example.com/file".to_owned(),
+            etag: Some("\"abc\"".to_owned()),
+            last_modified: None,
+            size: 10,
+            digests: vec![(ChecksumAlgorithm::Sha256, "00ff".to_owned())],
+        };
+        metadata.save(&output).unwrap();
+
+        let saved: Value = serde_json::from_str(
+            &fs::read_to_string(temp_dir.path().join("file.bin.meta.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(saved["final_url"], "http://cdn.example.com/file");
+        assert_eq!(saved["etag"], "\"abc\"");
+        assert_eq!(saved["last_modified"], Value::Null);
+        assert_eq!(saved["size"], 10);
+        assert_eq!(saved["digests"]["sha256"], "00ff");
+        assert_eq!(Metadata::load(&output).unwrap(), Some(metadata));
+        assert_eq!(
+            Metadata::load(&temp_dir.path().join("other.bin")).unwrap(),
+            None
+        );
+    }
+}
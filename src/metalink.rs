@@ -0,0 +1,174 @@
+use std::path::Path;
+
+use roxmltree::{Document, Node};
+
+use crate::errors::{FetchError, Result};
+use crate::verify::{Checksum, ChecksumAlgorithm};
+
+#[derive(Debug, Clone, PartialEq)]
+/// A file described by a Metalink 4 (`.meta4`) document
+pub struct MetalinkFile {
+    /// The name to save the file as
+    pub name: String,
+    /// The size of the file, if given
+    pub size: Option<u64>,
+    /// The hashes of the file in supported algorithms,
+    /// strongest first
+    pub checksums: Vec<Checksum>,
+    /// The urls serving the file, most preferred first
+    pub urls: Vec<String>,
+}
+
+impl MetalinkFile {
+    /// The strongest hash of the file, if any
+    pub fn checksum(&self) -> Option<&Checksum> {
+        self.checksums.first()
+    }
+}
+
+/// Parse the files described by a Metalink 4 document
+pub fn parse_metalink(xml: &str) -> Result<Vec<MetalinkFile>> {
+    let document = Document::parse(xml).map_err(|err| {
+        FetchError::InvalidArgumentsError(format!("Metalink could not be parsed: {}", err))
+    })?;
+
+    let root = document.root_element();
+    if root.tag_name().name() != "metalink" {
+        return Err(FetchError::InvalidArgumentsError(
+            "Metalink root element is not metalink".to_owned(),
+        ));
+    }
+
+    elements(root, "file").map(parse_file).collect()
+}
+
+fn parse_file(file: Node<'_, '_>) -> Result<MetalinkFile> {
+    let name = file
+        .attribute("name")
+        .and_then(|name| Path::new(name).file_name())
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| {
+            FetchError::InvalidArgumentsError("Metalink file has no valid name".to_owned())
+        })?
+        .to_owned();
+
+    let size = match elements(file, "size").next().and_then(|size| size.text()) {
+        Some(size) => Some(size.trim().parse().map_err(|_| {
+            FetchError::InvalidArgumentsError(format!("Metalink size {} is not a number", size))
+        })?),
+        None => None,
+    };
+
+    let mut checksums: Vec<Checksum> = elements(file, "hash")
+        .filter_map(|hash| {
+            let algorithm = match hash.attribute("type")? {
+                "md5" => ChecksumAlgorithm::Md5,
+                "sha-1" => ChecksumAlgorithm::Sha1,
+                "sha-256" => ChecksumAlgorithm::Sha256,
+                "sha-512" => ChecksumAlgorithm::Sha512,
+                _ => return None,
+            };
+            let digest = hex::decode(hash.text()?.trim()).ok()?;
+            Some(Checksum { algorithm, digest })
+        })
+        .collect();
+    checksums.sort_by_key(|checksum| strength(checksum.algorithm));
+
+    // Urls without a priority come after those with one
+    let mut urls: Vec<(u64, String)> = elements(file, "url")
+        .filter_map(|url| {
+            let priority = url
+                .attribute("priority")
+                .and_then(|priority| priority.parse().ok())
+                .unwrap_or(u64::MAX);
+            Some((priority, url.text()?.trim().to_owned()))
+        })
+        .collect();
+    urls.sort_by_key(|(priority, _)| *priority);
+    let urls: Vec<String> = urls.into_iter().map(|(_, url)| url).collect();
+
+    if urls.is_empty() {
+        return Err(FetchError::InvalidArgumentsError(format!(
+            "Metalink file {} has no urls",
+            name
+        )));
+    }
+
+    Ok(MetalinkFile {
+        name,
+        size,
+        checksums,
+        urls,
+    })
+}
+
+/// The child elements of node named name, in any namespace
+fn elements<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |child| child.is_element() && child.tag_name().name() == name)
+}
+
+/// Orders algorithms strongest first
+fn strength(algorithm: ChecksumAlgorithm) -> u8 {
+    match algorithm {
+        ChecksumAlgorithm::Sha512 => 0,
+        ChecksumAlgorithm::Blake3 => 1,
+        ChecksumAlgorithm::Sha256 => 2,
+        ChecksumAlgorithm::Sha1 => 3,
+        ChecksumAlgorithm::Md5 | ChecksumAlgorithm::Md5Multipart(_) => 4,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METALINK: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<metalink xmlns="urn:ietf:params:xml:ns:metalink">
+  <file name="../release.bin">
+    <size>5</size>
+    <hash type="md5">8b1a9953c4611296a827abf8c47804d7</hash>
+    <hash type="sha-256">185f8db32271fe25f561a6fc938b2e264306ec304eda518007d1764826381969</hash>
+    <hash type="crc32">00000000</hash>
+    <url>http://c.example/release.bin</url>
+    <url priority="2">http://b.example/release.bin</url>
+    <url priority="1">http://a.example/release.bin</url>
+  </file>
+</metalink>"#;
+
+    #[test]
+    fn parse_file_entries() {
+        let files = parse_metalink(METALINK).unwrap();
+        assert_eq!(files.len(), 1);
+
+        let file = &files[0];
+        assert_eq!(file.name, "release.bin");
+        assert_eq!(file.size, Some(5));
+        assert_eq!(
+            file.urls,
+            vec![
+                "http://a.example/release.bin",
+                "http://b.example/release.bin",
+                "http://c.example/release.bin"
+            ]
+        );
+        assert_eq!(file.checksums.len(), 2);
+        assert_eq!(
+            file.checksum().map(|checksum| checksum.algorithm),
+            Some(ChecksumAlgorithm::Sha256)
+        );
+    }
+
+    #[test]
+    fn reject_other_documents() {
+        let error = parse_metalink("<html></html>").expect_err("testing");
+        if let FetchError::InvalidArgumentsError(msg) = error {
+            assert_eq!(msg, "Metalink root element is not metalink");
+        } else {
+            panic!("Expected InvalidArgumentsError");
+        }
+    }
+}
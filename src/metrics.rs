@@ -0,0 +1,24 @@
+use std::time::Duration;
+
+use crate::fetch::Range;
+
+/// Receives measurements of downloads as they happen, so that they can be
+/// recorded in a metrics backend such as Prometheus or StatsD. Every method
+/// does nothing by default, so an implementation need only provide those
+/// it records. Methods are called from the tasks fetching ranges, and
+/// should return quickly
+pub trait FetchMetrics: Send + Sync {
+    /// Count a request sent to url, including probes, range requests and
+    /// fetches of checksums and signatures
+    fn request_issued(&self, _url: &str) {}
+
+    /// Count bytes received from the network, before any decompression
+    fn bytes_downloaded(&self, _bytes: u64) {}
+
+    /// Count a failed attempt at fetching range which will be retried
+    fn chunk_retried(&self, _range: Range) {}
+
+    /// Observe how long an attempt at fetching range took to succeed,
+    /// from sending its request to writing its last byte
+    fn chunk_latency(&self, _range: Range, _latency: Duration) {}
+}
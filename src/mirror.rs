@@ -0,0 +1,169 @@
+use std::sync::Mutex;
+
+/// The consecutive failures after which a mirror is demoted
+const MAX_FAILURES: u32 = 3;
+
+/// The fraction of the fastest mirror's throughput below
+/// which a mirror is demoted as too slow
+const SLOW_FRACTION: f64 = 0.25;
+
+/// The weight of the newest sample in a mirror's throughput average
+const THROUGHPUT_WEIGHT: f64 = 0.5;
+
+#[derive(Debug)]
+struct Mirror {
+    url: String,
+    in_flight: usize,
+    failures: u32,
+    throughput: Option<f64>,
+    demoted: bool,
+}
+
+#[derive(Debug)]
+/// The source urls of a download, which must all serve the same
+/// content, along with the health of each. Ranges are spread across
+/// healthy mirrors, and mirrors which keep failing or are much slower
+/// than the others are demoted so their ranges go elsewhere
+pub struct Mirrors {
+    mirrors: Mutex<Vec<Mirror>>,
+}
+
+impl Mirrors {
+    /// Track urls, the first of which is preferred when all else is equal
+    pub fn new<I: IntoIterator<Item = String>>(urls: I) -> Mirrors {
+        let mirrors = urls
+            .into_iter()
+            .map(|url| Mirror {
+                url,
+                in_flight: 0,
+                failures: 0,
+                throughput: None,
+                demoted: false,
+            })
+            .collect();
+        Mirrors {
+            mirrors: Mutex::new(mirrors),
+        }
+    }
+
+    /// Choose the mirror to fetch the next range from, preferring healthy
+    /// mirrors with the fewest recent failures and ranges in flight.
+    /// Returns its index and url
+    pub fn pick(&self) -> (usize, String) {
+        let mut mirrors = self.mirrors.lock().unwrap();
+        let (index, mirror) = mirrors
+            .iter_mut()
+            .enumerate()
+            .min_by_key(|(_, mirror)| (mirror.demoted, mirror.failures, mirror.in_flight))
+            .expect("a download has at least one url");
+        mirror.in_flight += 1;
+        (index, mirror.url.clone())
+    }
+
+    /// Whether a mirror other than index is healthy,
+    /// so that a range failing on index can move to it
+    pub fn has_alternative(&self, index: usize) -> bool {
+        let mirrors = self.mirrors.lock().unwrap();
+        mirrors
+            .iter()
+            .enumerate()
+            .any(|(i, mirror)| i != index && !mirror.demoted)
+    }
+
+    /// Record a range fetched from index at throughput bytes per second,
+    /// returning the urls of any mirrors demoted as too slow
+    pub fn succeeded(&self, index: usize, throughput: Option<f64>) -> Vec<String> {
+        let mut mirrors = self.mirrors.lock().unwrap();
+        let mirror = &mut mirrors[index];
+        mirror.in_flight -= 1;
+        mirror.failures = 0;
+        if let Some(throughput) = throughput {
+            mirror.throughput = Some(match mirror.throughput {
+                Some(average) => {
+                    average * (1.0 - THROUGHPUT_WEIGHT) + throughput * THROUGHPUT_WEIGHT
+                }
+                None => throughput,
+            });
+        }
+
+        let fastest = mirrors
+            .iter()
+            .filter_map(|mirror| mirror.throughput)
+            .fold(0.0, f64::max);
+        let mut demoted = Vec::new();
+        for mirror in mirrors.iter_mut().filter(|mirror| !mirror.demoted) {
+            if let Some(throughput) = mirror.throughput {
+                if throughput < fastest * SLOW_FRACTION {
+                    mirror.demoted = true;
+                    demoted.push(mirror.url.clone());
+                }
+            }
+        }
+        demoted
+    }
+
+    /// Record that a request to index was abandoned,
+    /// without counting it for or against the mirror
+    pub fn cancelled(&self, index: usize) {
+        self.mirrors.lock().unwrap()[index].in_flight -= 1;
+    }
+
+    /// Record a failed attempt on index, demoting it immediately if
+    /// the failure was specific to the mirror, or once it has failed
+    /// too many times in a row. Returns whether it was demoted
+    pub fn failed(&self, index: usize, demote: bool) -> bool {
+        let mut mirrors = self.mirrors.lock().unwrap();
+        let mirror = &mut mirrors[index];
+        mirror.in_flight -= 1;
+        mirror.failures += 1;
+        if !mirror.demoted && (demote || mirror.failures >= MAX_FAILURES) {
+            mirror.demoted = true;
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mirrors() -> Mirrors {
+        Mirrors::new(vec!["http://a".to_owned(), "http://b".to_owned()])
+    }
+
+    #[test]
+    fn spreads_ranges_across_mirrors() {
+        let mirrors = mirrors();
+        assert_eq!(mirrors.pick(), (0, "http://a".to_owned()));
+        assert_eq!(mirrors.pick(), (1, "http://b".to_owned()));
+    }
+
+    #[test]
+    fn failure_moves_ranges_to_another_mirror() {
+        let mirrors = mirrors();
+        let (index, _) = mirrors.pick();
+        assert!(!mirrors.failed(index, false));
+        assert!(mirrors.has_alternative(index));
+        assert_eq!(mirrors.pick().0, 1);
+    }
+
+    #[test]
+    fn demotes_failing_and_slow_mirrors() {
+        let mirrors = mirrors();
+        mirrors.pick();
+        assert!(mirrors.failed(0, true));
+        assert!(!mirrors.has_alternative(1));
+
+        let mirrors = Mirrors::new(vec!["http://a".to_owned(), "http://b".to_owned()]);
+        mirrors.pick();
+        mirrors.pick();
+        assert!(mirrors.succeeded(0, Some(1000.0)).is_empty());
+        assert_eq!(
+            mirrors.succeeded(1, Some(10.0)),
+            vec!["http://b".to_owned()]
+        );
+        assert_eq!(mirrors.pick().0, 0);
+        assert_eq!(mirrors.pick().0, 0);
+    }
+}
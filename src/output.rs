@@ -0,0 +1,433 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io::{self, Write};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::timer::delay_for;
+use tokio_executor::blocking;
+
+use crate::errors::Result;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use crate::uring::{sync_file, write_file_at};
+
+/// The default capacity of each range's write buffer
+pub const DEFAULT_WRITE_BUFFER: usize = 8 * 1024;
+
+/// The future returned by the methods of a ChunkSink
+pub type SinkFuture<'a> = Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+/// Where the bytes of a download are written. Ranges are fetched
+/// concurrently, so writes to different parts of the content arrive
+/// interleaved and out of order, each at an explicit offset
+pub trait ChunkSink: fmt::Debug + Send + Sync {
+    /// Write all of buf at offset in the content
+    fn write_at(&self, buf: Vec<u8>, offset: u64) -> SinkFuture<'_>;
+
+    /// Make the bytes written so far durable. Called at the
+    /// sync interval of the download, if it has one
+    fn sync(&self) -> SinkFuture<'_> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Called once the whole content has been written
+    fn finish(&self) -> SinkFuture<'_> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[derive(Debug, Clone)]
+/// The file a download is written to unless another sink is given
+pub struct OutputFile {
+    file: Arc<File>,
+}
+
+impl OutputFile {
+    /// Open the file at path for writing, creating it if
+    /// needed and keeping any existing contents
+    pub fn open(path: &Path) -> Result<OutputFile> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(path)?;
+        Ok(OutputFile {
+            file: Arc::new(file),
+        })
+    }
+
+    /// Create the file at path, discarding any existing contents
+    pub fn create(path: &Path) -> Result<OutputFile> {
+        Ok(OutputFile {
+            file: Arc::new(File::create(path)?),
+        })
+    }
+}
+
+impl ChunkSink for OutputFile {
+    fn write_at(&self, buf: Vec<u8>, offset: u64) -> SinkFuture<'_> {
+        let file = self.file.clone();
+        // Written on the blocking pool so as not to block the executor
+        Box::pin(async move {
+            blocking::run(move || write_file_at(&file, buf, offset)).await?;
+            Ok(())
+        })
+    }
+
+    fn sync(&self) -> SinkFuture<'_> {
+        let file = self.file.clone();
+        Box::pin(async move {
+            blocking::run(move || sync_file(&file)).await?;
+            Ok(())
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+/// A sink assembling the download in memory
+pub struct MemorySink {
+    bytes: Mutex<Vec<u8>>,
+}
+
+impl MemorySink {
+    /// The bytes written so far, leaving the sink empty
+    pub fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.bytes.lock().unwrap())
+    }
+}
+
+impl ChunkSink for MemorySink {
+    fn write_at(&self, buf: Vec<u8>, offset: u64) -> SinkFuture<'_> {
+        let mut bytes = self.bytes.lock().unwrap();
+        let start = offset as usize;
+        let end = start + buf.len();
+        if bytes.len() < end {
+            bytes.resize(end, 0);
+        }
+        bytes[start..end].copy_from_slice(&buf);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// The default most bytes an OrderedSink holds while
+/// waiting for the bytes before them to arrive
+pub const DEFAULT_ORDERED_WINDOW: u64 = 64 * 1024 * 1024;
+
+/// How often a write held back by a full window checks for room
+const ORDERED_WAIT: Duration = Duration::from_millis(10);
+
+struct Ordered<W> {
+    writer: W,
+    /// The number of bytes written to the writer so far
+    position: u64,
+    /// Bytes which arrived ahead of position, by their offset
+    pending: BTreeMap<u64, Vec<u8>>,
+    pending_bytes: u64,
+    window: u64,
+}
+
+impl<W: Write> Ordered<W> {
+    /// Write buf if it is next in order, or hold it if there is room,
+    /// otherwise handing it back to be offered again later
+    fn accept(&mut self, mut buf: Vec<u8>, offset: u64) -> io::Result<Option<Vec<u8>>> {
+        let end = offset + buf.len() as u64;
+        if end <= self.position {
+            // Already written, as when a hedged range rewrites its start
+            return Ok(None);
+        }
+        if offset > self.position {
+            if !self.pending.is_empty() && self.pending_bytes + buf.len() as u64 > self.window {
+                return Ok(Some(buf));
+            }
+            self.pending_bytes += buf.len() as u64;
+            if let Some(replaced) = self.pending.insert(offset, buf) {
+                self.pending_bytes -= replaced.len() as u64;
+            }
+            return Ok(None);
+        }
+
+        buf.drain(..(self.position - offset) as usize);
+        self.writer.write_all(&buf)?;
+        self.position = end;
+        while let Some(&start) = self.pending.keys().next() {
+            if start > self.position {
+                break;
+            }
+            let mut bytes = self.pending.remove(&start).unwrap_or_default();
+            self.pending_bytes -= bytes.len() as u64;
+            let end = start + bytes.len() as u64;
+            if end > self.position {
+                bytes.drain(..(self.position - start) as usize);
+                self.writer.write_all(&bytes)?;
+                self.position = end;
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// A sink writing a download strictly in order to a writer such as
+/// stdout. Bytes arriving ahead of those before them are held, up to
+/// a window, beyond which the ranges fetching them wait their turn
+pub struct OrderedSink<W> {
+    ordered: Arc<Mutex<Ordered<W>>>,
+}
+
+impl<W: Write + Send + 'static> OrderedSink<W> {
+    /// Write to writer, holding at most window bytes out of order
+    pub fn new(writer: W, window: u64) -> OrderedSink<W> {
+        OrderedSink {
+            ordered: Arc::new(Mutex::new(Ordered {
+                writer,
+                position: 0,
+                pending: BTreeMap::new(),
+                pending_bytes: 0,
+                window,
+            })),
+        }
+    }
+}
+
+impl<W> fmt::Debug for OrderedSink<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ordered = self.ordered.lock().unwrap();
+        f.debug_struct("OrderedSink")
+            .field("position", &ordered.position)
+            .field("pending_bytes", &ordered.pending_bytes)
+            .field("window", &ordered.window)
+            .finish()
+    }
+}
+
+impl<W: Write + Send + 'static> ChunkSink for OrderedSink<W> {
+    fn write_at(&self, buf: Vec<u8>, offset: u64) -> SinkFuture<'_> {
+        Box::pin(async move {
+            let mut buf = buf;
+            loop {
+                let ordered = self.ordered.clone();
+                // The writer may block, as a pipe does when full
+                let held =
+                    blocking::run(move || ordered.lock().unwrap().accept(buf, offset)).await?;
+                match held {
+                    Some(held) => {
+                        buf = held;
+                        delay_for(ORDERED_WAIT).await;
+                    }
+                    None => return Ok(()),
+                }
+            }
+        })
+    }
+
+    fn sync(&self) -> SinkFuture<'_> {
+        let ordered = self.ordered.clone();
+        Box::pin(async move {
+            blocking::run(move || ordered.lock().unwrap().writer.flush()).await?;
+            Ok(())
+        })
+    }
+
+    fn finish(&self) -> SinkFuture<'_> {
+        let ordered = self.ordered.clone();
+        Box::pin(async move {
+            blocking::run(move || {
+                let mut ordered = ordered.lock().unwrap();
+                if !ordered.pending.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "bytes were missing from the download",
+                    ));
+                }
+                ordered.writer.flush()
+            })
+            .await?;
+            Ok(())
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+/// The sink of a download, shared by every worker, along
+/// with how the writes of each range are buffered
+pub struct Output {
+    sink: Arc<dyn ChunkSink>,
+    buffer_size: usize,
+    sync_interval: Option<Duration>,
+}
+
+impl Output {
+    /// Write to sink, buffering up to buffer_size bytes of each range,
+    /// and if a sync_interval is given, flushing and syncing at least that often
+    pub fn new(
+        sink: Arc<dyn ChunkSink>,
+        buffer_size: usize,
+        sync_interval: Option<Duration>,
+    ) -> Output {
+        Output {
+            sink,
+            buffer_size,
+            sync_interval,
+        }
+    }
+
+    /// Write all of buf at offset
+    pub async fn write_at(&self, buf: Vec<u8>, offset: u64) -> Result<()> {
+        self.sink.write_at(buf, offset).await
+    }
+
+    /// Tell the sink the whole content has been written
+    pub async fn finish(&self) -> Result<()> {
+        self.sink.finish().await
+    }
+
+    /// A buffered writer for the bytes of a range from position onwards
+    pub fn writer(&self, position: u64) -> RangeWriter<'_> {
+        RangeWriter {
+            output: self,
+            position,
+            buffer: Vec::with_capacity(self.buffer_size),
+            synced: Instant::now(),
+        }
+    }
+}
+
+/// Buffers the sequential writes of a single range,
+/// writing them to the output at its position when full
+pub struct RangeWriter<'a> {
+    output: &'a Output,
+    /// Where the first buffered byte belongs in the output
+    position: u64,
+    buffer: Vec<u8>,
+    synced: Instant,
+}
+
+impl<'a> RangeWriter<'a> {
+    /// Append bytes to the range
+    pub async fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        if self.buffer.len() + bytes.len() > self.buffer.capacity() {
+            self.flush().await?;
+        }
+        if bytes.len() >= self.buffer.capacity() {
+            self.output.write_at(bytes.to_vec(), self.position).await?;
+            self.position += bytes.len() as u64;
+        } else {
+            self.buffer.extend_from_slice(bytes);
+        }
+
+        if let Some(interval) = self.output.sync_interval {
+            if self.synced.elapsed() >= interval {
+                self.flush().await?;
+                self.output.sink.sync().await?;
+                self.synced = Instant::now();
+            }
+        }
+        Ok(())
+    }
+
+    /// Write any buffered bytes to the output
+    pub async fn flush(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let capacity = self.buffer.capacity();
+        let buffer = std::mem::replace(&mut self.buffer, Vec::with_capacity(capacity));
+        let len = buffer.len() as u64;
+        self.output.write_at(buffer, self.position).await?;
+        self.position += len;
+        Ok(())
+    }
+}
+
+/// Write all of buf at offset in file
+#[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+fn write_file_at(file: &File, buf: Vec<u8>, offset: u64) -> io::Result<()> {
+    write_all_at(file, &buf, offset)
+}
+
+/// Make the data written to file durable
+#[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+fn sync_file(file: &File) -> io::Result<()> {
+    file.sync_data()
+}
+
+#[cfg(unix)]
+pub(crate) fn write_all_at(file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+
+    file.write_all_at(buf, offset)
+}
+
+#[cfg(windows)]
+pub(crate) fn write_all_at(file: &File, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+
+    while !buf.is_empty() {
+        match file.seek_write(buf, offset) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "failed to write whole buffer",
+                ))
+            }
+            Ok(written) => {
+                buf = &buf[written..];
+                offset += written as u64;
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn ranges_written_at_their_offsets() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("out.bin");
+        let file = OutputFile::open(&path).unwrap();
+        let output = Output::new(Arc::new(file), 4, Some(Duration::from_secs(0)));
+
+        let mut world = output.writer(5);
+        let mut hello = output.writer(0);
+        world.write(b"Wor").await.unwrap();
+        hello.write(b"Hello").await.unwrap();
+        world.write(b"ld").await.unwrap();
+        world.flush().await.unwrap();
+        hello.flush().await.unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"HelloWorld");
+    }
+
+    #[test]
+    fn ordered_writes_wait_for_earlier_bytes() {
+        let mut ordered = Ordered {
+            writer: Vec::new(),
+            position: 0,
+            pending: BTreeMap::new(),
+            pending_bytes: 0,
+            window: 4,
+        };
+        assert_eq!(ordered.accept(b"Wor".to_vec(), 5).unwrap(), None);
+        // The window is full, so later bytes are handed back
+        assert_eq!(
+            ordered.accept(b"ld".to_vec(), 8).unwrap(),
+            Some(b"ld".to_vec())
+        );
+        assert_eq!(ordered.accept(b"Hello".to_vec(), 0).unwrap(), None);
+        assert_eq!(ordered.writer, b"HelloWor");
+        // Bytes already written are skipped
+        assert_eq!(ordered.accept(b"oWorld".to_vec(), 4).unwrap(), None);
+        assert_eq!(ordered.writer, b"HelloWorld");
+        assert!(ordered.pending.is_empty());
+    }
+}
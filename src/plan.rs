@@ -0,0 +1,38 @@
+use std::path::PathBuf;
+
+use crate::fetch::Range;
+use crate::verify::{Checksum, ChecksumAlgorithm};
+
+#[derive(Debug, Clone, PartialEq)]
+/// What a download would do, worked out from a HEAD request without
+/// fetching any of the content, returned by `plan_download`
+pub struct DownloadPlan {
+    /// The url the HEAD request ended at, after any redirects
+    pub url: String,
+    /// Where the download would be saved, after any renaming to avoid
+    /// clobbering, or None if it would be written to a sink or skipped
+    /// because the output already exists
+    pub path: Option<PathBuf>,
+    /// The length of the remote file, if the server reported it
+    pub total_length: Option<u64>,
+    /// Why the download would be fetched with a single request rather
+    /// than ranges in parallel, if it would be
+    pub single_stream: Option<String>,
+    /// The number of parallel fetches the ranges would be shared between
+    pub num_fetches: u64,
+    /// The ranges of the remote file which would be requested
+    pub ranges: Vec<Range>,
+    /// The checksums the output would be verified against, both given
+    /// and advertised in the headers of the response
+    pub checksums: Vec<Checksum>,
+    /// The checksum file a checksum of the output would be looked up in
+    pub checksum_url: Option<String>,
+    /// The url a detached signature of the output would be fetched from
+    pub signature_url: Option<String>,
+    /// The digest the ETag would be checked as, if it would be checked
+    pub etag_check: Option<ChecksumAlgorithm>,
+    /// The ETag of the remote file, if it has one
+    pub etag: Option<String>,
+    /// The Last-Modified time of the remote file, if it has one
+    pub last_modified: Option<String>,
+}
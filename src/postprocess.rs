@@ -0,0 +1,167 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::encoding::{ContentEncoding, Decoder};
+use crate::errors::{FetchError, Result};
+use crate::utils::part_path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// A compression format a downloaded file may be in
+pub enum Compression {
+    /// `.gz`, or `.tgz` for a tarball
+    Gzip,
+    /// `.zst`
+    Zstd,
+    /// `.xz`, or `.txz` for a tarball
+    Xz,
+}
+
+impl Compression {
+    /// The format of the file at path, from its suffix or else from its
+    /// first bytes, along with the path it decompresses to. A file
+    /// recognised only by its first bytes is decompressed in place
+    pub fn detect(path: &Path) -> Result<Option<(Compression, PathBuf)>> {
+        if let Some((compression, decompressed)) = Compression::from_suffix(path) {
+            return Ok(Some((compression, decompressed)));
+        }
+        let mut magic = [0; 6];
+        let read = File::open(path)?.read(&mut magic)?;
+        Ok(Compression::from_magic(&magic[..read]).map(|compression| (compression, path.into())))
+    }
+
+    fn from_suffix(path: &Path) -> Option<(Compression, PathBuf)> {
+        let (compression, extension) = match path.extension()?.to_str()? {
+            "gz" => (Compression::Gzip, None),
+            "tgz" => (Compression::Gzip, Some("tar")),
+            "zst" => (Compression::Zstd, None),
+            "xz" => (Compression::Xz, None),
+            "txz" => (Compression::Xz, Some("tar")),
+            _ => return None,
+        };
+        let decompressed = match extension {
+            Some(extension) => path.with_extension(extension),
+            None => path.with_extension(""),
+        };
+        Some((compression, decompressed))
+    }
+
+    fn from_magic(magic: &[u8]) -> Option<Compression> {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0]) {
+            Some(Compression::Xz)
+        } else {
+            None
+        }
+    }
+}
+
+/// Decompress the downloaded file at path, returning the path of the
+/// decompressed file, or None if it is not compressed. The compressed
+/// file is removed unless keep is set, in which case a file decompressed
+/// in place keeps its compressed form at path with `.gz` appended
+pub fn decompress(path: &Path, keep: bool) -> Result<Option<PathBuf>> {
+    let (compression, decompressed) = match Compression::detect(path)? {
+        Some(detected) => detected,
+        None => return Ok(None),
+    };
+    let encoding = match compression {
+        Compression::Gzip => ContentEncoding::Gzip,
+        Compression::Zstd | Compression::Xz => {
+            return Err(FetchError::InvalidArgumentsError(format!(
+                "Cannot decompress {:?}, only gzip is supported and it is {:?}",
+                path, compression
+            )))
+        }
+    };
+
+    let temporary = part_path(&decompressed);
+    if let Err(err) = decode_file(encoding, path, &temporary) {
+        let _ = fs::remove_file(&temporary);
+        return Err(err);
+    }
+    if keep && decompressed == path {
+        let mut kept = path.as_os_str().to_owned();
+        kept.push(".gz");
+        fs::rename(path, kept)?;
+    } else if !keep {
+        fs::remove_file(path)?;
+    }
+    fs::rename(&temporary, &decompressed)?;
+    Ok(Some(decompressed))
+}
+
+fn decode_file(encoding: ContentEncoding, from: &Path, to: &Path) -> Result<()> {
+    let mut input = File::open(from)?;
+    let mut output = BufWriter::new(File::create(to)?);
+    let mut decoder = Decoder::new(encoding);
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = input.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        output.write_all(&decoder.decode(&buf[..read])?)?;
+    }
+    output.write_all(&decoder.finish()?)?;
+    output.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// "HelloWorld" gzipped
+    const HELLO_GZIP: [u8; 30] = [
+        31, 139, 8, 0, 0, 0, 0, 0, 2, 3, 243, 72, 205, 201, 201, 15, 207, 47, 202, 73, 1, 0, 121,
+        12, 119, 119, 10, 0, 0, 0,
+    ];
+
+    #[test]
+    fn detect_compression() {
+        assert_eq!(
+            Compression::from_suffix(Path::new("dir/file.tar.gz")),
+            Some((Compression::Gzip, PathBuf::from("dir/file.tar")))
+        );
+        assert_eq!(
+            Compression::from_suffix(Path::new("file.txz")),
+            Some((Compression::Xz, PathBuf::from("file.tar")))
+        );
+        assert_eq!(Compression::from_suffix(Path::new("file.tar")), None);
+        assert_eq!(
+            Compression::from_magic(&[0x28, 0xb5, 0x2f, 0xfd, 0, 0]),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(Compression::from_magic(b"Hello"), None);
+    }
+
+    #[test]
+    fn decompress_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let suffixed = temp_dir.path().join("hello.txt.gz");
+        fs::write(&suffixed, &HELLO_GZIP[..]).unwrap();
+        let decompressed = decompress(&suffixed, false).unwrap().unwrap();
+        assert_eq!(decompressed, temp_dir.path().join("hello.txt"));
+        assert_eq!(fs::read(&decompressed).unwrap(), b"HelloWorld");
+        assert!(!suffixed.exists());
+
+        let unsuffixed = temp_dir.path().join("hello.bin");
+        fs::write(&unsuffixed, &HELLO_GZIP[..]).unwrap();
+        assert_eq!(
+            decompress(&unsuffixed, true).unwrap(),
+            Some(unsuffixed.clone())
+        );
+        assert_eq!(fs::read(&unsuffixed).unwrap(), b"HelloWorld");
+        assert_eq!(
+            fs::read(temp_dir.path().join("hello.bin.gz")).unwrap(),
+            &HELLO_GZIP[..]
+        );
+
+        assert_eq!(decompress(&decompressed, false).unwrap(), None);
+    }
+}
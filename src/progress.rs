@@ -0,0 +1,187 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::events::DownloadEvent;
+use crate::fetch::{FetchOptions, Range};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// A report of download progress, emitted whenever
+/// a chunk of a range is written
+pub struct ProgressUpdate {
+    /// The range the chunk belongs to
+    pub range: Range,
+    /// The bytes of range written so far
+    pub range_downloaded: u64,
+    /// The bytes written so far across all ranges
+    pub downloaded: u64,
+    /// The total length of the content, or 0 if the server did not report it
+    pub total: u64,
+    /// The download rate in bytes per second, smoothed over recent
+    /// samples, or 0 until the first sample is taken
+    pub speed: f64,
+    /// How long the rest of the download should take at the smoothed
+    /// rate, if the total and rate are known
+    pub eta: Option<Duration>,
+}
+
+/// How often the download rate is sampled
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The weight of the newest sample in the moving average of the rate
+const SMOOTHING: f64 = 0.3;
+
+/// An exponential moving average of the aggregate download rate
+struct Rate {
+    sampled_at: Instant,
+    sampled_downloaded: u64,
+    speed: Option<f64>,
+}
+
+impl Rate {
+    /// Take a sample if one is due, now that downloaded bytes are written
+    fn sample(&mut self, downloaded: u64) -> Option<f64> {
+        let elapsed = self.sampled_at.elapsed();
+        if elapsed >= SAMPLE_INTERVAL {
+            let bytes = downloaded.saturating_sub(self.sampled_downloaded);
+            let latest = bytes as f64 / elapsed.as_secs_f64();
+            self.speed = Some(match self.speed {
+                Some(speed) => SMOOTHING * latest + (1.0 - SMOOTHING) * speed,
+                None => latest,
+            });
+            self.sampled_at = Instant::now();
+            self.sampled_downloaded = downloaded;
+        }
+        self.speed
+    }
+}
+
+/// A callback receiving progress updates
+pub type ProgressCallback = Box<dyn Fn(ProgressUpdate) + Send + Sync>;
+
+/// Aggregates bytes written by every range of a download
+/// and reports them to the callback, control and events of its options
+pub struct Progress<'a> {
+    options: &'a FetchOptions,
+    total: u64,
+    downloaded: AtomicU64,
+    rate: Mutex<Rate>,
+}
+
+impl<'a> Progress<'a> {
+    /// Create a tracker for a download of total bytes, of which
+    /// already_downloaded were written by a previous run
+    pub fn new(options: &'a FetchOptions, total: u64, already_downloaded: u64) -> Self {
+        Progress {
+            options,
+            total,
+            downloaded: AtomicU64::new(already_downloaded),
+            rate: Mutex::new(Rate {
+                sampled_at: Instant::now(),
+                sampled_downloaded: already_downloaded,
+                speed: None,
+            }),
+        }
+    }
+
+    /// Record bytes written to range, which now has range_downloaded bytes written
+    pub fn advance(&self, range: Range, range_downloaded: u64, bytes: u64) {
+        let downloaded = self.downloaded.fetch_add(bytes, Ordering::SeqCst) + bytes;
+        if let Some(control) = &self.options.control {
+            control.record(range, range_downloaded, downloaded, self.total);
+        }
+        if let Some(events) = &self.options.events {
+            events.send(DownloadEvent::ChunkProgress {
+                range,
+                range_downloaded,
+                downloaded,
+                total: self.total,
+            });
+        }
+        if let Some(callback) = &self.options.progress {
+            let speed = self.rate.lock().unwrap().sample(downloaded);
+            let eta = match speed {
+                Some(speed) if speed > 0.0 && self.total > 0 => Some(Duration::from_secs_f64(
+                    self.total.saturating_sub(downloaded) as f64 / speed,
+                )),
+                _ => None,
+            };
+            callback(ProgressUpdate {
+                range,
+                range_downloaded,
+                downloaded,
+                total: self.total,
+                speed: speed.unwrap_or(0.0),
+                eta,
+            });
+        }
+    }
+
+    /// Discard bytes written by a failed attempt which will be fetched again
+    pub fn rewind(&self, bytes: u64) {
+        self.downloaded.fetch_sub(bytes, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn advance_reports_aggregate() {
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let recorded = updates.clone();
+        let callback: ProgressCallback =
+            Box::new(move |update| recorded.lock().unwrap().push(update));
+        let options = FetchOptions::builder("http://example.com")
+            .progress(callback)
+            .build();
+
+        let progress = Progress::new(&options, 10, 2);
+        let range = Range { start: 5, end: 9 };
+        progress.advance(range, 3, 3);
+        progress.rewind(3);
+        progress.advance(range, 5, 5);
+
+        assert_eq!(
+            *updates.lock().unwrap(),
+            vec![
+                ProgressUpdate {
+                    range,
+                    range_downloaded: 3,
+                    downloaded: 5,
+                    total: 10,
+                    speed: 0.0,
+                    eta: None,
+                },
+                ProgressUpdate {
+                    range,
+                    range_downloaded: 5,
+                    downloaded: 7,
+                    total: 10,
+                    speed: 0.0,
+                    eta: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rate_is_smoothed() {
+        let mut rate = Rate {
+            sampled_at: Instant::now() - Duration::from_secs(1),
+            sampled_downloaded: 0,
+            speed: None,
+        };
+        let speed = rate.sample(1000).unwrap();
+        assert!(speed > 900.0 && speed <= 1000.0);
+        // Too soon for another sample
+        assert_eq!(rate.sample(5000), Some(speed));
+
+        // A stalled second only pulls the rate partway down
+        rate.sampled_at = Instant::now() - Duration::from_secs(1);
+        let smoothed = rate.sample(1000).unwrap();
+        assert!((smoothed - speed * (1.0 - SMOOTHING)).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::io::{self, SeekFrom};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::io::{AsyncRead, AsyncSeek};
+use reqwest::header::{
+    HeaderMap, HeaderValue, ACCEPT_ENCODING, AUTHORIZATION, CONTENT_RANGE, IF_MATCH, RANGE,
+};
+use reqwest::StatusCode;
+use slog::{info, Logger};
+use tokio_sync::oneshot;
+
+use crate::encoding::accept_encoding;
+use crate::errors::{FetchError, Result};
+use crate::fetch::{build_client, FetchOptions, Range};
+use crate::retry::RetryPolicy;
+
+/// The size of the blocks a ParallelReader fetches unless a chunk size is given
+const DEFAULT_BLOCK_SIZE: u64 = 1024 * 1024;
+
+/// How a ParallelReader requests the blocks of its url
+#[derive(Clone)]
+struct Source {
+    client: reqwest::Client,
+    url: String,
+    headers: HeaderMap,
+    max_retries: u64,
+    retry_policy: RetryPolicy,
+    logger: Logger,
+}
+
+impl Source {
+    /// Fetch the bytes of range, retrying failures the policy allows
+    async fn fetch(self, range: Range) -> Result<Vec<u8>> {
+        let mut attempts = 0;
+        loop {
+            match self.fetch_once(range).await {
+                Ok(bytes) => return Ok(bytes),
+                Err(error) => {
+                    attempts += 1;
+                    if attempts >= self.max_retries || !self.retry_policy.is_retryable(&error) {
+                        return Err(FetchError::RangeError {
+                            url: self.url,
+                            range,
+                            attempts,
+                            source: Box::new(error),
+                        });
+                    }
+                    info!(self.logger, "retrying block"; "range" => &range, "attempts" => attempts, "error" => format!("{:?}", &error));
+                }
+            }
+        }
+    }
+
+    async fn fetch_once(&self, range: Range) -> Result<Vec<u8>> {
+        let mut headers = self.headers.clone();
+        headers.insert(
+            RANGE,
+            format!("bytes={}-{}", range.start, range.end).parse()?,
+        );
+        let res = self.client.get(&self.url).headers(headers).send().await?;
+        if res.status() == StatusCode::PRECONDITION_FAILED {
+            return Err(FetchError::ContentChangedError(
+                "Remote file changed while it was being read".to_owned(),
+            ));
+        }
+        let mut res = res.error_for_status()?;
+        if res.status() != StatusCode::PARTIAL_CONTENT {
+            return Err(FetchError::ServerSupportError(
+                "Range response status code was not a 206".to_owned(),
+            ));
+        }
+
+        let mut bytes = Vec::with_capacity((range.end - range.start + 1) as usize);
+        while let Some(chunk) = res.chunk().await? {
+            bytes.extend_from_slice(&chunk);
+        }
+        if bytes.len() as u64 != range.end - range.start + 1 {
+            return Err(FetchError::ServerSupportError(
+                "Range response was not the length requested".to_owned(),
+            ));
+        }
+        Ok(bytes)
+    }
+
+    /// Learn the length of the file and its ETag from the first byte
+    async fn probe(&self) -> Result<(u64, Option<HeaderValue>)> {
+        let mut headers = self.headers.clone();
+        headers.insert(RANGE, HeaderValue::from_static("bytes=0-0"));
+        let res = self
+            .client
+            .get(&self.url)
+            .headers(headers)
+            .send()
+            .await?
+            .error_for_status()?;
+        if res.status() != StatusCode::PARTIAL_CONTENT {
+            return Err(FetchError::ServerSupportError(
+                "Server ignored a range request".to_owned(),
+            ));
+        }
+        let content_length = res
+            .headers()
+            .get(CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit('/').next())
+            .and_then(|total| total.parse::<u64>().ok())
+            .ok_or_else(|| {
+                FetchError::ServerSupportError(
+                    "Range response did not include the total size in its Content-Range header"
+                        .to_owned(),
+                )
+            })?;
+        // Only a strong ETag shows that two ranges are of the same content
+        let etag = res
+            .headers()
+            .get(reqwest::header::ETAG)
+            .filter(|etag| !etag.as_bytes().starts_with(b"W/"))
+            .cloned();
+        Ok((content_length, etag))
+    }
+}
+
+/// A block being fetched in the background, or its bytes once fetched
+struct Block {
+    start: u64,
+    state: BlockState,
+}
+
+enum BlockState {
+    Fetching(oneshot::Receiver<Result<Vec<u8>>>),
+    Fetched(Vec<u8>),
+}
+
+/// Reads a remote file as if it were local, fetching it in blocks with
+/// range requests. Reading prefetches the blocks ahead of the position
+/// over as many connections as the options give fetches, and seeking
+/// starts prefetching from the new position, so a remote archive or
+/// media file can be read without downloading the whole of it first.
+///
+/// Blocks are fetched on the default executor, so the reader must be
+/// used from within a tokio runtime
+pub struct ParallelReader {
+    source: Source,
+    content_length: u64,
+    block_size: u64,
+    prefetch: usize,
+    position: u64,
+    /// The block holding position and those after it, in order
+    blocks: VecDeque<Block>,
+}
+
+impl ParallelReader {
+    /// Open the url of options for reading. The block size is the chunk
+    /// size of options, if given, and the number of blocks fetched ahead
+    /// at once is its number of fetches
+    pub async fn open(options: FetchOptions) -> Result<ParallelReader> {
+        if options.num_fetches == 0 {
+            return Err(FetchError::InvalidArgumentsError(
+                "Number of fetches must be greater than zero".to_owned(),
+            ));
+        }
+        if options.chunk_size == Some(0) {
+            return Err(FetchError::InvalidArgumentsError(
+                "Chunk size must be greater than zero".to_owned(),
+            ));
+        }
+        let client = match &options.client {
+            Some(client) => client.clone(),
+            None => build_client(&options)?,
+        };
+        let mut headers = options.headers.clone();
+        if let Some(credentials) = &options.credentials {
+            headers.insert(AUTHORIZATION, credentials.header_value()?);
+        }
+        // Blocks are ranges of the file itself, never of a compressed form
+        headers.insert(ACCEPT_ENCODING, accept_encoding(false));
+        let mut source = Source {
+            client,
+            url: options.url.clone(),
+            headers,
+            max_retries: options.max_retries.max(1),
+            retry_policy: options.retry_policy,
+            logger: options.logger.clone(),
+        };
+
+        let (content_length, etag) = source.probe().await?;
+        if let Some(etag) = etag {
+            source.headers.insert(IF_MATCH, etag);
+        }
+        info!(source.logger, "opened for reading"; "url" => &source.url, "content_length" => content_length);
+
+        Ok(ParallelReader {
+            source,
+            content_length,
+            block_size: options.chunk_size.unwrap_or(DEFAULT_BLOCK_SIZE),
+            prefetch: options.num_fetches as usize,
+            position: 0,
+            blocks: VecDeque::new(),
+        })
+    }
+
+    /// The length of the remote file
+    pub fn content_length(&self) -> u64 {
+        self.content_length
+    }
+
+    /// The start of the block holding offset
+    fn block_start(&self, offset: u64) -> u64 {
+        offset - offset % self.block_size
+    }
+
+    /// Drop blocks before position and start fetching
+    /// any missing blocks up to the prefetch limit
+    fn schedule(&mut self) {
+        let first = self.block_start(self.position);
+        while self.blocks.front().is_some_and(|block| block.start < first) {
+            self.blocks.pop_front();
+        }
+        // After seeking backwards the blocks held are further on
+        if self
+            .blocks
+            .front()
+            .is_some_and(|block| block.start != first)
+        {
+            self.blocks.clear();
+        }
+
+        let mut next = match self.blocks.back() {
+            Some(block) => block.start + self.block_size,
+            None => first,
+        };
+        while self.blocks.len() < self.prefetch && next < self.content_length {
+            let range = Range {
+                start: next,
+                end: (next + self.block_size).min(self.content_length) - 1,
+            };
+            let (sender, receiver) = oneshot::channel();
+            let source = self.source.clone();
+            tokio::spawn(async move {
+                // The reader may have moved on and dropped the block
+                let _ = sender.send(source.fetch(range).await);
+            });
+            self.blocks.push_back(Block {
+                start: next,
+                state: BlockState::Fetching(receiver),
+            });
+            next += self.block_size;
+        }
+    }
+}
+
+impl fmt::Debug for ParallelReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParallelReader")
+            .field("url", &self.source.url)
+            .field("content_length", &self.content_length)
+            .field("block_size", &self.block_size)
+            .field("prefetch", &self.prefetch)
+            .field("position", &self.position)
+            .field("blocks", &self.blocks.len())
+            .finish()
+    }
+}
+
+impl AsyncRead for ParallelReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let reader = self.get_mut();
+        if reader.position >= reader.content_length || buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        reader.schedule();
+
+        let position = reader.position;
+        let block = match reader.blocks.front_mut() {
+            Some(block) => block,
+            None => return Poll::Ready(Ok(0)),
+        };
+        if let BlockState::Fetching(receiver) = &mut block.state {
+            let bytes = match Pin::new(receiver).poll(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Ok(Ok(bytes))) => bytes,
+                Poll::Ready(Ok(Err(err))) => {
+                    reader.blocks.pop_front();
+                    return Poll::Ready(Err(io::Error::other(err)));
+                }
+                Poll::Ready(Err(_)) => {
+                    reader.blocks.pop_front();
+                    return Poll::Ready(Err(io::Error::other(
+                        "Block fetch stopped before finishing",
+                    )));
+                }
+            };
+            block.state = BlockState::Fetched(bytes);
+        }
+
+        let read = match &block.state {
+            BlockState::Fetched(bytes) => {
+                let skip = (position - block.start) as usize;
+                let read = buf.len().min(bytes.len() - skip);
+                buf[..read].copy_from_slice(&bytes[skip..skip + read]);
+                read
+            }
+            BlockState::Fetching(_) => 0,
+        };
+        reader.position += read as u64;
+        Poll::Ready(Ok(read))
+    }
+}
+
+impl AsyncSeek for ParallelReader {
+    fn poll_seek(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<io::Result<u64>> {
+        let reader = self.get_mut();
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => add_offset(reader.content_length, offset),
+            SeekFrom::Current(offset) => add_offset(reader.position, offset),
+        };
+        match position {
+            Some(position) => {
+                reader.position = position;
+                Poll::Ready(Ok(position))
+            }
+            None => Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Seek to a negative or overflowing position",
+            ))),
+        }
+    }
+}
+
+fn add_offset(base: u64, offset: i64) -> Option<u64> {
+    if offset >= 0 {
+        base.checked_add(offset as u64)
+    } else {
+        base.checked_sub(offset.unsigned_abs())
+    }
+}
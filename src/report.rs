@@ -0,0 +1,207 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use reqwest::header::{HeaderMap, HeaderValue, ETAG, LAST_MODIFIED};
+
+use crate::events::DownloadEvent;
+use crate::fetch::Range;
+use crate::metrics::FetchMetrics;
+use crate::verify::ChecksumAlgorithm;
+
+/// How long bytes are counted for before a throughput is sampled
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq)]
+/// What happened during a download which succeeded, returned by `fetch`
+pub struct FetchReport {
+    /// Where the download was saved, after any renaming or decompressing,
+    /// or None if it was written to a sink or the output was already there
+    pub path: Option<PathBuf>,
+    /// The bytes received from the network, before any decompression
+    pub bytes: u64,
+    /// How long the download took, including any restarts
+    pub duration: Duration,
+    /// The highest rate bytes were received at over a second, in bytes per
+    /// second, or the average rate if the download took less than a second
+    pub peak_throughput: f64,
+    /// Each range which needed retrying, with the number of failed attempts
+    pub retries: Vec<(Range, u64)>,
+    /// The algorithms of the checksums the output matched
+    pub checksums: Vec<ChecksumAlgorithm>,
+    /// Whether a signature was verified
+    pub signature: bool,
+    /// The ETag of the version of the file downloaded, if it had one
+    pub etag: Option<String>,
+    /// The Last-Modified time of the version of the file downloaded,
+    /// if it had one
+    pub last_modified: Option<String>,
+}
+
+impl FetchReport {
+    /// The average rate bytes were received at, in bytes per second
+    pub fn average_throughput(&self) -> f64 {
+        let seconds = self.duration.as_secs_f64();
+        if seconds > 0.0 {
+            self.bytes as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Gathers the FetchReport of a download from its events and measurements
+pub struct Recorder {
+    started: Instant,
+    state: Mutex<RecorderState>,
+}
+
+struct RecorderState {
+    path: Option<PathBuf>,
+    bytes: u64,
+    window_start: Instant,
+    window_bytes: u64,
+    peak_throughput: f64,
+    /// The retries of each range which needed any, by the start of the range
+    retries: BTreeMap<u64, (Range, u64)>,
+    checksums: Vec<ChecksumAlgorithm>,
+    signature: bool,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl Recorder {
+    /// Start recording a download which is starting now
+    pub fn new() -> Recorder {
+        let started = Instant::now();
+        Recorder {
+            started,
+            state: Mutex::new(RecorderState {
+                path: None,
+                bytes: 0,
+                window_start: started,
+                window_bytes: 0,
+                peak_throughput: 0.0,
+                retries: BTreeMap::new(),
+                checksums: Vec::new(),
+                signature: false,
+                etag: None,
+                last_modified: None,
+            }),
+        }
+    }
+
+    /// Note what event says about the download
+    pub fn record(&self, event: &DownloadEvent) {
+        let mut state = self.state.lock().unwrap();
+        match event {
+            DownloadEvent::ChunkRetried {
+                range, attempts, ..
+            } => {
+                state.retries.insert(range.start, (*range, *attempts));
+            }
+            DownloadEvent::Verified {
+                checksums,
+                signature,
+            } => {
+                state.checksums = checksums.clone();
+                state.signature = *signature;
+            }
+            DownloadEvent::Saved { path } => state.path = Some(path.clone()),
+            _ => {}
+        }
+    }
+
+    /// Note the validators of the version of the file being downloaded,
+    /// from the headers its probe was answered with
+    pub fn validators(&self, headers: &HeaderMap) {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value: &HeaderValue| value.to_str().ok())
+                .map(String::from)
+        };
+        let mut state = self.state.lock().unwrap();
+        state.etag = header(ETAG);
+        state.last_modified = header(LAST_MODIFIED);
+    }
+
+    /// The report of the download, which has finished
+    pub fn report(&self) -> FetchReport {
+        let state = self.state.lock().unwrap();
+        let mut report = FetchReport {
+            path: state.path.clone(),
+            bytes: state.bytes,
+            duration: self.started.elapsed(),
+            peak_throughput: state.peak_throughput,
+            retries: state.retries.values().cloned().collect(),
+            checksums: state.checksums.clone(),
+            signature: state.signature,
+            etag: state.etag.clone(),
+            last_modified: state.last_modified.clone(),
+        };
+        if report.peak_throughput == 0.0 {
+            report.peak_throughput = report.average_throughput();
+        }
+        report
+    }
+}
+
+impl FetchMetrics for Recorder {
+    fn bytes_downloaded(&self, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.bytes += bytes;
+        state.window_bytes += bytes;
+        let elapsed = state.window_start.elapsed();
+        if elapsed >= THROUGHPUT_WINDOW {
+            let throughput = state.window_bytes as f64 / elapsed.as_secs_f64();
+            state.peak_throughput = state.peak_throughput.max(throughput);
+            state.window_start = Instant::now();
+            state.window_bytes = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_report() {
+        let recorder = Recorder::new();
+        let range = Range { start: 0, end: 9 };
+        recorder.bytes_downloaded(4);
+        recorder.record(&DownloadEvent::ChunkRetried {
+            range,
+            attempts: 1,
+            error: "reset".to_owned(),
+        });
+        recorder.record(&DownloadEvent::ChunkRetried {
+            range,
+            attempts: 2,
+            error: "reset".to_owned(),
+        });
+        recorder.bytes_downloaded(10);
+        recorder.record(&DownloadEvent::Verified {
+            checksums: vec![ChecksumAlgorithm::Sha256],
+            signature: false,
+        });
+        recorder.record(&DownloadEvent::Saved {
+            path: PathBuf::from("out.bin"),
+        });
+        let mut headers = HeaderMap::new();
+        headers.insert(ETAG, HeaderValue::from_static("\"abc\""));
+        recorder.validators(&headers);
+
+        let report = recorder.report();
+        assert_eq!(report.path, Some(PathBuf::from("out.bin")));
+        assert_eq!(report.bytes, 14);
+        assert_eq!(report.retries, vec![(range, 2)]);
+        assert_eq!(report.checksums, vec![ChecksumAlgorithm::Sha256]);
+        assert!(!report.signature);
+        assert_eq!(report.etag.as_deref(), Some("\"abc\""));
+        assert_eq!(report.last_modified, None);
+        assert_eq!(report.peak_throughput, report.average_throughput());
+    }
+}
@@ -0,0 +1,131 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::errors::Result;
+use crate::fetch::Range;
+
+#[derive(Debug)]
+/// The completed ranges of a download, persisted in a state file
+/// next to the output so that an interrupted download can be resumed
+///
+/// The state file starts with the content length, ETag and any Last-Modified
+/// time of the remote file, followed by one completed `start-end` range per line
+pub struct ResumeState {
+    path: PathBuf,
+    completed: Vec<Range>,
+}
+
+impl ResumeState {
+    /// The location of the state file for an output path
+    pub fn state_path(output: &Path) -> PathBuf {
+        let mut path = output.as_os_str().to_owned();
+        path.push(".pfstate");
+        PathBuf::from(path)
+    }
+
+    /// Open the state file for output, keeping any previously completed
+    /// ranges only if the output still exists and the state was recorded
+    /// for the same content length, ETag and Last-Modified time
+    pub fn open(
+        output: &Path,
+        content_length: u64,
+        etag: Option<&str>,
+        last_modified: Option<&str>,
+    ) -> Result<ResumeState> {
+        let path = ResumeState::state_path(output);
+        let mut header = format!(
+            "content-length: {}\netag: {}\n",
+            content_length,
+            etag.unwrap_or("")
+        );
+        if let Some(last_modified) = last_modified {
+            header.push_str(&format!("last-modified: {}\n", last_modified));
+        }
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut completed = Vec::new();
+        if output.is_file() && contents.starts_with(&header) {
+            completed = contents[header.len()..]
+                .lines()
+                .filter_map(parse_range)
+                .collect();
+        } else {
+            fs::write(&path, &header)?;
+        }
+
+        Ok(ResumeState { path, completed })
+    }
+
+    /// The ranges already written to the output
+    pub fn completed(&self) -> &[Range] {
+        &self.completed
+    }
+
+    /// Record that range has been written to the output
+    pub fn record(&mut self, range: Range) -> Result<()> {
+        let mut file = OpenOptions::new().append(true).open(&self.path)?;
+        writeln!(file, "{}-{}", range.start, range.end)?;
+        self.completed.push(range);
+        Ok(())
+    }
+
+    /// Remove the state file once the download has finished
+    pub fn remove(self) -> Result<()> {
+        fs::remove_file(&self.path)?;
+        Ok(())
+    }
+}
+
+fn parse_range(line: &str) -> Option<Range> {
+    let mut bounds = line.splitn(2, '-');
+    let start = bounds.next()?.parse().ok()?;
+    let end = bounds.next()?.parse().ok()?;
+    Some(Range { start, end })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn state_path_appends_extension() {
+        assert_eq!(
+            ResumeState::state_path(Path::new("/tmp/out.tmp")),
+            PathBuf::from("/tmp/out.tmp.pfstate")
+        );
+    }
+
+    #[test]
+    fn reopen_keeps_completed_ranges() {
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().join("out.tmp");
+        fs::write(&output, b"Hello").unwrap();
+
+        let mut state = ResumeState::open(&output, 10, Some("\"abc\""), None).unwrap();
+        assert!(state.completed().is_empty());
+        state.record(Range { start: 0, end: 4 }).unwrap();
+
+        let state = ResumeState::open(&output, 10, Some("\"abc\""), None).unwrap();
+        assert_eq!(state.completed(), &[Range { start: 0, end: 4 }]);
+    }
+
+    #[test]
+    fn reopen_with_changed_etag_discards_ranges() {
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().join("out.tmp");
+        fs::write(&output, b"Hello").unwrap();
+
+        let mut state = ResumeState::open(&output, 10, Some("\"abc\""), None).unwrap();
+        state.record(Range { start: 0, end: 4 }).unwrap();
+
+        let state = ResumeState::open(&output, 10, Some("\"def\""), None).unwrap();
+        assert!(state.completed().is_empty());
+    }
+}
@@ -0,0 +1,110 @@
+use std::io;
+use std::str::FromStr;
+
+use reqwest::StatusCode;
+
+use crate::errors::FetchError;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Which classes of errors cause a failed chunk fetch to be retried
+pub enum RetryPolicy {
+    /// Retry HTTP server errors and transport errors such as refused
+    /// connections, but not client errors
+    Network,
+    /// Also retry transient I/O errors such as timeouts,
+    /// connection resets and truncated responses
+    Transient,
+    /// Retry every error other than client errors and invalid arguments
+    All,
+}
+
+impl RetryPolicy {
+    /// Whether a chunk fetch which failed with error should be retried
+    pub fn is_retryable(self, error: &FetchError) -> bool {
+        match error {
+            FetchError::ReqwestError(err) => match err.status() {
+                Some(status) => {
+                    !status.is_client_error() || status == StatusCode::TOO_MANY_REQUESTS
+                }
+                None => true,
+            },
+            FetchError::IoError(err) => match self {
+                RetryPolicy::Network => false,
+                RetryPolicy::Transient => is_transient(err.kind()),
+                RetryPolicy::All => true,
+            },
+            FetchError::InvalidArgumentsError(_) => false,
+            FetchError::ContentChangedError(_) => false,
+            FetchError::InterruptedError(_) => false,
+            _ => self == RetryPolicy::All,
+        }
+    }
+}
+
+fn is_transient(kind: io::ErrorKind) -> bool {
+    matches!(
+        kind,
+        io::ErrorKind::TimedOut
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::Interrupted
+    )
+}
+
+impl FromStr for RetryPolicy {
+    type Err = FetchError;
+
+    fn from_str(s: &str) -> Result<RetryPolicy, Self::Err> {
+        match s {
+            "network" => Ok(RetryPolicy::Network),
+            "transient" => Ok(RetryPolicy::Transient),
+            "all" => Ok(RetryPolicy::All),
+            _ => Err(FetchError::InvalidArgumentsError(format!(
+                "Unknown retry policy {}",
+                s
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn io_error(kind: io::ErrorKind) -> FetchError {
+        FetchError::IoError(io::Error::new(kind, "testing"))
+    }
+
+    #[test]
+    fn network_does_not_retry_io() {
+        assert!(!RetryPolicy::Network.is_retryable(&io_error(io::ErrorKind::TimedOut)));
+    }
+
+    #[test]
+    fn transient_retries_transient_io() {
+        assert!(RetryPolicy::Transient.is_retryable(&io_error(io::ErrorKind::TimedOut)));
+        assert!(RetryPolicy::Transient.is_retryable(&io_error(io::ErrorKind::ConnectionReset)));
+        assert!(!RetryPolicy::Transient.is_retryable(&io_error(io::ErrorKind::PermissionDenied)));
+    }
+
+    #[test]
+    fn all_retries_everything_but_invalid_arguments() {
+        assert!(RetryPolicy::All.is_retryable(&io_error(io::ErrorKind::PermissionDenied)));
+        assert!(
+            RetryPolicy::All.is_retryable(&FetchError::ServerSupportError("testing".to_owned()))
+        );
+        assert!(!RetryPolicy::All
+            .is_retryable(&FetchError::InvalidArgumentsError("testing".to_owned())));
+    }
+
+    #[test]
+    fn parse_policy() {
+        assert_eq!(
+            "transient".parse::<RetryPolicy>().unwrap(),
+            RetryPolicy::Transient
+        );
+        assert!("sometimes".parse::<RetryPolicy>().is_err());
+    }
+}
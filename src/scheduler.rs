@@ -0,0 +1,213 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::errors::Result;
+use crate::fetch::Range;
+use crate::utils::{chunk_count, create_ranges};
+
+/// The largest range the default scheduler queues, so that large
+/// downloads are split into many more ranges than there are workers
+const DEFAULT_CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
+#[derive(Debug)]
+/// The state of a download provided to a ChunkScheduler
+/// when a fetch worker asks for its next range
+pub struct ScheduleState<'a> {
+    /// The total length of the content being fetched
+    pub content_length: u64,
+    /// The ranges which have been successfully fetched
+    pub completed: &'a [Range],
+    /// The ranges which are currently being fetched
+    pub in_flight: &'a [Range],
+    /// The index of the fetch worker asking for a range
+    pub worker: usize,
+    /// The throughput in bytes per second of the worker's last range,
+    /// if it has completed one
+    pub throughput: Option<f64>,
+}
+
+/// Decides which range each parallel fetch worker downloads next
+pub trait ChunkScheduler: fmt::Debug + Send + Sync {
+    /// Prepare to schedule a download of content_length bytes
+    /// using num_fetches parallel workers. Called at the start of
+    /// every download attempt, including restarts
+    fn plan(&mut self, content_length: u64, num_fetches: u64) -> Result<()>;
+
+    /// Return the next range for a worker to fetch, or None
+    /// if the worker should stop
+    fn next_range(&mut self, state: &ScheduleState<'_>) -> Option<Range>;
+}
+
+#[derive(Debug)]
+/// The part of an in-flight range its worker has yet to write, whose
+/// tail may be split off so that an idle worker can fetch it instead
+pub struct Claim {
+    /// The first byte not yet written
+    next: u64,
+    /// The last byte the worker may write
+    end: u64,
+}
+
+impl Claim {
+    /// Claim all of range for its worker
+    pub fn new(range: Range) -> Claim {
+        Claim {
+            next: range.start,
+            end: range.end,
+        }
+    }
+
+    /// The last byte the worker may write
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    /// The first byte not yet written
+    pub fn next(&self) -> u64 {
+        self.next
+    }
+
+    /// The bytes left to write
+    pub fn remaining(&self) -> u64 {
+        (self.end + 1).saturating_sub(self.next)
+    }
+
+    /// Claim len bytes to be written at position, returning
+    /// how many of them come before the end of the range
+    pub fn take(&mut self, position: u64, len: u64) -> u64 {
+        let allowed = (self.end + 1).saturating_sub(position).min(len);
+        self.next = self.next.max(position + allowed);
+        allowed
+    }
+
+    /// Split off the second half of the bytes left to write,
+    /// if each half would be at least min_size bytes
+    pub fn split(&mut self, min_size: u64) -> Option<Range> {
+        let remaining = self.remaining();
+        if remaining < min_size.max(1) * 2 {
+            return None;
+        }
+        let start = self.next + remaining / 2;
+        let tail = Range {
+            start,
+            end: self.end,
+        };
+        self.end = start - 1;
+        Some(tail)
+    }
+}
+
+#[derive(Debug, Default)]
+/// The default scheduler, which splits the content up front into a
+/// queue of ranges of at most 8 MiB, and at least one per worker.
+/// Each worker takes the next range as soon as it finishes one, so
+/// faster connections fetch more of the content and a failure only
+/// retries a small range. A target chunk size replaces the default
+pub struct StaticScheduler {
+    ranges: VecDeque<Range>,
+    chunk_size: Option<u64>,
+}
+
+impl StaticScheduler {
+    /// Split the content into ranges of roughly chunk_size bytes,
+    /// however many workers there are
+    pub fn with_chunk_size(chunk_size: u64) -> StaticScheduler {
+        StaticScheduler {
+            ranges: VecDeque::new(),
+            chunk_size: Some(chunk_size),
+        }
+    }
+}
+
+impl ChunkScheduler for StaticScheduler {
+    fn plan(&mut self, content_length: u64, num_fetches: u64) -> Result<()> {
+        let num_ranges = match self.chunk_size {
+            Some(chunk_size) => chunk_count(content_length, chunk_size),
+            None => chunk_count(content_length, DEFAULT_CHUNK_SIZE).max(num_fetches),
+        };
+        self.ranges = create_ranges(content_length, num_ranges)?.into();
+        Ok(())
+    }
+
+    fn next_range(&mut self, _state: &ScheduleState<'_>) -> Option<Range> {
+        self.ranges.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn static_scheduler_assigns_each_range_once() {
+        let mut scheduler = StaticScheduler::default();
+        scheduler.plan(10, 2).unwrap();
+
+        let state = ScheduleState {
+            content_length: 10,
+            completed: &[],
+            in_flight: &[],
+            worker: 0,
+            throughput: None,
+        };
+
+        assert_eq!(
+            scheduler.next_range(&state),
+            Some(Range { start: 0, end: 4 })
+        );
+        assert_eq!(
+            scheduler.next_range(&state),
+            Some(Range { start: 5, end: 9 })
+        );
+        assert_eq!(scheduler.next_range(&state), None);
+    }
+
+    #[test]
+    fn static_scheduler_queues_small_ranges() {
+        let mut scheduler = StaticScheduler::default();
+        scheduler.plan(100 * 1024 * 1024, 2).unwrap();
+        assert_eq!(scheduler.ranges.len(), 13);
+        assert!(scheduler
+            .ranges
+            .iter()
+            .all(|range| range.end - range.start < DEFAULT_CHUNK_SIZE));
+    }
+
+    #[test]
+    fn claim_splits_unwritten_tail() {
+        let mut claim = Claim::new(Range { start: 0, end: 99 });
+        assert_eq!(claim.take(0, 20), 20);
+        assert_eq!(claim.split(50), None);
+        assert_eq!(claim.split(10), Some(Range { start: 60, end: 99 }));
+        assert_eq!(claim.end(), 59);
+
+        // A write straddling the new end is cut short
+        assert_eq!(claim.take(20, 30), 30);
+        assert_eq!(claim.take(50, 30), 10);
+        assert_eq!(claim.remaining(), 0);
+    }
+
+    #[test]
+    fn static_scheduler_splits_by_chunk_size() {
+        let mut scheduler = StaticScheduler::with_chunk_size(4);
+        scheduler.plan(10, 2).unwrap();
+
+        let state = ScheduleState {
+            content_length: 10,
+            completed: &[],
+            in_flight: &[],
+            worker: 0,
+            throughput: None,
+        };
+
+        let ranges: Vec<Range> = std::iter::from_fn(|| scheduler.next_range(&state)).collect();
+        assert_eq!(
+            ranges,
+            vec![
+                Range { start: 0, end: 2 },
+                Range { start: 3, end: 5 },
+                Range { start: 6, end: 9 }
+            ]
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use std::sync::Mutex;
+
+/// How much slower than the median of the others a range may be
+/// before it is reported as slow
+const SLOW_FACTOR: f64 = 0.25;
+
+/// How many ranges must have completed before one is compared to them
+const MIN_COMPARED: usize = 3;
+
+/// The smallest range whose throughput is compared, as the time to
+/// first byte dominates how long smaller ranges take
+const MIN_COMPARED_SIZE: u64 = 256 * 1024;
+
+#[derive(Debug, Default)]
+/// The throughput of each range of a download completed so far, against
+/// which each newly completed range is compared, so that ranges slowed
+/// by a throttling server or CDN node stand out
+pub struct Throughputs {
+    completed: Mutex<Vec<f64>>,
+}
+
+impl Throughputs {
+    /// Track the throughput of a download's ranges
+    pub fn new() -> Throughputs {
+        Throughputs::default()
+    }
+
+    /// Record the throughput in bytes per second of a completed range of
+    /// bytes, returning the median throughput of the ranges before it if
+    /// the range was much slower than them
+    pub fn record(&self, bytes: u64, throughput: f64) -> Option<f64> {
+        if bytes < MIN_COMPARED_SIZE {
+            return None;
+        }
+        let mut completed = self.completed.lock().unwrap();
+        let median = if completed.len() >= MIN_COMPARED {
+            let mut sorted = completed.clone();
+            sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+            Some(sorted[sorted.len() / 2])
+        } else {
+            None
+        };
+        completed.push(throughput);
+        median.filter(|median| throughput < median * SLOW_FACTOR)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slow_ranges_stand_out() {
+        let throughputs = Throughputs::new();
+        // Too few ranges to compare against at first
+        assert_eq!(throughputs.record(MIN_COMPARED_SIZE, 1000.0), None);
+        assert_eq!(throughputs.record(MIN_COMPARED_SIZE, 10.0), None);
+        assert_eq!(throughputs.record(MIN_COMPARED_SIZE, 1200.0), None);
+
+        assert_eq!(throughputs.record(MIN_COMPARED_SIZE, 900.0), None);
+        // A small range is not compared, however slow
+        assert_eq!(throughputs.record(1024, 1.0), None);
+        assert_eq!(throughputs.record(MIN_COMPARED_SIZE, 100.0), Some(1000.0));
+    }
+}
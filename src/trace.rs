@@ -0,0 +1,151 @@
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::str::FromStr;
+
+use reqwest::header::HeaderValue;
+
+use crate::errors::FetchError;
+
+/// The only version of the traceparent header which is defined
+const VERSION: &str = "00";
+
+#[derive(Debug, Clone, PartialEq)]
+/// A W3C trace context, identifying a span within a distributed trace.
+///
+/// Given the context of the span which triggered it, a download starts a
+/// span of its own as a child of it, and sends every request with a
+/// `traceparent` header naming a new child of the download's span, so
+/// that servers taking part in the trace can attach their spans to it.
+/// Spans are not exported anywhere; their ids are logged so that the
+/// download's logs can be matched up with the trace
+pub struct TraceContext {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    flags: u8,
+}
+
+impl TraceContext {
+    /// A context starting a new trace, which is sampled
+    pub fn new() -> TraceContext {
+        let mut trace_id = [0; 16];
+        trace_id[..8].copy_from_slice(&random_id());
+        trace_id[8..].copy_from_slice(&random_id());
+        TraceContext {
+            trace_id,
+            span_id: random_id(),
+            flags: 1,
+        }
+    }
+
+    /// A new span in the same trace, whose parent is this one
+    pub fn child(&self) -> TraceContext {
+        TraceContext {
+            trace_id: self.trace_id,
+            span_id: random_id(),
+            flags: self.flags,
+        }
+    }
+
+    /// The id of the trace, as hex
+    pub fn trace_id(&self) -> String {
+        hex::encode(self.trace_id)
+    }
+
+    /// The id of the span, as hex
+    pub fn span_id(&self) -> String {
+        hex::encode(self.span_id)
+    }
+
+    /// The value of a traceparent header naming this span as the parent
+    pub fn header_value(&self) -> HeaderValue {
+        HeaderValue::from_str(&self.to_string()).expect("a traceparent is always valid")
+    }
+}
+
+impl Default for TraceContext {
+    fn default() -> TraceContext {
+        TraceContext::new()
+    }
+}
+
+impl fmt::Display for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}-{}-{:02x}",
+            VERSION,
+            self.trace_id(),
+            self.span_id(),
+            self.flags
+        )
+    }
+}
+
+impl FromStr for TraceContext {
+    type Err = FetchError;
+
+    /// Parse a traceparent header, such as
+    /// `00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01`
+    fn from_str(s: &str) -> Result<TraceContext, Self::Err> {
+        let invalid = || FetchError::InvalidArgumentsError(format!("Invalid traceparent {}", s));
+        let parts: Vec<&str> = s.trim().split('-').collect();
+        if parts.len() != 4 || parts[0] != VERSION {
+            return Err(invalid());
+        }
+        let mut trace_id = [0; 16];
+        let mut span_id = [0; 8];
+        let mut flags = [0; 1];
+        hex::decode_to_slice(parts[1], &mut trace_id).map_err(|_| invalid())?;
+        hex::decode_to_slice(parts[2], &mut span_id).map_err(|_| invalid())?;
+        hex::decode_to_slice(parts[3], &mut flags).map_err(|_| invalid())?;
+        // Ids of all zeroes are reserved as invalid
+        if trace_id == [0; 16] || span_id == [0; 8] {
+            return Err(invalid());
+        }
+        Ok(TraceContext {
+            trace_id,
+            span_id,
+            flags: flags[0],
+        })
+    }
+}
+
+/// Eight random bytes which are not all zero
+fn random_id() -> [u8; 8] {
+    loop {
+        let id = RandomState::new().build_hasher().finish();
+        if id != 0 {
+            return id.to_be_bytes();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn traceparent_round_trip() {
+        let traceparent = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+        let context: TraceContext = traceparent.parse().unwrap();
+        assert_eq!(context.trace_id(), "0af7651916cd43dd8448eb211c80319c");
+        assert_eq!(context.span_id(), "b7ad6b7169203331");
+        assert_eq!(context.to_string(), traceparent);
+
+        let child = context.child();
+        assert_eq!(child.trace_id(), context.trace_id());
+        assert_ne!(child.span_id(), context.span_id());
+        assert!(child.to_string().ends_with("-01"));
+
+        assert!("01-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"
+            .parse::<TraceContext>()
+            .is_err());
+        assert!("00-00000000000000000000000000000000-b7ad6b7169203331-01"
+            .parse::<TraceContext>()
+            .is_err());
+        assert!("00-0af7651916cd43dd-b7ad6b7169203331-01"
+            .parse::<TraceContext>()
+            .is_err());
+    }
+}
@@ -0,0 +1,177 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io;
+use std::mem;
+use std::os::unix::io::AsRawFd;
+
+use io_uring::{opcode, squeue, types, IoUring};
+
+use crate::output::write_all_at;
+
+/// The number of submission queue entries of each thread's ring.
+/// A thread waits for each entry it submits, so few are needed
+const RING_ENTRIES: u32 = 8;
+
+thread_local! {
+    /// The ring of each blocking pool thread, or None where the kernel
+    /// refuses to create one, as when io_uring is disabled
+    static RING: RefCell<Option<Ring>> = RefCell::new(IoUring::new(RING_ENTRIES).ok().map(|ring| Ring {
+        ring,
+        last: 0,
+        broken: false,
+    }));
+}
+
+/// An io_uring ring used by one thread, one entry at a time
+struct Ring {
+    ring: IoUring,
+    /// The user_data of the last entry submitted
+    last: u64,
+    /// Set once the ring fails without knowing whether an entry completed.
+    /// The kernel may still use that entry's buffer, so the ring is not
+    /// used again and the thread writes with pwrite instead
+    broken: bool,
+}
+
+impl Ring {
+    /// Submit entry and wait for it to complete, returning its result,
+    /// a negated errno on failure. Completions of any other entry are
+    /// discarded, so the result read is always this entry's own.
+    ///
+    /// The buffer entry refers to must stay valid until it completes.
+    /// It has once this returns Ok; if it returns an error with the ring
+    /// broken, the caller must leak the buffer rather than free it
+    fn complete(&mut self, entry: squeue::Entry) -> io::Result<i32> {
+        self.last = self.last.wrapping_add(1);
+        let id = self.last;
+        // Safe as the caller keeps the buffer of entry valid, as above
+        unsafe {
+            self.ring
+                .submission()
+                .push(&entry.user_data(id))
+                .map_err(|_| io::Error::other("io_uring submission queue is full"))?;
+        }
+        loop {
+            match self.ring.submit_and_wait(1) {
+                Ok(_) => {}
+                Err(ref err)
+                    if [libc::EINTR, libc::EAGAIN, libc::EBUSY]
+                        .contains(&err.raw_os_error().unwrap_or(0)) => {}
+                Err(err) => {
+                    self.broken = true;
+                    return Err(err);
+                }
+            }
+            if let Some(completed) = self
+                .ring
+                .completion()
+                .find(|completed| completed.user_data() == id)
+            {
+                return Ok(completed.result());
+            }
+        }
+    }
+}
+
+/// Write all of buf at offset in file through the ring of the calling
+/// thread, or with pwrite where it has none
+pub(crate) fn write_file_at(file: &File, buf: Vec<u8>, mut offset: u64) -> io::Result<()> {
+    RING.with(|ring| {
+        let mut ring = ring.borrow_mut();
+        let ring = match ring.as_mut() {
+            Some(ring) if !ring.broken => ring,
+            _ => return write_all_at(file, &buf, offset),
+        };
+
+        let mut rest = &buf[..];
+        while !rest.is_empty() {
+            let len = rest.len().min(u32::MAX as usize) as u32;
+            let write = opcode::Write::new(types::Fd(file.as_raw_fd()), rest.as_ptr(), len)
+                .offset(offset)
+                .build();
+            match ring.complete(write) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "failed to write whole buffer",
+                    ))
+                }
+                Ok(result) if result == -libc::EINTR => {}
+                Ok(result) if result < 0 => return Err(io::Error::from_raw_os_error(-result)),
+                Ok(written) => {
+                    rest = &rest[written as usize..];
+                    offset += written as u64;
+                }
+                Err(err) => {
+                    if ring.broken {
+                        mem::forget(buf);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Make the data written to file durable through the ring of the calling
+/// thread, or with fdatasync where it has none
+pub(crate) fn sync_file(file: &File) -> io::Result<()> {
+    RING.with(|ring| {
+        let mut ring = ring.borrow_mut();
+        let ring = match ring.as_mut() {
+            Some(ring) if !ring.broken => ring,
+            _ => return file.sync_data(),
+        };
+
+        loop {
+            let fsync = opcode::Fsync::new(types::Fd(file.as_raw_fd()))
+                .flags(types::FsyncFlags::DATASYNC)
+                .build();
+            match ring.complete(fsync)? {
+                result if result == -libc::EINTR => {}
+                result if result < 0 => return Err(io::Error::from_raw_os_error(-result)),
+                _ => return Ok(()),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn writes_at_offsets() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("out.bin");
+        let file = File::create(&path).unwrap();
+
+        write_file_at(&file, b"World".to_vec(), 5).unwrap();
+        write_file_at(&file, b"Hello".to_vec(), 0).unwrap();
+        sync_file(&file).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"HelloWorld");
+    }
+
+    #[test]
+    fn stale_completions_ignored() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("out.bin");
+        let file = File::create(&path).unwrap();
+
+        // Leave the completion of an entry nobody waits for in the ring
+        RING.with(|ring| {
+            if let Some(ring) = ring.borrow_mut().as_mut() {
+                let nop = opcode::Nop::new().build().user_data(u64::MAX);
+                unsafe { ring.ring.submission().push(&nop).unwrap() };
+                ring.ring.submit_and_wait(1).unwrap();
+            }
+        });
+
+        write_file_at(&file, b"Hello".to_vec(), 0).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"Hello");
+    }
+}
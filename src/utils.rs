@@ -1,38 +1,87 @@
-use std::path::{Path, PathBuf};
+use std::path::{is_separator, Path, PathBuf};
+use std::time::{Duration, SystemTime};
 use std::{fs, io};
 
+use chrono::{DateTime, Utc};
+
 use hex;
-use md5::{Digest, Md5};
+use percent_encoding::percent_decode_str;
+use reqwest::header::{HeaderName, HeaderValue};
+#[cfg(feature = "native-tls")]
+use reqwest::Certificate;
 use reqwest::Url;
 
 use crate::errors::{FetchError, Result};
 use crate::fetch::Range;
 
 /// Check a ETag in the form of a md5 hash hex string
-/// against a file at path location
-pub fn check_etag(etag: &str, path: &PathBuf) -> Result<()> {
-    let mut file = fs::File::open(path)?;
-    let mut hasher = Md5::new();
-    let _n = io::copy(&mut file, &mut hasher)?;
-    let hash = hasher.result();
+/// against the md5 hash of the downloaded file
+pub fn check_etag(etag: &str, hash: &[u8]) -> Result<()> {
     let etag_bytes = hex::decode(&etag).map_err(|_| {
-        Box::new(FetchError::ServerSupportError(
+        FetchError::ServerSupportError(
             "Server returned ETag which could not be parsed into bytes".to_owned(),
-        ))
+        )
     })?;
     if etag_bytes[..] == hash[..] {
         Ok(())
     } else {
-        Err(Box::new(FetchError::ValidationError(
+        Err(FetchError::ValidationError(
             "ETag does not match".to_owned(),
-        )))
+        ))
+    }
+}
+
+/// Split an S3 style multipart ETag such as `abc123-8`
+/// into its hex hash and its number of parts
+pub fn split_multipart_etag(etag: &str) -> Option<(&str, u64)> {
+    let mut parts = etag.rsplitn(2, '-');
+    let count = parts.next()?.parse().ok()?;
+    let hash = parts.next()?;
+    Some((hash, count))
+}
+
+/// Names reserved for devices on Windows, with or without an extension
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Percent-decode the last segment of a url's path into a filename
+/// which is safe to create in the output directory. Any directory
+/// components are stripped, and characters which are reserved on
+/// some platforms replaced, returning None if nothing usable is left
+pub fn sanitize_filename(segment: &str) -> Option<String> {
+    let decoded = percent_decode_str(segment).decode_utf8_lossy();
+    let name = decoded.rsplit(['/', '\\']).next()?;
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    // Windows drops trailing dots and spaces
+    let name = name.trim_end_matches(['.', ' ']);
+    if name.is_empty() {
+        return None;
+    }
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+    {
+        return Some(format!("_{}", name));
     }
+    Some(name.to_owned())
 }
 
 /// Takes an optional output and a url to download from
-/// and returns an output path to write to
-pub fn parse_path(output_option: &Option<String>, url: &str) -> Result<PathBuf> {
-    let parsed_url = Url::parse(url).unwrap();
+/// and returns an output path to write to. If create_dirs is set,
+/// missing directories of the output are created rather than rejected
+pub fn parse_path(output_option: &Option<String>, url: &str, create_dirs: bool) -> Result<PathBuf> {
+    let parsed_url = Url::parse(url)
+        .map_err(|_| FetchError::InvalidArgumentsError("Url could not be parsed".to_owned()))?;
 
     let segments = parsed_url.path_segments();
 
@@ -46,6 +95,8 @@ pub fn parse_path(output_option: &Option<String>, url: &str) -> Result<PathBuf>
     if url_filename == "" {
         url_filename = default_filename;
     }
+    let url_filename =
+        sanitize_filename(url_filename).unwrap_or_else(|| default_filename.to_owned());
 
     let mut output_path = if let Some(o) = output_option {
         Path::new(&o).to_path_buf()
@@ -53,41 +104,216 @@ pub fn parse_path(output_option: &Option<String>, url: &str) -> Result<PathBuf>
         Path::new("./").to_path_buf()
     };
 
+    // An output ending in a separator names a directory
+    if create_dirs
+        && output_option
+            .as_ref()
+            .is_some_and(|o| o.ends_with(is_separator))
+    {
+        fs::create_dir_all(&output_path)?;
+    }
+
     // If the path is a directory, the filename
     // comes from the url
     if output_path.is_dir() {
-        output_path.push(url_filename);
+        output_path.push(&url_filename);
     } else {
         // If path is not a directory, ensure that
         // parent *is*
         match output_path.parent() {
             None => {
-                return Err(Box::new(FetchError::InvalidArgumentsError(
+                return Err(FetchError::InvalidArgumentsError(
                     "Output argument invalid".to_owned(),
-                )));
+                ));
             }
             Some(p) => {
+                if create_dirs && !p.as_os_str().is_empty() {
+                    fs::create_dir_all(p)?;
+                }
                 if !p.is_dir() {
-                    return Err(Box::new(FetchError::InvalidArgumentsError(
+                    return Err(FetchError::InvalidArgumentsError(
                         "Output argument invalid".to_owned(),
-                    )));
+                    ));
                 }
             }
         }
     }
 
-    // TODO: check if file already exists?
+    // An existing file is handled by the clobber policy
     Ok(output_path)
 }
 
+/// The temporary file a download to path is written to
+/// until it completes, named path with `.part` appended
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    path.with_file_name(name)
+}
+
+/// Whether the media type of a Content-Type value matches pattern,
+/// ignoring case and any parameters such as `charset`. A `*` in the
+/// pattern matches any characters, and one without matches as a prefix
+pub fn content_type_matches(pattern: &str, content_type: &str) -> bool {
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let pattern = pattern.trim().to_ascii_lowercase();
+    if !pattern.contains('*') {
+        return media_type.starts_with(&pattern);
+    }
+    let parts: Vec<&str> = pattern.split('*').collect();
+    // A pattern containing a `*` splits into at least two parts
+    let (first, rest) = parts.split_first().unwrap();
+    let (last, middle) = rest.split_last().unwrap();
+    let mut remaining = match media_type.strip_prefix(first) {
+        Some(remaining) => remaining,
+        None => return false,
+    };
+    for part in middle {
+        match remaining.find(part) {
+            Some(index) => remaining = &remaining[index + part.len()..],
+            None => return false,
+        }
+    }
+    remaining.ends_with(last)
+}
+
+/// Parse a human readable byte size such as `512`, `8K`, `2MiB` or `1GB`.
+/// Binary suffixes (`K`, `KiB`, `M`, `MiB`, ...) are powers of 1024 and
+/// decimal suffixes (`KB`, `MB`, ...) are powers of 1000
+pub fn parse_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (number, suffix) = size.split_at(split);
+
+    let multiplier: u64 = match suffix.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kib" => 1 << 10,
+        "kb" => 1_000,
+        "m" | "mib" => 1 << 20,
+        "mb" => 1_000_000,
+        "g" | "gib" => 1 << 30,
+        "gb" => 1_000_000_000,
+        "t" | "tib" => 1 << 40,
+        "tb" => 1_000_000_000_000,
+        _ => {
+            return Err(FetchError::InvalidArgumentsError(format!(
+                "Unknown size suffix in {}",
+                size
+            )))
+        }
+    };
+
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| FetchError::InvalidArgumentsError(format!("Invalid size {}", size)))
+}
+
+/// Parse an inclusive byte range such as `1000-2000000`, either end of
+/// which may be given as a size such as `4MiB`. A missing end, as in
+/// `1000-`, runs to the end of the file
+pub fn parse_byte_range(range: &str) -> Result<Range> {
+    let invalid = || {
+        FetchError::InvalidArgumentsError(format!("Invalid range {}, expected START-END", range))
+    };
+    let (start, end) = range.trim().split_once('-').ok_or_else(invalid)?;
+    let start = parse_size(start)?;
+    let end = if end.trim().is_empty() {
+        u64::MAX
+    } else {
+        parse_size(end)?
+    };
+    if start > end {
+        return Err(invalid());
+    }
+    Ok(Range { start, end })
+}
+
+/// Parse a newline delimited list of urls, skipping
+/// blank lines and comments starting with `#`
+pub fn parse_url_list(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+/// Parse a `Name: value` request header
+pub fn parse_header(header: &str) -> Result<(HeaderName, HeaderValue)> {
+    let invalid = || {
+        FetchError::InvalidArgumentsError(format!(
+            "Header {} is not of the form Name: value",
+            header
+        ))
+    };
+    let split = header.find(':').ok_or_else(invalid)?;
+    let (name, value) = header.split_at(split);
+    let name = HeaderName::from_bytes(name.trim().as_bytes()).map_err(|_| invalid())?;
+    let value = HeaderValue::from_str(value[1..].trim())?;
+    Ok((name, value))
+}
+
+/// Read every certificate of a PEM bundle
+#[cfg(feature = "native-tls")]
+pub fn read_certificates(path: &Path) -> Result<Vec<Certificate>> {
+    const END: &str = "-----END CERTIFICATE-----";
+    let contents = fs::read_to_string(path)?;
+    let certificates = contents
+        .split_inclusive(END)
+        .filter(|block| block.contains(END))
+        .map(|block| Certificate::from_pem(block.as_bytes()))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    if certificates.is_empty() {
+        return Err(FetchError::InvalidArgumentsError(format!(
+            "No certificates found in {}",
+            path.display()
+        )));
+    }
+    Ok(certificates)
+}
+
+/// Parse a Retry-After header value, given either as a number
+/// of seconds or as an HTTP date relative to now
+pub fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        date.with_timezone(&Utc)
+            .signed_duration_since(now)
+            .to_std()
+            .unwrap_or_else(|_| Duration::from_secs(0)),
+    )
+}
+
+/// Parse an HTTP date, as sent in a Last-Modified header
+pub fn parse_http_date(value: &str) -> Option<SystemTime> {
+    let date = DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    Some(date.with_timezone(&Utc).into())
+}
+
 /// Takes a content_length and num_fetches
 /// and returns a Vec<Range> which covers the content_length and where result.len() ==
-/// num_fetches
+/// num_fetches. This is how a StaticScheduler plans its ranges, and
+/// may be used to split the work of fetches made some other way
 pub fn create_ranges(content_length: u64, num_fetches: u64) -> Result<Vec<Range>> {
     if num_fetches == 0 {
-        return Err(Box::new(FetchError::InvalidArgumentsError(
+        return Err(FetchError::InvalidArgumentsError(
             "Number of fetches must be greater than zero".to_owned(),
-        )));
+        ));
     }
     let mut cursor = 0;
     let mut ranges = Vec::new();
@@ -111,16 +337,189 @@ pub fn create_ranges(content_length: u64, num_fetches: u64) -> Result<Vec<Range>
     Ok(ranges)
 }
 
+/// The number of ranges of at most roughly chunk_size bytes
+/// which content_length splits into, and at least one
+pub fn chunk_count(content_length: u64, chunk_size: u64) -> u64 {
+    if chunk_size == 0 {
+        return 1;
+    }
+    content_length.div_ceil(chunk_size).max(1)
+}
+
+/// The number of fetches to split content_length between, so that
+/// no range is smaller than min_split_size, capped at num_fetches
+pub fn split_fetches(content_length: u64, num_fetches: u64, min_split_size: u64) -> u64 {
+    (content_length / min_split_size.max(1))
+        .max(1)
+        .min(num_fetches)
+}
+
+/// Takes the requested num_fetches and returns the sequence of fetch
+/// counts to attempt a download with, each restart using fewer
+/// connections and ending with a single stream
+pub fn degraded_fetches(num_fetches: u64) -> Vec<u64> {
+    let mut plan = vec![num_fetches];
+
+    let reduced = num_fetches / 2;
+    if reduced > 1 {
+        plan.push(reduced);
+    }
+    if num_fetches > 1 {
+        plan.push(1);
+    }
+
+    plan
+}
+
+/// Takes the path of a partially downloaded output and the remote
+/// content_length and returns the range already present on disk,
+/// assuming the existing bytes are a contiguous prefix of the content
+pub fn existing_range(path: &PathBuf, content_length: u64) -> Result<Option<Range>> {
+    let len = match fs::metadata(path) {
+        Ok(metadata) => metadata.len(),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    if len > content_length {
+        return Err(FetchError::ValidationError(
+            "Existing output is larger than the remote content".to_owned(),
+        ));
+    }
+
+    if len == 0 {
+        Ok(None)
+    } else {
+        Ok(Some(Range {
+            start: 0,
+            end: len - 1,
+        }))
+    }
+}
+
+/// Reserve len bytes of disk for the file at path, creating it if
+/// needed, so that a full disk is reported before downloading
+/// rather than partway through. The file is left exactly len bytes long
+pub fn preallocate(path: &Path, len: u64) -> Result<()> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(path)?;
+
+    // Elsewhere set_len leaves a sparse file, reserving nothing
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+
+        if len > 0 {
+            let result = unsafe { libc::posix_fallocate(file.as_raw_fd(), 0, len as libc::off_t) };
+            if result != 0 {
+                return Err(io::Error::from_raw_os_error(result).into());
+            }
+        }
+    }
+
+    file.set_len(len)?;
+    Ok(())
+}
+
+/// The bytes available to unprivileged users on the filesystem which
+/// holds path, or None on platforms where this is not known
+pub fn available_space(path: &Path) -> Result<Option<u64>> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    #[cfg(unix)]
+    {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = CString::new(dir.as_os_str().as_bytes()).map_err(|_| {
+            FetchError::InvalidArgumentsError(format!(
+                "Output directory {:?} contains a nul byte",
+                dir
+            ))
+        })?;
+        let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::statvfs(dir.as_ptr(), &mut stats) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(Some(stats.f_bavail as u64 * stats.f_frsize as u64))
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = dir;
+        Ok(None)
+    }
+}
+
+/// Takes a range and a list of already completed ranges
+/// and returns the parts of range which are not yet completed
+pub fn subtract_ranges(range: Range, completed: &[Range]) -> Vec<Range> {
+    let mut completed = completed.to_vec();
+    completed.sort_by_key(|r| r.start);
+
+    let mut cursor = range.start;
+    let mut remaining = Vec::new();
+
+    for done in completed {
+        if done.end < cursor || done.start > range.end {
+            continue;
+        }
+        if done.start > cursor {
+            remaining.push(Range {
+                start: cursor,
+                end: done.start - 1,
+            });
+        }
+        cursor = done.end + 1;
+        if cursor > range.end {
+            return remaining;
+        }
+    }
+
+    remaining.push(Range {
+        start: cursor,
+        end: range.end,
+    });
+    remaining
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn split_multipart_etags() {
+        assert_eq!(
+            split_multipart_etag("059d5559e903e95356438a41dfe641e4-3"),
+            Some(("059d5559e903e95356438a41dfe641e4", 3))
+        );
+        assert_eq!(
+            split_multipart_etag("059d5559e903e95356438a41dfe641e4"),
+            None
+        );
+    }
+
+    #[test]
+    fn url_list() {
+        assert_eq!(
+            parse_url_list("# mirrors\nhttp://a.example/one\n\n  http://b.example/two  \n"),
+            vec!["http://a.example/one", "http://b.example/two"]
+        );
+    }
 
     #[test]
     fn range_with_0_chunks() {
         let ranges = create_ranges(100, 0);
         let error = ranges.expect_err("testing");
 
-        if let FetchError::InvalidArgumentsError(msg) = *error {
+        if let FetchError::InvalidArgumentsError(msg) = error {
             assert_eq!("Number of fetches must be greater than zero", msg);
         } else {
             panic!("Expected InvalidArgumentsError");
@@ -151,32 +550,320 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_size_suffixes() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("8K").unwrap(), 8 * 1024);
+        assert_eq!(parse_size("2MiB").unwrap(), 2 * 1024 * 1024);
+        assert_eq!(parse_size("3mb").unwrap(), 3_000_000);
+        assert_eq!(parse_size("1 GiB").unwrap(), 1 << 30);
+    }
+
+    #[test]
+    fn parse_size_invalid() {
+        let error = parse_size("2XB").expect_err("testing");
+
+        if let FetchError::InvalidArgumentsError(msg) = error {
+            assert_eq!("Unknown size suffix in 2XB", msg);
+        } else {
+            panic!("Expected InvalidArgumentsError");
+        }
+
+        assert!(parse_size("MiB").is_err());
+    }
+
+    #[test]
+    fn content_type_patterns() {
+        let binary = "application/octet-stream";
+        assert!(content_type_matches(binary, binary));
+        assert!(content_type_matches("application/", binary));
+        assert!(content_type_matches("application/*", binary));
+        assert!(content_type_matches("*/*-stream", binary));
+        assert!(content_type_matches("Application/Gzip", "application/gzip"));
+        assert!(content_type_matches("text/*", "text/plain; charset=utf-8"));
+        assert!(!content_type_matches(binary, "text/html"));
+        assert!(!content_type_matches("application/*", "text/html"));
+        assert!(!content_type_matches(
+            "*/gzip",
+            "application/x-gzip-compressed"
+        ));
+        assert!(!content_type_matches("application/x-*", ""));
+    }
+
+    #[test]
+    fn parse_byte_ranges() {
+        assert_eq!(
+            parse_byte_range("1000-2000000").unwrap(),
+            Range {
+                start: 1000,
+                end: 2_000_000
+            }
+        );
+        assert_eq!(
+            parse_byte_range("1K-").unwrap(),
+            Range {
+                start: 1024,
+                end: u64::MAX
+            }
+        );
+        assert!(parse_byte_range("2000").is_err());
+        assert!(parse_byte_range("20-10").is_err());
+    }
+
+    #[test]
+    fn parse_headers() {
+        let (name, value) = parse_header("X-Api-Key:  secret ").unwrap();
+        assert_eq!(name, "x-api-key");
+        assert_eq!(value, "secret");
+
+        let error = parse_header("X-Api-Key secret").expect_err("testing");
+        if let FetchError::InvalidArgumentsError(msg) = error {
+            assert_eq!(
+                "Header X-Api-Key secret is not of the form Name: value",
+                msg
+            );
+        } else {
+            panic!("Expected InvalidArgumentsError");
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "native-tls")]
+    fn read_certificate_bundle() {
+        const BUNDLE: &str = "-----BEGIN CERTIFICATE-----
+MIIBfjCCASWgAwIBAgIUfs5y9/6uJW9oYhad2VJB0GsavY0wCgYIKoZIzj0EAwIw
+FDESMBAGA1UEAwwJdGVzdC1jYS1hMCAXDTI2MTAxNTAzNDEyNloYDzIxMjYwOTIx
+MDM0MTI2WjAUMRIwEAYDVQQDDAl0ZXN0LWNhLWEwWTATBgcqhkjOPQIBBggqhkjO
+PQMBBwNCAASIHmQHRCGze4qn17tIFQZ/pUxB/Hqro+v0qRq2Xq5XOMrGYaMqzj5/
+0rSVC2phIGkX2ETb1Ti/XeQ4tETlzm68o1MwUTAdBgNVHQ4EFgQUyCzeGjklbmc6
+MY/VnOOL3w6IRPMwHwYDVR0jBBgwFoAUyCzeGjklbmc6MY/VnOOL3w6IRPMwDwYD
+VR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNHADBEAiA36RClrwcaBM1aQV+Cl+Zl
+VLWzvBAiMrC7f4MpSQwRFwIgWOiIErlijpygrGO9JyNjiQPO2p1uAz3vHkyhK2dV
+OLU=
+-----END CERTIFICATE-----
+-----BEGIN CERTIFICATE-----
+MIIBfzCCASWgAwIBAgIUDSBfRQwJQbtvhJRZ2BljrtpiveMwCgYIKoZIzj0EAwIw
+FDESMBAGA1UEAwwJdGVzdC1jYS1iMCAXDTI2MTAxNTAzNDEyNloYDzIxMjYwOTIx
+MDM0MTI2WjAUMRIwEAYDVQQDDAl0ZXN0LWNhLWIwWTATBgcqhkjOPQIBBggqhkjO
+PQMBBwNCAATaX+yyLKxBKSutOEVcLku+ulS5tS2Rl6Y7nQdS+FvQqCp+MB2rKLaJ
+VBegzt4B8+HAVQB0O5tmGSaqbgRs/OPZo1MwUTAdBgNVHQ4EFgQUw6413xxo2te6
+mzj13ZMBszvvgm0wHwYDVR0jBBgwFoAUw6413xxo2te6mzj13ZMBszvvgm0wDwYD
+VR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNIADBFAiEA7Tzxkz6jBQrshPRyvZXn
+EQRxVngqGIHoSmOjA2kwLRsCIFKOc8ksOzwyiuTdcX8AEqKOqg+1HF9UNb4DAQcX
+7jGx
+-----END CERTIFICATE-----
+";
+
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("ca.pem");
+        fs::write(&path, BUNDLE).unwrap();
+        assert_eq!(read_certificates(&path).unwrap().len(), 2);
+
+        fs::write(&path, "not a certificate").unwrap();
+        let error = read_certificates(&path).expect_err("testing");
+        if let FetchError::InvalidArgumentsError(msg) = error {
+            assert_eq!(format!("No certificates found in {}", path.display()), msg);
+        } else {
+            panic!("Expected InvalidArgumentsError");
+        }
+    }
+
+    #[test]
+    fn parse_retry_after_seconds_and_dates() {
+        let now = DateTime::parse_from_rfc2822("Wed, 21 Oct 2015 07:28:00 GMT")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:29:30 GMT", now),
+            Some(Duration::from_secs(90))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+            Some(Duration::from_secs(0))
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn existing_range_larger_than_content() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("out.tmp");
+        fs::write(&path, b"HelloWorld").unwrap();
+
+        let error = existing_range(&path, 5).expect_err("testing");
+
+        if let FetchError::ValidationError(msg) = error {
+            assert_eq!("Existing output is larger than the remote content", msg);
+        } else {
+            panic!("Expected ValidationError");
+        }
+    }
+
+    #[test]
+    fn existing_range_partial() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("out.tmp");
+        fs::write(&path, b"Hello").unwrap();
+
+        let range = existing_range(&path, 10).unwrap();
+
+        assert_eq!(range, Some(Range { start: 0, end: 4 }));
+        assert_eq!(
+            existing_range(&temp_dir.path().join("missing"), 10).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn preallocate_sets_length() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("out.bin");
+        preallocate(&path, 4096).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 4096);
+
+        fs::write(&path, vec![1; 8192]).unwrap();
+        preallocate(&path, 10).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), vec![1; 10]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn available_space_of_output_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("out.bin");
+        assert!(available_space(&path).unwrap().is_some());
+        assert!(available_space(Path::new("out.bin")).unwrap().is_some());
+    }
+
+    #[test]
+    fn part_path_appends_suffix() {
+        assert_eq!(
+            part_path(Path::new("downloads/archive.tar.gz")),
+            PathBuf::from("downloads/archive.tar.gz.part")
+        );
+    }
+
+    #[test]
+    fn subtract_ranges_with_nothing_completed() {
+        let remaining = subtract_ranges(Range { start: 0, end: 9 }, &[]);
+
+        assert_eq!(remaining, vec![Range { start: 0, end: 9 }]);
+    }
+
+    #[test]
+    fn subtract_ranges_with_partial_overlap() {
+        let remaining = subtract_ranges(
+            Range { start: 0, end: 9 },
+            &[Range { start: 6, end: 7 }, Range { start: 0, end: 2 }],
+        );
+
+        assert_eq!(
+            remaining,
+            vec![Range { start: 3, end: 5 }, Range { start: 8, end: 9 }]
+        );
+    }
+
+    #[test]
+    fn subtract_ranges_fully_completed() {
+        let remaining = subtract_ranges(
+            Range { start: 5, end: 9 },
+            &[Range { start: 0, end: 4 }, Range { start: 5, end: 9 }],
+        );
+
+        assert_eq!(remaining, vec![]);
+    }
+
+    #[test]
+    fn split_fetches_by_content_length() {
+        let mib = 1024 * 1024;
+        assert_eq!(split_fetches(512 * 1024, 10, mib), 1);
+        assert_eq!(split_fetches(4 * mib, 10, mib), 4);
+        assert_eq!(split_fetches(100 * mib, 10, mib), 10);
+        assert_eq!(split_fetches(100 * mib, 10, 40 * mib), 2);
+    }
+
+    #[test]
+    fn parse_http_dates() {
+        let date = parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+        assert_eq!(
+            date.duration_since(SystemTime::UNIX_EPOCH).unwrap(),
+            Duration::from_secs(1_445_412_480)
+        );
+        assert_eq!(parse_http_date("yesterday"), None);
+    }
+
+    #[test]
+    fn degraded_fetches_halves_then_single() {
+        assert_eq!(degraded_fetches(10), vec![10, 5, 1]);
+        assert_eq!(degraded_fetches(3), vec![3, 1]);
+        assert_eq!(degraded_fetches(1), vec![1]);
+    }
+
     #[test]
     fn parse_path_with_none_output_option() {
         let url = "https://test.com/big-image.jpg";
-        let path = parse_path(&None, url).unwrap();
+        let path = parse_path(&None, url, false).unwrap();
 
         assert_eq!(path, PathBuf::from("./big-image.jpg"));
     }
 
+    #[test]
+    fn parse_path_decodes_url_filename() {
+        let url = "https://test.com/files/big%20image.jpg";
+        let path = parse_path(&None, url, false).unwrap();
+
+        assert_eq!(path, PathBuf::from("./big image.jpg"));
+    }
+
+    #[test]
+    fn sanitize_url_filenames() {
+        assert_eq!(
+            sanitize_filename("a%2F..%2Fetc%2Fpasswd").unwrap(),
+            "passwd"
+        );
+        assert_eq!(sanitize_filename("..%5Cboot.ini").unwrap(), "boot.ini");
+        assert_eq!(sanitize_filename("what%3F%2A.txt").unwrap(), "what__.txt");
+        assert_eq!(sanitize_filename("con.txt").unwrap(), "_con.txt");
+        assert_eq!(sanitize_filename("LPT1").unwrap(), "_LPT1");
+        assert_eq!(sanitize_filename("console.txt").unwrap(), "console.txt");
+        assert_eq!(sanitize_filename(".."), None);
+        assert_eq!(sanitize_filename("a%2F"), None);
+    }
+
     #[test]
     fn parse_path_with_none_output_option_and_no_url_filename() {
         let url = "https://test.com/";
-        let path = parse_path(&None, url).unwrap();
+        let path = parse_path(&None, url, false).unwrap();
 
         assert_eq!(path, PathBuf::from("./index.html"));
     }
 
+    #[test]
+    fn parse_path_with_invalid_url() {
+        let error = parse_path(&None, "not a url", false).expect_err("testing");
+
+        if let FetchError::InvalidArgumentsError(msg) = error {
+            assert_eq!("Url could not be parsed", msg);
+        } else {
+            panic!("Expected InvalidArgumentsError");
+        }
+    }
+
     #[test]
     fn parse_path_with_non_existent_output_option_dir() {
         let url = "https://test.com/";
         // I posit this will never exist on a test environment
         let output_option = Some("/tmp/fake/fake/fake/fake".to_owned());
-        let path = parse_path(&output_option, url);
+        let path = parse_path(&output_option, url, false);
 
         let error = path.expect_err("testing");
 
-        if let FetchError::InvalidArgumentsError(msg) = *error {
+        if let FetchError::InvalidArgumentsError(msg) = error {
             assert_eq!("Output argument invalid", msg);
         } else {
             panic!("Expected InvalidArgumentsError");
@@ -187,7 +874,7 @@ mod tests {
     fn parse_path_with_output_option_dir() {
         let url = "https://test.com/big-image.jpg";
         let output_option = Some("/tmp".to_owned());
-        let path = parse_path(&output_option, url).unwrap();
+        let path = parse_path(&output_option, url, false).unwrap();
 
         assert_eq!(path, PathBuf::from("/tmp/big-image.jpg"));
     }
@@ -196,8 +883,25 @@ mod tests {
     fn parse_path_with_output_option_file() {
         let url = "https://test.com/big-image.jpg";
         let output_option = Some("/tmp/my-big-image.jpg".to_owned());
-        let path = parse_path(&output_option, url).unwrap();
+        let path = parse_path(&output_option, url, false).unwrap();
 
         assert_eq!(path, PathBuf::from("/tmp/my-big-image.jpg"));
     }
+
+    #[test]
+    fn parse_path_creating_dirs() {
+        let temp_dir = TempDir::new().unwrap();
+        let url = "https://test.com/big-image.jpg";
+        let output = temp_dir.path().join("a/b/image.jpg");
+        let output_option = Some(output.to_str().unwrap().to_owned());
+        let path = parse_path(&output_option, url, true).unwrap();
+
+        assert_eq!(path, output);
+        assert!(temp_dir.path().join("a/b").is_dir());
+
+        let output_option = Some(format!("{}/c/", temp_dir.path().to_str().unwrap()));
+        let path = parse_path(&output_option, url, true).unwrap();
+
+        assert_eq!(path, temp_dir.path().join("c/big-image.jpg"));
+    }
 }
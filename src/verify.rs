@@ -0,0 +1,670 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use md5::Md5;
+use minisign_verify::{PublicKey, Signature};
+use reqwest::header::HeaderMap;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::errors::{FetchError, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// A hash algorithm which a downloaded file can be verified with
+pub enum ChecksumAlgorithm {
+    /// MD5, as used by plain ETags
+    Md5,
+    /// The MD5 of the concatenated MD5s of each part of the given
+    /// size, as used by the ETags of S3 multipart uploads
+    Md5Multipart(u64),
+    /// SHA-1
+    Sha1,
+    /// SHA-256
+    Sha256,
+    /// SHA-512
+    Sha512,
+    /// BLAKE3
+    Blake3,
+}
+
+impl ChecksumAlgorithm {
+    fn hasher(self) -> Hasher {
+        match self {
+            ChecksumAlgorithm::Md5 => Hasher::Md5(Md5::new()),
+            ChecksumAlgorithm::Md5Multipart(part_size) => {
+                Hasher::Md5Multipart(Box::new(MultipartMd5::new(part_size)))
+            }
+            ChecksumAlgorithm::Sha1 => Hasher::Sha1(Sha1::new()),
+            ChecksumAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            ChecksumAlgorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+            ChecksumAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+}
+
+impl FromStr for ChecksumAlgorithm {
+    type Err = FetchError;
+
+    fn from_str(s: &str) -> Result<ChecksumAlgorithm> {
+        match s.to_ascii_lowercase().as_str() {
+            "md5" => Ok(ChecksumAlgorithm::Md5),
+            "sha1" => Ok(ChecksumAlgorithm::Sha1),
+            "sha256" => Ok(ChecksumAlgorithm::Sha256),
+            "sha512" => Ok(ChecksumAlgorithm::Sha512),
+            "blake3" => Ok(ChecksumAlgorithm::Blake3),
+            _ => Err(FetchError::InvalidArgumentsError(format!(
+                "Unknown checksum algorithm {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ChecksumAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ChecksumAlgorithm::Md5 => "md5",
+            ChecksumAlgorithm::Md5Multipart(_) => "md5-multipart",
+            ChecksumAlgorithm::Sha1 => "sha1",
+            ChecksumAlgorithm::Sha256 => "sha256",
+            ChecksumAlgorithm::Sha512 => "sha512",
+            ChecksumAlgorithm::Blake3 => "blake3",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The expected digest of a downloaded file,
+/// parsed from the form `algorithm:hex`
+pub struct Checksum {
+    /// The hash algorithm
+    pub algorithm: ChecksumAlgorithm,
+    /// The expected digest
+    pub digest: Vec<u8>,
+}
+
+impl Checksum {
+    /// Check the file at path against the checksum
+    pub fn verify(&self, path: &Path) -> Result<()> {
+        let mut file = fs::File::open(path)?;
+        let mut hasher = self.algorithm.hasher();
+        io::copy(&mut file, &mut hasher)?;
+        self.check(&hasher.finish())
+    }
+
+    /// Check a digest computed with the checksum's algorithm against the checksum
+    pub fn check(&self, digest: &[u8]) -> Result<()> {
+        if digest == &self.digest[..] {
+            Ok(())
+        } else {
+            Err(FetchError::ValidationError(format!(
+                "{} checksum does not match",
+                self.algorithm
+            )))
+        }
+    }
+}
+
+impl FromStr for Checksum {
+    type Err = FetchError;
+
+    fn from_str(s: &str) -> Result<Checksum> {
+        let mut parts = s.splitn(2, ':');
+        let algorithm = parts.next().unwrap_or("");
+        let digest = parts.next().ok_or_else(|| {
+            FetchError::InvalidArgumentsError(format!(
+                "Checksum {} is not in the form algorithm:hex",
+                s
+            ))
+        })?;
+        let algorithm = algorithm.parse()?;
+        let digest = hex::decode(digest).map_err(|_| {
+            FetchError::InvalidArgumentsError(format!("Checksum {} is not valid hex", digest))
+        })?;
+        Ok(Checksum { algorithm, digest })
+    }
+}
+
+/// Find the checksum of any of filenames in the contents of a checksum
+/// file such as `SHA256SUMS`. Lines are either in the GNU `<hex>  <file>`
+/// form, whose algorithm is inferred from the digest length, or the BSD
+/// `SHA256 (<file>) = <hex>` form
+pub fn find_checksum(contents: &str, filenames: &[&str]) -> Option<Checksum> {
+    contents.lines().find_map(|line| {
+        let (algorithm, file, digest) = parse_bsd_line(line).or_else(|| parse_gnu_line(line))?;
+        // Entries may name files by relative path
+        let file = file.rsplit('/').next().unwrap_or(file);
+        if !filenames.contains(&file) {
+            return None;
+        }
+        let digest = hex::decode(digest).ok()?;
+        let algorithm = match algorithm {
+            Some(algorithm) => algorithm.parse().ok()?,
+            None => match digest.len() {
+                20 => ChecksumAlgorithm::Sha1,
+                32 => ChecksumAlgorithm::Sha256,
+                64 => ChecksumAlgorithm::Sha512,
+                _ => return None,
+            },
+        };
+        Some(Checksum { algorithm, digest })
+    })
+}
+
+fn parse_gnu_line(line: &str) -> Option<(Option<&str>, &str, &str)> {
+    let mut parts = line.trim().splitn(2, char::is_whitespace);
+    let digest = parts.next()?;
+    // A leading `*` marks a file hashed in binary mode
+    let file = parts.next()?.trim_start().trim_start_matches('*');
+    Some((None, file, digest))
+}
+
+fn parse_bsd_line(line: &str) -> Option<(Option<&str>, &str, &str)> {
+    let open = line.find(" (")?;
+    let close = line.rfind(") = ")?;
+    if close < open {
+        return None;
+    }
+    let algorithm = line[..open].trim();
+    let file = &line[open + 2..close];
+    let digest = line[close + 4..].trim();
+    Some((Some(algorithm), file, digest))
+}
+
+/// Collect the checksums a server advertises for the content in headers:
+/// `Content-MD5`, RFC 3230 `Digest`, `x-amz-checksum-*` and `x-goog-hash`.
+/// Digests in unsupported algorithms, such as CRC32, are ignored
+pub fn header_checksums(headers: &HeaderMap) -> Vec<Checksum> {
+    let mut checksums = Vec::new();
+    let mut push = |algorithm, encoded: &str| {
+        if let Ok(digest) = base64::decode(encoded.trim()) {
+            checksums.push(Checksum { algorithm, digest });
+        }
+    };
+
+    for (name, value) in headers {
+        let value = match value.to_str() {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
+        match name.as_str() {
+            "content-md5" => push(ChecksumAlgorithm::Md5, value),
+            "x-amz-checksum-sha1" => push(ChecksumAlgorithm::Sha1, value),
+            "x-amz-checksum-sha256" => push(ChecksumAlgorithm::Sha256, value),
+            "digest" | "x-goog-hash" => {
+                for instance in value.split(',') {
+                    let mut parts = instance.trim().splitn(2, '=');
+                    let algorithm = match parts.next().map(str::to_ascii_lowercase) {
+                        Some(ref algorithm) if algorithm == "md5" => ChecksumAlgorithm::Md5,
+                        Some(ref algorithm) if algorithm == "sha" => ChecksumAlgorithm::Sha1,
+                        Some(ref algorithm) if algorithm == "sha-256" => ChecksumAlgorithm::Sha256,
+                        Some(ref algorithm) if algorithm == "sha-512" => ChecksumAlgorithm::Sha512,
+                        _ => continue,
+                    };
+                    if let Some(encoded) = parts.next() {
+                        push(algorithm, encoded);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    checksums
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// The format of a detached signature
+pub enum SignatureKind {
+    /// A minisign signature, verified with a `minisign.pub` public key
+    Minisign,
+    /// An OpenPGP signature, verified by `gpgv` with a keyring of public keys
+    Gpg,
+}
+
+impl SignatureKind {
+    /// The extension appended to the download url to find its signature
+    pub fn extension(self) -> &'static str {
+        match self {
+            SignatureKind::Minisign => ".minisig",
+            SignatureKind::Gpg => ".sig",
+        }
+    }
+}
+
+impl FromStr for SignatureKind {
+    type Err = FetchError;
+
+    fn from_str(s: &str) -> Result<SignatureKind> {
+        match s.to_ascii_lowercase().as_str() {
+            "minisign" => Ok(SignatureKind::Minisign),
+            "gpg" => Ok(SignatureKind::Gpg),
+            _ => Err(FetchError::InvalidArgumentsError(format!(
+                "Unknown signature kind {}",
+                s
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A detached signature to verify a downloaded file with
+pub struct SignatureCheck {
+    /// The format of the signature
+    pub kind: SignatureKind,
+    /// The public key to verify with, either a `minisign.pub`
+    /// file or a GPG keyring
+    pub public_key: PathBuf,
+    /// A local signature file, or None to fetch the signature from
+    /// the download url with the kind's extension appended
+    pub signature_file: Option<PathBuf>,
+}
+
+impl SignatureCheck {
+    /// Check the file at path against signature, the
+    /// contents of a detached signature file
+    pub fn verify(&self, path: &Path, signature: &[u8]) -> Result<()> {
+        match self.kind {
+            SignatureKind::Minisign => self.verify_minisign(path, signature),
+            SignatureKind::Gpg => self.verify_gpg(path, signature),
+        }
+    }
+
+    fn verify_minisign(&self, path: &Path, signature: &[u8]) -> Result<()> {
+        let public_key = PublicKey::from_file(&self.public_key).map_err(|err| {
+            FetchError::InvalidArgumentsError(format!(
+                "Minisign public key could not be read: {}",
+                err
+            ))
+        })?;
+        let signature = String::from_utf8_lossy(signature);
+        let signature = Signature::decode(&signature).map_err(|_| {
+            FetchError::ValidationError("Minisign signature could not be parsed".to_owned())
+        })?;
+        // minisign can only verify a file held in memory
+        let contents = fs::read(path)?;
+        public_key.verify(&contents, &signature).map_err(|_| {
+            FetchError::ValidationError("Minisign signature does not match".to_owned())
+        })
+    }
+
+    fn verify_gpg(&self, path: &Path, signature: &[u8]) -> Result<()> {
+        // gpgv looks up keyrings named without a directory in its home directory
+        let keyring = fs::canonicalize(&self.public_key)?;
+        let mut child = Command::new("gpgv")
+            .arg("--keyring")
+            .arg(keyring)
+            .arg("-")
+            .arg(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        if let Some(stdin) = child.stdin.as_mut() {
+            stdin.write_all(signature)?;
+        }
+        if child.wait()?.success() {
+            Ok(())
+        } else {
+            Err(FetchError::ValidationError(
+                "GPG signature does not match".to_owned(),
+            ))
+        }
+    }
+}
+
+/// The most bytes a StreamingHasher buffers while waiting for
+/// the chunks before them to arrive
+const REORDER_LIMIT: u64 = 16 * 1024 * 1024;
+
+/// Hashes a download as its chunks are written, so that checksums can
+/// be verified without reading the whole output back from disk.
+///
+/// Chunks are hashed in file order. Chunks arriving ahead of the hashed
+/// prefix are buffered up to a limit, and whatever is still unhashed when
+/// the download finishes is read back from the output
+pub struct StreamingHasher {
+    hashers: Vec<(ChecksumAlgorithm, Hasher)>,
+    offset: u64,
+    pending: BTreeMap<u64, Vec<u8>>,
+    pending_bytes: u64,
+}
+
+impl StreamingHasher {
+    /// Create a hasher computing a digest for each of algorithms
+    pub fn new(algorithms: &[ChecksumAlgorithm]) -> StreamingHasher {
+        let mut hashers: Vec<(ChecksumAlgorithm, Hasher)> = Vec::new();
+        for &algorithm in algorithms {
+            if !hashers.iter().any(|(existing, _)| *existing == algorithm) {
+                hashers.push((algorithm, algorithm.hasher()));
+            }
+        }
+        StreamingHasher {
+            hashers,
+            offset: 0,
+            pending: BTreeMap::new(),
+            pending_bytes: 0,
+        }
+    }
+
+    /// The length of the prefix of the file hashed so far
+    pub fn hashed(&self) -> u64 {
+        self.offset
+    }
+
+    /// Hash bytes written to the file at offset
+    pub fn update(&mut self, offset: u64, bytes: &[u8]) {
+        if offset <= self.offset {
+            self.hash_from(offset, bytes);
+            self.drain();
+        } else if self.pending_bytes + bytes.len() as u64 <= REORDER_LIMIT {
+            self.pending_bytes += bytes.len() as u64;
+            if let Some(replaced) = self.pending.insert(offset, bytes.to_vec()) {
+                self.pending_bytes -= replaced.len() as u64;
+            }
+        }
+    }
+
+    /// Finish hashing, reading whatever was not hashed while
+    /// downloading from the file at path, and return each digest
+    pub fn finish(mut self, path: &Path) -> Result<Vec<(ChecksumAlgorithm, Vec<u8>)>> {
+        let mut file = fs::File::open(path)?;
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let n = file.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            let offset = self.offset;
+            self.hash_from(offset, &buffer[..n]);
+        }
+        Ok(self
+            .hashers
+            .into_iter()
+            .map(|(algorithm, hasher)| (algorithm, hasher.finish()))
+            .collect())
+    }
+
+    /// Hash the part of bytes starting at offset which extends past the hashed prefix
+    fn hash_from(&mut self, offset: u64, bytes: &[u8]) {
+        let skip = (self.offset - offset) as usize;
+        if skip < bytes.len() {
+            for (_, hasher) in &mut self.hashers {
+                hasher.update(&bytes[skip..]);
+            }
+            self.offset = offset + bytes.len() as u64;
+        }
+    }
+
+    fn drain(&mut self) {
+        while let Some(&start) = self.pending.keys().next() {
+            if start > self.offset {
+                break;
+            }
+            let bytes = self.pending.remove(&start).unwrap_or_default();
+            self.pending_bytes -= bytes.len() as u64;
+            self.hash_from(start, &bytes);
+        }
+    }
+}
+
+/// A running hash of one of the supported algorithms
+enum Hasher {
+    Md5(Md5),
+    Md5Multipart(Box<MultipartMd5>),
+    Sha1(Sha1),
+    Sha256(Sha256),
+    Sha512(Sha512),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Hasher::Md5(hasher) => hasher.input(bytes),
+            Hasher::Md5Multipart(hasher) => hasher.update(bytes),
+            Hasher::Sha1(hasher) => hasher.input(bytes),
+            Hasher::Sha256(hasher) => hasher.input(bytes),
+            Hasher::Sha512(hasher) => hasher.input(bytes),
+            Hasher::Blake3(hasher) => {
+                hasher.update(bytes);
+            }
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        match self {
+            Hasher::Md5(hasher) => hasher.result().to_vec(),
+            Hasher::Md5Multipart(hasher) => hasher.finish(),
+            Hasher::Sha1(hasher) => hasher.result().to_vec(),
+            Hasher::Sha256(hasher) => hasher.result().to_vec(),
+            Hasher::Sha512(hasher) => hasher.result().to_vec(),
+            Hasher::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+        }
+    }
+}
+
+struct MultipartMd5 {
+    part_size: u64,
+    part: Md5,
+    part_len: u64,
+    parts: Md5,
+}
+
+impl MultipartMd5 {
+    fn new(part_size: u64) -> MultipartMd5 {
+        MultipartMd5 {
+            part_size,
+            part: Md5::new(),
+            part_len: 0,
+            parts: Md5::new(),
+        }
+    }
+
+    fn update(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let take = std::cmp::min(self.part_size - self.part_len, bytes.len() as u64) as usize;
+            self.part.input(&bytes[..take]);
+            self.part_len += take as u64;
+            bytes = &bytes[take..];
+            if self.part_len == self.part_size {
+                self.parts.input(self.part.result_reset());
+                self.part_len = 0;
+            }
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.part_len > 0 {
+            self.parts.input(self.part.result());
+        }
+        self.parts.result().to_vec()
+    }
+}
+
+impl Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const HELLO_SHA256: &str = "185f8db32271fe25f561a6fc938b2e264306ec304eda518007d1764826381969";
+
+    #[test]
+    fn parse_checksum() {
+        let checksum: Checksum = format!("SHA256:{}", HELLO_SHA256).parse().unwrap();
+        assert_eq!(checksum.algorithm, ChecksumAlgorithm::Sha256);
+        assert_eq!(checksum.digest, hex::decode(HELLO_SHA256).unwrap());
+
+        assert!("md4:00".parse::<Checksum>().is_err());
+        assert!("sha256".parse::<Checksum>().is_err());
+        assert!("sha256:xyz".parse::<Checksum>().is_err());
+    }
+
+    #[test]
+    fn find_checksum_in_file() {
+        let contents = format!(
+            "{}  other.tar.gz\n{} *dist/out.tmp\n",
+            "0".repeat(64),
+            HELLO_SHA256
+        );
+        let checksum = find_checksum(&contents, &["out.tmp"]).unwrap();
+        assert_eq!(checksum.algorithm, ChecksumAlgorithm::Sha256);
+        assert_eq!(checksum.digest, hex::decode(HELLO_SHA256).unwrap());
+
+        let contents = "SHA1 (out.tmp) = f7ff9e8b7bb2e09b70935a5d785e0cc5d9d0abf0\n";
+        let checksum = find_checksum(contents, &["out.tmp"]).unwrap();
+        assert_eq!(checksum.algorithm, ChecksumAlgorithm::Sha1);
+
+        assert_eq!(find_checksum(contents, &["missing.tmp"]), None);
+    }
+
+    #[test]
+    fn verify_minisign_signature() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("out.tmp");
+        fs::write(&path, b"test").unwrap();
+        let public_key = temp_dir.path().join("minisign.pub");
+        fs::write(
+            &public_key,
+            "untrusted comment: minisign public key\n\
+             RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3\n",
+        )
+        .unwrap();
+        let signature = "untrusted comment: signature from minisign secret key\n\
+             RWQf6LRCGA9i59SLOFxz6NxvASXDJeRtuZykwQepbDEGt87ig1BNpWaVWuNrm73YiIiJbq71Wi+dP9eKL8OC351vwIasSSbXxwA=\n\
+             trusted comment: timestamp:1555779966\tfile:test\n\
+             QtKMXWyYcwdpZAlPF7tE2ENJkRd1ujvKjlj1m9RtHTBnZPa5WKU5uWRs5GoP5M/VqE81QFuMKI5k/SfNQUaOAA==\n";
+
+        let check = SignatureCheck {
+            kind: SignatureKind::Minisign,
+            public_key,
+            signature_file: None,
+        };
+        assert!(check.verify(&path, signature.as_bytes()).is_ok());
+
+        fs::write(&path, b"tent").unwrap();
+        let error = check
+            .verify(&path, signature.as_bytes())
+            .expect_err("testing");
+        if let FetchError::ValidationError(msg) = error {
+            assert_eq!(msg, "Minisign signature does not match");
+        } else {
+            panic!("Expected ValidationError");
+        }
+    }
+
+    #[test]
+    fn streaming_hasher_reorders_chunks() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("out.tmp");
+        fs::write(&path, b"Hello").unwrap();
+
+        let mut hasher = StreamingHasher::new(&[ChecksumAlgorithm::Sha256]);
+        hasher.update(3, b"lo");
+        hasher.update(0, b"He");
+        assert_eq!(hasher.hashed(), 2);
+        // A retried chunk overlapping the hashed prefix
+        hasher.update(1, b"el");
+        assert_eq!(hasher.hashed(), 5);
+
+        let digests = hasher.finish(&path).unwrap();
+        assert_eq!(
+            digests,
+            vec![(
+                ChecksumAlgorithm::Sha256,
+                hex::decode(HELLO_SHA256).unwrap()
+            )]
+        );
+    }
+
+    #[test]
+    fn streaming_hasher_reads_unhashed_remainder() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("out.tmp");
+        fs::write(&path, b"Hello").unwrap();
+
+        let mut hasher = StreamingHasher::new(&[ChecksumAlgorithm::Sha256]);
+        hasher.update(0, b"He");
+
+        let digests = hasher.finish(&path).unwrap();
+        assert_eq!(digests[0].1, hex::decode(HELLO_SHA256).unwrap());
+    }
+
+    #[test]
+    fn multipart_md5() {
+        let mut hasher = ChecksumAlgorithm::Md5Multipart(4).hasher();
+        hasher.update(b"Hel");
+        hasher.update(b"loWorld");
+        assert_eq!(
+            hasher.finish(),
+            hex::decode("059d5559e903e95356438a41dfe641e4").unwrap()
+        );
+    }
+
+    #[test]
+    fn checksums_from_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-md5", "ixqZU8RhEpaoJ6v4xHgE1w==".parse().unwrap());
+        headers.insert(
+            "digest",
+            "SHA-256=GF+NsyJx/iX1Yab8k4suJkMG7DBO2lGAB9F2SCY4GWk=, crc32c=AAAAAA=="
+                .parse()
+                .unwrap(),
+        );
+
+        let checksums = header_checksums(&headers);
+        assert_eq!(
+            checksums,
+            vec![
+                Checksum {
+                    algorithm: ChecksumAlgorithm::Md5,
+                    digest: hex::decode("8b1a9953c4611296a827abf8c47804d7").unwrap(),
+                },
+                Checksum {
+                    algorithm: ChecksumAlgorithm::Sha256,
+                    digest: hex::decode(HELLO_SHA256).unwrap(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn verify_checksum() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("out.tmp");
+        fs::write(&path, b"Hello").unwrap();
+
+        let checksum: Checksum = format!("sha256:{}", HELLO_SHA256).parse().unwrap();
+        assert!(checksum.verify(&path).is_ok());
+
+        let checksum: Checksum = "sha1:f7ff9e8b7bb2e09b70935a5d785e0cc5d9d0abf0"
+            .parse()
+            .unwrap();
+        assert!(checksum.verify(&path).is_ok());
+
+        let checksum: Checksum = "blake3:00".parse().unwrap();
+        let error = checksum.verify(&path).expect_err("testing");
+        if let FetchError::ValidationError(msg) = error {
+            assert_eq!(msg, "blake3 checksum does not match");
+        } else {
+            panic!("Expected ValidationError");
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::time::{Duration, Instant};
+
+use reqwest::header::{ETAG, LAST_MODIFIED};
+use slog::{info, warn};
+use tokio::timer::delay_for;
+
+use crate::errors::{FetchError, Result};
+use crate::fetch::{build_client, fetch, send_head, FetchOptions};
+
+/// How often a watch checks whether its interrupt was raised while waiting
+const INTERRUPT_CHECK: Duration = Duration::from_millis(100);
+
+/// The ETag and Last-Modified time of a version of a remote file
+type Validators = (Option<String>, Option<String>);
+
+/// Keep the output of a download a fresh mirror of the remote file.
+/// The options build returns are downloaded, and then every interval the
+/// url is sent a HEAD request, and downloaded again whenever its ETag or
+/// Last-Modified time differs from those of the version downloaded. A
+/// download which fails is tried again after the next interval. Runs
+/// until the interrupt of the options is raised, returning the error
+/// of a download it interrupted
+pub async fn fetch_watched(
+    mut build: impl FnMut() -> FetchOptions,
+    interval: Duration,
+) -> Result<()> {
+    // Options kept for sending HEAD requests and checking the interrupt
+    let watching = build();
+    let logger = watching.logger.clone();
+    let mut downloaded: Option<Validators> = None;
+    loop {
+        let changed = match &downloaded {
+            Some(validators) => match remote_validators(&watching).await {
+                Ok(current) if &current == validators => false,
+                Ok(current) => {
+                    info!(logger, "remote file changed"; "url" => &watching.url, "etag" => format!("{:?}", &current.0), "last_modified" => format!("{:?}", &current.1));
+                    true
+                }
+                Err(err) => {
+                    warn!(logger, "failed to check remote file"; "url" => &watching.url, "error" => format!("{:?}", err));
+                    false
+                }
+            },
+            None => true,
+        };
+        if changed {
+            let mut options = build();
+            // The output holds an older version, perhaps of the same length
+            options.force = true;
+            match fetch(options).await {
+                Ok(report) => {
+                    if report.etag.is_none() && report.last_modified.is_none() {
+                        warn!(logger, "remote file has neither an ETag nor a Last-Modified time, so changes to it cannot be seen");
+                    }
+                    downloaded = Some((report.etag, report.last_modified));
+                }
+                Err(err) => {
+                    if let FetchError::InterruptedError(_) = err {
+                        return Err(err);
+                    }
+                    warn!(logger, "download failed, trying again after the interval"; "error" => format!("{:?}", err));
+                    downloaded = None;
+                }
+            }
+        }
+
+        let started = Instant::now();
+        while started.elapsed() < interval {
+            if watching.is_interrupted() {
+                return Ok(());
+            }
+            delay_for(INTERRUPT_CHECK.min(interval.saturating_sub(started.elapsed()))).await;
+        }
+    }
+}
+
+/// The validators the remote file of options currently has
+async fn remote_validators(options: &FetchOptions) -> Result<Validators> {
+    let client = match &options.client {
+        Some(client) => client.clone(),
+        None => build_client(options)?,
+    };
+    let head = send_head(&client, options).await?;
+    let header = |name| {
+        head.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from)
+    };
+    Ok((header(ETAG), header(LAST_MODIFIED)))
+}
@@ -0,0 +1,129 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveTime, Utc};
+
+use parallel_fetch::{FetchError, Result};
+
+/// The format times of day are given in
+const TIME_FORMAT: &str = "%H:%M";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// A daily window of local time, such as `22:00-06:00`, outside which
+/// a download is held back, so that large transfers on metered or shared
+/// links happen at quiet times. A window may wrap around midnight
+pub struct TimeWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl TimeWindow {
+    /// Whether time falls within the window
+    pub fn contains(self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+
+    /// Whether the window is open now
+    pub fn is_open(self) -> bool {
+        self.contains(Local::now().time())
+    }
+}
+
+impl fmt::Display for TimeWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format(TIME_FORMAT),
+            self.end.format(TIME_FORMAT)
+        )
+    }
+}
+
+impl FromStr for TimeWindow {
+    type Err = FetchError;
+
+    /// Parse a window such as `22:00-06:00`. A window starting when it
+    /// ends would never be open, so it is rejected
+    fn from_str(s: &str) -> Result<TimeWindow> {
+        let invalid = || FetchError::InvalidArgumentsError(format!("Invalid time window {}", s));
+        let mut times = s.splitn(2, '-');
+        let start = times.next().ok_or_else(invalid)?;
+        let end = times.next().ok_or_else(invalid)?;
+        let window = TimeWindow {
+            start: NaiveTime::parse_from_str(start.trim(), TIME_FORMAT).map_err(|_| invalid())?,
+            end: NaiveTime::parse_from_str(end.trim(), TIME_FORMAT).map_err(|_| invalid())?,
+        };
+        if window.start == window.end {
+            return Err(FetchError::InvalidArgumentsError(format!(
+                "Time window {} is never open, as it ends when it starts",
+                s
+            )));
+        }
+        Ok(window)
+    }
+}
+
+/// Parse when to start a download, either as an RFC 3339 date and time
+/// or as a local time of day such as `02:00`, meaning the next time the
+/// clock reads it
+pub fn parse_start_at(s: &str) -> Result<DateTime<FixedOffset>> {
+    if let Ok(start_at) = DateTime::parse_from_rfc3339(s) {
+        return Ok(start_at);
+    }
+    let time = NaiveTime::parse_from_str(s, TIME_FORMAT)
+        .map_err(|_| FetchError::InvalidArgumentsError(format!("Invalid start time {}", s)))?;
+    let now = Local::now();
+    let mut start_at = now.date().and_time(time).ok_or_else(|| {
+        FetchError::InvalidArgumentsError(format!("Start time {} does not exist today", s))
+    })?;
+    if start_at <= now {
+        start_at = start_at + Duration::days(1);
+    }
+    Ok(start_at.with_timezone(&FixedOffset::east(now.offset().local_minus_utc())))
+}
+
+/// Whether start_at has passed
+pub fn has_started(start_at: &DateTime<FixedOffset>) -> bool {
+    Utc::now() >= start_at.with_timezone(&Utc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_wrap_midnight() {
+        let time = |s| NaiveTime::parse_from_str(s, TIME_FORMAT).unwrap();
+        let day: TimeWindow = "09:00-17:00".parse().unwrap();
+        assert!(day.contains(time("09:00")));
+        assert!(day.contains(time("16:59")));
+        assert!(!day.contains(time("17:00")));
+        assert!(!day.contains(time("02:00")));
+
+        let night: TimeWindow = "22:00 - 06:00".parse().unwrap();
+        assert_eq!(night.to_string(), "22:00-06:00");
+        assert!(night.contains(time("23:30")));
+        assert!(night.contains(time("02:00")));
+        assert!(!night.contains(time("06:00")));
+        assert!(!night.contains(time("12:00")));
+
+        assert!("22:00".parse::<TimeWindow>().is_err());
+        assert!("25:00-06:00".parse::<TimeWindow>().is_err());
+        assert!("00:00-00:00".parse::<TimeWindow>().is_err());
+    }
+
+    #[test]
+    fn start_at_is_in_the_future() {
+        let start_at = parse_start_at("02:00").unwrap();
+        assert!(!has_started(&start_at));
+        assert!(has_started(
+            &parse_start_at("2019-11-01T02:00:00+00:00").unwrap()
+        ));
+        assert!(parse_start_at("tomorrow").is_err());
+    }
+}
@@ -0,0 +1,62 @@
+#![cfg(feature = "cli")]
+
+use std::fs;
+use std::process::{Command, Output};
+
+use tempfile::TempDir;
+
+/// Run the parallel-fetch binary with args, without any
+/// PARALLEL_FETCH_ variables of the environment running the tests
+fn run(args: &[&str]) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_parallel-fetch"));
+    for (name, _) in std::env::vars() {
+        if name.starts_with("PARALLEL_FETCH_") {
+            command.env_remove(name);
+        }
+    }
+    command
+        .args(args)
+        .output()
+        .expect("unable to run parallel-fetch")
+}
+
+#[test]
+fn watch_with_every_url_skipped() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let url = "http://127.0.0.1:1/watched";
+    let history = temp_dir.path().join("history");
+    fs::write(&history, format!("{}\n", url)).unwrap();
+    let empty = temp_dir.path().join("empty.txt");
+    fs::write(&empty, "").unwrap();
+
+    let output = run(&[
+        "-u",
+        url,
+        "--history",
+        history.to_str().unwrap(),
+        "--skip-existing-history",
+        "--watch",
+        "5",
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+
+    let output = run(&["-i", empty.to_str().unwrap(), "--watch", "5"]);
+    assert!(output.status.success(), "{:?}", output);
+}
+
+#[test]
+fn bench_with_url_skipped() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let url = "http://127.0.0.1:1/benchmarked";
+    let history = temp_dir.path().join("history");
+    fs::write(&history, format!("{}\n", url)).unwrap();
+
+    let output = run(&[
+        "--history",
+        history.to_str().unwrap(),
+        "--skip-existing-history",
+        "bench",
+        url,
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+}
@@ -1,10 +1,18 @@
 use std::fs::File;
 use std::io::prelude::*;
+use std::io::{ErrorKind, SeekFrom};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
+use futures::channel::mpsc::unbounded;
+use futures::io::{AsyncReadExt, AsyncSeekExt};
+use futures::StreamExt;
 use hex;
 use md5::{Digest, Md5};
-use mockito;
+use mockito::{self, Matcher};
+use reqwest::header::HeaderMap;
 use reqwest::StatusCode;
 use slog::debug;
 use sloggers::null::NullLoggerBuilder;
@@ -14,7 +22,14 @@ use sloggers::Build;
 use tempfile::TempDir;
 use tokio;
 
-use parallel_fetch::{fetch, FetchError, FetchOptions};
+use parallel_fetch::{
+    fetch, fetch_controlled, fetch_many, fetch_partial, fetch_to_vec, fetch_watched,
+    fetch_with_events, inspect, plan_download, Checksum, ChecksumAlgorithm, ChunkScheduler,
+    ChunkSink, Clobber, CookieJar, Credentials, DeltaIndex, DownloadEvent, FetchError, FetchLimits,
+    FetchMetrics, FetchOptions, Interrupt, MemorySink, OnInterrupt, OrderedSink, ParallelReader,
+    ProgressUpdate, ProxyOptions, Range, Result, RetryPolicy, ScheduleState, SignatureCheck,
+    SignatureKind, SinkFuture, TraceContext,
+};
 
 #[tokio::test]
 async fn accept_ranges_none() {
@@ -28,27 +43,59 @@ async fn accept_ranges_none() {
         .with_header("accept-ranges", "none")
         .create();
 
-    let options = FetchOptions {
-        url: url.to_owned(),
-        output_option: None,
-        num_fetches: 1,
-        logger: logger.clone(),
-        check_etag: false,
-        max_retries: 1,
-    };
+    let options = FetchOptions::builder(url.to_owned())
+        .num_fetches(1)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
 
     let result = fetch(options).await;
     debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
 
     let error = result.expect_err("testing");
 
-    if let FetchError::ServerSupportError(msg) = *error {
+    if let FetchError::ServerSupportError(msg) = error {
         assert_eq!("Server's Accept-Ranges header set to none", msg);
     } else {
         panic!("Expected ServerSupportError");
     }
 }
 
+#[tokio::test]
+async fn insufficient_space() {
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("content-length", "10")
+        .with_header("accept-ranges", "bytes")
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .num_fetches(1)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .space_margin(u64::MAX / 2)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    let error = result.expect_err("testing");
+
+    if let FetchError::InsufficientSpaceError(msg) = error {
+        assert!(msg.starts_with("Not enough disk space"));
+    } else {
+        panic!("Expected InsufficientSpaceError");
+    }
+}
+
 #[tokio::test]
 async fn accept_ranges_missing() {
     let url = &mockito::server_url();
@@ -57,27 +104,227 @@ async fn accept_ranges_missing() {
 
     let _head_mock = mockito::mock("HEAD", "/").with_status(200).create();
 
-    let options = FetchOptions {
-        url: url.to_owned(),
-        output_option: None,
-        num_fetches: 1,
-        logger: logger.clone(),
-        check_etag: false,
-        max_retries: 1,
-    };
+    let options = FetchOptions::builder(url.to_owned())
+        .num_fetches(1)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
 
     let result = fetch(options).await;
     debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
 
     let error = result.expect_err("testing");
 
-    if let FetchError::ServerSupportError(msg) = *error {
+    if let FetchError::ServerSupportError(msg) = error {
         assert_eq!("Server does not include Accept-Ranges header", msg);
     } else {
         panic!("Expected ServerSupportError");
     }
 }
 
+#[tokio::test]
+async fn accept_ranges_none_single_stream() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "none")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(200)
+        .match_header("accept-encoding", "identity")
+        .with_body(&b"HelloWorld")
+        .expect(1)
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .logger(logger.clone())
+        .max_retries(1)
+        .allow_single_stream(true)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    _body_mock.assert();
+
+    assert!(result.is_ok());
+
+    let mut file = File::open(temp_file_path).unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "HelloWorld");
+}
+
+#[tokio::test]
+async fn compressed_response() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    // "HelloWorld" gzipped
+    let encoded = [
+        31, 139, 8, 0, 0, 0, 0, 0, 2, 3, 243, 72, 205, 201, 201, 15, 207, 47, 202, 73, 1, 0, 121,
+        12, 119, 119, 10, 0, 0, 0,
+    ];
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .match_header("accept-encoding", "gzip, deflate")
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-encoding", "gzip")
+        .with_header("content-length", &encoded.len().to_string())
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(200)
+        .match_header("accept-encoding", "gzip, deflate")
+        .with_header("content-encoding", "gzip")
+        .with_body(&encoded)
+        .expect(1)
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .logger(logger.clone())
+        .max_retries(1)
+        .compressed(true)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    _body_mock.assert();
+
+    assert!(result.is_ok());
+
+    let mut file = File::open(temp_file_path).unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "HelloWorld");
+}
+
+#[tokio::test]
+async fn decompress_output() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.txt.gz");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    // "HelloWorld" gzipped
+    let encoded = [
+        31, 139, 8, 0, 0, 0, 0, 0, 2, 3, 243, 72, 205, 201, 201, 15, 207, 47, 202, 73, 1, 0, 121,
+        12, 119, 119, 10, 0, 0, 0,
+    ];
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "none")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(200)
+        .with_body(&encoded)
+        .expect(1)
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(1)
+        .logger(logger.clone())
+        .max_retries(1)
+        .allow_single_stream(true)
+        .decompress(true)
+        .keep_compressed(true)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    _body_mock.assert();
+
+    assert!(result.is_ok());
+
+    let mut file = File::open(temp_dir.path().join("out.txt")).unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "HelloWorld");
+    assert!(temp_file_path.exists());
+}
+
+#[tokio::test]
+async fn single_stream_read_timeout() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "none")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(200)
+        .with_body_from_fn(|w| {
+            w.write_all(b"Hello")?;
+            w.flush()?;
+            std::thread::sleep(Duration::from_millis(500));
+            w.write_all(b"World")
+        })
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(1)
+        .logger(logger.clone())
+        .max_retries(1)
+        .allow_single_stream(true)
+        .read_timeout(Duration::from_millis(100))
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    let error = result.expect_err("testing");
+
+    if let FetchError::IoError(error) = error {
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+    } else {
+        panic!("Expected IoError");
+    }
+}
+
 #[tokio::test]
 async fn content_length_missing() {
     let url = &mockito::server_url();
@@ -89,21 +336,20 @@ async fn content_length_missing() {
         .with_header("accept-ranges", "bytes")
         .create();
 
-    let options = FetchOptions {
-        url: url.to_owned(),
-        output_option: None,
-        num_fetches: 1,
-        logger: logger.clone(),
-        check_etag: false,
-        max_retries: 1,
-    };
+    let options = FetchOptions::builder(url.to_owned())
+        .num_fetches(1)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
 
     let result = fetch(options).await;
     debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
 
     let error = result.expect_err("testing");
 
-    if let FetchError::ServerSupportError(msg) = *error {
+    if let FetchError::ServerSupportError(msg) = error {
         assert_eq!("Server does not include Content-Length header", msg);
     } else {
         panic!("Expected ServerSupportError");
@@ -133,14 +379,14 @@ async fn single_fetch() {
         .with_body(&b"HelloWorld")
         .create();
 
-    let options = FetchOptions {
-        url: url.to_owned(),
-        output_option: Some(temp_file_path.to_str().unwrap().to_owned()),
-        num_fetches: 1,
-        logger: logger.clone(),
-        check_etag: false,
-        max_retries: 1,
-    };
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(1)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
 
     let result = fetch(options).await;
     debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
@@ -182,24 +428,44 @@ async fn second_fetch_fails() {
         .with_status(500)
         .create();
 
-    let options = FetchOptions {
-        url: url.to_owned(),
-        output_option: Some(temp_file_path.to_str().unwrap().to_owned()),
-        num_fetches: 2,
-        logger: logger.clone(),
-        check_etag: false,
-        max_retries: 1,
-    };
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
 
     let result = fetch(options).await;
     debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
 
     let error = result.expect_err("testing");
 
-    if let FetchError::ReqwestError(error) = *error {
-        assert_eq!(error.status(), Some(StatusCode::INTERNAL_SERVER_ERROR));
+    let mut failed = if let FetchError::RangesError { failed, completed } = error {
+        // The first range finishes although the second failed
+        assert_eq!(completed, 5);
+        assert_eq!(failed.len(), 1);
+        failed
     } else {
-        panic!("Expected ReqwestError");
+        panic!("Expected RangesError");
+    };
+    if let FetchError::RangeError {
+        range,
+        attempts,
+        source,
+        ..
+    } = failed.remove(0)
+    {
+        assert_eq!(range, Range { start: 5, end: 9 });
+        assert_eq!(attempts, 1);
+        if let FetchError::ReqwestError(error) = *source {
+            assert_eq!(error.status(), Some(StatusCode::INTERNAL_SERVER_ERROR));
+        } else {
+            panic!("Expected ReqwestError");
+        }
+    } else {
+        panic!("Expected RangeError");
     }
 }
 
@@ -235,14 +501,14 @@ async fn two_fetches() {
         .with_body(&b"World")
         .create();
 
-    let options = FetchOptions {
-        url: url.to_owned(),
-        output_option: Some(temp_file_path.to_str().unwrap().to_owned()),
-        num_fetches: 2,
-        logger: logger.clone(),
-        check_etag: false,
-        max_retries: 1,
-    };
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
 
     let result = fetch(options).await;
     debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
@@ -255,11 +521,70 @@ async fn two_fetches() {
     assert_eq!(contents, "HelloWorld");
 }
 
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
 #[tokio::test]
-async fn second_fetch_retries() {
+async fn io_uring_writes() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &format!("{}/io_uring", mockito::server_url());
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/io_uring")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/io_uring")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_body(b"Hello")
+        .create();
+
+    let _body_mock2 = mockito::mock("GET", "/io_uring")
+        .with_status(206)
+        .match_header("range", "bytes=5-9")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 5-9/10")
+        .with_body(b"World")
+        .create();
+
+    // A small buffer and no sync interval write each range's bytes
+    // and sync them through the ring as soon as they arrive
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .write_buffer(2)
+        .sync_interval(Duration::from_secs(0))
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    assert!(result.is_ok());
+
+    let mut file = File::open(temp_file_path).unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "HelloWorld");
+}
+
+#[tokio::test]
+async fn preallocate_output() {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
     let mut temp_file_path = PathBuf::from(temp_dir.path());
     temp_file_path.push("out.tmp");
+    // A longer stale file is cut to the content length
+    std::fs::write(&temp_file_path, b"Stale contents of another file").unwrap();
 
     let url = &mockito::server_url();
 
@@ -280,81 +605,85 @@ async fn second_fetch_retries() {
         .create();
 
     let _body_mock2 = mockito::mock("GET", "/")
-        .with_status(500)
+        .with_status(206)
         .match_header("range", "bytes=5-9")
         .with_header("content-length", "5")
         .with_header("content-range", "bytes 5-9/10")
-        .expect(2)
+        .with_body(&b"World")
         .create();
 
-    let options = FetchOptions {
-        url: url.to_owned(),
-        output_option: Some(temp_file_path.to_str().unwrap().to_owned()),
-        num_fetches: 2,
-        logger: logger.clone(),
-        check_etag: false,
-        max_retries: 2,
-    };
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .preallocate(true)
+        .build();
 
     let result = fetch(options).await;
     debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
 
-    _body_mock.assert();
-    _body_mock2.assert();
-
-    assert!(result.is_err());
-
-    let error = result.expect_err("testing");
+    assert!(result.is_ok());
 
-    if let FetchError::ReqwestError(error) = *error {
-        assert_eq!(error.status(), Some(StatusCode::INTERNAL_SERVER_ERROR));
-    } else {
-        panic!("Expected ReqwestError");
-    }
+    let mut file = File::open(temp_file_path).unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "HelloWorld");
 }
 
 #[tokio::test]
-async fn check_etag_success() {
+async fn part_file_renamed() {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
     let mut temp_file_path = PathBuf::from(temp_dir.path());
     temp_file_path.push("out.tmp");
 
-    let mut hasher = Md5::new();
-    hasher.input(b"HelloWorld");
-    let hash_result = hasher.result();
-
     let url = &mockito::server_url();
 
     let logger = NullLoggerBuilder.build().unwrap();
 
     let _head_mock = mockito::mock("HEAD", "/")
         .with_status(200)
-        .with_header("etag", &hex::encode(hash_result))
         .with_header("accept-ranges", "bytes")
         .with_header("content-length", "10")
         .create();
 
     let _body_mock = mockito::mock("GET", "/")
         .with_status(206)
-        .with_header("content-length", "10")
-        .with_header("content-range", "bytes 0-9/10")
-        .with_body(&b"HelloWorld")
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_body(&b"Hello")
         .create();
 
-    let options = FetchOptions {
-        url: url.to_owned(),
-        output_option: Some(temp_file_path.to_str().unwrap().to_owned()),
-        num_fetches: 1,
-        logger: logger.clone(),
-        check_etag: true,
-        max_retries: 1,
-    };
+    let _body_mock2 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=5-9")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 5-9/10")
+        .with_body(&b"World")
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .part_file(true)
+        .build();
 
     let result = fetch(options).await;
     debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
 
     assert!(result.is_ok());
 
+    let mut part_path = temp_file_path.clone();
+    part_path.set_extension("tmp.part");
+    assert!(!part_path.exists());
+
     let mut file = File::open(temp_file_path).unwrap();
     let mut contents = String::new();
     file.read_to_string(&mut contents).unwrap();
@@ -362,7 +691,7 @@ async fn check_etag_success() {
 }
 
 #[tokio::test]
-async fn check_etag_failure() {
+async fn controlled_pause_resume() {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
     let mut temp_file_path = PathBuf::from(temp_dir.path());
     temp_file_path.push("out.tmp");
@@ -373,35 +702,3346 @@ async fn check_etag_failure() {
 
     let _head_mock = mockito::mock("HEAD", "/")
         .with_status(200)
-        .with_header("etag", "feeddead")
         .with_header("accept-ranges", "bytes")
         .with_header("content-length", "10")
         .create();
 
     let _body_mock = mockito::mock("GET", "/")
         .with_status(206)
-        .with_header("content-length", "10")
-        .with_header("content-range", "bytes 0-9/10")
-        .with_body(&b"HelloWorld")
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_body(&b"Hello")
         .create();
 
-    let options = FetchOptions {
-        url: url.to_owned(),
-        output_option: Some(temp_file_path.to_str().unwrap().to_owned()),
-        num_fetches: 1,
-        logger: logger.clone(),
-        check_etag: true,
-        max_retries: 1,
-    };
+    let _body_mock2 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=5-9")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 5-9/10")
+        .with_body(&b"World")
+        .create();
 
-    let result = fetch(options).await;
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let handle = fetch_controlled(options);
+    handle.pause();
+    tokio::timer::delay_for(Duration::from_millis(300)).await;
+    let status = handle.status();
+    assert!(status.paused);
+    assert!(!status.finished);
+    assert_eq!(status.downloaded, 0);
+
+    handle.resume();
+    let result = handle.wait().await;
     debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
 
-    let error = result.expect_err("testing");
+    assert!(result.is_ok());
+
+    let mut file = File::open(temp_file_path).unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "HelloWorld");
+}
+
+#[tokio::test]
+async fn controlled_cancel() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_body(&b"Hello")
+        .create();
+
+    let _body_mock2 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=5-9")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 5-9/10")
+        .with_body(&b"World")
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let handle = fetch_controlled(options);
+    handle.pause();
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+    handle.cancel();
+    let result = handle.wait().await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    let error = result.expect_err("testing");
+
+    if let FetchError::CancelledError(msg) = error {
+        assert_eq!("The download was cancelled", msg);
+    } else {
+        panic!("Expected CancelledError");
+    }
+}
+
+#[derive(Default)]
+struct CountingMetrics {
+    requests: AtomicU64,
+    bytes: AtomicU64,
+    retries: AtomicU64,
+    latencies: Mutex<Vec<Range>>,
+}
+
+impl FetchMetrics for CountingMetrics {
+    fn request_issued(&self, _url: &str) {
+        self.requests.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn bytes_downloaded(&self, bytes: u64) {
+        self.bytes.fetch_add(bytes, Ordering::SeqCst);
+    }
+
+    fn chunk_retried(&self, _range: Range) {
+        self.retries.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn chunk_latency(&self, range: Range, _latency: Duration) {
+        self.latencies.lock().unwrap().push(range);
+    }
+}
+
+#[tokio::test]
+async fn download_metrics() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _first_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_body(&b"Hello")
+        .create();
+
+    let _second_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=5-9")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 5-9/10")
+        .with_body(&b"World")
+        .create();
+
+    let metrics = Arc::new(CountingMetrics::default());
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .max_retries(3)
+        .metrics(metrics.clone())
+        .build();
+
+    let result = fetch(options).await;
+    assert!(result.is_ok());
+
+    // A stale pooled connection may be reset, costing a retry
+    let retries = metrics.retries.load(Ordering::SeqCst);
+    assert_eq!(metrics.requests.load(Ordering::SeqCst), 3 + retries);
+    assert_eq!(metrics.bytes.load(Ordering::SeqCst), 10);
+    let mut latencies = metrics.latencies.lock().unwrap().clone();
+    latencies.sort_by_key(|range| range.start);
+    assert_eq!(
+        latencies,
+        vec![Range { start: 0, end: 4 }, Range { start: 5, end: 9 }]
+    );
+}
+
+#[tokio::test]
+async fn download_report() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-9")
+        .with_header("content-length", "10")
+        .with_header("content-range", "bytes 0-9/10")
+        .with_body(&b"HelloWorld")
+        .create();
+
+    let checksum: Checksum =
+        "sha256:872e4e50ce9990d8b041330c47c9ddd11bec6b503ae9386a99da8584e9bb12c4"
+            .parse()
+            .unwrap();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(1)
+        .max_retries(3)
+        .checksum(checksum)
+        .build();
+
+    let report = fetch(options).await.unwrap();
+    assert_eq!(report.path, Some(temp_file_path));
+    assert_eq!(report.bytes, 10);
+    assert_eq!(report.checksums.len(), 1);
+    assert!(!report.signature);
+    assert!(report.peak_throughput > 0.0);
+}
+
+#[tokio::test]
+async fn download_events() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-9")
+        .with_header("content-length", "10")
+        .with_header("content-range", "bytes 0-9/10")
+        .with_body(&b"HelloWorld")
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(1)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let (download, events) = fetch_with_events(options);
+    let result = download.await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    assert!(result.is_ok());
+
+    let range = Range { start: 0, end: 9 };
+    let events: Vec<DownloadEvent> = events.collect().await;
+    assert_eq!(
+        events.iter().rev().find_map(|event| match event {
+            DownloadEvent::ChunkProgress { downloaded, .. } => Some(*downloaded),
+            _ => None,
+        }),
+        Some(10)
+    );
+    let events: Vec<DownloadEvent> = events
+        .into_iter()
+        .filter(|event| !matches!(event, DownloadEvent::ChunkProgress { .. }))
+        .collect();
+    assert_eq!(
+        events,
+        vec![
+            DownloadEvent::HeadReceived {
+                url: format!("{}/", url),
+                content_length: Some(10),
+                ranges: true,
+            },
+            DownloadEvent::RangePlanned {
+                content_length: 10,
+                num_fetches: 1,
+            },
+            DownloadEvent::ChunkStarted {
+                range,
+                url: url.to_owned(),
+            },
+            DownloadEvent::ChunkCompleted { range },
+            DownloadEvent::Saved {
+                path: temp_file_path.clone(),
+            },
+            DownloadEvent::Finished { error: None },
+        ]
+    );
+
+    let mut file = File::open(temp_file_path).unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "HelloWorld");
+}
+
+#[tokio::test]
+async fn events_through_fetch_many() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-9")
+        .with_header("content-length", "10")
+        .with_header("content-range", "bytes 0-9/10")
+        .with_body(&b"HelloWorld")
+        .create();
+
+    let (sender, events) = unbounded();
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(1)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .events(sender)
+        .build();
+
+    let results = fetch_many(vec![options], 1).await.unwrap();
+    debug!(logger, "fetch finished"; "results" => format!("{:?}", &results));
+
+    assert!(results[0].is_ok());
+
+    // The sender is dropped with the options once the download finishes
+    let events: Vec<DownloadEvent> = events.collect().await;
+    assert_eq!(
+        events.last(),
+        Some(&DownloadEvent::Saved {
+            path: temp_file_path.clone(),
+        })
+    );
+}
+
+#[tokio::test]
+async fn clobber_rename() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+    std::fs::write(&temp_file_path, b"Existing").unwrap();
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_body(&b"Hello")
+        .create();
+
+    let _body_mock2 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=5-9")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 5-9/10")
+        .with_body(&b"World")
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .clobber(Clobber::Rename)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    assert!(result.is_ok());
+
+    let contents = std::fs::read_to_string(&temp_file_path).unwrap();
+    assert_eq!(contents, "Existing");
+
+    temp_file_path.set_extension("tmp.1");
+    let contents = std::fs::read_to_string(&temp_file_path).unwrap();
+    assert_eq!(contents, "HelloWorld");
+}
+
+#[tokio::test]
+async fn preserve_timestamps() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .with_header("last-modified", "Wed, 21 Oct 2015 07:28:00 GMT")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_body(&b"Hello")
+        .create();
+
+    let _body_mock2 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=5-9")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 5-9/10")
+        .with_body(&b"World")
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .preserve_timestamps(true)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    assert!(result.is_ok());
+
+    let modified = std::fs::metadata(&temp_file_path)
+        .unwrap()
+        .modified()
+        .unwrap();
+    assert_eq!(
+        modified.duration_since(UNIX_EPOCH).unwrap(),
+        Duration::from_secs(1_445_412_480)
+    );
+
+    let mut file = File::open(temp_file_path).unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "HelloWorld");
+}
+
+#[tokio::test]
+async fn write_metadata() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .with_header("etag", "\"abc\"")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_body(&b"Hello")
+        .create();
+
+    let _body_mock2 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=5-9")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 5-9/10")
+        .with_body(&b"World")
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .write_metadata(true)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    assert!(result.is_ok());
+
+    temp_file_path.set_extension("tmp.meta.json");
+    let metadata = std::fs::read_to_string(temp_file_path).unwrap();
+    assert!(metadata.contains(&format!("\"url\": \"{}\"", url)));
+    assert!(metadata.contains("\"etag\": \"\\\"abc\\\"\""));
+    assert!(metadata.contains("\"size\": 10"));
+    assert!(metadata.contains(
+        "\"sha256\": \"872e4e50ce9990d8b041330c47c9ddd11bec6b503ae9386a99da8584e9bb12c4\""
+    ));
+}
+
+#[tokio::test]
+async fn existing_output_matches() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+    std::fs::write(&temp_file_path, b"HelloWorld").unwrap();
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .with_header("etag", "\"68e109f0f40ca72a15e05cc22786f8e6\"")
+        .create();
+
+    let body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_body(&b"Hello")
+        .expect(0)
+        .create();
+
+    let _body_mock2 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=5-9")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 5-9/10")
+        .with_body(&b"World")
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .logger(logger.clone())
+        .check_etag(true)
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    assert!(result.is_ok());
+    body_mock.assert();
+
+    let mut file = File::open(temp_file_path).unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "HelloWorld");
+}
+
+#[tokio::test]
+async fn conditional_not_modified() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+    std::fs::write(&temp_file_path, b"HelloWorld").unwrap();
+
+    let url = &format!("{}/conditional", mockito::server_url());
+    std::fs::write(
+        temp_dir.path().join("out.tmp.meta.json"),
+        format!(
+            "{{\"url\": \"{}\", \"final_url\": \"{}\", \"etag\": \"\\\"v1\\\"\", \"size\": 10}}",
+            url, url
+        ),
+    )
+    .unwrap();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let head_mock = mockito::mock("HEAD", "/conditional")
+        .match_header("if-none-match", "\"v1\"")
+        .with_status(304)
+        .create();
+
+    let body_mock = mockito::mock("GET", "/conditional")
+        .with_status(200)
+        .with_body(&b"HelloThere")
+        .expect(0)
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .logger(logger.clone())
+        .conditional(true)
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    assert!(result.is_ok());
+    head_mock.assert();
+    body_mock.assert();
+    assert_eq!(std::fs::read(temp_file_path).unwrap(), b"HelloWorld");
+}
+
+#[tokio::test]
+async fn cached_download() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let cache_dir = temp_dir.path().join("cache");
+
+    let url = &format!("{}/cached", mockito::server_url());
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/cached")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .with_header("etag", "\"cached\"")
+        .create();
+
+    let body_mock = mockito::mock("GET", "/cached")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_body(&b"Hello")
+        .expect(1)
+        .create();
+
+    let body_mock2 = mockito::mock("GET", "/cached")
+        .with_status(206)
+        .match_header("range", "bytes=5-9")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 5-9/10")
+        .with_body(&b"World")
+        .expect(1)
+        .create();
+
+    for name in &["first.tmp", "second.tmp"] {
+        let options = FetchOptions::builder(url.to_owned())
+            .output_option(temp_dir.path().join(name).to_str().unwrap().to_owned())
+            .num_fetches(2)
+            .logger(logger.clone())
+            .cache(cache_dir.clone())
+            .max_retries(1)
+            .max_retry_after(None)
+            .retry_policy(RetryPolicy::Network)
+            .build();
+
+        let result = fetch(options).await;
+        debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+        assert!(result.is_ok());
+    }
+
+    body_mock.assert();
+    body_mock2.assert();
+    assert_eq!(
+        std::fs::read(temp_dir.path().join("second.tmp")).unwrap(),
+        b"HelloWorld"
+    );
+}
+
+#[tokio::test]
+async fn delta_update() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+    std::fs::write(&temp_file_path, b"AAAAXXXXCCCCDDDD").unwrap();
+    let remote_path = temp_dir.path().join("remote.tmp");
+    std::fs::write(&remote_path, b"AAAABBBBCCCCDDDDEE").unwrap();
+
+    let url = &format!("{}/delta", mockito::server_url());
+    let index = DeltaIndex::build(&remote_path, 4).unwrap().to_zsync(url);
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _index_mock = mockito::mock("GET", "/delta.zsync")
+        .with_status(200)
+        .with_body(&index)
+        .create();
+
+    let _head_mock = mockito::mock("HEAD", "/delta")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "18")
+        .create();
+
+    let body_mock = mockito::mock("GET", "/delta")
+        .with_status(206)
+        .match_header("range", "bytes=4-7")
+        .with_header("content-length", "4")
+        .with_header("content-range", "bytes 4-7/18")
+        .with_body(&b"BBBB")
+        .create();
+
+    let body_mock2 = mockito::mock("GET", "/delta")
+        .with_status(206)
+        .match_header("range", "bytes=16-17")
+        .with_header("content-length", "2")
+        .with_header("content-range", "bytes 16-17/18")
+        .with_body(&b"EE")
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(1)
+        .logger(logger.clone())
+        .delta_index(format!("{}.zsync", url))
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    assert!(result.is_ok());
+    body_mock.assert();
+    body_mock2.assert();
+    assert_eq!(
+        std::fs::read(&temp_file_path).unwrap(),
+        b"AAAABBBBCCCCDDDDEE"
+    );
+}
+
+#[tokio::test]
+async fn changed_during_download() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .with_header("etag", "\"abc\"")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .match_header("if-match", "\"abc\"")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_body(&b"Hello")
+        .create();
+
+    // The file was replaced after the first range was fetched
+    let _body_mock2 = mockito::mock("GET", "/")
+        .with_status(412)
+        .match_header("range", "bytes=5-9")
+        .match_header("if-match", "\"abc\"")
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    let error = result.expect_err("testing");
+
+    if let FetchError::ContentChangedError(msg) = error.inner() {
+        assert_eq!("Remote file changed during the download", msg);
+    } else {
+        panic!("Expected ContentChangedError");
+    }
+}
+
+#[tokio::test]
+async fn range_etag_changed() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .with_header("etag", "W/\"abc\"")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_header("etag", "W/\"abc\"")
+        .with_body(&b"Hello")
+        .create();
+
+    let _body_mock2 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=5-9")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 5-9/10")
+        .with_header("etag", "W/\"def\"")
+        .with_body(&b"World")
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    let error = result.expect_err("testing");
+
+    if let FetchError::ContentChangedError(msg) = error.inner() {
+        assert_eq!(
+            "Range response ETag \"W/\\\"def\\\"\" differs from \"W/\\\"abc\\\"\"",
+            msg
+        );
+    } else {
+        panic!("Expected ContentChangedError");
+    }
+}
+
+#[tokio::test]
+async fn zero_fetches_rejected() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &format!("{}/zero_fetches", mockito::server_url());
+
+    let head_mock = mockito::mock("HEAD", "/zero_fetches")
+        .with_status(200)
+        .with_header("Accept-Ranges", "bytes")
+        .with_header("Content-Length", "10")
+        .expect(0)
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(0)
+        .logger(NullLoggerBuilder.build().unwrap())
+        .build();
+
+    let error = fetch(options).await.expect_err("testing");
+
+    head_mock.assert();
+    if let FetchError::InvalidArgumentsError(msg) = error {
+        assert_eq!("Number of fetches must be greater than zero", msg);
+    } else {
+        panic!("Expected InvalidArgumentsError");
+    }
+    assert!(!temp_file_path.exists());
+}
+
+#[tokio::test]
+async fn chunk_size_ranges() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-2")
+        .with_header("content-length", "3")
+        .with_header("content-range", "bytes 0-2/10")
+        .with_body(&b"Hel")
+        .create();
+
+    let _body_mock2 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=3-5")
+        .with_header("content-length", "3")
+        .with_header("content-range", "bytes 3-5/10")
+        .with_body(&b"loW")
+        .create();
+
+    let _body_mock3 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=6-9")
+        .with_header("content-length", "4")
+        .with_header("content-range", "bytes 6-9/10")
+        .with_body(&b"orld")
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .chunk_size(4)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    assert!(result.is_ok());
+
+    let mut file = File::open(temp_file_path).unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "HelloWorld");
+}
+
+#[tokio::test]
+async fn adaptive_concurrency() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-2")
+        .with_header("content-length", "3")
+        .with_header("content-range", "bytes 0-2/10")
+        .with_body(&b"Hel")
+        .create();
+
+    let _body_mock2 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=3-5")
+        .with_header("content-length", "3")
+        .with_header("content-range", "bytes 3-5/10")
+        .with_body(&b"loW")
+        .create();
+
+    let _body_mock3 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=6-9")
+        .with_header("content-length", "4")
+        .with_header("content-range", "bytes 6-9/10")
+        .with_body(&b"orld")
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(4)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .chunk_size(4)
+        .adaptive(true)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    assert!(result.is_ok());
+
+    let mut file = File::open(temp_file_path).unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "HelloWorld");
+}
+
+#[tokio::test]
+async fn head_rejected_range_probe() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/").with_status(405).create();
+
+    let _probe_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-0")
+        .with_header("content-length", "1")
+        .with_header("content-range", "bytes 0-0/10")
+        .with_body(&b"H")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_body(&b"Hello")
+        .create();
+
+    let _body_mock2 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=5-9")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 5-9/10")
+        .with_body(&b"World")
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    assert!(result.is_ok());
+
+    let mut file = File::open(temp_file_path).unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "HelloWorld");
+}
+
+#[tokio::test]
+async fn accept_ranges_missing_probe() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("content-length", "10")
+        .create();
+
+    let _probe_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-0")
+        .with_header("content-length", "1")
+        .with_header("content-range", "bytes 0-0/10")
+        .with_body(&b"H")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_body(&b"Hello")
+        .create();
+
+    let _body_mock2 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=5-9")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 5-9/10")
+        .with_body(&b"World")
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .probe_ranges(true)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    assert!(result.is_ok());
+
+    let mut file = File::open(temp_file_path).unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "HelloWorld");
+}
+
+#[tokio::test]
+async fn second_fetch_retries() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_body(&b"Hello")
+        .create();
+
+    let _body_mock2 = mockito::mock("GET", "/")
+        .with_status(500)
+        .match_header("range", "bytes=5-9")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 5-9/10")
+        .expect(2)
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .logger(logger.clone())
+        .max_retries(2)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    _body_mock.assert();
+    _body_mock2.assert();
+
+    assert!(result.is_err());
+
+    let error = result.expect_err("testing");
+
+    if let FetchError::ReqwestError(error) = error.inner() {
+        assert_eq!(error.status(), Some(StatusCode::INTERNAL_SERVER_ERROR));
+    } else {
+        panic!("Expected ReqwestError");
+    }
+}
+
+#[tokio::test]
+async fn second_fetch_fails_degraded_restart() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_body(&b"Hello")
+        .create();
+
+    let _body_mock2 = mockito::mock("GET", "/")
+        .with_status(500)
+        .match_header("range", "bytes=5-9")
+        .create();
+
+    let _body_mock3 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-9")
+        .with_header("content-length", "10")
+        .with_header("content-range", "bytes 0-9/10")
+        .with_body(&b"HelloWorld")
+        .expect(1)
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .logger(logger.clone())
+        .max_retries(1)
+        .degraded_restart(true)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    _body_mock3.assert();
+
+    assert!(result.is_ok());
+
+    let mut file = File::open(temp_file_path).unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "HelloWorld");
+}
+
+#[derive(Debug, Default)]
+struct FixedSizeScheduler {
+    content_length: u64,
+    cursor: u64,
+}
+
+impl ChunkScheduler for FixedSizeScheduler {
+    fn plan(&mut self, content_length: u64, _num_fetches: u64) -> Result<()> {
+        self.content_length = content_length;
+        self.cursor = 0;
+        Ok(())
+    }
+
+    fn next_range(&mut self, _state: &ScheduleState<'_>) -> Option<Range> {
+        if self.cursor >= self.content_length {
+            return None;
+        }
+        let start = self.cursor;
+        let end = std::cmp::min(start + 5, self.content_length) - 1;
+        self.cursor = end + 1;
+        Some(Range { start, end })
+    }
+}
+
+#[tokio::test]
+async fn custom_scheduler() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_body(&b"Hello")
+        .expect(1)
+        .create();
+
+    let _body_mock2 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=5-9")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 5-9/10")
+        .with_body(&b"World")
+        .expect(1)
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(1)
+        .logger(logger.clone())
+        .max_retries(1)
+        .scheduler(Box::new(FixedSizeScheduler::default()))
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    _body_mock.assert();
+    _body_mock2.assert();
+
+    assert!(result.is_ok());
+
+    let mut file = File::open(temp_file_path).unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "HelloWorld");
+}
+
+#[derive(Debug, Default)]
+struct BufferSink {
+    bytes: Mutex<Vec<u8>>,
+    finished: AtomicBool,
+}
+
+impl ChunkSink for BufferSink {
+    fn write_at(&self, buf: Vec<u8>, offset: u64) -> SinkFuture<'_> {
+        let mut bytes = self.bytes.lock().unwrap();
+        let end = offset as usize + buf.len();
+        if bytes.len() < end {
+            bytes.resize(end, 0);
+        }
+        bytes[offset as usize..end].copy_from_slice(&buf);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn finish(&self) -> SinkFuture<'_> {
+        self.finished.store(true, Ordering::SeqCst);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[tokio::test]
+async fn custom_sink() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_body(&b"Hello")
+        .expect(1)
+        .create();
+
+    let _body_mock2 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=5-9")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 5-9/10")
+        .with_body(&b"World")
+        .expect(1)
+        .create();
+
+    let sink = Arc::new(BufferSink::default());
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .logger(logger.clone())
+        .max_retries(1)
+        .num_fetches(2)
+        .sink(sink.clone())
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    _body_mock.assert();
+    _body_mock2.assert();
+
+    assert!(result.is_ok());
+
+    assert!(!temp_file_path.exists());
+    assert_eq!(&sink.bytes.lock().unwrap()[..], b"HelloWorld");
+    assert!(sink.finished.load(Ordering::SeqCst));
+}
+
+#[derive(Debug, Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn ordered_sink() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_body(&b"Hello")
+        .expect(1)
+        .create();
+
+    let _body_mock2 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=5-9")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 5-9/10")
+        .with_body(&b"World")
+        .expect(1)
+        .create();
+
+    let written = SharedBuffer::default();
+    let sink = Arc::new(OrderedSink::new(written.clone(), 1));
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .logger(logger.clone())
+        .max_retries(1)
+        .num_fetches(2)
+        .sink(sink.clone())
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    _body_mock.assert();
+    _body_mock2.assert();
+
+    assert!(result.is_ok());
+
+    assert!(!temp_file_path.exists());
+    assert_eq!(&written.0.lock().unwrap()[..], b"HelloWorld");
+}
+
+#[tokio::test]
+async fn parallel_reader_seeks() {
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _probe_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-0")
+        .with_header("content-range", "bytes 0-0/10")
+        .with_header("etag", "\"abc\"")
+        .with_body(&b"H")
+        .expect(1)
+        .create();
+
+    let mut mocks = Vec::new();
+    for (start, end, body) in &[(0, 3, "Hell"), (4, 7, "oWor"), (8, 9, "ld")] {
+        let mock = mockito::mock("GET", "/")
+            .with_status(206)
+            .match_header("range", format!("bytes={}-{}", start, end).as_str())
+            .match_header("if-match", "\"abc\"")
+            .with_header("content-range", &format!("bytes {}-{}/10", start, end))
+            .with_body(body)
+            .expect(1)
+            .create();
+        mocks.push(mock);
+    }
+
+    let options = FetchOptions::builder(url.to_owned())
+        .logger(logger.clone())
+        .max_retries(1)
+        .num_fetches(2)
+        .chunk_size(4)
+        .build();
+
+    let mut reader = ParallelReader::open(options).await.unwrap();
+    assert_eq!(reader.content_length(), 10);
+
+    let mut head = [0u8; 4];
+    reader.read_exact(&mut head).await.unwrap();
+    assert_eq!(&head, b"Hell");
+
+    reader.seek(SeekFrom::Current(1)).await.unwrap();
+    let mut tail = Vec::new();
+    reader.read_to_end(&mut tail).await.unwrap();
+    assert_eq!(tail, b"World");
+
+    _probe_mock.assert();
+    for mock in &mocks {
+        mock.assert();
+    }
+}
+
+#[tokio::test]
+async fn fetch_into_memory() {
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_body(&b"Hello")
+        .expect(1)
+        .create();
+
+    let _body_mock2 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=5-9")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 5-9/10")
+        .with_body(&b"World")
+        .expect(1)
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .logger(logger.clone())
+        .max_retries(1)
+        .num_fetches(2)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let result = fetch_to_vec(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    _body_mock.assert();
+    _body_mock2.assert();
+
+    assert_eq!(result.unwrap(), b"HelloWorld");
+}
+
+#[tokio::test]
+async fn partial_range() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=2-4")
+        .with_header("content-length", "3")
+        .with_header("content-range", "bytes 2-4/10")
+        .with_body(&b"llo")
+        .expect(1)
+        .create();
+
+    let _body_mock2 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=5-7")
+        .with_header("content-length", "3")
+        .with_header("content-range", "bytes 5-7/10")
+        .with_body(&b"Wor")
+        .expect(1)
+        .create();
+
+    let sink = Arc::new(MemorySink::default());
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .logger(logger.clone())
+        .max_retries(1)
+        .num_fetches(2)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let result = fetch_partial(options, 2..8, sink.clone()).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    _body_mock.assert();
+    _body_mock2.assert();
+
+    assert!(result.is_ok());
+
+    assert!(!temp_file_path.exists());
+    assert_eq!(sink.take(), b"lloWor");
+}
+
+#[tokio::test]
+async fn range_option() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=2-4")
+        .with_header("content-length", "3")
+        .with_header("content-range", "bytes 2-4/10")
+        .with_body(&b"llo")
+        .expect(1)
+        .create();
+
+    let _body_mock2 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=5-7")
+        .with_header("content-length", "3")
+        .with_header("content-range", "bytes 5-7/10")
+        .with_body(&b"Wor")
+        .expect(1)
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .logger(logger.clone())
+        .max_retries(1)
+        .num_fetches(2)
+        .range(Range { start: 2, end: 7 })
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    _body_mock.assert();
+    _body_mock2.assert();
+
+    assert!(result.is_ok());
+
+    let mut file = File::open(temp_file_path).unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "lloWor");
+}
+
+#[tokio::test]
+async fn resume_from_state_file() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+    let mut state_file_path = PathBuf::from(temp_dir.path());
+    state_file_path.push("out.tmp.pfstate");
+
+    std::fs::write(&temp_file_path, b"Hello").unwrap();
+    std::fs::write(&state_file_path, b"content-length: 10\netag: \n0-4\n").unwrap();
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_body(&b"Hello")
+        .expect(0)
+        .create();
+
+    let _body_mock2 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=5-9")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 5-9/10")
+        .with_body(&b"World")
+        .expect(1)
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .logger(logger.clone())
+        .max_retries(1)
+        .resumable(true)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    _body_mock.assert();
+    _body_mock2.assert();
+
+    assert!(result.is_ok());
+    assert!(!state_file_path.exists());
+
+    let mut file = File::open(temp_file_path).unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "HelloWorld");
+}
+
+#[tokio::test]
+async fn interrupt_saves_state_file() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+    let mut state_file_path = PathBuf::from(temp_dir.path());
+    state_file_path.push("out.tmp.pfstate");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_body(&b"Hello")
+        .create();
+
+    let body_mock2 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=5-9")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 5-9/10")
+        .with_body(&b"World")
+        .expect(0)
+        .create();
+
+    // Interrupt once the first range is written
+    let interrupt = Interrupt::new();
+    let raised = interrupt.clone();
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(1)
+        .chunk_size(5)
+        .logger(logger.clone())
+        .max_retries(3)
+        .interrupt(interrupt)
+        .progress(Box::new(move |update| {
+            if update.downloaded >= 5 {
+                raised.interrupt();
+            }
+        }))
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    match result {
+        Err(FetchError::InterruptedError(_)) => {}
+        result => panic!("Expected an InterruptedError, got {:?}", result),
+    }
+    body_mock2.assert();
+    assert_eq!(
+        std::fs::read_to_string(&state_file_path).unwrap(),
+        "content-length: 10\netag: \n0-4\n"
+    );
+}
+
+#[tokio::test]
+async fn interrupt_deletes_output() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+    let mut state_file_path = PathBuf::from(temp_dir.path());
+    state_file_path.push("out.tmp.pfstate");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_body(&b"Hello")
+        .create();
+
+    let body_mock2 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=5-9")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 5-9/10")
+        .with_body(&b"World")
+        .expect(0)
+        .create();
+
+    // Interrupt once the first range is written
+    let interrupt = Interrupt::new();
+    let raised = interrupt.clone();
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(1)
+        .chunk_size(5)
+        .logger(logger.clone())
+        .max_retries(3)
+        .interrupt(interrupt)
+        .on_interrupt(OnInterrupt::Delete)
+        .progress(Box::new(move |update| {
+            if update.downloaded >= 5 {
+                raised.interrupt();
+            }
+        }))
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    match result {
+        Err(FetchError::InterruptedError(_)) => {}
+        result => panic!("Expected an InterruptedError, got {:?}", result),
+    }
+    body_mock2.assert();
+    assert!(!temp_file_path.exists());
+    assert!(!state_file_path.exists());
+}
+
+#[tokio::test]
+async fn watch_skips_unchanged_file() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = format!("{}/watched", mockito::server_url());
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/watched")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "5")
+        .with_header("etag", "\"v1\"")
+        .create();
+
+    // Fetched once, as the ETag stays the same
+    let body_mock = mockito::mock("GET", "/watched")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/5")
+        .with_body(&b"Hello")
+        .expect(1)
+        .create();
+
+    let interrupt = Interrupt::new();
+    let raised = interrupt.clone();
+    tokio::spawn(async move {
+        tokio::timer::delay_for(Duration::from_millis(500)).await;
+        raised.interrupt();
+    });
+
+    let result = fetch_watched(
+        || {
+            FetchOptions::builder(url.clone())
+                .output_option(temp_file_path.to_str().unwrap().to_owned())
+                .num_fetches(1)
+                .logger(logger.clone())
+                .max_retries(3)
+                .interrupt(interrupt.clone())
+                .build()
+        },
+        Duration::from_millis(100),
+    )
+    .await;
+    debug!(logger, "watch finished"; "result" => format!("{:?}", &result));
+
+    assert!(result.is_ok());
+    body_mock.assert();
+    assert_eq!(std::fs::read(&temp_file_path).unwrap(), b"Hello");
+}
+
+#[tokio::test]
+async fn resume_changed_remote_file() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+    let mut state_file_path = PathBuf::from(temp_dir.path());
+    state_file_path.push("out.tmp.pfstate");
+
+    // The first half of a file which has since changed
+    std::fs::write(&temp_file_path, b"Jello").unwrap();
+    std::fs::write(
+        &state_file_path,
+        b"content-length: 10\netag: \"abc\"\n0-4\n",
+    )
+    .unwrap();
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .with_header("etag", "\"abc\"")
+        .create();
+
+    let changed_mock = mockito::mock("GET", "/")
+        .with_status(200)
+        .match_header("range", "bytes=5-9")
+        .match_header("if-range", "\"abc\"")
+        .with_header("content-length", "10")
+        .with_body(&b"HelloWorld")
+        .expect(1)
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .match_header("if-range", Matcher::Missing)
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_body(&b"Hello")
+        .expect(1)
+        .create();
+
+    let _body_mock2 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=5-9")
+        .match_header("if-range", Matcher::Missing)
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 5-9/10")
+        .with_body(&b"World")
+        .expect(1)
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .logger(logger.clone())
+        .max_retries(1)
+        .resumable(true)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    changed_mock.assert();
+    _body_mock.assert();
+    _body_mock2.assert();
+
+    assert!(result.is_ok());
+    assert!(!state_file_path.exists());
+
+    let mut file = File::open(temp_file_path).unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "HelloWorld");
+}
+
+#[tokio::test]
+async fn continue_existing_file() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    std::fs::write(&temp_file_path, b"Hel").unwrap();
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=3-4")
+        .with_header("content-length", "2")
+        .with_header("content-range", "bytes 3-4/10")
+        .with_body(&b"lo")
+        .expect(1)
+        .create();
+
+    let _body_mock2 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=5-9")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 5-9/10")
+        .with_body(&b"World")
+        .expect(1)
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .logger(logger.clone())
+        .max_retries(1)
+        .resume(true)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    _body_mock.assert();
+    _body_mock2.assert();
+
+    assert!(result.is_ok());
+
+    let mut file = File::open(temp_file_path).unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "HelloWorld");
+}
+
+#[tokio::test]
+async fn progress_updates() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_body(&b"Hello")
+        .create();
+
+    let _body_mock2 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=5-9")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 5-9/10")
+        .with_body(&b"World")
+        .create();
+
+    let updates: Arc<Mutex<Vec<ProgressUpdate>>> = Arc::new(Mutex::new(Vec::new()));
+    let recorded = updates.clone();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .logger(logger.clone())
+        .max_retries(1)
+        .progress(Box::new(move |update| {
+            recorded.lock().unwrap().push(update)
+        }))
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    assert!(result.is_ok());
+
+    let updates = updates.lock().unwrap();
+    let last = updates.last().expect("expected progress updates");
+    assert_eq!(last.downloaded, 10);
+    assert_eq!(last.total, 10);
+    for range in &[Range { start: 0, end: 4 }, Range { start: 5, end: 9 }] {
+        let range_downloaded = updates
+            .iter()
+            .filter(|update| update.range == *range)
+            .map(|update| update.range_downloaded)
+            .max();
+        assert_eq!(range_downloaded, Some(5));
+    }
+}
+
+#[tokio::test]
+async fn limit_rate() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .with_header("content-length", "10")
+        .with_header("content-range", "bytes 0-9/10")
+        .with_body(&b"HelloWorld")
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(1)
+        .logger(logger.clone())
+        .max_retries(1)
+        .limit_rate(5)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let started = Instant::now();
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    assert!(result.is_ok());
+    // 5 bytes may burst immediately, the other 5 take a second
+    assert!(started.elapsed() >= Duration::from_millis(900));
+}
+
+#[tokio::test]
+async fn second_fetch_retry_after() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_body(&b"Hello")
+        .create();
+
+    let _body_mock2 = mockito::mock("GET", "/")
+        .with_status(429)
+        .match_header("range", "bytes=5-9")
+        .with_header("retry-after", "120")
+        .expect(2)
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .logger(logger.clone())
+        .max_retries(2)
+        .max_retry_after(Duration::from_millis(300))
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let started = Instant::now();
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    _body_mock2.assert();
+
+    // The 120 second Retry-After is capped by max_retry_after
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(300));
+    assert!(elapsed < Duration::from_secs(60));
+
+    let error = result.expect_err("testing");
+
+    if let FetchError::ReqwestError(error) = error.inner() {
+        assert_eq!(error.status(), Some(StatusCode::TOO_MANY_REQUESTS));
+    } else {
+        panic!("Expected ReqwestError");
+    }
+}
+
+#[tokio::test]
+async fn check_etag_success() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let mut hasher = Md5::new();
+    hasher.input(b"HelloWorld");
+    let hash_result = hasher.result();
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("etag", &hex::encode(hash_result))
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .with_header("content-length", "10")
+        .with_header("content-range", "bytes 0-9/10")
+        .with_body(&b"HelloWorld")
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(1)
+        .logger(logger.clone())
+        .check_etag(true)
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    assert!(result.is_ok());
+
+    let mut file = File::open(temp_file_path).unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "HelloWorld");
+}
+
+#[tokio::test]
+async fn check_etag_failure() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("etag", "feeddead")
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .with_header("content-length", "10")
+        .with_header("content-range", "bytes 0-9/10")
+        .with_body(&b"HelloWorld")
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(1)
+        .logger(logger.clone())
+        .check_etag(true)
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    let error = result.expect_err("testing");
+
+    if let FetchError::ValidationError(msg) = error {
+        assert_eq!("ETag does not match", msg);
+    } else {
+        panic!("Expected ValidationError");
+    }
+}
+
+#[tokio::test]
+async fn retry_resumes_partial_range() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    // The connection closes after only two of the five bytes
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_body("He")
+        .create();
+
+    let _body_mock2 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=2-4")
+        .with_header("content-length", "3")
+        .with_header("content-range", "bytes 2-4/10")
+        .with_body("llo")
+        .create();
+
+    let _body_mock3 = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=5-9")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 5-9/10")
+        .with_body("World")
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .logger(logger.clone())
+        .max_retries(2)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    _body_mock.assert();
+    _body_mock2.assert();
+
+    assert!(result.is_ok());
+
+    let mut file = File::open(temp_file_path).unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "HelloWorld");
+}
+
+#[tokio::test]
+async fn checksum_mismatch() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "5")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/5")
+        .with_body("Hello")
+        .create();
+
+    let checksum: Checksum =
+        "sha256:0000000000000000000000000000000000000000000000000000000000000000"
+            .parse()
+            .unwrap();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(1)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .checksum(checksum)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    let error = result.expect_err("testing");
+
+    if let FetchError::ValidationError(msg) = error {
+        assert_eq!(msg, "sha256 checksum does not match");
+    } else {
+        panic!("Expected ValidationError");
+    }
+}
+
+#[tokio::test]
+async fn checksum_url() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &format!("{}/release.bin", mockito::server_url());
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/release.bin")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "5")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/release.bin")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/5")
+        .with_body("Hello")
+        .create();
+
+    let _sums_mock = mockito::mock("GET", "/SHA256SUMS")
+        .with_status(200)
+        .with_body(
+            "0000000000000000000000000000000000000000000000000000000000000000  other.bin\n\
+             185f8db32271fe25f561a6fc938b2e264306ec304eda518007d1764826381969  release.bin\n",
+        )
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(1)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .checksum_url(format!("{}/SHA256SUMS", mockito::server_url()))
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    _sums_mock.assert();
+
+    assert!(result.is_ok());
+}
+
+#[tokio::test]
+async fn minisign_signature() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+    let public_key = temp_dir.path().join("minisign.pub");
+    std::fs::write(
+        &public_key,
+        "untrusted comment: minisign public key\n\
+         RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3\n",
+    )
+    .unwrap();
+
+    let url = &format!("{}/release.bin", mockito::server_url());
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/release.bin")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "4")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/release.bin")
+        .with_status(206)
+        .match_header("range", "bytes=0-3")
+        .with_header("content-length", "4")
+        .with_header("content-range", "bytes 0-3/4")
+        .with_body("test")
+        .create();
+
+    let _signature_mock = mockito::mock("GET", "/release.bin.minisig")
+        .with_status(200)
+        .with_body(
+            "untrusted comment: signature from minisign secret key\n\
+             RWQf6LRCGA9i59SLOFxz6NxvASXDJeRtuZykwQepbDEGt87ig1BNpWaVWuNrm73YiIiJbq71Wi+dP9eKL8OC351vwIasSSbXxwA=\n\
+             trusted comment: timestamp:1555779966\tfile:test\n\
+             QtKMXWyYcwdpZAlPF7tE2ENJkRd1ujvKjlj1m9RtHTBnZPa5WKU5uWRs5GoP5M/VqE81QFuMKI5k/SfNQUaOAA==\n",
+        )
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(1)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .signature(SignatureCheck {
+            kind: SignatureKind::Minisign,
+            public_key,
+            signature_file: None,
+        })
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    _signature_mock.assert();
+
+    assert!(result.is_ok());
+}
+
+#[tokio::test]
+async fn check_multipart_etag() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("etag", "\"059d5559e903e95356438a41dfe641e4-3\"")
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .with_header("content-length", "10")
+        .with_header("content-range", "bytes 0-9/10")
+        .with_body("HelloWorld")
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(1)
+        .logger(logger.clone())
+        .check_etag(true)
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .etag_part_size(4)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    assert!(result.is_ok());
+}
+
+#[tokio::test]
+async fn verify_headers_mismatch() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "5")
+        .with_header("content-md5", "AAAAAAAAAAAAAAAAAAAAAA==")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/5")
+        .with_body("Hello")
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(1)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .verify_headers(true)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    let error = result.expect_err("testing");
+
+    if let FetchError::ValidationError(msg) = error {
+        assert_eq!(msg, "md5 checksum does not match");
+    } else {
+        panic!("Expected ValidationError");
+    }
+}
+
+#[tokio::test]
+async fn fetch_many_downloads() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let mut mocks = Vec::new();
+    for (path, body) in &[("/hello", "Hello"), ("/world", "World")] {
+        mocks.push(
+            mockito::mock("HEAD", *path)
+                .with_status(200)
+                .with_header("accept-ranges", "bytes")
+                .with_header("content-length", "5")
+                .create(),
+        );
+        mocks.push(
+            mockito::mock("GET", *path)
+                .with_status(206)
+                .match_header("range", "bytes=0-4")
+                .with_header("content-length", "5")
+                .with_header("content-range", "bytes 0-4/5")
+                .with_body(*body)
+                .create(),
+        );
+    }
+
+    let options: Vec<FetchOptions> = ["/hello", "/world"]
+        .iter()
+        .map(|path| {
+            FetchOptions::builder(format!("{}{}", mockito::server_url(), path))
+                .output_option(temp_dir.path().to_str().unwrap().to_owned())
+                .num_fetches(1)
+                .logger(logger.clone())
+                .max_retries(1)
+                .max_retry_after(None)
+                .retry_policy(RetryPolicy::Network)
+                .build()
+        })
+        .collect();
+
+    let results = fetch_many(options, 1).await.unwrap();
+    debug!(logger, "fetch finished"; "results" => format!("{:?}", &results));
+
+    assert_eq!(results.len(), 2);
+    assert!(results.iter().all(|result| result.is_ok()));
+
+    for (name, expected) in &[("hello", "Hello"), ("world", "World")] {
+        let mut file = File::open(temp_dir.path().join(name)).unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(&contents, expected);
+    }
+}
+
+#[tokio::test]
+async fn fetch_many_per_host_limit() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/per-host-hello")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "5")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/per-host-hello")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/5")
+        .with_body("Hello")
+        .create();
+
+    let _missing_mock = mockito::mock("HEAD", "/per-host-missing")
+        .with_status(404)
+        .create();
+
+    let options = ["/per-host-hello", "/per-host-missing"].iter().map(|path| {
+        FetchOptions::builder(format!("{}{}", mockito::server_url(), path))
+            .output_option(temp_dir.path().to_str().unwrap().to_owned())
+            .num_fetches(1)
+            .logger(logger.clone())
+            .max_retries(1)
+            .max_retry_after(None)
+            .retry_policy(RetryPolicy::Network)
+            .build()
+    });
+
+    let results = fetch_many(options, FetchLimits::new(2).per_host(1))
+        .await
+        .unwrap();
+    debug!(logger, "fetch finished"; "results" => format!("{:?}", &results));
+
+    assert_eq!(results.len(), 2);
+    assert!(results[0].is_ok());
+    assert!(results[1].is_err());
+
+    let mut file = File::open(temp_dir.path().join("per-host-hello")).unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "Hello");
+}
+
+#[tokio::test]
+async fn mirror_serves_failed_range() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &format!("{}/release.bin", mockito::server_url());
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/release.bin")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "5")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/release.bin")
+        .with_status(404)
+        .match_header("range", "bytes=0-4")
+        .create();
+
+    let _mirror_mock = mockito::mock("GET", "/mirror/release.bin")
+        .with_status(206)
+        .match_header("range", "bytes=0-4")
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/5")
+        .with_body("Hello")
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(1)
+        .logger(logger.clone())
+        .max_retries(2)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .mirrors(vec![format!(
+            "{}/mirror/release.bin",
+            mockito::server_url()
+        )])
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    _body_mock.assert();
+    _mirror_mock.assert();
+
+    assert!(result.is_ok());
+
+    let mut file = File::open(temp_file_path).unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "Hello");
+}
 
-    if let FetchError::ValidationError(msg) = *error {
-        assert_eq!("ETag does not match", msg);
+#[tokio::test]
+async fn custom_headers() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let head_mock = mockito::mock("HEAD", "/")
+        .match_header("x-api-key", "secret")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let body_mock = mockito::mock("GET", "/")
+        .match_header("x-api-key", "secret")
+        .match_header("range", "bytes=0-9")
+        .with_status(206)
+        .with_header("content-length", "10")
+        .with_header("content-range", "bytes 0-9/10")
+        .with_body(&b"HelloWorld")
+        .create();
+
+    let mut headers = HeaderMap::new();
+    headers.insert("x-api-key", "secret".parse().unwrap());
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(1)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .headers(headers)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    assert!(result.is_ok());
+    head_mock.assert();
+    body_mock.assert();
+}
+
+#[tokio::test]
+async fn bearer_credentials() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let head_mock = mockito::mock("HEAD", "/")
+        .match_header("authorization", "Bearer secret")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let body_mock = mockito::mock("GET", "/")
+        .match_header("authorization", "Bearer secret")
+        .with_status(206)
+        .with_header("content-length", "10")
+        .with_header("content-range", "bytes 0-9/10")
+        .with_body(&b"HelloWorld")
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(1)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .credentials(Credentials::Bearer("secret".to_owned()))
+        .build();
+    assert!(!format!("{:?}", options).contains("secret"));
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    assert!(result.is_ok());
+    head_mock.assert();
+    body_mock.assert();
+}
+
+#[tokio::test]
+async fn cookies_shared_across_ranges() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _head_mock = mockito::mock("HEAD", "/")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .with_header("set-cookie", "session=abc; Path=/")
+        .create();
+
+    let first_mock = mockito::mock("GET", "/")
+        .match_header("cookie", "session=abc")
+        .match_header("range", "bytes=0-4")
+        .with_status(206)
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 0-4/10")
+        .with_body(&b"Hello")
+        .create();
+
+    let second_mock = mockito::mock("GET", "/")
+        .match_header("cookie", "session=abc")
+        .match_header("range", "bytes=5-9")
+        .with_status(206)
+        .with_header("content-length", "5")
+        .with_header("content-range", "bytes 5-9/10")
+        .with_body(&b"World")
+        .create();
+
+    let jar = Arc::new(CookieJar::new());
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .cookies(jar.clone())
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    assert!(result.is_ok());
+    first_mock.assert();
+    second_mock.assert();
+
+    let mut file = File::open(temp_file_path).unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "HelloWorld");
+}
+
+#[tokio::test]
+async fn fetch_through_proxy() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    // The mock server acts as the proxy, receiving requests for the absolute url
+    let url = "http://download.invalid/file";
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let head_mock = mockito::mock("HEAD", url)
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let body_mock = mockito::mock("GET", url)
+        .with_status(206)
+        .with_header("content-length", "10")
+        .with_header("content-range", "bytes 0-9/10")
+        .with_body(&b"HelloWorld")
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(1)
+        .logger(logger.clone())
+        .max_retries(1)
+        .max_retry_after(None)
+        .retry_policy(RetryPolicy::Network)
+        .proxy(ProxyOptions {
+            url: Some(mockito::server_url()),
+            credentials: None,
+            no_proxy: vec!["localhost".to_owned()],
+        })
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    assert!(result.is_ok());
+    head_mock.assert();
+    body_mock.assert();
+
+    let mut file = File::open(temp_file_path).unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "HelloWorld");
+}
+
+#[tokio::test]
+async fn traceparent_sent() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &mockito::server_url();
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    // Each request is a new span in the trace, a child of the download's
+    let traceparent =
+        Matcher::Regex("^00-0af7651916cd43dd8448eb211c80319c-[0-9a-f]{16}-01$".to_owned());
+
+    let head_mock = mockito::mock("HEAD", "/")
+        .match_header("traceparent", traceparent.clone())
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let body_mock = mockito::mock("GET", "/")
+        .match_header("traceparent", traceparent)
+        .match_header("range", "bytes=0-9")
+        .with_status(206)
+        .with_header("content-length", "10")
+        .with_header("content-range", "bytes 0-9/10")
+        .with_body(&b"HelloWorld")
+        .create();
+
+    let trace: TraceContext = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"
+        .parse()
+        .unwrap();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(1)
+        .logger(logger.clone())
+        .max_retries(3)
+        .trace(trace)
+        .build();
+
+    let result = fetch(options).await;
+    debug!(logger, "fetch finished"; "result" => format!("{:?}", &result));
+
+    assert!(result.is_ok());
+    head_mock.assert();
+    body_mock.assert();
+}
+
+#[tokio::test]
+async fn dry_run_plans_ranges() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &format!("{}/planned", mockito::server_url());
+
+    let head_mock = mockito::mock("HEAD", "/planned")
+        .with_status(200)
+        .with_header("Accept-Ranges", "bytes")
+        .with_header("Content-Length", "10")
+        .with_header("ETag", "\"68e109f0f40ca72a15e05cc22786f8e6\"")
+        .create();
+
+    let body_mock = mockito::mock("GET", "/planned")
+        .with_status(200)
+        .with_body(&b"HelloWorld")
+        .expect(0)
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .num_fetches(2)
+        .check_etag(true)
+        .logger(NullLoggerBuilder.build().unwrap())
+        .build();
+
+    let plan = plan_download(options).await.unwrap();
+
+    head_mock.assert();
+    body_mock.assert();
+    assert_eq!(plan.path, Some(temp_file_path.clone()));
+    assert_eq!(plan.total_length, Some(10));
+    assert_eq!(plan.num_fetches, 2);
+    assert_eq!(
+        plan.ranges,
+        vec![Range { start: 0, end: 4 }, Range { start: 5, end: 9 }]
+    );
+    assert_eq!(plan.etag_check, Some(ChecksumAlgorithm::Md5));
+    assert!(!temp_file_path.exists());
+}
+
+#[tokio::test]
+async fn head_only_follows_redirects() {
+    let url = &format!("{}/moved", mockito::server_url());
+
+    let moved_mock = mockito::mock("HEAD", "/moved")
+        .with_status(301)
+        .with_header("Location", "/inspected")
+        .create();
+
+    let head_mock = mockito::mock("HEAD", "/inspected")
+        .with_status(200)
+        .with_header("Accept-Ranges", "bytes")
+        .with_header("Content-Length", "10")
+        .with_header("ETag", "\"v1\"")
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .logger(NullLoggerBuilder.build().unwrap())
+        .build();
+
+    let info = inspect(options).await.unwrap();
+
+    moved_mock.assert();
+    head_mock.assert();
+    assert_eq!(info.redirects, vec![(301, url.to_owned())]);
+    assert_eq!(info.url, format!("{}/inspected", mockito::server_url()));
+    assert_eq!(info.status, 200);
+    assert_eq!(info.content_length, Some(10));
+    assert_eq!(info.accept_ranges.as_deref(), Some("bytes"));
+    assert_eq!(info.etag.as_deref(), Some("\"v1\""));
+    assert_eq!(info.last_modified, None);
+}
+
+#[tokio::test]
+async fn expected_size_rejects_other_length() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &format!("{}/sized", mockito::server_url());
+
+    let head_mock = mockito::mock("HEAD", "/sized")
+        .with_status(200)
+        .with_header("Accept-Ranges", "bytes")
+        .with_header("Content-Length", "10")
+        .create();
+
+    let body_mock = mockito::mock("GET", "/sized")
+        .with_status(200)
+        .with_body(&b"HelloWorld")
+        .expect(0)
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .expected_size(12)
+        .logger(NullLoggerBuilder.build().unwrap())
+        .build();
+
+    let error = fetch(options).await.expect_err("testing");
+
+    head_mock.assert();
+    body_mock.assert();
+    if let FetchError::ValidationError(msg) = error {
+        assert_eq!("Server reports 10 bytes but expected 12", msg);
+    } else {
+        panic!("Expected ValidationError");
+    }
+    assert!(!temp_file_path.exists());
+}
+
+#[tokio::test]
+async fn max_filesize_rejects_large_file() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &format!("{}/too_large", mockito::server_url());
+
+    let head_mock = mockito::mock("HEAD", "/too_large")
+        .with_status(200)
+        .with_header("Accept-Ranges", "bytes")
+        .with_header("Content-Length", "10")
+        .create();
+
+    let body_mock = mockito::mock("GET", "/too_large")
+        .with_status(200)
+        .with_body(&b"HelloWorld")
+        .expect(0)
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .max_filesize(5)
+        .logger(NullLoggerBuilder.build().unwrap())
+        .build();
+
+    let error = fetch(options).await.expect_err("testing");
+
+    head_mock.assert();
+    body_mock.assert();
+    if let FetchError::FileTooLargeError(msg) = error {
+        assert_eq!("Server reports 10 bytes, more than the most allowed 5", msg);
+    } else {
+        panic!("Expected FileTooLargeError");
+    }
+    assert!(!temp_file_path.exists());
+}
+
+#[tokio::test]
+async fn expect_content_type_rejects_error_page() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut temp_file_path = PathBuf::from(temp_dir.path());
+    temp_file_path.push("out.tmp");
+
+    let url = &format!("{}/error_page", mockito::server_url());
+
+    let head_mock = mockito::mock("HEAD", "/error_page")
+        .with_status(200)
+        .with_header("Accept-Ranges", "bytes")
+        .with_header("Content-Length", "10")
+        .with_header("Content-Type", "text/html; charset=utf-8")
+        .create();
+
+    let body_mock = mockito::mock("GET", "/error_page")
+        .with_status(200)
+        .with_body(&b"<html></html>")
+        .expect(0)
+        .create();
+
+    let options = FetchOptions::builder(url.to_owned())
+        .output_option(temp_file_path.to_str().unwrap().to_owned())
+        .expect_content_type("application/*".to_owned())
+        .logger(NullLoggerBuilder.build().unwrap())
+        .build();
+
+    let error = fetch(options).await.expect_err("testing");
+
+    head_mock.assert();
+    body_mock.assert();
+    if let FetchError::ValidationError(msg) = error {
+        assert_eq!(
+            "Server reports Content-Type text/html; charset=utf-8 but expected application/*",
+            msg
+        );
     } else {
         panic!("Expected ValidationError");
     }
+    assert!(!temp_file_path.exists());
+}
+
+#[tokio::test]
+async fn fetch_many_fail_fast() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+
+    let logger = NullLoggerBuilder.build().unwrap();
+
+    let _missing_mock = mockito::mock("HEAD", "/missing_fast")
+        .with_status(404)
+        .create();
+
+    let _head_mock = mockito::mock("HEAD", "/slow_fast")
+        .with_status(200)
+        .with_header("accept-ranges", "bytes")
+        .with_header("content-length", "10")
+        .create();
+
+    let _body_mock = mockito::mock("GET", "/slow_fast")
+        .with_status(206)
+        .with_header("content-length", "10")
+        .with_header("content-range", "bytes 0-9/10")
+        .with_body_from_fn(|w| {
+            w.write_all(b"Hello")?;
+            w.flush()?;
+            std::thread::sleep(Duration::from_millis(300));
+            w.write_all(b"World")
+        })
+        .create();
+
+    let options: Vec<FetchOptions> = ["/missing_fast", "/slow_fast"]
+        .iter()
+        .map(|path| {
+            FetchOptions::builder(format!("{}{}", mockito::server_url(), path))
+                .output_option(temp_dir.path().to_str().unwrap().to_owned())
+                .num_fetches(1)
+                .logger(logger.clone())
+                .max_retries(1)
+                .max_retry_after(None)
+                .retry_policy(RetryPolicy::Network)
+                .build()
+        })
+        .collect();
+
+    let results = fetch_many(options, FetchLimits::new(2).fail_fast())
+        .await
+        .unwrap();
+    debug!(logger, "fetch finished"; "results" => format!("{:?}", &results));
+
+    assert_eq!(results.len(), 2);
+    let failed = results[0].as_ref().expect_err("testing");
+    assert!(!matches!(failed.inner(), FetchError::InterruptedError(_)));
+    let stopped = results[1].as_ref().expect_err("testing");
+    assert!(matches!(stopped.inner(), FetchError::InterruptedError(_)));
 }